@@ -0,0 +1,75 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+
+/// Process-local record of each driver's in-progress routes, oldest first
+#[derive(Default)]
+pub struct ActiveRoutes {
+    drivers: Mutex<HashMap<String, VecDeque<String>>>,
+}
+
+impl ActiveRoutes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Note a point for `route_id`. Returns the routes that no longer fit under
+    /// `cap`, oldest first; they are forgotten. A `cap` of 0 tracks nothing.
+    pub fn touch(&self, driver_id: &str, route_id: &str, cap: usize) -> Vec<String> {
+        if cap == 0 {
+            return Vec::new();
+        }
+
+        let mut drivers = self.drivers.lock().unwrap();
+        let routes = drivers.entry(driver_id.to_string()).or_default();
+        if routes.iter().any(|route| route == route_id) {
+            return Vec::new();
+        }
+
+        routes.push_back(route_id.to_string());
+        let over = routes.len().saturating_sub(cap);
+        routes.drain(..over).collect()
+    }
+
+    /// Forget a route once it is finalized
+    pub fn remove(&self, driver_id: &str, route_id: &str) {
+        let mut drivers = self.drivers.lock().unwrap();
+        if let Some(routes) = drivers.get_mut(driver_id) {
+            routes.retain(|route| route != route_id);
+            if routes.is_empty() {
+                drivers.remove(driver_id);
+            }
+        }
+    }
+
+    /// In-progress routes of a driver, oldest first
+    pub fn routes(&self, driver_id: &str) -> Vec<String> {
+        self.drivers
+            .lock()
+            .unwrap()
+            .get(driver_id)
+            .map(|routes| routes.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_oldest_route_pushed_over_cap() {
+        let active = ActiveRoutes::new();
+
+        assert!(active.touch("d", "r1", 2).is_empty());
+        assert!(active.touch("d", "r2", 2).is_empty());
+        assert!(active.touch("d", "r1", 2).is_empty());
+        assert_eq!(active.touch("d", "r3", 2), vec!["r1"]);
+        assert_eq!(active.routes("d"), vec!["r2", "r3"]);
+
+        active.remove("d", "r2");
+        assert!(active.touch("d", "r4", 2).is_empty());
+        assert!(active.touch("other", "r1", 2).is_empty());
+        assert!(active.touch("d", "r5", 0).is_empty());
+        assert!(active.routes("nobody").is_empty());
+    }
+}
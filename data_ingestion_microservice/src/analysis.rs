@@ -0,0 +1,728 @@
+use crate::geodesy::{haversine_distance, EARTH_RADIUS_METERS};
+use crate::route_simplification::deviations_meters;
+use crate::types::{millis_to_secs, Location, MILLIS_PER_SEC};
+use geo::line_intersection::{line_intersection, LineIntersection};
+use geo::{coord, Line};
+use serde::Serialize;
+
+/// Total elevation gain and loss in meters over consecutive points with altitude.
+/// Returns `None` when fewer than two points carry an altitude.
+pub fn elevation_gain_loss(locations: &[Location]) -> Option<(f64, f64)> {
+    let altitudes: Vec<f64> = locations.iter().filter_map(|loc| loc.altitude).collect();
+    if altitudes.len() < 2 {
+        return None;
+    }
+
+    Some(altitudes.windows(2).fold((0.0, 0.0), |(gain, loss), pair| {
+        let delta = pair[1] - pair[0];
+        if delta > 0.0 {
+            (gain + delta, loss)
+        } else {
+            (gain, loss - delta)
+        }
+    }))
+}
+
+/// Indices of local altitude maxima and minima whose rise or drop to the next
+/// extremum is at least `min_delta` meters, so sensor noise does not count.
+pub fn elevation_extrema(locations: &[Location], min_delta: f64) -> Vec<usize> {
+    let samples: Vec<(usize, f64)> = locations
+        .iter()
+        .enumerate()
+        .filter_map(|(i, loc)| loc.altitude.map(|altitude| (i, altitude)))
+        .collect();
+    if samples.len() < 3 {
+        return Vec::new();
+    }
+
+    let mut extrema = Vec::new();
+    let mut candidate = samples[0];
+    // Direction of the current trend: 1 climbing, -1 descending, 0 unknown
+    let mut trend = 0;
+
+    for &(index, altitude) in &samples[1..] {
+        match trend {
+            1 if altitude > candidate.1 => candidate = (index, altitude),
+            -1 if altitude < candidate.1 => candidate = (index, altitude),
+            1 if candidate.1 - altitude >= min_delta => {
+                extrema.push(candidate.0);
+                candidate = (index, altitude);
+                trend = -1;
+            }
+            -1 if altitude - candidate.1 >= min_delta => {
+                extrema.push(candidate.0);
+                candidate = (index, altitude);
+                trend = 1;
+            }
+            0 if (altitude - candidate.1).abs() >= min_delta => {
+                trend = if altitude > candidate.1 { 1 } else { -1 };
+                candidate = (index, altitude);
+            }
+            _ => {}
+        }
+    }
+
+    extrema
+}
+
+/// Fraction of the elapsed time spent moving faster than `min_speed_mps`, using
+/// the speed implied by consecutive timestamped points (timestamps in
+/// milliseconds). Returns `None` when no time elapses between timestamped points.
+pub fn moving_time_fraction(locations: &[Location], min_speed_mps: f64) -> Option<f64> {
+    let samples: Vec<(&Location, u64)> = locations
+        .iter()
+        .filter_map(|loc| loc.timestamp.map(|timestamp| (loc, timestamp)))
+        .collect();
+
+    let (moving, total) = samples
+        .windows(2)
+        .filter(|pair| pair[1].1 > pair[0].1)
+        .fold((0u64, 0u64), |(moving, total), pair| {
+            let elapsed = pair[1].1 - pair[0].1;
+            let speed = haversine_distance(pair[0].0, pair[1].0) / millis_to_secs(elapsed);
+            if speed > min_speed_mps {
+                (moving + elapsed, total + elapsed)
+            } else {
+                (moving, total + elapsed)
+            }
+        });
+
+    (total > 0).then(|| moving as f64 / total as f64)
+}
+
+/// Speed in m/s at each point of a route, as the average speed of the leg
+/// arriving at it; the first point takes the speed of the first leg. Legs
+/// with no elapsed time repeat the previous speed. Timestamps are in
+/// milliseconds. Returns `None` unless every point is timestamped and there
+/// are at least two.
+pub fn speed_profile(locations: &[Location]) -> Option<Vec<f64>> {
+    if locations.len() < 2 || locations.iter().any(|loc| loc.timestamp.is_none()) {
+        return None;
+    }
+
+    let mut speeds = Vec::with_capacity(locations.len());
+    let mut previous = 0.0;
+    for pair in locations.windows(2) {
+        let elapsed = pair[1].timestamp?.saturating_sub(pair[0].timestamp?);
+        if elapsed > 0 {
+            previous = haversine_distance(&pair[0], &pair[1]) / millis_to_secs(elapsed);
+        }
+        speeds.push(previous);
+    }
+    speeds.insert(0, speeds[0]);
+    Some(speeds)
+}
+
+/// Straight-line distance between the endpoints over the length of the path,
+/// both great-circle: near 1 for a direct route, lower the more it winds.
+/// Returns `None` when the path has no length.
+pub fn straightness_index(locations: &[Location]) -> Option<f64> {
+    let (first, last) = (locations.first()?, locations.last()?);
+    let path: f64 = locations
+        .windows(2)
+        .map(|pair| haversine_distance(&pair[0], &pair[1]))
+        .sum();
+    (path > 0.0).then(|| haversine_distance(first, last) / path)
+}
+
+/// Number of stationary points at the start and at the end of a route: the
+/// points before the last one still within `radius_meters` of the first point,
+/// and mirrored at the end. The points where movement begins and ends are
+/// kept. A route that never leaves the radius is left untouched.
+pub fn stationary_ends(locations: &[Location], radius_meters: f64) -> (usize, usize) {
+    let (Some(first), Some(last)) = (locations.first(), locations.last()) else {
+        return (0, 0);
+    };
+    let Some(departure) = locations
+        .iter()
+        .position(|loc| haversine_distance(first, loc) > radius_meters)
+    else {
+        return (0, 0);
+    };
+    let arrival = locations
+        .iter()
+        .rposition(|loc| haversine_distance(last, loc) > radius_meters)
+        .unwrap_or(0);
+
+    let leading = departure - 1;
+    let trailing = locations.len() - arrival - 2;
+    if leading + trailing + 2 > locations.len() {
+        return (leading, 0);
+    }
+    (leading, trailing)
+}
+
+/// Earliest and latest point timestamps of a route. Returns `None` when no
+/// point carries a timestamp.
+pub fn timestamp_range(locations: &[Location]) -> Option<(u64, u64)> {
+    let timestamps = locations.iter().filter_map(|loc| loc.timestamp);
+    Some((timestamps.clone().min()?, timestamps.max()?))
+}
+
+/// Average and maximum occupancy over the points that report one. Returns
+/// `None` when no point carries an occupancy.
+pub fn occupancy_summary(locations: &[Location]) -> Option<(f64, u32)> {
+    let counts: Vec<u32> = locations.iter().filter_map(|loc| loc.occupancy).collect();
+    let max = *counts.iter().max()?;
+    let total: u64 = counts.iter().map(|&count| u64::from(count)).sum();
+    Some((total as f64 / counts.len() as f64, max))
+}
+
+/// Points where the route crosses itself, in route order of the earlier segment.
+///
+/// Segments are compared pairwise in plain longitude/latitude, which is fine for
+/// simplified routes of a few hundred points. Only proper crossings count:
+/// consecutive segments sharing a point, or a loop closing on its start, do not.
+pub fn self_intersections(locations: &[Location]) -> Vec<Location> {
+    let segments: Vec<Line<f64>> = locations
+        .windows(2)
+        .map(|pair| {
+            Line::new(
+                coord! { x: pair[0].longitude, y: pair[0].latitude },
+                coord! { x: pair[1].longitude, y: pair[1].latitude },
+            )
+        })
+        .collect();
+
+    let mut crossings = Vec::new();
+    for (i, first) in segments.iter().enumerate() {
+        for second in segments.iter().skip(i + 2) {
+            if let Some(LineIntersection::SinglePoint {
+                intersection,
+                is_proper: true,
+            }) = line_intersection(*first, *second)
+            {
+                crossings.push(Location::new(intersection.y, intersection.x));
+            }
+        }
+    }
+    crossings
+}
+
+/// Whether every point of `simplified` lies within the bounding box of
+/// `original` grown by `margin_degrees` on each side. Simplification only
+/// drops points, so a point outside the box means the route was corrupted on
+/// the way, typically by swapped latitude and longitude.
+pub fn within_bounding_box(
+    original: &[Location],
+    simplified: &[Location],
+    margin_degrees: f64,
+) -> bool {
+    let Some(first) = original.first() else {
+        return simplified.is_empty();
+    };
+    let (mut min_lat, mut max_lat) = (first.latitude, first.latitude);
+    let (mut min_lon, mut max_lon) = (first.longitude, first.longitude);
+    for location in original {
+        min_lat = min_lat.min(location.latitude);
+        max_lat = max_lat.max(location.latitude);
+        min_lon = min_lon.min(location.longitude);
+        max_lon = max_lon.max(location.longitude);
+    }
+
+    simplified.iter().all(|location| {
+        (min_lat - margin_degrees..=max_lat + margin_degrees).contains(&location.latitude)
+            && (min_lon - margin_degrees..=max_lon + margin_degrees).contains(&location.longitude)
+    })
+}
+
+/// Overlap of the bounding boxes of two routes, each grown by `margin_meters` on
+/// every side, as a fraction of the smaller box. The margin keeps routes running
+/// straight along a meridian or parallel, whose boxes have no area, comparable.
+/// Returns `None` when a route is empty or a grown box still has no area.
+pub fn bounding_box_overlap(a: &[Location], b: &[Location], margin_meters: f64) -> Option<f64> {
+    let (a, b) = (
+        grown_bounds(a, margin_meters)?,
+        grown_bounds(b, margin_meters)?,
+    );
+    let area =
+        |[min_lat, min_lon, max_lat, max_lon]: [f64; 4]| (max_lat - min_lat) * (max_lon - min_lon);
+    let smaller = area(a).min(area(b));
+    if smaller <= 0.0 {
+        return None;
+    }
+
+    let height = (a[2].min(b[2]) - a[0].max(b[0])).max(0.0);
+    let width = (a[3].min(b[3]) - a[1].max(b[1])).max(0.0);
+    Some(height * width / smaller)
+}
+
+/// `[min_lat, min_lon, max_lat, max_lon]` of a route grown by `margin_meters`
+fn grown_bounds(locations: &[Location], margin_meters: f64) -> Option<[f64; 4]> {
+    let first = locations.first()?;
+    let mut bounds = [
+        first.latitude,
+        first.longitude,
+        first.latitude,
+        first.longitude,
+    ];
+    for location in locations {
+        bounds[0] = bounds[0].min(location.latitude);
+        bounds[1] = bounds[1].min(location.longitude);
+        bounds[2] = bounds[2].max(location.latitude);
+        bounds[3] = bounds[3].max(location.longitude);
+    }
+
+    let lat_margin = (margin_meters / EARTH_RADIUS_METERS).to_degrees();
+    let mid_latitude = ((bounds[0] + bounds[2]) / 2.0).to_radians();
+    let lon_margin = lat_margin / mid_latitude.cos().max(1e-6);
+    Some([
+        bounds[0] - lat_margin,
+        bounds[1] - lon_margin,
+        bounds[2] + lat_margin,
+        bounds[3] + lon_margin,
+    ])
+}
+
+/// Fraction of the points of `route` lying within `radius_meters` of the line
+/// through `other`. Returns `None` when either route is empty.
+pub fn route_overlap(route: &[Location], other: &[Location], radius_meters: f64) -> Option<f64> {
+    if route.is_empty() || other.is_empty() {
+        return None;
+    }
+    let within = deviations_meters(route, other)
+        .into_iter()
+        .filter(|deviation| *deviation <= radius_meters)
+        .count();
+    Some(within as f64 / route.len() as f64)
+}
+
+/// Distribution of the time between consecutive timestamped points, in seconds
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SampleIntervalStats {
+    /// Number of timestamped points the intervals were taken from
+    pub sample_count: usize,
+    pub min: f64,
+    pub p25: f64,
+    pub median: f64,
+    pub p75: f64,
+    pub max: f64,
+}
+
+/// Index of the point of `route` nearest to `location`, the first of equally
+/// near ones. Returns `None` for an empty route.
+pub fn nearest_point_index(route: &[Location], location: &Location) -> Option<usize> {
+    route
+        .iter()
+        .map(|point| haversine_distance(point, location))
+        .enumerate()
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(index, _)| index)
+}
+
+/// Sampling interval statistics of a raw route, in seconds from millisecond
+/// timestamps, using linearly interpolated quartiles. Returns `None` when fewer
+/// than two points carry a timestamp.
+pub fn sample_interval_stats(locations: &[Location]) -> Option<SampleIntervalStats> {
+    let timestamps: Vec<u64> = locations.iter().filter_map(|loc| loc.timestamp).collect();
+    if timestamps.len() < 2 {
+        return None;
+    }
+
+    let mut intervals: Vec<f64> = timestamps
+        .windows(2)
+        .map(|pair| millis_to_secs(pair[1].saturating_sub(pair[0])))
+        .collect();
+    intervals.sort_by(f64::total_cmp);
+
+    let quantile = |q: f64| {
+        let position = q * (intervals.len() - 1) as f64;
+        let (lower, upper) = (position.floor() as usize, position.ceil() as usize);
+        intervals[lower] + (intervals[upper] - intervals[lower]) * (position - lower as f64)
+    };
+
+    Some(SampleIntervalStats {
+        sample_count: timestamps.len(),
+        min: intervals[0],
+        p25: quantile(0.25),
+        median: quantile(0.5),
+        p75: quantile(0.75),
+        max: intervals[intervals.len() - 1],
+    })
+}
+
+/// Longest interval between consecutive timestamped points, in whole seconds,
+/// and the number of intervals longer than `threshold_secs`, i.e. device
+/// dropouts. Timestamps are in milliseconds. Returns `None` when fewer than two
+/// points carry a timestamp.
+pub fn fix_gaps(locations: &[Location], threshold_secs: u64) -> Option<(u64, usize)> {
+    let timestamps: Vec<u64> = locations.iter().filter_map(|loc| loc.timestamp).collect();
+    if timestamps.len() < 2 {
+        return None;
+    }
+
+    let threshold_ms = threshold_secs.saturating_mul(MILLIS_PER_SEC);
+    let intervals = timestamps
+        .windows(2)
+        .map(|pair| pair[1].saturating_sub(pair[0]));
+    let (max_ms, count) = intervals.fold((0, 0), |(max, count), interval| {
+        (
+            max.max(interval),
+            count + usize::from(interval > threshold_ms),
+        )
+    });
+    Some((max_ms / MILLIS_PER_SEC, count))
+}
+
+/// Index ranges `(first, last)` where the vehicle stayed within `radius_meters`
+/// of the first point for at least `min_dwell_secs` (timestamps in
+/// milliseconds). Points without a timestamp never belong to a stop.
+pub fn detect_stops(
+    locations: &[Location],
+    radius_meters: f64,
+    min_dwell_secs: u64,
+) -> Vec<(usize, usize)> {
+    let min_dwell_ms = min_dwell_secs.saturating_mul(MILLIS_PER_SEC);
+    let mut stops = Vec::new();
+    let mut start = 0;
+
+    while start < locations.len() {
+        let Some(arrived) = locations[start].timestamp else {
+            start += 1;
+            continue;
+        };
+        let mut end = start;
+        while let Some(next) = locations.get(end + 1) {
+            if next.timestamp.is_none()
+                || haversine_distance(&locations[start], next) > radius_meters
+            {
+                break;
+            }
+            end += 1;
+        }
+
+        let left = locations[end].timestamp.unwrap_or(arrived);
+        if end > start && left.saturating_sub(arrived) >= min_dwell_ms {
+            stops.push((start, end));
+            start = end + 1;
+        } else {
+            start += 1;
+        }
+    }
+
+    stops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(altitudes: &[f64]) -> Vec<Location> {
+        altitudes
+            .iter()
+            .enumerate()
+            .map(|(i, &altitude)| Location {
+                altitude: Some(altitude),
+                ..Location::new(0.0, i as f64 * 0.001)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_elevation_gain_loss_up_down_profile() {
+        let route = profile(&[100.0, 110.0, 120.0, 115.0, 105.0, 125.0]);
+
+        let (gain, loss) = elevation_gain_loss(&route).unwrap();
+        assert!((gain - 40.0).abs() < 1e-9);
+        assert!((loss - 15.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_elevation_gain_loss_requires_altitudes() {
+        assert!(elevation_gain_loss(&[Location::new(0.0, 0.0), Location::new(1.0, 1.0)]).is_none());
+    }
+
+    #[test]
+    fn test_elevation_extrema_ignores_noise() {
+        let route = profile(&[100.0, 110.0, 120.0, 119.0, 121.0, 105.0, 104.0, 125.0]);
+
+        // Peak at index 4 (121m) and valley at index 6 (104m); the 1m dip is noise
+        assert_eq!(elevation_extrema(&route, 5.0), vec![4, 6]);
+    }
+
+    /// One point every 10 s, timestamped in milliseconds; `moving[i]` says
+    /// whether the bus travels ~111 m before point i + 1
+    fn timed_route(moving: &[bool]) -> Vec<Location> {
+        let mut longitude = 0.0;
+        let mut route = vec![Location {
+            timestamp: Some(0),
+            ..Location::new(0.0, longitude)
+        }];
+        for (i, &is_moving) in moving.iter().enumerate() {
+            if is_moving {
+                longitude += 0.001;
+            }
+            route.push(Location {
+                timestamp: Some((i as u64 + 1) * 10_000),
+                ..Location::new(0.0, longitude)
+            });
+        }
+        route
+    }
+
+    #[test]
+    fn test_moving_time_fraction_mostly_idle() {
+        let route = timed_route(&[true, false, false, false, false, false, false, false]);
+
+        let fraction = moving_time_fraction(&route, 1.0).unwrap();
+        assert!((fraction - 0.125).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_moving_time_fraction_mostly_moving() {
+        let route = timed_route(&[true, true, true, false]);
+
+        let fraction = moving_time_fraction(&route, 1.0).unwrap();
+        assert!((fraction - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_moving_time_fraction_at_walking_pace() {
+        // ~1.1 m per second-long leg, timestamped in milliseconds
+        let route: Vec<Location> = (0..5u64)
+            .map(|i| Location {
+                timestamp: Some(1_700_000_000_000 + i * 1000),
+                ..Location::new(0.0, i as f64 * 0.00001)
+            })
+            .collect();
+
+        assert_eq!(moving_time_fraction(&route, 1.0), Some(1.0));
+        assert_eq!(moving_time_fraction(&route, 2.0), Some(0.0));
+    }
+
+    #[test]
+    fn test_speed_profile_from_timestamps() {
+        let route = timed_route(&[true, false, true]);
+
+        let profile = speed_profile(&route).unwrap();
+        assert_eq!(profile.len(), 4);
+        // 0.001° of longitude at the equator in 10 seconds
+        assert!((profile[0] - 11.1195).abs() < 1e-3);
+        assert_eq!(profile[0], profile[1]);
+        assert_eq!(profile[2], 0.0);
+        assert_eq!(profile[3], profile[1]);
+
+        let mut untimed = route.clone();
+        untimed[2].timestamp = None;
+        assert!(speed_profile(&untimed).is_none());
+        assert!(speed_profile(&route[..1]).is_none());
+    }
+
+    #[test]
+    fn test_straightness_of_direct_and_winding_routes() {
+        let straight = route(&[(0.0, 0.0), (0.0, 0.01), (0.0, 0.02), (0.0, 0.03)]);
+        assert!((straightness_index(&straight).unwrap() - 1.0).abs() < 1e-9);
+
+        // Out along three sides of a square
+        let winding = route(&[(0.0, 0.0), (0.01, 0.0), (0.01, 0.01), (0.0, 0.01)]);
+        let index = straightness_index(&winding).unwrap();
+        assert!((index - 1.0 / 3.0).abs() < 1e-3);
+
+        let parked = route(&[(0.0, 0.0), (0.0, 0.0)]);
+        assert_eq!(straightness_index(&parked), None);
+        assert_eq!(straightness_index(&[]), None);
+    }
+
+    #[test]
+    fn test_timestamp_range_ignores_untimed_points() {
+        let route = vec![
+            Location::new(0.0, 0.0),
+            Location {
+                timestamp: Some(30),
+                ..Location::new(0.0, 0.0)
+            },
+            Location {
+                timestamp: Some(10),
+                ..Location::new(0.0, 0.0)
+            },
+        ];
+        assert_eq!(timestamp_range(&route), Some((10, 30)));
+        assert_eq!(timestamp_range(&route[..1]), None);
+    }
+
+    #[test]
+    fn test_occupancy_summary_skips_unknown_counts() {
+        let route: Vec<Location> = [Some(10), None, Some(25), Some(4)]
+            .into_iter()
+            .map(|occupancy| Location {
+                occupancy,
+                ..Location::new(0.0, 0.0)
+            })
+            .collect();
+
+        let (average, max) = occupancy_summary(&route).unwrap();
+        assert!((average - 13.0).abs() < 1e-9);
+        assert_eq!(max, 25);
+        assert!(occupancy_summary(&[Location::new(0.0, 0.0)]).is_none());
+    }
+
+    fn route(points: &[(f64, f64)]) -> Vec<Location> {
+        points
+            .iter()
+            .map(|&(latitude, longitude)| Location::new(latitude, longitude))
+            .collect()
+    }
+
+    #[test]
+    fn test_figure_eight_crosses_once() {
+        // A bow tie: two loops drawn as one stroke, crossing in the middle
+        let figure_eight = route(&[
+            (0.0, 0.0),
+            (0.002, 0.002),
+            (0.0, 0.002),
+            (0.002, 0.0),
+            (0.0, 0.0),
+        ]);
+
+        let crossings = self_intersections(&figure_eight);
+        assert_eq!(crossings.len(), 1);
+        assert!((crossings[0].latitude - 0.001).abs() < 1e-12);
+        assert!((crossings[0].longitude - 0.001).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_simple_or_closed_route_has_no_crossings() {
+        let simple = route(&[(0.0, 0.0), (0.001, 0.001), (0.001, 0.002), (0.0, 0.003)]);
+        assert!(self_intersections(&simple).is_empty());
+
+        let closed_loop = route(&[(0.0, 0.0), (0.001, 0.0), (0.001, 0.001), (0.0, 0.0)]);
+        assert!(self_intersections(&closed_loop).is_empty());
+        assert!(self_intersections(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_stationary_ends_counted() {
+        // Parked at the depot, a drive east, then parked again
+        let route = route(&[
+            (0.0, 0.0),
+            (0.00001, 0.0),
+            (0.0, 0.00001),
+            (0.0, 0.001),
+            (0.0, 0.002),
+            (0.0, 0.003),
+            (0.00001, 0.003),
+        ]);
+
+        assert_eq!(stationary_ends(&route, 10.0), (2, 1));
+        assert_eq!(stationary_ends(&route[2..6], 10.0), (0, 0));
+        assert_eq!(stationary_ends(&route[..3], 10.0), (0, 0));
+        assert_eq!(stationary_ends(&[], 10.0), (0, 0));
+    }
+
+    #[test]
+    fn test_bounding_box_catches_swapped_axes() {
+        let original = route(&[(6.2442, -75.5812), (6.2501, -75.5702), (6.2603, -75.5650)]);
+        let simplified = vec![original[0].clone(), original[2].clone()];
+        assert!(within_bounding_box(&original, &simplified, 0.0001));
+
+        let swapped: Vec<Location> = simplified
+            .iter()
+            .map(|loc| Location::new(loc.longitude, loc.latitude))
+            .collect();
+        assert!(!within_bounding_box(&original, &swapped, 0.0001));
+
+        // The margin absorbs floating-point drift around the box edges
+        let drifted = vec![Location::new(6.26035, -75.5812)];
+        assert!(within_bounding_box(&original, &drifted, 0.0001));
+        assert!(!within_bounding_box(&original, &drifted, 0.0));
+    }
+
+    #[test]
+    fn test_overlap_of_repeated_and_separate_routes() {
+        let first = route(&[(6.2442, -75.5812), (6.2501, -75.5702), (6.2603, -75.5650)]);
+        // The same trip reported again from its midpoint, about 10 m off
+        let repeated = route(&[(6.2502, -75.5703), (6.2553, -75.5677), (6.2604, -75.5651)]);
+        let elsewhere = route(&[(6.3000, -75.6000), (6.3100, -75.5900)]);
+
+        assert!(bounding_box_overlap(&first, &repeated, 20.0).unwrap() > 0.95);
+        assert_eq!(route_overlap(&repeated, &first, 50.0), Some(1.0));
+        // Only the second half of the first trip was repeated
+        assert_eq!(route_overlap(&first, &repeated, 50.0), Some(2.0 / 3.0));
+
+        assert_eq!(bounding_box_overlap(&first, &elsewhere, 20.0), Some(0.0));
+        assert_eq!(route_overlap(&elsewhere, &first, 50.0), Some(0.0));
+
+        // Straight east-west routes only overlap through the margin
+        let straight = route(&[(0.0, 0.0), (0.0, 0.02)]);
+        let shifted = route(&[(0.0, 0.01), (0.0, 0.03)]);
+        assert_eq!(bounding_box_overlap(&straight, &shifted, 0.0), None);
+        let overlap = bounding_box_overlap(&straight, &shifted, 10.0).unwrap();
+        assert!((overlap - 0.5).abs() < 0.01, "{overlap}");
+        assert_eq!(route_overlap(&[], &first, 50.0), None);
+    }
+
+    #[test]
+    fn test_fix_gaps_counted_over_threshold() {
+        // Regular 5 s fixes in milliseconds with 60 s and 120 s dropouts
+        let route: Vec<Location> = [0, 5, 10, 70, 75, 80, 200, 205]
+            .iter()
+            .map(|&secs: &u64| Location {
+                timestamp: Some(1_700_000_000_000 + secs * 1000),
+                ..Location::new(0.0, 0.0)
+            })
+            .collect();
+
+        assert_eq!(fix_gaps(&route, 30), Some((120, 2)));
+        assert_eq!(fix_gaps(&route, 120), Some((120, 0)));
+        assert_eq!(fix_gaps(&route, 5), Some((120, 2)));
+        assert_eq!(fix_gaps(&route[..1], 30), None);
+    }
+
+    #[test]
+    fn test_sample_interval_stats_irregular_sampling() {
+        // Intervals: 1, 1, 2, 4, 10 (a gap) seconds, timestamped in milliseconds
+        let route: Vec<Location> = [0, 1, 2, 4, 8, 18]
+            .iter()
+            .map(|&secs: &u64| Location {
+                timestamp: Some(1_700_000_000_000 + secs * 1000),
+                ..Location::new(0.0, 0.0)
+            })
+            .collect();
+
+        let stats = sample_interval_stats(&route).unwrap();
+        assert_eq!(stats.sample_count, 6);
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.p25, 1.0);
+        assert_eq!(stats.median, 2.0);
+        assert_eq!(stats.p75, 4.0);
+        assert_eq!(stats.max, 10.0);
+
+        assert!(sample_interval_stats(&route[..1]).is_none());
+    }
+
+    #[test]
+    fn test_moving_time_fraction_requires_timestamps() {
+        assert!(
+            moving_time_fraction(&[Location::new(0.0, 0.0), Location::new(1.0, 1.0)], 1.0)
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn test_detect_stops_needs_dwell_within_radius() {
+        // ~11 m per 0.0001 degree: drive, wait 60 s at a stop, drive, brief
+        // halt; timestamped in milliseconds
+        let route: Vec<Location> = [
+            (0.0, 0),
+            (0.001, 10),
+            (0.00101, 30),
+            (0.00102, 50),
+            (0.001, 70),
+            (0.002, 80),
+            (0.003, 90),
+            (0.00301, 100),
+            (0.004, 110),
+        ]
+        .iter()
+        .map(|&(longitude, secs): &(f64, u64)| Location {
+            timestamp: Some(1_700_000_000_000 + secs * 1000),
+            ..Location::new(0.0, longitude)
+        })
+        .collect();
+
+        assert_eq!(detect_stops(&route, 25.0, 30), vec![(1, 4)]);
+        assert_eq!(detect_stops(&route, 25.0, 10), vec![(1, 4), (6, 7)]);
+        assert!(detect_stops(&route, 25.0, 120).is_empty());
+    }
+}
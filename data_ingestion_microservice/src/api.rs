@@ -0,0 +1,334 @@
+use crate::config::Config;
+use crate::geojson::trips_feature_collection;
+use crate::metrics::RoutePointCounts;
+use crate::privacy::stored_driver_id;
+use crate::route_simplification::RouteSimplifier;
+use crate::storage::TripStore;
+use crate::types::{Location, ServiceResult};
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::Router;
+use log::error;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+
+#[derive(Clone)]
+struct ApiState {
+    config: Arc<Config>,
+    trip_store: Arc<dyn TripStore>,
+    route_points: Arc<RoutePointCounts>,
+}
+
+/// Optional `from`/`to` bounds on the trip `timestamp`, both inclusive
+#[derive(Debug, Default, Deserialize)]
+pub struct TripRange {
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+/// Body of `POST /debug/simplify`; the tolerance defaults to `ROUTE_TOLERANCE`
+#[derive(Debug, Deserialize)]
+pub struct SimplifyRequest {
+    pub route: Vec<Location>,
+    pub tolerance: Option<f64>,
+}
+
+/// Routes of the HTTP API:
+/// - `GET /trips/{driverId}.geojson?from=&to=`: the driver's trips, oldest
+///   first, as a GeoJSON `FeatureCollection`
+/// - `POST /debug/simplify`: simplification diagnostics for a posted route,
+///   only with `API_DEBUG_ROUTES`
+/// - `GET /stats/routes`: points buffered in Redis by each in-progress route,
+///   only with `ROUTE_POINTS_REFRESH_SECS`
+pub fn router(
+    config: Arc<Config>,
+    trip_store: Arc<dyn TripStore>,
+    route_points: Arc<RoutePointCounts>,
+) -> Router {
+    let mut router = Router::new().route("/trips/:file", get(driver_trips_geojson));
+    if config.api.debug_routes {
+        router = router.route("/debug/simplify", post(debug_simplify));
+    }
+    if config.metrics.route_points_refresh_secs > 0 {
+        router = router.route("/stats/routes", get(route_stats));
+    }
+    router.with_state(ApiState {
+        config,
+        trip_store,
+        route_points,
+    })
+}
+
+/// Renders the current metrics in Prometheus text exposition format
+pub type RenderMetrics = Arc<dyn Fn() -> String + Send + Sync>;
+
+/// Router of the metrics endpoint, served on its own port:
+/// - `GET /metrics`: the output of `render`, for Prometheus to scrape
+pub fn metrics_router(render: RenderMetrics) -> Router {
+    Router::new()
+        .route("/metrics", get(prometheus_metrics))
+        .with_state(render)
+}
+
+/// Serve `router` on `listener` until the task is dropped
+pub async fn serve(listener: TcpListener, router: Router) -> ServiceResult<()> {
+    axum::serve(listener, router).await?;
+    Ok(())
+}
+
+async fn driver_trips_geojson(
+    State(state): State<ApiState>,
+    Path(file): Path<String>,
+    Query(range): Query<TripRange>,
+) -> Response {
+    let Some(driver_id) = file.strip_suffix(".geojson").filter(|id| !id.is_empty()) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    // Trips are stored under the pseudonym when driver ids are pseudonymized
+    let stored_id = stored_driver_id(&state.config.privacy, driver_id);
+    let collection = match state
+        .trip_store
+        .find_driver_trips(&stored_id, range.from, range.to, state.config.api.max_trips)
+        .await
+        .and_then(|trips| trips_feature_collection(&trips))
+    {
+        Ok(collection) => collection,
+        Err(e) => {
+            error!("Failed to export trips of driver {}: {}", driver_id, e);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    (
+        [(header::CONTENT_TYPE, "application/geo+json")],
+        collection.to_string(),
+    )
+        .into_response()
+}
+
+async fn debug_simplify(State(state): State<ApiState>, body: Bytes) -> Response {
+    let request: SimplifyRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+    let tolerance = request
+        .tolerance
+        .unwrap_or(state.config.route_simplification.tolerance);
+    let diagnostics = match RouteSimplifier::new(tolerance)
+        .and_then(|simplifier| simplifier.diagnose(&request.route))
+    {
+        Ok(diagnostics) => diagnostics,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    match serde_json::to_string(&diagnostics) {
+        Ok(body) => ([(header::CONTENT_TYPE, "application/json")], body).into_response(),
+        Err(e) => {
+            error!("Failed to serialize simplification diagnostics: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn prometheus_metrics(State(render): State<RenderMetrics>) -> Response {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(),
+    )
+        .into_response()
+}
+
+async fn route_stats(State(state): State<ApiState>) -> Response {
+    let body = serde_json::json!({ "routes": state.route_points.snapshot() });
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        body.to_string(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fields;
+    use crate::privacy::hash_driver_id;
+    use crate::service::tests::{message, Harness};
+    use crate::storage::InMemoryTripStore;
+    use crate::types::BusStatus;
+    use mongodb::bson::doc;
+    use serde_json::Value;
+
+    async fn start(config: Config, trip_store: Arc<InMemoryTripStore>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let route_points = Arc::new(RoutePointCounts::new());
+        tokio::spawn(serve(
+            listener,
+            router(Arc::new(config), trip_store, route_points),
+        ));
+        format!("http://{}", addr)
+    }
+
+    async fn seed(store: &InMemoryTripStore, driver_id: &str, timestamps: &[i64]) {
+        for &timestamp in timestamps {
+            store
+                .insert_trip(doc! {
+                    fields::DRIVER_ID: driver_id,
+                    fields::SIMPLIFIED_ROUTE: [{ fields::LATITUDE: 1.0, fields::LONGITUDE: 2.0 }],
+                    fields::TIMESTAMP: timestamp,
+                })
+                .await
+                .unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_driver_trips_exported_as_feature_collection() {
+        let store = Arc::new(InMemoryTripStore::new());
+        seed(&store, "driver_1", &[300, 100, 200]).await;
+        seed(&store, "driver_2", &[150]).await;
+        let base = start(Config::default(), store).await;
+
+        let response = reqwest::get(format!("{}/trips/driver_1.geojson", base))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE.as_str()],
+            "application/geo+json"
+        );
+        let collection: Value = response.json().await.unwrap();
+        assert_eq!(collection["type"], "FeatureCollection");
+        let timestamps: Vec<i64> = collection["features"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|feature| feature["properties"][fields::TIMESTAMP].as_i64().unwrap())
+            .collect();
+        assert_eq!(timestamps, vec![100, 200, 300]);
+
+        let collection: Value =
+            reqwest::get(format!("{}/trips/driver_1.geojson?from=150&to=250", base))
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+        assert_eq!(collection["features"].as_array().unwrap().len(), 1);
+
+        let response = reqwest::get(format!("{}/trips/driver_1.json", base))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_endpoint_counts_finished_routes() {
+        let harness = Harness::new(Config::default());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/metrics", listener.local_addr().unwrap());
+        let service = harness.service.clone();
+        tokio::spawn(serve(
+            listener,
+            metrics_router(Arc::new(move || service.render_prometheus())),
+        ));
+        let scrape = || async {
+            let response = reqwest::get(&url).await.unwrap();
+            assert_eq!(response.status(), 200);
+            assert!(response.headers()[header::CONTENT_TYPE.as_str()]
+                .to_str()
+                .unwrap()
+                .starts_with("text/plain"));
+            response.text().await.unwrap()
+        };
+
+        assert!(scrape().await.contains("\nroutes_completed 0\n"));
+        for i in 0..3 {
+            let msg = message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i);
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 3))
+            .await
+            .unwrap();
+
+        let text = scrape().await;
+        assert!(text.contains("\nroutes_completed 1\n"));
+        assert!(text.contains("\nmessages_processed 4\n"));
+    }
+
+    #[tokio::test]
+    async fn test_debug_simplify_returns_diagnostics() {
+        let mut config = Config::default();
+        config.api.debug_routes = true;
+        let base = start(config, Arc::new(InMemoryTripStore::new())).await;
+        let client = reqwest::Client::new();
+
+        let body = r#"{"tolerance": 0.01, "route": [
+            {"latitude": 0.0, "longitude": 0.0},
+            {"latitude": 0.001, "longitude": 0.001},
+            {"latitude": 0.0, "longitude": 0.002}
+        ]}"#;
+        let response = client
+            .post(format!("{}/debug/simplify", base))
+            .body(body)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let diagnostics: Value = response.json().await.unwrap();
+        assert_eq!(diagnostics["tolerance"], 0.01);
+        assert_eq!(diagnostics["originalPoints"], 3);
+        assert_eq!(diagnostics["retainedIndices"], serde_json::json!([0, 2]));
+        let dropped = &diagnostics["droppedPoints"][0];
+        assert_eq!(dropped["index"], 1);
+        assert!(dropped["deviationMeters"].as_f64().unwrap() > 100.0);
+        assert!(diagnostics["compressionRatio"].as_f64().is_some());
+        assert!(diagnostics["elapsedMicros"].as_u64().is_some());
+
+        let response = client
+            .post(format!("{}/debug/simplify", base))
+            .body(r#"{"route": [], "tolerance": -1.0}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_debug_routes_disabled_by_default() {
+        let base = start(Config::default(), Arc::new(InMemoryTripStore::new())).await;
+
+        let response = reqwest::Client::new()
+            .post(format!("{}/debug/simplify", base))
+            .body(r#"{"route": []}"#)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_export_limited_and_pseudonymized() {
+        let mut config = Config::default();
+        config.api.max_trips = 2;
+        config.privacy.pseudonymize_drivers = true;
+        config.privacy.driver_id_salt = "salt".to_string();
+        let store = Arc::new(InMemoryTripStore::new());
+        seed(&store, &hash_driver_id("driver_1", "salt"), &[1, 2, 3]).await;
+        let base = start(config, store).await;
+
+        let collection: Value = reqwest::get(format!("{}/trips/driver_1.geojson", base))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(collection["features"].as_array().unwrap().len(), 2);
+    }
+}
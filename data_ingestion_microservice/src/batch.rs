@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// Process-local buffer of encoded `in_route` points not yet appended to their
+/// route list, so several points go to Redis in one RPUSH
+#[derive(Default)]
+pub struct AppendBuffer {
+    routes: Mutex<HashMap<String, Vec<Vec<u8>>>>,
+}
+
+impl AppendBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Buffer a point for the route list at `key`. Once `batch_size` points
+    /// are waiting they are taken and returned, oldest first, to be appended.
+    pub fn push(&self, key: &str, point: Vec<u8>, batch_size: usize) -> Option<Vec<Vec<u8>>> {
+        let mut routes = self.routes.lock().unwrap();
+        let points = routes.entry(key.to_string()).or_default();
+        points.push(point);
+        if points.len() < batch_size {
+            return None;
+        }
+        routes.remove(key)
+    }
+
+    /// Take the buffered points of one route, oldest first
+    pub fn take(&self, key: &str) -> Vec<Vec<u8>> {
+        self.routes.lock().unwrap().remove(key).unwrap_or_default()
+    }
+
+    /// Number of points buffered for one route
+    pub fn pending(&self, key: &str) -> usize {
+        self.routes.lock().unwrap().get(key).map_or(0, Vec::len)
+    }
+
+    /// Take the buffered points of every route
+    pub fn take_all(&self) -> Vec<(String, Vec<Vec<u8>>)> {
+        self.routes.lock().unwrap().drain().collect()
+    }
+
+    /// Put back points whose append failed, ahead of any buffered since
+    pub fn restore(&self, key: &str, mut points: Vec<Vec<u8>>) {
+        let mut routes = self.routes.lock().unwrap();
+        let pending = routes.entry(key.to_string()).or_default();
+        points.append(pending);
+        *pending = points;
+    }
+
+    /// Number of buffered points across every route
+    pub fn len(&self) -> usize {
+        self.routes.lock().unwrap().values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_returned_once_full() {
+        let buffer = AppendBuffer::new();
+        assert!(buffer.push("d:r1", vec![1], 3).is_none());
+        assert!(buffer.push("d:r2", vec![9], 3).is_none());
+        assert!(buffer.push("d:r1", vec![2], 3).is_none());
+        assert_eq!(buffer.pending("d:r1"), 2);
+
+        assert_eq!(
+            buffer.push("d:r1", vec![3], 3),
+            Some(vec![vec![1], vec![2], vec![3]])
+        );
+        assert!(buffer.take("d:r1").is_empty());
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.take_all(), vec![("d:r2".to_string(), vec![vec![9]])]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_restored_points_keep_their_order() {
+        let buffer = AppendBuffer::new();
+        buffer.push("d:r1", vec![3], 10);
+        buffer.restore("d:r1", vec![vec![1], vec![2]]);
+
+        assert_eq!(buffer.take("d:r1"), vec![vec![1], vec![2], vec![3]]);
+    }
+}
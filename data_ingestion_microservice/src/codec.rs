@@ -0,0 +1,181 @@
+use crate::config::PointEncoding;
+use crate::types::{Location, ServiceError, ServiceResult};
+
+/// First byte of a binary point; JSON points always start with `{`
+const BINARY_VERSION: u8 = 1;
+
+const HAS_TIMESTAMP: u8 = 1 << 0;
+const HAS_ALTITUDE: u8 = 1 << 1;
+const HAS_IMPORTANCE: u8 = 1 << 2;
+const HAS_OCCUPANCY: u8 = 1 << 3;
+const HAS_ACCURACY: u8 = 1 << 4;
+const HAS_SPEED: u8 = 1 << 5;
+const HAS_HEADING: u8 = 1 << 6;
+/// Flag without a payload
+const DEAD_RECKONED: u8 = 1 << 7;
+
+/// Serialize a point for storage in a Redis list.
+///
+/// The binary layout is a version byte, a flags byte, latitude and longitude as
+/// little-endian `f64`, then each optional field present in `flags` in the order
+/// timestamp (`u64`), altitude (`f64`), importance (`f64`), occupancy (`u32`),
+/// accuracy (`f64`), speed (`f64`), heading (`f64`). The `DEAD_RECKONED` flag
+/// carries no payload.
+pub fn encode_point(location: &Location, encoding: PointEncoding) -> ServiceResult<Vec<u8>> {
+    match encoding {
+        PointEncoding::Json => Ok(serde_json::to_vec(location)?),
+        PointEncoding::Binary => {
+            let mut flags = 0;
+            let mut buf = Vec::with_capacity(70);
+            buf.push(BINARY_VERSION);
+            buf.push(0);
+            buf.extend_from_slice(&location.latitude.to_le_bytes());
+            buf.extend_from_slice(&location.longitude.to_le_bytes());
+            if let Some(timestamp) = location.timestamp {
+                flags |= HAS_TIMESTAMP;
+                buf.extend_from_slice(&timestamp.to_le_bytes());
+            }
+            if let Some(altitude) = location.altitude {
+                flags |= HAS_ALTITUDE;
+                buf.extend_from_slice(&altitude.to_le_bytes());
+            }
+            if let Some(importance) = location.importance {
+                flags |= HAS_IMPORTANCE;
+                buf.extend_from_slice(&importance.to_le_bytes());
+            }
+            if let Some(occupancy) = location.occupancy {
+                flags |= HAS_OCCUPANCY;
+                buf.extend_from_slice(&occupancy.to_le_bytes());
+            }
+            if let Some(accuracy) = location.accuracy {
+                flags |= HAS_ACCURACY;
+                buf.extend_from_slice(&accuracy.to_le_bytes());
+            }
+            if let Some(speed) = location.speed {
+                flags |= HAS_SPEED;
+                buf.extend_from_slice(&speed.to_le_bytes());
+            }
+            if let Some(heading) = location.heading {
+                flags |= HAS_HEADING;
+                buf.extend_from_slice(&heading.to_le_bytes());
+            }
+            if location.dead_reckoned {
+                flags |= DEAD_RECKONED;
+            }
+            buf[1] = flags;
+            Ok(buf)
+        }
+    }
+}
+
+/// Decode a stored point written with either encoding, so a route started
+/// before a change of `REDIS_POINT_ENCODING` can still be finalized.
+pub fn decode_point(bytes: &[u8]) -> ServiceResult<Location> {
+    match bytes.first() {
+        Some(&BINARY_VERSION) => decode_binary(bytes),
+        _ => Ok(serde_json::from_slice(bytes)?),
+    }
+}
+
+fn decode_binary(bytes: &[u8]) -> ServiceResult<Location> {
+    let mut reader = Reader { bytes, pos: 2 };
+    let flags = *bytes.get(1).ok_or_else(|| truncated_point(bytes.len()))?;
+
+    let mut location = Location::new(reader.f64()?, reader.f64()?);
+    if flags & HAS_TIMESTAMP != 0 {
+        location.timestamp = Some(u64::from_le_bytes(reader.take()?));
+    }
+    if flags & HAS_ALTITUDE != 0 {
+        location.altitude = Some(reader.f64()?);
+    }
+    if flags & HAS_IMPORTANCE != 0 {
+        location.importance = Some(reader.f64()?);
+    }
+    if flags & HAS_OCCUPANCY != 0 {
+        location.occupancy = Some(u32::from_le_bytes(reader.take()?));
+    }
+    if flags & HAS_ACCURACY != 0 {
+        location.accuracy = Some(reader.f64()?);
+    }
+    if flags & HAS_SPEED != 0 {
+        location.speed = Some(reader.f64()?);
+    }
+    if flags & HAS_HEADING != 0 {
+        location.heading = Some(reader.f64()?);
+    }
+    location.dead_reckoned = flags & DEAD_RECKONED != 0;
+    Ok(location)
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> ServiceResult<[u8; N]> {
+        let chunk = self
+            .bytes
+            .get(self.pos..self.pos + N)
+            .ok_or_else(|| truncated_point(self.bytes.len()))?;
+        self.pos += N;
+        Ok(chunk.try_into().expect("chunk is N bytes"))
+    }
+
+    fn f64(&mut self) -> ServiceResult<f64> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+}
+
+fn truncated_point(len: usize) -> ServiceError {
+    ServiceError::Validation(format!("Truncated binary point ({} bytes)", len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full_location() -> Location {
+        Location {
+            timestamp: Some(1_700_000_000_123),
+            altitude: Some(2640.5),
+            importance: Some(3.0),
+            occupancy: Some(42),
+            accuracy: Some(4.5),
+            speed: Some(11.2),
+            heading: Some(274.0),
+            dead_reckoned: true,
+            ..Location::new(6.2442, -75.5812)
+        }
+    }
+
+    #[test]
+    fn test_round_trip_both_encodings() {
+        for location in [Location::new(1.0, 2.0), full_location()] {
+            for encoding in [PointEncoding::Json, PointEncoding::Binary] {
+                let bytes = encode_point(&location, encoding).unwrap();
+                assert_eq!(decode_point(&bytes).unwrap(), location);
+            }
+        }
+    }
+
+    #[test]
+    fn test_binary_layout() {
+        let bytes = encode_point(&Location::new(1.0, 2.0), PointEncoding::Binary).unwrap();
+        assert_eq!(bytes.len(), 18);
+        assert_eq!(bytes[..2], [BINARY_VERSION, 0]);
+        assert_eq!(bytes[2..10], 1.0f64.to_le_bytes());
+
+        let bytes = encode_point(&full_location(), PointEncoding::Binary).unwrap();
+        assert_eq!(bytes.len(), 70);
+        assert_eq!(bytes[1], 0xff);
+    }
+
+    #[test]
+    fn test_truncated_binary_point_rejected() {
+        let bytes = encode_point(&full_location(), PointEncoding::Binary).unwrap();
+        assert!(decode_point(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode_point(&[BINARY_VERSION]).is_err());
+        assert!(decode_point(b"").is_err());
+    }
+}
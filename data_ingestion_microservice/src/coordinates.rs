@@ -0,0 +1,134 @@
+use crate::config::CoordinateOrder;
+use crate::geodesy::is_pole;
+use crate::types::{BusMessage, Location, ServiceError, ServiceResult};
+
+/// Location of a message, built from its `coordinates` pair in `order` when
+/// present, otherwise taken from `driverLocation`. Rejects positions outside
+/// the valid latitude/longitude ranges, which usually means the publisher
+/// uses the other coordinate order.
+pub fn resolve_location(msg: &BusMessage, order: CoordinateOrder) -> ServiceResult<Location> {
+    let location = match msg.coordinates {
+        Some([first, second]) => {
+            let (latitude, longitude) = match order {
+                CoordinateOrder::LatLon => (first, second),
+                CoordinateOrder::LonLat => (second, first),
+            };
+            Location {
+                latitude,
+                longitude,
+                ..msg.driver_location.clone()
+            }
+        }
+        None if msg.has_driver_location() => msg.driver_location.clone(),
+        None => {
+            return Err(ServiceError::Validation(
+                "Message has neither driverLocation nor coordinates".to_string(),
+            ))
+        }
+    };
+
+    location.validate()?;
+    Ok(location)
+}
+
+/// Canonical form of an edge coordinate: longitude 0 at the poles, where any
+/// longitude names the same point, and 180 rather than -180 on the antimeridian
+pub fn normalize_edges(location: &Location) -> Location {
+    let longitude = if is_pole(location) {
+        0.0
+    } else if location.longitude == -180.0 {
+        180.0
+    } else {
+        location.longitude
+    };
+    Location {
+        longitude,
+        ..location.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(payload: &str) -> BusMessage {
+        serde_json::from_str(payload).unwrap()
+    }
+
+    #[test]
+    fn test_coordinates_in_both_orders() {
+        // Medellín, sent in GeoJSON order and in latitude-first order
+        let lon_lat = parse(
+            r#"{"driverId":"d","currentRouteId":"r","timestamp":1,"status":"in_route",
+                "coordinates":[-75.5812,6.2442]}"#,
+        );
+        let lat_lon = parse(
+            r#"{"driverId":"d","currentRouteId":"r","timestamp":1,"status":"in_route",
+                "coordinates":[6.2442,-75.5812]}"#,
+        );
+
+        let expected = Location::new(6.2442, -75.5812);
+        assert_eq!(
+            resolve_location(&lon_lat, CoordinateOrder::LonLat).unwrap(),
+            expected
+        );
+        assert_eq!(
+            resolve_location(&lat_lon, CoordinateOrder::LatLon).unwrap(),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_driver_location_still_accepted() {
+        let msg = parse(
+            r#"{"driverId":"d","currentRouteId":"r","timestamp":1,"status":"in_route",
+                "driverLocation":{"latitude":6.2442,"longitude":-75.5812,"altitude":1495.0}}"#,
+        );
+
+        let location = resolve_location(&msg, CoordinateOrder::LonLat).unwrap();
+        assert_eq!(location.latitude, 6.2442);
+        assert_eq!(location.altitude, Some(1495.0));
+    }
+
+    #[test]
+    fn test_swapped_or_missing_coordinates_rejected() {
+        let swapped = parse(
+            r#"{"driverId":"d","currentRouteId":"r","timestamp":1,"status":"in_route",
+                "coordinates":[-120.5,6.2]}"#,
+        );
+        let error = resolve_location(&swapped, CoordinateOrder::LatLon).unwrap_err();
+        assert!(error.to_string().contains("swapped"));
+        assert!(resolve_location(&swapped, CoordinateOrder::LonLat).is_ok());
+
+        let far_east = BusMessage {
+            driver_location: Location::new(10.0, 190.0),
+            ..Default::default()
+        };
+        assert!(resolve_location(&far_east, CoordinateOrder::LatLon).is_err());
+
+        let missing =
+            parse(r#"{"driverId":"d","currentRouteId":"r","timestamp":1,"status":"in_route"}"#);
+        assert!(resolve_location(&missing, CoordinateOrder::LatLon).is_err());
+    }
+
+    #[test]
+    fn test_edge_coordinates_normalized() {
+        assert_eq!(
+            normalize_edges(&Location::new(90.0, -42.0)),
+            Location::new(90.0, 0.0)
+        );
+        assert_eq!(
+            normalize_edges(&Location::new(-90.0, 180.0)),
+            Location::new(-90.0, 0.0)
+        );
+        assert_eq!(
+            normalize_edges(&Location::new(10.0, -180.0)),
+            Location::new(10.0, 180.0)
+        );
+        let regular = Location::new(6.2442, -75.5812);
+        assert_eq!(normalize_edges(&regular), regular);
+
+        let nan = Location::new(f64::NAN, 0.0);
+        assert!(nan.validate().is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use crate::config::CoordinateStorage;
+use crate::fields;
+use crate::route_simplification::{calculate_route_stats, DistanceUnit};
+use crate::types::{Location, ServiceError, ServiceResult};
+use mongodb::bson::{doc, Bson, Document};
+
+/// Micro-degrees per degree, used by `CoordinateStorage::MicrodegInt`
+const MICRODEGREES: f64 = 1_000_000.0;
+
+/// Render a location for a stored trip document.
+///
+/// Readback convention per format:
+/// - `double`: degrees as BSON doubles
+/// - `microdeg_int`: `round(degrees * 1e6)` as BSON int64
+/// - `string`: degrees formatted with 6 decimals
+///
+/// The point's occupancy, when known, is stored next to the coordinates, and
+/// points filled in by dead reckoning are flagged `deadReckoned`.
+pub fn location_to_document(location: &Location, storage: CoordinateStorage) -> Document {
+    let mut document = match storage {
+        CoordinateStorage::Double => doc! {
+            fields::LATITUDE: location.latitude,
+            fields::LONGITUDE: location.longitude,
+        },
+        CoordinateStorage::MicrodegInt => doc! {
+            fields::LATITUDE: (location.latitude * MICRODEGREES).round() as i64,
+            fields::LONGITUDE: (location.longitude * MICRODEGREES).round() as i64,
+        },
+        CoordinateStorage::String => doc! {
+            fields::LATITUDE: format!("{:.6}", location.latitude),
+            fields::LONGITUDE: format!("{:.6}", location.longitude),
+        },
+    };
+    if let Some(occupancy) = location.occupancy {
+        document.insert(fields::OCCUPANCY, i64::from(occupancy));
+    }
+    if location.dead_reckoned {
+        document.insert(fields::DEAD_RECKONED, true);
+    }
+    document
+}
+
+/// Great-circle lengths of a route before and after simplification, showing
+/// how much of the route's extent simplification cut off
+pub fn route_lengths_document(original: &[Location], simplified: &[Location]) -> Document {
+    let stats = calculate_route_stats(original, simplified, DistanceUnit::Meters);
+    doc! {
+        fields::ORIGINAL_LENGTH_METERS: stats.original_length,
+        fields::SIMPLIFIED_LENGTH_METERS: stats.simplified_length,
+        fields::LENGTH_DIFFERENCE_METERS: stats.length_difference,
+    }
+}
+
+/// Read a location back from a stored trip document, accepting any storage format
+pub fn location_from_document(document: &Document) -> ServiceResult<Location> {
+    let occupancy = match document.get(fields::OCCUPANCY) {
+        Some(Bson::Int64(count)) => u32::try_from(*count).ok(),
+        Some(Bson::Int32(count)) => u32::try_from(*count).ok(),
+        _ => None,
+    };
+    Ok(Location {
+        occupancy,
+        dead_reckoned: document.get_bool(fields::DEAD_RECKONED).unwrap_or(false),
+        ..Location::new(
+            coordinate_from_bson(document.get(fields::LATITUDE))?,
+            coordinate_from_bson(document.get(fields::LONGITUDE))?,
+        )
+    })
+}
+
+fn coordinate_from_bson(value: Option<&Bson>) -> ServiceResult<f64> {
+    match value {
+        Some(Bson::Double(degrees)) => Ok(*degrees),
+        Some(Bson::Int64(microdegrees)) => Ok(*microdegrees as f64 / MICRODEGREES),
+        Some(Bson::Int32(microdegrees)) => Ok(*microdegrees as f64 / MICRODEGREES),
+        Some(Bson::String(degrees)) => degrees
+            .parse()
+            .map_err(|_| ServiceError::Validation(format!("Invalid coordinate '{}'", degrees))),
+        other => Err(ServiceError::Validation(format!(
+            "Invalid coordinate {:?}",
+            other
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point() -> Location {
+        Location::new(6.2442034, -75.5812119)
+    }
+
+    #[test]
+    fn test_double_storage() {
+        let document = location_to_document(&point(), CoordinateStorage::Double);
+        assert_eq!(document.get_f64("latitude").unwrap(), 6.2442034);
+        assert_eq!(document.get_f64("longitude").unwrap(), -75.5812119);
+    }
+
+    #[test]
+    fn test_microdegree_storage() {
+        let document = location_to_document(&point(), CoordinateStorage::MicrodegInt);
+        assert_eq!(document.get_i64("latitude").unwrap(), 6_244_203);
+        assert_eq!(document.get_i64("longitude").unwrap(), -75_581_212);
+    }
+
+    #[test]
+    fn test_string_storage() {
+        let document = location_to_document(&point(), CoordinateStorage::String);
+        assert_eq!(document.get_str("latitude").unwrap(), "6.244203");
+        assert_eq!(document.get_str("longitude").unwrap(), "-75.581212");
+    }
+
+    #[test]
+    fn test_readback_from_every_format() {
+        for storage in [
+            CoordinateStorage::Double,
+            CoordinateStorage::MicrodegInt,
+            CoordinateStorage::String,
+        ] {
+            let document = location_to_document(&point(), storage);
+            let location = location_from_document(&document).unwrap();
+            assert!((location.latitude - point().latitude).abs() < 1e-6);
+            assert!((location.longitude - point().longitude).abs() < 1e-6);
+        }
+
+        assert!(location_from_document(&doc! { "latitude": true }).is_err());
+    }
+}
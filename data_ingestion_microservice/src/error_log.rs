@@ -0,0 +1,143 @@
+use log::{error, warn};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Rate-limited error log.
+///
+/// Within each `window` the first `burst` errors are logged, then only one in
+/// every `sample_every`. The rest are counted and reported in a single summary
+/// line once the window is over, so a failing dependency cannot flood the logs.
+pub struct ErrorLog {
+    burst: u64,
+    sample_every: u64,
+    window: Duration,
+    inner: Mutex<ErrorLogInner>,
+}
+
+struct ErrorLogInner {
+    window_start: Instant,
+    seen: u64,
+    suppressed: u64,
+}
+
+impl ErrorLog {
+    /// A `burst` of 0 disables sampling: every error is logged
+    pub fn new(burst: u64, sample_every: u64, window: Duration) -> Self {
+        Self {
+            burst,
+            sample_every,
+            window,
+            inner: Mutex::new(ErrorLogInner {
+                window_start: Instant::now(),
+                seen: 0,
+                suppressed: 0,
+            }),
+        }
+    }
+
+    /// Log `message` at error level unless it is sampled out; returns whether
+    /// it was logged
+    pub fn error(&self, message: fmt::Arguments) -> bool {
+        if self.burst == 0 {
+            error!("{}", message);
+            return true;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        self.roll_window(&mut inner);
+        inner.seen += 1;
+        let past_burst = inner.seen - self.burst.min(inner.seen);
+        let logged = past_burst == 0
+            || (self.sample_every > 0 && past_burst.is_multiple_of(self.sample_every));
+        if logged {
+            error!("{}", message);
+        } else {
+            inner.suppressed += 1;
+        }
+        logged
+    }
+
+    /// Errors suppressed so far in the current window
+    pub fn suppressed(&self) -> u64 {
+        self.inner.lock().unwrap().suppressed
+    }
+
+    /// Close the current window if it is over, logging how many errors it
+    /// suppressed. Returns that count when a summary was written.
+    pub fn summarize(&self) -> Option<u64> {
+        self.roll_window(&mut self.inner.lock().unwrap())
+    }
+
+    /// Write the summary of every window as it ends, even when no further
+    /// error arrives to trigger it
+    pub async fn run_summaries(self: Arc<Self>) {
+        if self.burst == 0 {
+            return;
+        }
+        let mut ticker = tokio::time::interval(self.window);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            self.summarize();
+        }
+    }
+
+    fn roll_window(&self, inner: &mut ErrorLogInner) -> Option<u64> {
+        let now = Instant::now();
+        if now.duration_since(inner.window_start) < self.window {
+            return None;
+        }
+
+        let suppressed = inner.suppressed;
+        if suppressed > 0 {
+            warn!(
+                "Suppressed {} of {} errors in the last {}s",
+                suppressed,
+                inner.seen,
+                now.duration_since(inner.window_start).as_secs()
+            );
+        }
+        inner.window_start = now;
+        inner.seen = 0;
+        inner.suppressed = 0;
+        (suppressed > 0).then_some(suppressed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_burst_then_sampled_then_summarized() {
+        let log = ErrorLog::new(3, 5, Duration::from_secs(60));
+
+        let logged: Vec<bool> = (0..14)
+            .map(|i| log.error(format_args!("Redis down ({})", i)))
+            .collect();
+        // First three in full, then errors 8 and 13 as the 1-in-5 samples
+        let expected: Vec<bool> = (1..=14).map(|n| n <= 3 || (n - 3) % 5 == 0).collect();
+        assert_eq!(logged, expected);
+        assert_eq!(log.suppressed(), 9);
+
+        // Nothing is summarized before the window is over
+        assert_eq!(log.summarize(), None);
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(log.summarize(), Some(9));
+        assert_eq!(log.suppressed(), 0);
+
+        // A new window starts with a fresh burst
+        assert!(log.error(format_args!("Redis down again")));
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(log.summarize(), None);
+    }
+
+    #[test]
+    fn test_zero_burst_logs_everything() {
+        let log = ErrorLog::new(0, 0, Duration::from_secs(60));
+        assert!((0..100).all(|i| log.error(format_args!("error {}", i))));
+        assert_eq!(log.suppressed(), 0);
+    }
+}
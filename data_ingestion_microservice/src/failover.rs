@@ -0,0 +1,106 @@
+use crate::config::BrokerEndpoint;
+use log::warn;
+
+/// Tracks which MQTT broker to connect to, moving to the next endpoint after
+/// too many consecutive connection errors and wrapping back to the primary.
+#[derive(Debug, Clone)]
+pub struct BrokerFailover {
+    endpoints: Vec<BrokerEndpoint>,
+    current: usize,
+    consecutive_failures: u32,
+    max_failures: u32,
+}
+
+impl BrokerFailover {
+    /// `endpoints` must not be empty; the first one is the primary
+    pub fn new(endpoints: Vec<BrokerEndpoint>, max_failures: u32) -> Self {
+        assert!(
+            !endpoints.is_empty(),
+            "at least one broker endpoint is required"
+        );
+
+        Self {
+            endpoints,
+            current: 0,
+            consecutive_failures: 0,
+            max_failures: max_failures.max(1),
+        }
+    }
+
+    /// Endpoint the next connection attempt should use
+    pub fn current(&self) -> &BrokerEndpoint {
+        &self.endpoints[self.current]
+    }
+
+    /// Reset the failure count after a successful connection
+    pub fn record_success(&mut self) {
+        self.consecutive_failures = 0;
+    }
+
+    /// Count a connection error; returns `true` if the endpoint changed
+    pub fn record_failure(&mut self) -> bool {
+        self.consecutive_failures += 1;
+        if self.consecutive_failures < self.max_failures || self.endpoints.len() == 1 {
+            return false;
+        }
+
+        let failed = self.current;
+        self.current = (self.current + 1) % self.endpoints.len();
+        self.consecutive_failures = 0;
+        warn!(
+            "MQTT broker {} failed {} times in a row; switching to {}",
+            self.endpoints[failed],
+            self.max_failures,
+            self.current()
+        );
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn endpoints() -> Vec<BrokerEndpoint> {
+        vec![
+            "primary.local:1883".parse().unwrap(),
+            "fallback.local:1883".parse().unwrap(),
+        ]
+    }
+
+    #[test]
+    fn test_switches_to_fallback_after_repeated_failures() {
+        let mut failover = BrokerFailover::new(endpoints(), 3);
+
+        assert!(!failover.record_failure());
+        assert!(!failover.record_failure());
+        assert_eq!(failover.current().host, "primary.local");
+
+        assert!(failover.record_failure());
+        assert_eq!(failover.current().host, "fallback.local");
+
+        // The fallback failing as well wraps around to the primary
+        for _ in 0..3 {
+            failover.record_failure();
+        }
+        assert_eq!(failover.current().host, "primary.local");
+    }
+
+    #[test]
+    fn test_success_resets_failure_count() {
+        let mut failover = BrokerFailover::new(endpoints(), 2);
+
+        failover.record_failure();
+        failover.record_success();
+        assert!(!failover.record_failure());
+        assert_eq!(failover.current().host, "primary.local");
+    }
+
+    #[test]
+    fn test_single_endpoint_never_switches() {
+        let mut failover = BrokerFailover::new(endpoints()[..1].to_vec(), 1);
+
+        assert!(!failover.record_failure());
+        assert_eq!(failover.current().host, "primary.local");
+    }
+}
@@ -0,0 +1,119 @@
+//! Field names of trip documents stored in MongoDB and of outgoing payloads
+
+pub const DRIVER_ID: &str = "driverId";
+pub const CURRENT_ROUTE_ID: &str = "currentRouteId";
+pub const GLOBAL_ROUTE_ID: &str = "globalRouteId";
+pub const SIMPLIFIED_ROUTE: &str = "simplifiedRoute";
+pub const TIMESTAMP: &str = "timestamp";
+pub const START_TIMESTAMP: &str = "startTimestamp";
+pub const END_TIMESTAMP: &str = "endTimestamp";
+pub const ORIGINAL_POINTS_COUNT: &str = "originalPointsCount";
+pub const SIMPLIFIED_POINTS_COUNT: &str = "simplifiedPointsCount";
+pub const COMPRESSION_RATIO: &str = "compressionRatio";
+pub const ORIGINAL_LENGTH_METERS: &str = "originalLengthMeters";
+pub const SIMPLIFIED_LENGTH_METERS: &str = "simplifiedLengthMeters";
+pub const LENGTH_DIFFERENCE_METERS: &str = "lengthDifferenceMeters";
+pub const GEOFENCE_EVENTS: &str = "geofenceEvents";
+pub const ELEVATION_GAIN_METERS: &str = "elevationGainMeters";
+pub const ELEVATION_LOSS_METERS: &str = "elevationLossMeters";
+pub const MOVING_TIME_FRACTION: &str = "movingTimeFraction";
+pub const LOW_ACCURACY_POINTS: &str = "lowAccuracyPoints";
+pub const TRIMMED_LEADING: &str = "trimmedLeading";
+pub const TRIMMED_TRAILING: &str = "trimmedTrailing";
+pub const MAX_GAP_SECONDS: &str = "maxGapSeconds";
+pub const GAP_COUNT: &str = "gapCount";
+pub const AVG_OCCUPANCY: &str = "avgOccupancy";
+pub const MAX_OCCUPANCY: &str = "maxOccupancy";
+pub const SELF_INTERSECTION_COUNT: &str = "selfIntersectionCount";
+pub const SPEED_PROFILE: &str = "speedProfile";
+pub const STRAIGHTNESS_INDEX: &str = "straightnessIndex";
+pub const DUPLICATE_OF: &str = "duplicateOf";
+pub const MERGED_ROUTE_IDS: &str = "mergedRouteIds";
+pub const PROJECTED_ROUTE: &str = "projectedRoute";
+pub const PROJECTION_EPSG: &str = "projectionEpsg";
+pub const SELF_INTERSECTIONS: &str = "selfIntersections";
+pub const RAW_ROUTE: &str = "rawRoute";
+pub const RAW_START: &str = "rawStart";
+pub const RAW_END: &str = "rawEnd";
+pub const ALGORITHM_VERSION: &str = "algorithmVersion";
+pub const EXPIRES_AT: &str = "expiresAt";
+pub const TOLERANCE: &str = "tolerance";
+/// Prefix of the level-of-detail routes `lod0`, `lod1`, ...
+pub const LOD_PREFIX: &str = "lod";
+pub const STARTED_AT: &str = "startedAt";
+pub const PLANNED_LENGTH_METERS: &str = "plannedLengthMeters";
+pub const SAMPLE_INTERVAL_STATS: &str = "sampleIntervalStats";
+pub const LENGTH_PRESERVED: &str = "lengthPreserved";
+pub const SIMPLIFICATION_SKIPPED: &str = "simplificationSkipped";
+pub const SIMPLIFICATION_ANOMALY: &str = "simplificationAnomaly";
+pub const POINT_CAP_APPLIED: &str = "pointCapApplied";
+pub const MAX_DEVIATION_METERS: &str = "maxDeviationMeters";
+pub const SIMPLIFICATION_ALGORITHM: &str = "simplificationAlgorithm";
+pub const BOUNDING_BOX_MISMATCH: &str = "boundingBoxMismatch";
+pub const SIMPLIFICATION_DISABLED: &str = "simplificationDisabled";
+pub const ENCODED_POLYLINE: &str = "encodedPolyline";
+pub const SIMPLIFY_MICROS: &str = "simplifyMicros";
+pub const SIMPLIFY_ITERATIONS: &str = "iterations";
+pub const WRITE_VERIFIED: &str = "writeVerified";
+pub const EVENT: &str = "event";
+pub const PROGRESS_PERCENT: &str = "progressPercent";
+pub const TRIP_ID: &str = "tripId";
+pub const STORED_POINTS_COUNT: &str = "storedPointsCount";
+pub const SEQUENCE: &str = "sequence";
+pub const ROUTE_LENGTH: &str = "routeLength";
+
+/// Coordinates of a point in `simplifiedRoute`
+pub const LATITUDE: &str = "latitude";
+pub const LONGITUDE: &str = "longitude";
+pub const OCCUPANCY: &str = "occupancy";
+pub const DEAD_RECKONED: &str = "deadReckoned";
+/// Route events attached to a point of `simplifiedRoute`
+pub const EVENTS: &str = "events";
+
+/// Coordinates of a point in `projectedRoute`
+pub const X: &str = "x";
+pub const Y: &str = "y";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{Location, TripDocument};
+    use mongodb::bson;
+
+    #[test]
+    fn test_fields_match_trip_document() {
+        let trip = TripDocument::new(
+            "driver_1".to_string(),
+            "route_1".to_string(),
+            vec![Location::new(1.0, 2.0)],
+            0,
+            4,
+        );
+        let document = bson::to_document(&trip).unwrap();
+        let keys: Vec<&str> = document.keys().map(String::as_str).collect();
+
+        assert_eq!(
+            keys,
+            [
+                DRIVER_ID,
+                CURRENT_ROUTE_ID,
+                SIMPLIFIED_ROUTE,
+                TIMESTAMP,
+                ORIGINAL_POINTS_COUNT,
+                SIMPLIFIED_POINTS_COUNT,
+                COMPRESSION_RATIO,
+            ]
+        );
+
+        // Counts keep the int32 type trips have always been stored with
+        assert_eq!(document.get_i32(ORIGINAL_POINTS_COUNT).unwrap(), 4);
+        assert_eq!(document.get_i32(SIMPLIFIED_POINTS_COUNT).unwrap(), 1);
+        assert_eq!(document.get_f64(COMPRESSION_RATIO).unwrap(), 0.25);
+
+        let point = document.get_array(SIMPLIFIED_ROUTE).unwrap()[0]
+            .as_document()
+            .unwrap();
+        assert_eq!(point.get_f64(LATITUDE).unwrap(), 1.0);
+        assert_eq!(point.get_f64(LONGITUDE).unwrap(), 2.0);
+    }
+}
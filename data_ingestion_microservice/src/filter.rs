@@ -0,0 +1,81 @@
+use crate::config::IngestionConfig;
+
+/// Match `text` against a glob pattern where `*` matches any run of characters
+/// (so `test-*` is a prefix match) and every other character matches itself
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let Some((first, rest)) = pattern.split_once('*') else {
+        return pattern == text;
+    };
+    let Some(mut remaining) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let mut parts: Vec<&str> = rest.split('*').collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match remaining.find(part) {
+            Some(index) => remaining = &remaining[index + part.len()..],
+            None => return false,
+        }
+    }
+
+    remaining.len() >= last.len() && remaining.ends_with(last)
+}
+
+/// Whether messages from `driver_id` should be processed. A match in
+/// `deny_drivers` always wins; a non-empty `allow_drivers` admits only matches.
+pub fn driver_allowed(config: &IngestionConfig, driver_id: &str) -> bool {
+    let matches = |patterns: &[String]| patterns.iter().any(|p| glob_match(p, driver_id));
+
+    if matches(&config.deny_drivers) {
+        return false;
+    }
+    config.allow_drivers.is_empty() || matches(&config.allow_drivers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(allow: &[&str], deny: &[&str]) -> IngestionConfig {
+        IngestionConfig {
+            allow_drivers: allow.iter().map(|s| s.to_string()).collect(),
+            deny_drivers: deny.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("driver_1", "driver_1"));
+        assert!(!glob_match("driver_1", "driver_10"));
+        assert!(glob_match("pilot-*", "pilot-7"));
+        assert!(glob_match("*-test", "bus-test"));
+        assert!(glob_match("bus-*-dev", "bus-12-dev"));
+        assert!(!glob_match("bus-*-dev", "bus-12-prod"));
+        assert!(!glob_match("ab*ba", "aba"));
+        assert!(glob_match("*", "anything"));
+    }
+
+    #[test]
+    fn test_allow_only() {
+        let config = config(&["pilot-*"], &[]);
+        assert!(driver_allowed(&config, "pilot-1"));
+        assert!(!driver_allowed(&config, "driver_1"));
+    }
+
+    #[test]
+    fn test_deny_only() {
+        let config = config(&[], &["test-*"]);
+        assert!(driver_allowed(&config, "driver_1"));
+        assert!(!driver_allowed(&config, "test-device"));
+    }
+
+    #[test]
+    fn test_deny_takes_precedence_over_allow() {
+        let config = config(&["pilot-*"], &["pilot-test*"]);
+        assert!(driver_allowed(&config, "pilot-1"));
+        assert!(!driver_allowed(&config, "pilot-test-2"));
+        assert!(!driver_allowed(&config, "driver_1"));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::types::Location;
+
+/// Mean Earth radius in meters, as used by the Haversine formula
+pub const EARTH_RADIUS_METERS: f64 = 6_371_008.8;
+
+/// Great-circle distance between two locations in meters using the Haversine formula
+pub fn haversine_distance(p1: &Location, p2: &Location) -> f64 {
+    let lat1 = p1.latitude.to_radians();
+    let lat2 = p2.latitude.to_radians();
+    let d_lat = lat2 - lat1;
+
+    // Longitude is meaningless at a pole: the distance is along the meridian
+    if is_pole(p1) || is_pole(p2) {
+        return EARTH_RADIUS_METERS * d_lat.abs();
+    }
+
+    let d_lon = (p2.longitude - p1.longitude).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    // Rounding can push `a` just past 1 for antipodal points, making the root NaN
+    let a = a.clamp(0.0, 1.0);
+    let c = 2.0 * a.sqrt().atan2((1.0 - a).sqrt());
+
+    EARTH_RADIUS_METERS * c
+}
+
+/// Location reached from `origin` after `distance_meters` along the great circle
+/// starting at `bearing_degrees` (clockwise from north). Only the coordinates
+/// are set on the result.
+pub fn destination(origin: &Location, bearing_degrees: f64, distance_meters: f64) -> Location {
+    let lat1 = origin.latitude.to_radians();
+    let lon1 = origin.longitude.to_radians();
+    let bearing = bearing_degrees.to_radians();
+    let angular = distance_meters / EARTH_RADIUS_METERS;
+
+    let sin_lat2 = lat1.sin() * angular.cos() + lat1.cos() * angular.sin() * bearing.cos();
+    let lat2 = sin_lat2.clamp(-1.0, 1.0).asin();
+    let lon2 = lon1
+        + (bearing.sin() * angular.sin() * lat1.cos()).atan2(angular.cos() - lat1.sin() * sin_lat2);
+
+    Location::new(lat2.to_degrees(), wrap_longitude(lon2.to_degrees()))
+}
+
+/// Whether a location lies exactly on the north or south pole
+pub fn is_pole(location: &Location) -> bool {
+    location.latitude.abs() == 90.0
+}
+
+/// Shift longitudes by multiples of 360° so consecutive points never jump by more
+/// than 180°, making routes that cross the antimeridian continuous for planar math
+pub fn unwrap_longitudes(locations: &[Location]) -> Vec<Location> {
+    let mut unwrapped: Vec<Location> = Vec::with_capacity(locations.len());
+    let mut offset = 0.0;
+
+    for location in locations {
+        if let Some(previous) = unwrapped.last() {
+            let step = location.longitude + offset - previous.longitude;
+            if step > 180.0 {
+                offset -= 360.0;
+            } else if step < -180.0 {
+                offset += 360.0;
+            }
+        }
+        unwrapped.push(Location {
+            longitude: location.longitude + offset,
+            ..location.clone()
+        });
+    }
+
+    unwrapped
+}
+
+/// Bring a longitude produced by `unwrap_longitudes` back into `[-180, 180]`
+pub fn wrap_longitude(longitude: f64) -> f64 {
+    if (-180.0..=180.0).contains(&longitude) {
+        longitude
+    } else {
+        (longitude + 180.0).rem_euclid(360.0) - 180.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::route_simplification::{calculate_total_distance, DistanceUnit};
+
+    fn antimeridian_route() -> Vec<Location> {
+        [179.998, 179.999, -179.999, -179.998]
+            .iter()
+            .map(|&longitude| Location::new(0.0, longitude))
+            .collect()
+    }
+
+    #[test]
+    fn test_unwrap_longitudes_across_antimeridian() {
+        let route = antimeridian_route();
+        let unwrapped = unwrap_longitudes(&route);
+
+        let longitudes: Vec<f64> = unwrapped.iter().map(|loc| loc.longitude).collect();
+        assert!((longitudes[2] - 180.001).abs() < 1e-9);
+        assert!((longitudes[3] - 180.002).abs() < 1e-9);
+
+        // No false ~360° teleport in planar distance
+        let planar = calculate_total_distance(&unwrapped, DistanceUnit::Degrees);
+        assert!((planar - 0.004).abs() < 1e-9);
+        assert!(calculate_total_distance(&route, DistanceUnit::Degrees) > 359.0);
+
+        for (original, restored) in route.iter().zip(&unwrapped) {
+            assert!((wrap_longitude(restored.longitude) - original.longitude).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_unwrap_longitudes_leaves_regular_routes() {
+        let route = vec![Location::new(6.2, -75.5), Location::new(6.3, -75.4)];
+        assert_eq!(unwrap_longitudes(&route), route);
+        assert_eq!(wrap_longitude(-75.5), -75.5);
+    }
+
+    #[test]
+    fn test_haversine_one_degree_at_equator() {
+        let distance = haversine_distance(&Location::new(0.0, 0.0), &Location::new(0.0, 1.0));
+        assert!((distance - 111_195.0).abs() < 10.0);
+    }
+
+    #[test]
+    fn test_haversine_at_poles_and_antipodes() {
+        let pole = Location::new(90.0, 123.0);
+        let near_pole = Location::new(89.0, -57.0);
+        let distance = haversine_distance(&pole, &near_pole);
+        assert!((distance - 111_195.0).abs() < 10.0);
+        assert_eq!(distance, haversine_distance(&near_pole, &pole));
+        assert_eq!(haversine_distance(&pole, &Location::new(90.0, -10.0)), 0.0);
+
+        let half_circumference = EARTH_RADIUS_METERS * std::f64::consts::PI;
+        let antipodal = haversine_distance(&Location::new(0.0, 180.0), &Location::new(0.0, 0.0));
+        assert!((antipodal - half_circumference).abs() < 1.0);
+        let poles = haversine_distance(&pole, &Location::new(-90.0, 0.0));
+        assert!((poles - half_circumference).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_destination_round_trips_haversine() {
+        let origin = Location::new(6.2442, -75.5812);
+        for bearing in [0.0, 45.0, 90.0, 200.0] {
+            let reached = destination(&origin, bearing, 1_500.0);
+            assert!((haversine_distance(&origin, &reached) - 1_500.0).abs() < 1e-6);
+        }
+
+        let north = destination(&Location::new(0.0, 0.0), 0.0, 111_195.08);
+        assert!((north.latitude - 1.0).abs() < 1e-6);
+        assert!(north.longitude.abs() < 1e-9);
+        // Heading east across the antimeridian wraps the longitude
+        let wrapped = destination(&Location::new(0.0, 179.9995), 90.0, 111.0);
+        assert!(wrapped.longitude < -179.999);
+    }
+
+    #[test]
+    fn test_haversine_same_point() {
+        let point = Location::new(6.2442, -75.5812);
+        assert_eq!(haversine_distance(&point, &point), 0.0);
+    }
+}
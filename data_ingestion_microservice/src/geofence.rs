@@ -0,0 +1,162 @@
+use crate::types::Location;
+use serde::{Deserialize, Serialize};
+
+/// A named polygonal area whose boundary crossings are reported on finalize
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+pub struct Geofence {
+    pub name: String,
+    /// Polygon vertices in order; the ring is closed implicitly
+    pub polygon: Vec<Location>,
+}
+
+/// Direction of a geofence boundary crossing
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GeofenceEventKind {
+    Enter,
+    Exit,
+}
+
+/// A single boundary crossing detected on a route
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GeofenceEvent {
+    pub geofence: String,
+    pub kind: GeofenceEventKind,
+    /// Index of the first route point on the new side of the boundary
+    pub point_index: usize,
+    pub location: Location,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+}
+
+/// Check whether a point lies inside a polygon using the even-odd ray casting rule
+pub fn point_in_polygon(point: &Location, polygon: &[Location]) -> bool {
+    if polygon.len() < 3 {
+        return false;
+    }
+
+    let (x, y) = (point.longitude, point.latitude);
+    let mut inside = false;
+    let mut j = polygon.len() - 1;
+
+    for i in 0..polygon.len() {
+        let (xi, yi) = (polygon[i].longitude, polygon[i].latitude);
+        let (xj, yj) = (polygon[j].longitude, polygon[j].latitude);
+
+        if (yi > y) != (yj > y) && x < (xj - xi) * (y - yi) / (yj - yi) + xi {
+            inside = !inside;
+        }
+        j = i;
+    }
+
+    inside
+}
+
+/// Evaluate a route against the configured geofences.
+/// Only transitions are reported: a route starting inside a geofence does not
+/// produce an initial enter event.
+pub fn evaluate_geofences(locations: &[Location], geofences: &[Geofence]) -> Vec<GeofenceEvent> {
+    let mut events = Vec::new();
+
+    for geofence in geofences {
+        let mut was_inside: Option<bool> = None;
+
+        for (index, location) in locations.iter().enumerate() {
+            let inside = point_in_polygon(location, &geofence.polygon);
+
+            if let Some(previous) = was_inside {
+                if previous != inside {
+                    events.push(GeofenceEvent {
+                        geofence: geofence.name.clone(),
+                        kind: if inside {
+                            GeofenceEventKind::Enter
+                        } else {
+                            GeofenceEventKind::Exit
+                        },
+                        point_index: index,
+                        location: location.clone(),
+                        timestamp: location.timestamp,
+                    });
+                }
+            }
+
+            was_inside = Some(inside);
+        }
+    }
+
+    events.sort_by_key(|event| event.point_index);
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square_geofence() -> Geofence {
+        Geofence {
+            name: "depot".to_string(),
+            polygon: vec![
+                Location::new(1.0, 1.0),
+                Location::new(1.0, 2.0),
+                Location::new(2.0, 2.0),
+                Location::new(2.0, 1.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_point_in_polygon() {
+        let geofence = square_geofence();
+
+        assert!(point_in_polygon(
+            &Location::new(1.5, 1.5),
+            &geofence.polygon
+        ));
+        assert!(!point_in_polygon(
+            &Location::new(0.5, 1.5),
+            &geofence.polygon
+        ));
+        assert!(!point_in_polygon(
+            &Location::new(1.5, 2.5),
+            &geofence.polygon
+        ));
+        assert!(!point_in_polygon(&Location::new(1.5, 1.5), &[]));
+    }
+
+    #[test]
+    fn test_route_crossing_square_geofence() {
+        let route: Vec<Location> = (0..5)
+            .map(|i| Location {
+                timestamp: Some(100 + i as u64),
+                ..Location::new(1.5, 0.6 * i as f64)
+            })
+            .collect();
+
+        // Longitudes: 0.0, 0.6, 1.2, 1.8, 2.4 -> inside at indices 2 and 3
+        let events = evaluate_geofences(&route, &[square_geofence()]);
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].kind, GeofenceEventKind::Enter);
+        assert_eq!(events[0].point_index, 2);
+        assert_eq!(events[0].timestamp, Some(102));
+        assert_eq!(events[1].kind, GeofenceEventKind::Exit);
+        assert_eq!(events[1].point_index, 4);
+        assert_eq!(events[1].timestamp, Some(104));
+    }
+
+    #[test]
+    fn test_route_starting_inside_only_reports_exit() {
+        let route = vec![
+            Location::new(1.5, 1.5),
+            Location::new(1.5, 1.8),
+            Location::new(1.5, 3.0),
+        ];
+
+        let events = evaluate_geofences(&route, &[square_geofence()]);
+
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].kind, GeofenceEventKind::Exit);
+        assert_eq!(events[0].point_index, 2);
+    }
+}
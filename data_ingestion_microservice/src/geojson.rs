@@ -0,0 +1,110 @@
+use crate::document::location_from_document;
+use crate::fields;
+use crate::types::{ServiceError, ServiceResult};
+use mongodb::bson::{Bson, Document};
+use serde_json::{json, Map, Value};
+
+/// GeoJSON `Feature` for a stored trip: the simplified route as a `LineString`
+/// and every other field of the trip but `rawRoute` as a property. The trip
+/// `_id` becomes the feature `id`.
+pub fn trip_feature(trip: &Document) -> ServiceResult<Value> {
+    let route = trip.get_array(fields::SIMPLIFIED_ROUTE).map_err(|e| {
+        ServiceError::Validation(format!("Trip has no {}: {}", fields::SIMPLIFIED_ROUTE, e))
+    })?;
+    let coordinates = route
+        .iter()
+        .map(|point| match point {
+            Bson::Document(point) => {
+                let location = location_from_document(point)?;
+                Ok(json!([location.longitude, location.latitude]))
+            }
+            other => Err(ServiceError::Validation(format!(
+                "Invalid route point {:?}",
+                other
+            ))),
+        })
+        .collect::<ServiceResult<Vec<_>>>()?;
+
+    let mut properties = Map::new();
+    for (name, value) in trip {
+        if name != "_id" && name != fields::SIMPLIFIED_ROUTE && name != fields::RAW_ROUTE {
+            properties.insert(name.clone(), value.clone().into_relaxed_extjson());
+        }
+    }
+
+    let mut feature = json!({
+        "type": "Feature",
+        "geometry": { "type": "LineString", "coordinates": coordinates },
+        "properties": properties,
+    });
+    match trip.get("_id") {
+        Some(Bson::ObjectId(id)) => feature["id"] = json!(id.to_hex()),
+        Some(id) => feature["id"] = id.clone().into_relaxed_extjson(),
+        None => {}
+    }
+    Ok(feature)
+}
+
+/// GeoJSON `FeatureCollection` with one feature per trip, in the given order
+pub fn trips_feature_collection(trips: &[Document]) -> ServiceResult<Value> {
+    let features = trips
+        .iter()
+        .map(trip_feature)
+        .collect::<ServiceResult<Vec<_>>>()?;
+    Ok(json!({ "type": "FeatureCollection", "features": features }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::{doc, oid::ObjectId};
+
+    fn trip(route_id: &str) -> Document {
+        doc! {
+            "_id": ObjectId::new(),
+            fields::DRIVER_ID: "driver_1",
+            fields::CURRENT_ROUTE_ID: route_id,
+            fields::SIMPLIFIED_ROUTE: [
+                { fields::LATITUDE: 6.25, fields::LONGITUDE: -75.58 },
+                { fields::LATITUDE: 6_260_000i64, fields::LONGITUDE: -75_570_000i64 },
+            ],
+            fields::TIMESTAMP: 1_700_000_000i64,
+            fields::ORIGINAL_POINTS_COUNT: 12,
+        }
+    }
+
+    #[test]
+    fn test_feature_collection_has_one_feature_per_trip() {
+        let trips = vec![trip("route_1"), trip("route_2"), trip("route_3")];
+
+        let collection = trips_feature_collection(&trips).unwrap();
+        assert_eq!(collection["type"], "FeatureCollection");
+        let features = collection["features"].as_array().unwrap();
+        assert_eq!(features.len(), 3);
+
+        let feature = &features[1];
+        assert_eq!(feature["type"], "Feature");
+        assert_eq!(
+            feature["id"],
+            trips[1].get_object_id("_id").unwrap().to_hex()
+        );
+        assert_eq!(feature["geometry"]["type"], "LineString");
+        assert_eq!(
+            feature["geometry"]["coordinates"],
+            json!([[-75.58, 6.25], [-75.57, 6.26]])
+        );
+        assert_eq!(feature["properties"][fields::CURRENT_ROUTE_ID], "route_2");
+        assert_eq!(feature["properties"][fields::ORIGINAL_POINTS_COUNT], 12);
+        assert!(feature["properties"]
+            .get(fields::SIMPLIFIED_ROUTE)
+            .is_none());
+    }
+
+    #[test]
+    fn test_empty_collection_and_invalid_trip() {
+        let collection = trips_feature_collection(&[]).unwrap();
+        assert_eq!(collection["features"], json!([]));
+
+        assert!(trip_feature(&doc! { fields::DRIVER_ID: "driver_1" }).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use crate::publisher::Publisher;
+use crate::types::MetricsSnapshot;
+use log::{error, info, warn};
+use rumqttc::QoS;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Shared readiness state, updated by message processing and the watchdog
+#[derive(Clone)]
+pub struct HealthState {
+    inner: Arc<HealthInner>,
+}
+
+struct HealthInner {
+    ready: AtomicBool,
+    last_activity: Mutex<Instant>,
+}
+
+impl Default for HealthState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HealthState {
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(HealthInner {
+                ready: AtomicBool::new(true),
+                last_activity: Mutex::new(Instant::now()),
+            }),
+        }
+    }
+
+    /// Record that a message was received; restores readiness after an idle alert
+    pub fn record_activity(&self) {
+        *self.inner.last_activity.lock().unwrap() = Instant::now();
+        if !self.inner.ready.swap(true, Ordering::SeqCst) {
+            info!("Messages are flowing again; service is ready");
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.inner.ready.load(Ordering::SeqCst)
+    }
+
+    pub fn set_ready(&self, ready: bool) {
+        self.inner.ready.store(ready, Ordering::SeqCst);
+    }
+
+    /// Time elapsed since the last recorded activity
+    pub fn idle_for(&self) -> Duration {
+        self.inner.last_activity.lock().unwrap().elapsed()
+    }
+}
+
+/// Dead-man's switch: flags the service not-ready when no messages arrive
+/// for `idle_after`, optionally publishing an alert to MQTT.
+pub struct IdleWatchdog {
+    health: HealthState,
+    idle_after: Duration,
+    alert: Option<(Arc<dyn Publisher>, String)>,
+}
+
+impl IdleWatchdog {
+    pub fn new(health: HealthState, idle_after: Duration) -> Self {
+        Self {
+            health,
+            idle_after,
+            alert: None,
+        }
+    }
+
+    /// Publish an alert to `topic` each time the watchdog fires
+    pub fn with_alert(mut self, publisher: Arc<dyn Publisher>, topic: String) -> Self {
+        self.alert = Some((publisher, topic));
+        self
+    }
+
+    /// Check the idle time once; returns `true` if the watchdog fired
+    pub async fn check(&self) -> bool {
+        let idle_for = self.health.idle_for();
+        if idle_for < self.idle_after || !self.health.is_ready() {
+            return false;
+        }
+
+        error!(
+            "No messages received for {}s; marking service not ready",
+            idle_for.as_secs()
+        );
+        self.health.set_ready(false);
+
+        if let Some((publisher, topic)) = &self.alert {
+            let payload = serde_json::json!({
+                "alert": "idle",
+                "idleSeconds": idle_for.as_secs(),
+            });
+            if let Err(e) = publisher
+                .publish(topic, payload.to_string().into_bytes(), QoS::AtLeastOnce)
+                .await
+            {
+                warn!("Failed to publish idle alert: {}", e);
+            }
+        }
+
+        true
+    }
+
+    /// Run the watchdog forever, checking a few times per idle period
+    pub async fn run(self) {
+        let interval = (self.idle_after / 4).max(Duration::from_millis(100));
+        loop {
+            tokio::time::sleep(interval).await;
+            self.check().await;
+        }
+    }
+}
+
+/// Source of the counters reported in each heartbeat
+pub type MetricsSource = Arc<dyn Fn() -> MetricsSnapshot + Send + Sync>;
+
+/// Periodically publishes this instance's readiness and counters so a central
+/// monitor can follow every instance over MQTT
+pub struct Heartbeat {
+    health: HealthState,
+    metrics: MetricsSource,
+    publisher: Arc<dyn Publisher>,
+    topic: String,
+    instance_id: String,
+    interval: Duration,
+    started: Instant,
+}
+
+impl Heartbeat {
+    /// `topic` may contain `{instance_id}`, replaced by `instance_id`
+    pub fn new(
+        health: HealthState,
+        metrics: MetricsSource,
+        publisher: Arc<dyn Publisher>,
+        topic: &str,
+        instance_id: String,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            health,
+            metrics,
+            publisher,
+            topic: topic.replace("{instance_id}", &instance_id),
+            instance_id,
+            interval,
+            started: Instant::now(),
+        }
+    }
+
+    /// Publish one heartbeat
+    pub async fn beat(&self) {
+        let metrics = (self.metrics)();
+        let payload = serde_json::json!({
+            "instanceId": self.instance_id,
+            "ready": self.health.is_ready(),
+            "uptimeSecs": self.started.elapsed().as_secs(),
+            "messagesProcessed": metrics.messages_processed,
+            "messagesFiltered": metrics.messages_filtered,
+            "routesCompleted": metrics.routes_completed,
+            "errorsCount": metrics.errors_count,
+            "totalPointsProcessed": metrics.total_points_processed,
+            "compressionRatioEwma": metrics.compression_ratio_ewma,
+            "compressionDriftAlerts": metrics.compression_drift_alerts,
+        });
+        if let Err(e) = self
+            .publisher
+            .publish(
+                &self.topic,
+                payload.to_string().into_bytes(),
+                QoS::AtMostOnce,
+            )
+            .await
+        {
+            warn!("Failed to publish heartbeat: {}", e);
+        }
+    }
+
+    /// Publish a heartbeat every interval until `shutdown` completes
+    pub async fn run(self, shutdown: impl Future<Output = ()>) {
+        tokio::pin!(shutdown);
+        loop {
+            tokio::select! {
+                _ = &mut shutdown => {
+                    info!("Stopping heartbeat");
+                    return;
+                }
+                _ = tokio::time::sleep(self.interval) => self.beat().await,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::publisher::RecordingPublisher;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_watchdog_fires_after_idle_period() {
+        let health = HealthState::new();
+        let publisher = Arc::new(RecordingPublisher::new());
+        let watchdog = IdleWatchdog::new(health.clone(), Duration::from_secs(60))
+            .with_alert(publisher.clone(), "alerts/idle".to_string());
+        tokio::spawn(watchdog.run());
+
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        health.record_activity();
+        tokio::time::sleep(Duration::from_secs(45)).await;
+        assert!(health.is_ready());
+        assert!(publisher.messages().is_empty());
+
+        tokio::time::sleep(Duration::from_secs(30)).await;
+        assert!(!health.is_ready());
+        let alerts = publisher.messages_on("alerts/idle");
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].json()["alert"], "idle");
+
+        // Still idle: the alert is not repeated
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        assert_eq!(publisher.messages().len(), 1);
+
+        health.record_activity();
+        assert!(health.is_ready());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_heartbeat_published_every_interval() {
+        let publisher = Arc::new(RecordingPublisher::new());
+        let metrics: MetricsSource = Arc::new(|| MetricsSnapshot {
+            messages_processed: 7,
+            ..Default::default()
+        });
+        let heartbeat = Heartbeat::new(
+            HealthState::new(),
+            metrics,
+            publisher.clone(),
+            "ingest/{instance_id}/heartbeat",
+            "ingest-a".to_string(),
+            Duration::from_secs(30),
+        );
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let task = tokio::spawn(heartbeat.run(async {
+            let _ = stopped.await;
+        }));
+
+        tokio::time::sleep(Duration::from_secs(29)).await;
+        assert!(publisher.messages().is_empty());
+
+        tokio::time::sleep(Duration::from_secs(32)).await;
+        let beats = publisher.messages_on("ingest/ingest-a/heartbeat");
+        assert_eq!(beats.len(), 2);
+        assert_eq!(beats[0].json()["instanceId"], "ingest-a");
+        assert_eq!(beats[0].json()["messagesProcessed"], 7);
+        assert_eq!(beats[0].json()["uptimeSecs"], 30);
+        assert_eq!(beats[1].json()["uptimeSecs"], 60);
+
+        // Nothing is published once shutdown starts
+        stop.send(()).unwrap();
+        task.await.unwrap();
+        tokio::time::sleep(Duration::from_secs(120)).await;
+        assert_eq!(publisher.messages().len(), 2);
+    }
+}
@@ -0,0 +1,67 @@
+use crate::types::{BusMessage, BusStatus, ServiceResult};
+use async_trait::async_trait;
+use log::debug;
+use std::time::Duration;
+
+/// Side effects run on route status transitions.
+/// Errors are logged by the caller and never abort ingestion.
+#[async_trait]
+pub trait StatusHook: Send + Sync {
+    /// Called after a `started` message has recorded the route start
+    async fn on_started(&self, _msg: &BusMessage) -> ServiceResult<()> {
+        Ok(())
+    }
+
+    /// Called after an `in_route` point has been stored
+    async fn on_in_route(&self, _msg: &BusMessage) -> ServiceResult<()> {
+        Ok(())
+    }
+
+    /// Called after a `finished` message has been processed
+    async fn on_finished(&self, _msg: &BusMessage) -> ServiceResult<()> {
+        Ok(())
+    }
+}
+
+/// Hook that POSTs `{"status": ..., "message": ...}` to a webhook URL
+pub struct WebhookHook {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl WebhookHook {
+    pub fn new(url: String, timeout: Duration) -> ServiceResult<Self> {
+        let client = reqwest::Client::builder().timeout(timeout).build()?;
+        Ok(Self { client, url })
+    }
+
+    async fn notify(&self, status: BusStatus, msg: &BusMessage) -> ServiceResult<()> {
+        let body = serde_json::json!({
+            "status": status.to_string(),
+            "message": msg,
+        });
+        self.client
+            .post(&self.url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        debug!("Webhook {} notified of {} status", self.url, status);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StatusHook for WebhookHook {
+    async fn on_started(&self, msg: &BusMessage) -> ServiceResult<()> {
+        self.notify(BusStatus::Started, msg).await
+    }
+
+    async fn on_in_route(&self, msg: &BusMessage) -> ServiceResult<()> {
+        self.notify(BusStatus::InRoute, msg).await
+    }
+
+    async fn on_finished(&self, msg: &BusMessage) -> ServiceResult<()> {
+        self.notify(BusStatus::Finished, msg).await
+    }
+}
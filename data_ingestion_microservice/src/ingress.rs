@@ -0,0 +1,433 @@
+#[cfg(feature = "kafka")]
+use crate::config::IngressConfig;
+use crate::config::{BrokerEndpoint, MqttConfig};
+use crate::failover::BrokerFailover;
+#[cfg(feature = "kafka")]
+use crate::types::ServiceResult;
+use async_trait::async_trait;
+use log::{error, info, warn};
+#[cfg(feature = "kafka")]
+use rdkafka::consumer::{Consumer, StreamConsumer};
+#[cfg(feature = "kafka")]
+use rdkafka::{ClientConfig, Message};
+use rumqttc::{
+    AsyncClient, ConnectionError, Event, EventLoop, MqttOptions, Outgoing, Packet, Publish, QoS,
+};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// How long `MqttIngress::close` waits for the DISCONNECT to go out
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A message received from an `Ingress`, before it is parsed
+#[derive(Debug, Clone, PartialEq)]
+pub struct IngressMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    /// Replayed by the broker as a retained message rather than newly published
+    pub retained: bool,
+    /// When the message was received, for `MAX_PROCESSING_AGE_MS`
+    pub received_at: Instant,
+}
+
+impl IngressMessage {
+    pub fn new(topic: impl Into<String>, payload: Vec<u8>) -> Self {
+        Self {
+            topic: topic.into(),
+            payload,
+            retained: false,
+            received_at: Instant::now(),
+        }
+    }
+}
+
+impl From<Publish> for IngressMessage {
+    fn from(publish: Publish) -> Self {
+        Self {
+            topic: publish.topic,
+            payload: publish.payload.to_vec(),
+            retained: publish.retain,
+            received_at: Instant::now(),
+        }
+    }
+}
+
+/// Source of the messages fed to the service: MQTT, or Kafka with
+/// `INGRESS_SOURCE=kafka`
+#[async_trait]
+pub trait Ingress: Send {
+    /// Next message, or `None` once the source is closed. Connection errors
+    /// are handled inside the implementation, which keeps reconnecting.
+    async fn recv(&mut self) -> Option<IngressMessage>;
+
+    /// Disconnect from the source on shutdown
+    async fn close(&mut self) {}
+}
+
+/// Event source polled by `MqttIngress`; rumqttc's `EventLoop` in production
+#[async_trait]
+pub trait MqttConnection: Send {
+    async fn poll(&mut self) -> Result<Event, ConnectionError>;
+
+    /// Drop the network connection; the next poll reconnects. Clients sharing
+    /// the connection stay usable.
+    fn reset(&mut self);
+
+    /// Options used from the next (re)connection on
+    fn set_options(&mut self, options: MqttOptions);
+}
+
+#[async_trait]
+impl MqttConnection for EventLoop {
+    async fn poll(&mut self) -> Result<Event, ConnectionError> {
+        EventLoop::poll(self).await
+    }
+
+    fn reset(&mut self) {
+        self.clean();
+    }
+
+    fn set_options(&mut self, options: MqttOptions) {
+        self.mqtt_options = options;
+    }
+}
+
+/// `Ingress` reading publishes from the MQTT broker. The point topic and the
+/// resimplify topic are (re)subscribed on every ConnAck, and the broker is
+/// switched after too many consecutive connection errors. With a stall
+/// timeout, a connection that goes silent is dropped and re-established.
+pub struct MqttIngress<C = EventLoop> {
+    config: MqttConfig,
+    client: AsyncClient,
+    connection: C,
+    failover: BrokerFailover,
+    subscribe: bool,
+}
+
+impl MqttIngress {
+    pub fn new(config: MqttConfig) -> Self {
+        let failover = BrokerFailover::new(config.endpoints(), config.failover_after_errors);
+        let (client, eventloop) = AsyncClient::new(mqtt_options(&config, failover.current()), 10);
+        Self::with_connection(config, client, eventloop)
+    }
+}
+
+impl<C: MqttConnection> MqttIngress<C> {
+    /// Ingress polling `connection`, whose requests are sent through `client`
+    pub fn with_connection(config: MqttConfig, client: AsyncClient, connection: C) -> Self {
+        let failover = BrokerFailover::new(config.endpoints(), config.failover_after_errors);
+        Self {
+            config,
+            client,
+            connection,
+            failover,
+            subscribe: true,
+        }
+    }
+
+    /// Keep the connection up for publishing without subscribing to any
+    /// topic, when messages come from another ingress
+    pub fn publish_only(mut self) -> Self {
+        self.subscribe = false;
+        self
+    }
+
+    /// Client sharing this ingress' connection, for publishing
+    pub fn client(&self) -> AsyncClient {
+        self.client.clone()
+    }
+
+    /// Next event, or `None` once no event arrived within the stall timeout
+    async fn poll(&mut self) -> Option<Result<Event, ConnectionError>> {
+        if self.config.stall_timeout_secs == 0 {
+            return Some(self.connection.poll().await);
+        }
+        let stall_timeout = Duration::from_secs(self.config.stall_timeout_secs);
+        tokio::time::timeout(stall_timeout, self.connection.poll())
+            .await
+            .ok()
+    }
+}
+
+#[async_trait]
+impl<C: MqttConnection> Ingress for MqttIngress<C> {
+    async fn recv(&mut self) -> Option<IngressMessage> {
+        loop {
+            let Some(polled) = self.poll().await else {
+                warn!(
+                    "MQTT event loop silent for {}s on {}; reconnecting",
+                    self.config.stall_timeout_secs,
+                    self.failover.current()
+                );
+                self.connection.reset();
+                continue;
+            };
+            let event = match polled {
+                Ok(event) => event,
+                Err(e) => {
+                    error!(
+                        "MQTT connection error on {}: {}",
+                        self.failover.current(),
+                        e
+                    );
+                    if self.failover.record_failure() {
+                        self.connection
+                            .set_options(mqtt_options(&self.config, self.failover.current()));
+                    }
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    continue;
+                }
+            };
+            match event {
+                Event::Incoming(Packet::ConnAck(_)) => {
+                    info!("Connected to MQTT broker {}", self.failover.current());
+                    self.failover.record_success();
+                    if !self.subscribe {
+                        continue;
+                    }
+                    let topics = std::iter::once(&self.config.topic)
+                        .chain(self.config.resimplify_topic.as_ref());
+                    for topic in topics {
+                        if let Err(e) = self.client.try_subscribe(topic, QoS::AtLeastOnce) {
+                            warn!("Failed to subscribe to {}: {}", topic, e);
+                        }
+                    }
+                }
+                Event::Incoming(Packet::Publish(publish)) => return Some(publish.into()),
+                other => {
+                    info!("MQTT event: {:?}", other);
+                }
+            }
+        }
+    }
+
+    async fn close(&mut self) {
+        if let Err(e) = self.client.try_disconnect() {
+            warn!("Failed to disconnect from MQTT broker: {}", e);
+            return;
+        }
+        // The DISCONNECT is only written while the event loop is polled
+        let sent = tokio::time::timeout(DISCONNECT_TIMEOUT, async {
+            loop {
+                match self.connection.poll().await {
+                    Ok(Event::Outgoing(Outgoing::Disconnect)) => return true,
+                    Ok(_) => {}
+                    Err(_) => return false,
+                }
+            }
+        })
+        .await;
+        if sent == Ok(true) {
+            info!("Disconnected from MQTT broker {}", self.failover.current());
+        } else {
+            warn!(
+                "MQTT broker {} not disconnected cleanly",
+                self.failover.current()
+            );
+        }
+    }
+}
+
+/// MQTT connection options for one broker endpoint
+fn mqtt_options(config: &MqttConfig, endpoint: &BrokerEndpoint) -> MqttOptions {
+    let mut options = MqttOptions::new(
+        config.client_id.clone(),
+        endpoint.host.clone(),
+        endpoint.port,
+    );
+    options.set_keep_alive(Duration::from_secs(config.keep_alive_secs));
+    options
+}
+
+/// `Ingress` consuming the Kafka topic of bus messages, plus the resimplify
+/// topic when set. Offsets are committed automatically in the background.
+#[cfg(feature = "kafka")]
+pub struct KafkaIngress {
+    consumer: StreamConsumer,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaIngress {
+    pub fn new(config: &IngressConfig, resimplify_topic: Option<&str>) -> ServiceResult<Self> {
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &config.kafka_brokers)
+            .set("group.id", &config.kafka_group_id)
+            .set("auto.offset.reset", &config.kafka_auto_offset_reset)
+            .set("enable.auto.commit", "true")
+            .create()?;
+        let topics: Vec<&str> = std::iter::once(config.kafka_topic.as_str())
+            .chain(resimplify_topic)
+            .collect();
+        consumer.subscribe(&topics)?;
+        info!(
+            "Consuming Kafka topics {:?} from {}",
+            topics, config.kafka_brokers
+        );
+        Ok(Self { consumer })
+    }
+}
+
+#[cfg(feature = "kafka")]
+#[async_trait]
+impl Ingress for KafkaIngress {
+    async fn recv(&mut self) -> Option<IngressMessage> {
+        loop {
+            match self.consumer.recv().await {
+                Ok(message) => {
+                    return Some(IngressMessage::new(
+                        message.topic(),
+                        message.payload().unwrap_or_default().to_vec(),
+                    ))
+                }
+                Err(e) => {
+                    error!("Kafka consumer error: {}", e);
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+            }
+        }
+    }
+
+    async fn close(&mut self) {
+        self.consumer.unsubscribe();
+        info!("Unsubscribed from Kafka");
+    }
+}
+
+/// `Ingress` fed through a channel, used for tests and embedding. It closes
+/// once every sender is dropped.
+pub struct ChannelIngress {
+    receiver: mpsc::Receiver<IngressMessage>,
+}
+
+impl ChannelIngress {
+    pub fn new(capacity: usize) -> (mpsc::Sender<IngressMessage>, Self) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        (sender, Self { receiver })
+    }
+}
+
+#[async_trait]
+impl Ingress for ChannelIngress {
+    async fn recv(&mut self) -> Option<IngressMessage> {
+        self.receiver.recv().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_channel_ingress_closes_with_senders() {
+        let (sender, mut ingress) = ChannelIngress::new(4);
+        sender
+            .send(IngressMessage::new("drivers_location/d", b"{}".to_vec()))
+            .await
+            .unwrap();
+        drop(sender);
+
+        let message = ingress.recv().await.unwrap();
+        assert_eq!(message.topic, "drivers_location/d");
+        assert!(!message.retained);
+        assert!(ingress.recv().await.is_none());
+    }
+
+    /// Connection that hangs until it is reset, then yields one publish per poll
+    struct HangingConnection {
+        resets: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl MqttConnection for HangingConnection {
+        async fn poll(&mut self) -> Result<Event, ConnectionError> {
+            if self.resets.load(Ordering::SeqCst) == 0 {
+                std::future::pending::<()>().await;
+            }
+            let publish = Publish::new("drivers_location/d", QoS::AtLeastOnce, b"{}".to_vec());
+            Ok(Event::Incoming(Packet::Publish(publish)))
+        }
+
+        fn reset(&mut self) {
+            self.resets.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn set_options(&mut self, _options: MqttOptions) {}
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stalled_connection_reset_after_timeout() {
+        let config = MqttConfig {
+            stall_timeout_secs: 30,
+            ..MqttConfig::default()
+        };
+        let (client, _) = AsyncClient::new(MqttOptions::new("test", "localhost", 1883), 10);
+        let resets = Arc::new(AtomicUsize::new(0));
+        let connection = HangingConnection {
+            resets: resets.clone(),
+        };
+        let mut ingress = MqttIngress::with_connection(config, client, connection);
+
+        let started = tokio::time::Instant::now();
+        let message = ingress.recv().await.unwrap();
+        assert_eq!(message.topic, "drivers_location/d");
+        assert_eq!(resets.load(Ordering::SeqCst), 1);
+        assert_eq!(started.elapsed(), Duration::from_secs(30));
+
+        // A responsive connection is left alone
+        ingress.recv().await.unwrap();
+        assert_eq!(resets.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(feature = "kafka")]
+    #[tokio::test]
+    async fn test_kafka_ingress_consumes_topic() {
+        use crate::types::BusMessage;
+        use rdkafka::mocking::MockCluster;
+        use rdkafka::producer::{FutureProducer, FutureRecord};
+
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("drivers_location", 1, 1).unwrap();
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", cluster.bootstrap_servers())
+            .create()
+            .unwrap();
+        let payload = br#"{"driverId":"driver_1","currentRouteId":"route_1","timestamp":1,
+            "status":"in_route","driverLocation":{"latitude":6.25,"longitude":-75.58}}"#;
+        producer
+            .send(
+                FutureRecord::<(), _>::to("drivers_location").payload(&payload[..]),
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+
+        let config = IngressConfig {
+            kafka_brokers: cluster.bootstrap_servers(),
+            kafka_auto_offset_reset: "earliest".to_string(),
+            ..IngressConfig::default()
+        };
+        let mut ingress = KafkaIngress::new(&config, None).unwrap();
+        let message = tokio::time::timeout(Duration::from_secs(30), ingress.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(message.topic, "drivers_location");
+        let msg: BusMessage = serde_json::from_slice(&message.payload).unwrap();
+        assert_eq!(msg.driver_id, "driver_1");
+        assert!(!message.retained);
+        ingress.close().await;
+    }
+
+    #[test]
+    fn test_publish_converted_with_retain_flag() {
+        let mut publish = Publish::new("drivers_location/d", QoS::AtLeastOnce, b"{}".to_vec());
+        publish.retain = true;
+
+        let message = IngressMessage::from(publish);
+        assert_eq!(message.payload, b"{}");
+        assert!(message.retained);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::types::{BusMessage, ServiceError};
+use std::fmt;
+
+/// Suffix of the event list of a route, which is not a point list
+pub const EVENTS_SUFFIX: &str = ":events";
+
+/// Prefix of the per-driver progress keys, which are not route keys
+pub const PROGRESS_PREFIX: &str = "progress:";
+
+/// Redis key of the point list for one driver's route: `{driverId}:{currentRouteId}`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RouteKey {
+    pub driver_id: String,
+    pub route_id: String,
+}
+
+impl RouteKey {
+    pub fn new(driver_id: impl Into<String>, route_id: impl Into<String>) -> Self {
+        Self {
+            driver_id: driver_id.into(),
+            route_id: route_id.into(),
+        }
+    }
+
+    pub fn from_message(msg: &BusMessage) -> Self {
+        Self::new(msg.driver_id.clone(), msg.current_route_id.clone())
+    }
+
+    /// Set of point ids already stored for this route, used for deduplication
+    pub fn seen_key(&self) -> String {
+        format!("{}:seen", self)
+    }
+
+    /// Distance the driver has traveled on its current route and its last
+    /// position, used for progress reporting
+    pub fn traveled_key(&self) -> String {
+        format!("{}{}:traveled", PROGRESS_PREFIX, self.driver_id)
+    }
+
+    /// Estimated bytes of point payloads stored for the route
+    pub fn bytes_key(&self) -> String {
+        format!("{}:bytes", self)
+    }
+
+    /// Points detached from the live list while the route is being finalized
+    pub fn finalizing_key(&self) -> String {
+        format!("{}:finalizing", self)
+    }
+
+    /// Route metadata recorded by a `started` message
+    pub fn start_key(&self) -> String {
+        format!("{}:start", self)
+    }
+
+    /// Events reported during the route, one JSON `RouteEvent` per entry
+    pub fn events_key(&self) -> String {
+        format!("{}{}", self, EVENTS_SUFFIX)
+    }
+
+    /// Latest progress update of the driver this route belongs to
+    pub fn progress_key(&self) -> String {
+        format!("{}{}", PROGRESS_PREFIX, self.driver_id)
+    }
+
+    /// Dead-letter list of the route, one JSON `DeadLetterRecord` per entry
+    pub fn dead_letter_key(&self, prefix: &str) -> String {
+        format!("{}:{}", prefix, self)
+    }
+
+    /// Key the points of a rejected route are moved to
+    pub fn dead_letter_points_key(&self, prefix: &str) -> String {
+        format!("{}:{}:points", prefix, self)
+    }
+}
+
+impl fmt::Display for RouteKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.driver_id, self.route_id)
+    }
+}
+
+/// Parses `{driverId}:{currentRouteId}`, splitting on the first `:`
+impl std::str::FromStr for RouteKey {
+    type Err = ServiceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some((driver_id, route_id)) if !driver_id.is_empty() && !route_id.is_empty() => {
+                Ok(Self::new(driver_id, route_id))
+            }
+            _ => Err(ServiceError::Validation(format!(
+                "Invalid route key '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_key_round_trip() {
+        let key = RouteKey::new("driver_1", "route_1");
+
+        assert_eq!(key.to_string(), "driver_1:route_1");
+        assert_eq!("driver_1:route_1".parse::<RouteKey>().unwrap(), key);
+        assert_eq!(key.seen_key(), "driver_1:route_1:seen");
+        assert_eq!(key.traveled_key(), "progress:driver_1:traveled");
+        assert_eq!(key.bytes_key(), "driver_1:route_1:bytes");
+        assert_eq!(key.finalizing_key(), "driver_1:route_1:finalizing");
+        assert_eq!(key.start_key(), "driver_1:route_1:start");
+        assert_eq!(key.progress_key(), "progress:driver_1");
+        assert_eq!(
+            key.dead_letter_key("dead_letter"),
+            "dead_letter:driver_1:route_1"
+        );
+        assert_eq!(
+            key.dead_letter_points_key("dead_letter"),
+            "dead_letter:driver_1:route_1:points"
+        );
+    }
+
+    #[test]
+    fn test_route_key_rejects_malformed_keys() {
+        assert!("driver_1".parse::<RouteKey>().is_err());
+        assert!(":route_1".parse::<RouteKey>().is_err());
+        assert!("driver_1:".parse::<RouteKey>().is_err());
+    }
+}
@@ -0,0 +1,35 @@
+pub mod active;
+pub mod analysis;
+pub mod api;
+pub mod batch;
+pub mod codec;
+pub mod config;
+pub mod coordinates;
+pub mod document;
+pub mod error_log;
+pub mod failover;
+pub mod fields;
+pub mod filter;
+pub mod geodesy;
+pub mod geofence;
+pub mod geojson;
+pub mod health;
+pub mod hooks;
+pub mod ingress;
+pub mod keys;
+pub mod metrics;
+pub mod pipeline;
+pub mod polyline;
+pub mod postgis;
+pub mod privacy;
+pub mod projection;
+pub mod publisher;
+pub mod recent;
+pub mod route_simplification;
+pub mod service;
+pub mod shutdown;
+pub mod sink;
+pub mod spool;
+pub mod storage;
+pub mod types;
+pub mod writer;
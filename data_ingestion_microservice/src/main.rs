@@ -1,16 +1,26 @@
-mod config;
-mod route_simplification;
-mod types;
-
-use crate::config::Config;
-use crate::route_simplification::RouteSimplifier;
-use crate::types::{BusMessage, BusStatus, Location};
-
-use log::{error, info};
-use mongodb::{bson::doc, Client as MongoClient};
-use redis::AsyncCommands;
-use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use data_ingestion_microservice::api;
+use data_ingestion_microservice::config::{Config, IngressSource};
+use data_ingestion_microservice::health::{Heartbeat, IdleWatchdog};
+use data_ingestion_microservice::hooks::WebhookHook;
+#[cfg(feature = "kafka")]
+use data_ingestion_microservice::ingress::KafkaIngress;
+use data_ingestion_microservice::ingress::{Ingress, MqttIngress};
+#[cfg(feature = "postgis")]
+use data_ingestion_microservice::postgis::PostgisSink;
+use data_ingestion_microservice::publisher::{MqttPublisher, Publisher};
+use data_ingestion_microservice::service::IngestionService;
+use data_ingestion_microservice::shutdown;
+use data_ingestion_microservice::sink::NdjsonSink;
+use data_ingestion_microservice::spool::TripSpool;
+use data_ingestion_microservice::storage::{
+    MongoTripStore, RedisPointStore, RetryPolicy, SharedRedisConnection, TripStore,
+};
+
+use log::{error, info, warn};
+use mongodb::Client as MongoClient;
+use std::sync::Arc;
 use std::time::Duration;
+use tokio::task::JoinSet;
 
 #[tokio::main(flavor = "multi_thread")]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -20,7 +30,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     info!("🚀 Starting Distributed GPS Route Tracking System - Data Ingestion Microservice");
 
     // Load configuration from environment variables
-    let config = Config::from_env();
+    let config = Config::from_env()?;
 
     // Log configuration (without sensitive data)
     info!("Configuration loaded:");
@@ -28,16 +38,32 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         "  MQTT: {}:{} (topic: {})",
         config.mqtt.broker, config.mqtt.port, config.mqtt.topic
     );
+    if config.ingress.source == IngressSource::Kafka {
+        info!(
+            "  Kafka: {} (topic: {})",
+            config.ingress.kafka_brokers, config.ingress.kafka_topic
+        );
+    }
+    for fallback in &config.mqtt.brokers {
+        info!("  MQTT fallback: {}", fallback);
+    }
     info!("  Redis: {}", config.redis.url);
     info!(
         "  MongoDB: {} (db: {})",
-        config.mongodb.uri.split('@').last().unwrap_or("***"),
+        config.mongodb.uri.rsplit('@').next().unwrap_or("***"),
         config.mongodb.database
     );
     info!(
         "  Route tolerance: {}",
         config.route_simplification.tolerance
     );
+    info!("  Geofences: {}", config.geofence.geofences.len());
+    if !config.ingestion.allow_drivers.is_empty() || !config.ingestion.deny_drivers.is_empty() {
+        info!(
+            "  Driver filter: allow {:?}, deny {:?}",
+            config.ingestion.allow_drivers, config.ingestion.deny_drivers
+        );
+    }
 
     // Validate configuration
     if let Err(e) = config.validate() {
@@ -45,63 +71,220 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         std::process::exit(1);
     }
 
-    // Setup MQTT Client
-    let mut mqtt_options = MqttOptions::new(
-        config.mqtt.client_id.clone(),
-        config.mqtt.broker.clone(),
-        config.mqtt.port,
-    );
-    mqtt_options.set_keep_alive(Duration::from_secs(config.mqtt.keep_alive_secs));
-    let (mqtt_client, mut eventloop) = AsyncClient::new(mqtt_options, 10);
-    mqtt_client
-        .subscribe(&config.mqtt.topic, QoS::AtLeastOnce)
-        .await?;
+    // Setup MQTT ingress; the topics are (re)subscribed on every ConnAck
+    let mqtt_ingress = MqttIngress::new(config.mqtt.clone());
+    let mqtt_client = mqtt_ingress.client();
+    let mut ingress: Box<dyn Ingress> = match config.ingress.source {
+        IngressSource::Mqtt => Box::new(mqtt_ingress),
+        #[cfg(feature = "kafka")]
+        IngressSource::Kafka => {
+            // MQTT stays connected for acknowledgements, events and heartbeats
+            let mut mqtt_ingress = mqtt_ingress.publish_only();
+            tokio::spawn(async move { while mqtt_ingress.recv().await.is_some() {} });
+            Box::new(KafkaIngress::new(
+                &config.ingress,
+                config.mqtt.resimplify_topic.as_deref(),
+            )?)
+        }
+        // Rejected by `Config::validate` above
+        #[cfg(not(feature = "kafka"))]
+        IngressSource::Kafka => unreachable!("Kafka ingress needs the `kafka` feature"),
+    };
 
-    // Setup Redis connection
+    // Setup Redis connection, shared by every task and reopened when it drops
     let redis_client = redis::Client::open(config.redis.url.as_str())?;
+    let redis = SharedRedisConnection::open(redis_client).await?;
+    let redis_retry = RetryPolicy::new(
+        config.redis.retry_attempts,
+        Duration::from_millis(config.redis.retry_base_delay_ms),
+    );
 
     // Setup MongoDB connection
     let mongo_client = MongoClient::with_uri_str(&config.mongodb.uri).await?;
     let db = mongo_client.database(&config.mongodb.database);
     let trips_collection = db.collection(&config.mongodb.collection);
 
-    // Setup route simplifier
-    let route_simplifier = RouteSimplifier::new(config.route_simplification.tolerance)?;
+    // Setup the ingestion service
+    let publisher: Arc<dyn Publisher> = Arc::new(MqttPublisher::new(mqtt_client.clone()));
+    let webhook_url = config.hooks.webhook_url.clone();
+    let webhook_timeout = Duration::from_millis(config.hooks.webhook_timeout_ms);
+    let health_config = config.health.clone();
+    let stdout_sink = config.output.stdout_sink;
+    #[cfg(feature = "postgis")]
+    let postgis_url = config.output.postgis_url.clone();
+    #[cfg(feature = "postgis")]
+    let postgis_table = config.output.postgis_table.clone();
+    let spool_path = config.mongodb.spool_path.clone();
+    let spool_flush_interval = Duration::from_secs(config.mongodb.spool_flush_interval_secs);
+    let api_bind_addr = config.api.bind_addr.clone();
+    let metrics_port = config.metrics.port;
+    let append_batch_size = config.redis.append_batch_size;
+    let trip_batch_size = config.mongodb.batch_size;
+    let trip_flush_interval = Duration::from_millis(config.mongodb.flush_interval_ms);
+    let route_points_refresh = Duration::from_secs(config.metrics.route_points_refresh_secs);
+    let api_config = Arc::new(config.clone());
+    let trip_store: Arc<dyn TripStore> = Arc::new(MongoTripStore::new(trips_collection));
+    let mut service = IngestionService::new(config, trip_store.clone(), publisher.clone())?;
+    service.ensure_indexes().await?;
+    if let Some(url) = webhook_url {
+        info!("Status webhook enabled: {}", url);
+        service = service.with_hook(Arc::new(WebhookHook::new(url, webhook_timeout)?));
+    }
+    if stdout_sink {
+        info!("Writing finalized trips to stdout as NDJSON");
+        service = service.with_sink(Arc::new(NdjsonSink::stdout()));
+    }
+    #[cfg(feature = "postgis")]
+    if let Some(url) = postgis_url {
+        info!(
+            "Inserting finalized trips into PostGIS table {}",
+            postgis_table
+        );
+        service = service.with_sink(Arc::new(PostgisSink::connect(&url, &postgis_table).await?));
+    }
+    if let Some(addr) = api_bind_addr {
+        let listener = tokio::net::TcpListener::bind(&addr).await?;
+        info!("Trip export API listening on {}", addr);
+        let router = api::router(api_config, trip_store.clone(), service.route_points());
+        tokio::spawn(async move {
+            if let Err(e) = api::serve(listener, router).await {
+                error!("Trip export API stopped: {}", e);
+            }
+        });
+    }
+    if metrics_port > 0 {
+        let listener = tokio::net::TcpListener::bind(("0.0.0.0", metrics_port)).await?;
+        info!("Prometheus metrics served on port {}", metrics_port);
+        let metrics_service = service.clone();
+        let router = api::metrics_router(Arc::new(move || metrics_service.render_prometheus()));
+        tokio::spawn(async move {
+            if let Err(e) = api::serve(listener, router).await {
+                error!("Metrics endpoint stopped: {}", e);
+            }
+        });
+    }
+    if let Some(path) = spool_path {
+        info!("Spooling trips to {} while MongoDB is unavailable", path);
+        let spool = Arc::new(TripSpool::new(path));
+        service = service.with_spool(spool.clone());
+        tokio::spawn(spool.run_flusher(trip_store, spool_flush_interval));
+    }
+
+    // Keep the buffered point counts of active routes fresh
+    if !route_points_refresh.is_zero() {
+        let point_store = RedisPointStore::new(redis.clone()).with_retry(redis_retry);
+        tokio::spawn(
+            service
+                .route_points()
+                .run_refresher(point_store, route_points_refresh),
+        );
+    }
+
+    // Append batched points that did not fill a batch in time
+    if append_batch_size > 0 {
+        let point_store = RedisPointStore::new(redis.clone()).with_retry(redis_retry);
+        tokio::spawn(service.clone().run_append_flusher(point_store));
+    }
+
+    // Insert batched trips that did not fill a batch in time
+    if trip_batch_size > 0 {
+        info!("Inserting finished trips in batches of {}", trip_batch_size);
+        tokio::spawn(service.trip_writer().run_flusher(trip_flush_interval));
+    }
+
+    // Report errors suppressed by log sampling
+    tokio::spawn(service.error_log().run_summaries());
+
+    // Start the dead-man's switch
+    if health_config.idle_alert_secs > 0 {
+        let mut watchdog = IdleWatchdog::new(
+            service.health(),
+            Duration::from_secs(health_config.idle_alert_secs),
+        );
+        if let Some(topic) = health_config.idle_alert_topic {
+            watchdog = watchdog.with_alert(publisher.clone(), topic);
+        }
+        tokio::spawn(watchdog.run());
+    }
+
+    // Publish periodic heartbeats until the process is asked to stop
+    if health_config.heartbeat_interval_secs > 0 {
+        let metrics_service = service.clone();
+        let heartbeat = Heartbeat::new(
+            service.health(),
+            Arc::new(move || metrics_service.metrics()),
+            publisher.clone(),
+            &health_config.heartbeat_topic,
+            health_config.instance_id.clone(),
+            Duration::from_secs(health_config.heartbeat_interval_secs),
+        );
+        tokio::spawn(heartbeat.run(shutdown::signal()));
+    }
+
+    // Deal with giant routes left by a long outage before taking new messages
+    let startup_store = RedisPointStore::new(redis.clone()).with_retry(redis_retry);
+    let oversized = service.recover_oversized_routes(&startup_store).await?;
+    if oversized > 0 {
+        warn!("Found {} oversized route lists at startup", oversized);
+    }
 
     info!("Data ingestion microservice started.");
 
-    // Process incoming MQTT events
+    // Process incoming messages until SIGINT/SIGTERM or the ingress closes
+    let mut tasks = JoinSet::new();
+    let shutdown_signal = shutdown::signal();
+    tokio::pin!(shutdown_signal);
     loop {
-        let event = eventloop.poll().await?;
-        match event {
-            Event::Incoming(Packet::Publish(publish)) => {
-                let payload = publish.payload;
-                // Spawn a task to process each message concurrently
-                let mut redis_conn = redis_client.get_async_connection().await?;
-                let trips_collection = trips_collection.clone();
-                let route_simplifier = route_simplifier.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = process_message(
-                        &payload,
-                        &mut redis_conn,
-                        &trips_collection,
-                        &route_simplifier,
-                    )
-                    .await
-                    {
-                        error!("Error processing message: {e}");
-                    }
-                });
+        let message = tokio::select! {
+            _ = &mut shutdown_signal => {
+                info!("Shutting down; no longer accepting messages");
+                break;
             }
-            other => {
-                info!("MQTT event: {:?}", other);
+            message = ingress.recv() => message,
+        };
+        let Some(message) = message else {
+            break;
+        };
+        // Forget tasks that already finished
+        while tasks.try_join_next().is_some() {}
+
+        // Spawn a task to process each message concurrently
+        let point_store = RedisPointStore::new(redis.clone()).with_retry(redis_retry);
+        let service = service.clone();
+        tasks.spawn(async move {
+            if let Err(e) = service.process_delivery(&message, &point_store).await {
+                service
+                    .error_log()
+                    .error(format_args!("Error processing message: {e}"));
             }
-        }
+        });
     }
+
+    // Let in-flight messages finish writing to Redis and MongoDB
+    let drained = shutdown::drain(&mut tasks, ingress.as_mut()).await;
+    info!("Drained {} in-flight message tasks", drained);
+
+    // Keep no points buffered in memory past shutdown
+    if append_batch_size > 0 {
+        let point_store = RedisPointStore::new(redis.clone()).with_retry(redis_retry);
+        service.flush_appends(&point_store).await?;
+    }
+
+    // Nor trips waiting for their batch
+    if trip_batch_size > 0 {
+        let flushed = service.trip_writer().flush().await;
+        info!("Flushed {} buffered trips", flushed);
+    }
+
+    ingress.close().await;
+    info!("Data ingestion microservice stopped.");
+
+    Ok(())
 }
 
 /// Initialize logging with environment variable support
 fn init_logging() {
+    // Logs are written to stderr, keeping stdout free for the NDJSON trip sink.
     // Check if RUST_LOG is set, otherwise default to info level
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "info");
@@ -109,70 +292,3 @@ fn init_logging() {
 
     pretty_env_logger::init();
 }
-
-/// Process an incoming MQTT message payload.
-/// For "in_route": store the JSON in Redis list keyed by driverId:currentRouteId.
-/// For "finished": retrieve the list, simplify it, and store it in MongoDB.
-async fn process_message(
-    payload: &[u8],
-    redis_conn: &mut redis::aio::Connection,
-    trips_collection: &mongodb::Collection<mongodb::bson::Document>,
-    route_simplifier: &RouteSimplifier,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let msg: BusMessage = serde_json::from_slice(payload)?;
-    let key = format!("{}:{}", msg.driver_id, msg.current_route_id);
-
-    match msg.status {
-        BusStatus::InRoute => {
-            // Store the raw JSON of the location in Redis
-            let loc_json = serde_json::to_string(&msg.driver_location)?;
-            let _: () = redis_conn.rpush(&key, loc_json).await?;
-            info!("Stored location for key {} in Redis.", key);
-        }
-        BusStatus::Finished => {
-            // Retrieve all stored points from Redis
-            let points_json: Vec<String> = redis_conn.lrange(&key, 0, -1).await?;
-            if points_json.is_empty() {
-                info!("No stored points for key {}.", key);
-                return Ok(());
-            }
-
-            // Parse the JSON strings into Location structs
-            let mut locations: Vec<Location> = Vec::new();
-            for p in points_json {
-                let loc: Location = serde_json::from_str(&p)?;
-                locations.push(loc);
-            }
-
-            // Simplify the route using the Ramer-Douglas-Peucker algorithm
-            let simplified_locations = route_simplifier.simplify_route(&locations)?;
-
-            info!(
-                "Route {} finished. Original: {} points, Simplified: {} points",
-                key,
-                locations.len(),
-                simplified_locations.len()
-            );
-
-            // Insert the simplified route into the MongoDB trips collection.
-            let trip_doc = doc! {
-                "driverId": msg.driver_id,
-                "currentRouteId": msg.current_route_id,
-                "simplifiedRoute": simplified_locations.iter().map(|loc| {
-                    doc! { "latitude": loc.latitude, "longitude": loc.longitude }
-                }).collect::<Vec<_>>(),
-                "timestamp": msg.timestamp as i64,
-                "originalPointsCount": locations.len() as i32,
-                "simplifiedPointsCount": simplified_locations.len() as i32,
-            };
-            trips_collection.insert_one(trip_doc, None).await?;
-            info!("Stored trip for key {} in MongoDB.", key);
-
-            // Delete the Redis key
-            let _: () = redis_conn.del(&key).await?;
-            info!("Cleared route data for key {} from Redis.", key);
-        }
-    }
-
-    Ok(())
-}
@@ -0,0 +1,343 @@
+use crate::config::MetricsConfig;
+use crate::storage::PointStore;
+use crate::types::{BusMessage, MetricsSnapshot, ServiceMetrics, ServiceResult};
+use log::warn;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Fleet label used when a message names no fleet and its driver id has no prefix
+pub const DEFAULT_FLEET: &str = "default";
+
+/// Fleet a message is counted under: the `fleet` field when present, otherwise
+/// the driver id up to the first `fleet_separator`
+pub fn fleet_of(config: &MetricsConfig, msg: &BusMessage) -> String {
+    if let Some(fleet) = msg.fleet.as_deref().filter(|fleet| !fleet.is_empty()) {
+        return fleet.to_string();
+    }
+
+    match msg.driver_id.split_once(config.fleet_separator.as_str()) {
+        Some((prefix, _)) if !config.fleet_separator.is_empty() && !prefix.is_empty() => {
+            prefix.to_string()
+        }
+        _ => DEFAULT_FLEET.to_string(),
+    }
+}
+
+/// Prometheus name of a counter and how to read it
+type Counter = (&'static str, fn(&MetricsSnapshot) -> u64);
+
+/// Counters exported for every fleet
+const FLEET_COUNTERS: [Counter; 9] = [
+    ("messages_processed", |m| m.messages_processed),
+    ("messages_filtered", |m| m.messages_filtered),
+    ("errors_count", |m| m.errors_count),
+    ("routes_completed", |m| m.routes_completed),
+    ("total_points_processed", |m| m.total_points_processed),
+    ("total_points_simplified", |m| m.total_points_simplified),
+    ("routes_evicted", |m| m.routes_evicted),
+    ("messages_unauthorized", |m| m.messages_unauthorized),
+    ("routes_merged", |m| m.routes_merged),
+];
+
+/// Counters exported for the whole service, besides `FLEET_COUNTERS`
+const SERVICE_COUNTERS: [Counter; 4] = [
+    ("stale_dropped", |m| m.stale_dropped),
+    ("invalid_points", |m| m.invalid_points),
+    ("compression_trips", |m| m.compression_trips),
+    ("compression_drift_alerts", |m| m.compression_drift_alerts),
+];
+
+/// Prometheus name of a gauge and how to read it
+type Gauge = (&'static str, fn(&MetricsSnapshot) -> f64);
+
+/// Gauges exported for the whole service
+const SERVICE_GAUGES: [Gauge; 6] = [
+    ("routes_in_progress", |m| m.routes_in_progress as f64),
+    ("processing_permits_in_use", |m| {
+        m.processing_permits_in_use as f64
+    }),
+    ("compression_ratio", MetricsSnapshot::compression_ratio),
+    ("compression_ratio_ewma", |m| m.compression_ratio_ewma),
+    ("compression_ratio_baseline", |m| {
+        m.compression_ratio_baseline
+    }),
+    ("compression_drifting", |m| {
+        f64::from(u8::from(m.compression_drifting))
+    }),
+];
+
+/// Service counters and gauges in Prometheus text exposition format. With
+/// `fleets`, the `FLEET_COUNTERS` are only exported per fleet, so each metric
+/// name has a single family; their service totals are the sum over fleets.
+pub fn render_prometheus(metrics: &MetricsSnapshot, fleets: Option<&FleetMetrics>) -> String {
+    let mut out = match fleets {
+        Some(fleets) => fleets.render_prometheus(),
+        None => {
+            let mut out = String::new();
+            for (name, value) in FLEET_COUNTERS {
+                let _ = writeln!(out, "# TYPE {} counter\n{} {}", name, name, value(metrics));
+            }
+            out
+        }
+    };
+    for (name, value) in SERVICE_COUNTERS {
+        let _ = writeln!(out, "# TYPE {} counter\n{} {}", name, name, value(metrics));
+    }
+    for (name, value) in SERVICE_GAUGES {
+        let _ = writeln!(out, "# TYPE {} gauge\n{} {}", name, name, value(metrics));
+    }
+    out
+}
+
+/// Per-fleet breakdown of the service counters
+#[derive(Default)]
+pub struct FleetMetrics {
+    fleets: Mutex<HashMap<String, Arc<ServiceMetrics>>>,
+}
+
+impl FleetMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply `update` to the counters of `fleet`, creating them on first use
+    pub fn record(&self, fleet: &str, update: impl FnOnce(&ServiceMetrics)) {
+        let metrics = {
+            let mut fleets = self.fleets.lock().unwrap();
+            match fleets.get(fleet) {
+                Some(metrics) => metrics.clone(),
+                None => fleets.entry(fleet.to_string()).or_default().clone(),
+            }
+        };
+        update(&metrics);
+    }
+
+    /// Snapshot of every fleet's counters, ordered by fleet
+    pub fn snapshot(&self) -> BTreeMap<String, MetricsSnapshot> {
+        self.fleets
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(fleet, metrics)| (fleet.clone(), metrics.snapshot()))
+            .collect()
+    }
+
+    /// Counters in Prometheus text exposition format, one series per fleet
+    pub fn render_prometheus(&self) -> String {
+        let snapshot = self.snapshot();
+        let mut out = String::new();
+        for (name, value) in FLEET_COUNTERS {
+            let _ = writeln!(out, "# TYPE {} counter", name);
+            for (fleet, metrics) in &snapshot {
+                let _ = writeln!(
+                    out,
+                    "{}{{fleet=\"{}\"}} {}",
+                    name,
+                    escape_label(fleet),
+                    value(metrics)
+                );
+            }
+        }
+        out
+    }
+}
+
+/// Estimated bytes stored in Redis by each in-progress route
+#[derive(Default)]
+pub struct RouteMemory {
+    routes: Mutex<HashMap<String, u64>>,
+}
+
+impl RouteMemory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, route: &str, bytes: u64) {
+        self.routes.lock().unwrap().insert(route.to_string(), bytes);
+    }
+
+    /// Forget a route once it is finalized
+    pub fn remove(&self, route: &str) {
+        self.routes.lock().unwrap().remove(route);
+    }
+
+    /// Snapshot of the per-route byte estimates, ordered by route
+    pub fn snapshot(&self) -> BTreeMap<String, u64> {
+        self.routes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(route, bytes)| (route.clone(), *bytes))
+            .collect()
+    }
+
+    /// Byte estimates in Prometheus text exposition format, one gauge per route
+    pub fn render_prometheus(&self) -> String {
+        let mut out = String::from("# TYPE route_stored_bytes gauge\n");
+        for (route, bytes) in self.snapshot() {
+            let _ = writeln!(
+                out,
+                "route_stored_bytes{{route=\"{}\"}} {}",
+                escape_label(&route),
+                bytes
+            );
+        }
+        out
+    }
+}
+
+/// Points buffered in Redis by each in-progress route. Routes are tracked as
+/// points arrive; their lengths are only read from Redis (`LLEN`) by
+/// `refresh`, so a snapshot may lag behind by one refresh interval.
+#[derive(Default)]
+pub struct RoutePointCounts {
+    routes: Mutex<HashMap<String, u64>>,
+}
+
+impl RoutePointCounts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start tracking a route; its length stays at its last refreshed value
+    pub fn track(&self, route: &str) {
+        let mut routes = self.routes.lock().unwrap();
+        if !routes.contains_key(route) {
+            routes.insert(route.to_string(), 0);
+        }
+    }
+
+    /// Forget a route once it is finalized
+    pub fn remove(&self, route: &str) {
+        self.routes.lock().unwrap().remove(route);
+    }
+
+    /// Read the current length of every tracked route from `point_store`
+    pub async fn refresh(&self, point_store: &dyn PointStore) -> ServiceResult<()> {
+        let routes: Vec<String> = self.routes.lock().unwrap().keys().cloned().collect();
+        for route in routes {
+            let len = point_store.len(&route).await? as u64;
+            // The route may have been finalized while its length was read
+            if let Some(count) = self.routes.lock().unwrap().get_mut(&route) {
+                *count = len;
+            }
+        }
+        Ok(())
+    }
+
+    /// Refresh the lengths every `interval`, forever
+    pub async fn run_refresher(self: Arc<Self>, point_store: impl PointStore, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            if let Err(e) = self.refresh(&point_store).await {
+                warn!("Failed to refresh buffered route lengths: {}", e);
+            }
+        }
+    }
+
+    /// Snapshot of the per-route point counts, ordered by route
+    pub fn snapshot(&self) -> BTreeMap<String, u64> {
+        self.routes
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(route, count)| (route.clone(), *count))
+            .collect()
+    }
+}
+
+/// Escape a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(driver_id: &str, fleet: Option<&str>) -> BusMessage {
+        BusMessage {
+            driver_id: driver_id.to_string(),
+            fleet: fleet.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_fleet_from_metadata_or_prefix() {
+        let config = MetricsConfig::default();
+
+        assert_eq!(fleet_of(&config, &message("north-17", None)), "north");
+        assert_eq!(
+            fleet_of(&config, &message("north-17", Some("south"))),
+            "south"
+        );
+        assert_eq!(fleet_of(&config, &message("driver17", None)), DEFAULT_FLEET);
+        assert_eq!(fleet_of(&config, &message("-17", None)), DEFAULT_FLEET);
+
+        let config = MetricsConfig {
+            fleet_separator: String::new(),
+            ..MetricsConfig::default()
+        };
+        assert_eq!(fleet_of(&config, &message("north-17", None)), DEFAULT_FLEET);
+    }
+
+    #[test]
+    fn test_render_prometheus_service_totals() {
+        let counters = ServiceMetrics::default();
+        counters.increment_routes_completed();
+        counters.increment_routes_in_progress();
+        counters.add_points_processed(10);
+        counters.add_points_simplified(4);
+        let metrics = counters.snapshot();
+
+        let text = render_prometheus(&metrics, None);
+        assert!(text.contains("# TYPE routes_completed counter\nroutes_completed 1\n"));
+        assert!(text.contains("# TYPE routes_in_progress gauge\nroutes_in_progress 1\n"));
+        assert!(text.contains("compression_ratio 0.4\n"));
+        assert!(text.contains("compression_drifting 0\n"));
+
+        // Per-fleet counters replace the unlabeled ones
+        let fleets = FleetMetrics::new();
+        fleets.record("north", ServiceMetrics::increment_routes_completed);
+        let text = render_prometheus(&metrics, Some(&fleets));
+        assert_eq!(text.matches("# TYPE routes_completed counter").count(), 1);
+        assert!(text.contains("routes_completed{fleet=\"north\"} 1\n"));
+        assert!(!text.contains("routes_completed 1"));
+        assert!(text.contains("routes_in_progress 1\n"));
+    }
+
+    #[test]
+    fn test_route_memory_gauges() {
+        let memory = RouteMemory::new();
+        memory.set("driver_1:route_1", 120);
+        memory.set("driver_2:route_1", 60);
+        memory.set("driver_1:route_1", 180);
+        memory.remove("driver_2:route_1");
+
+        assert_eq!(memory.snapshot().len(), 1);
+        assert!(memory
+            .render_prometheus()
+            .contains("route_stored_bytes{route=\"driver_1:route_1\"} 180\n"));
+    }
+
+    #[test]
+    fn test_render_prometheus_labels_each_fleet() {
+        let metrics = FleetMetrics::new();
+        metrics.record("north", ServiceMetrics::increment_messages_processed);
+        metrics.record("north", ServiceMetrics::increment_messages_processed);
+        metrics.record("so\"uth", ServiceMetrics::increment_errors);
+
+        let text = metrics.render_prometheus();
+        assert!(text.contains("# TYPE messages_processed counter\n"));
+        assert!(text.contains("messages_processed{fleet=\"north\"} 2\n"));
+        assert!(text.contains("messages_processed{fleet=\"so\\\"uth\"} 0\n"));
+        assert!(text.contains("errors_count{fleet=\"so\\\"uth\"} 1\n"));
+    }
+}
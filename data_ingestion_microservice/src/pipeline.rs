@@ -0,0 +1,412 @@
+use crate::config::{FinalizeConfig, RouteStageKind};
+use crate::geodesy::{destination, haversine_distance};
+use crate::route_simplification::resample_fixed_interval;
+use crate::types::{millis_to_secs, Location, ServiceResult, MILLIS_PER_SEC};
+use log::warn;
+
+/// One preprocessing step applied to a raw route before it is simplified
+pub trait RouteStage: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn apply(&self, locations: &[Location]) -> ServiceResult<Vec<Location>>;
+}
+
+/// Drops points repeating the position of the point before them
+pub struct DedupStage;
+
+impl RouteStage for DedupStage {
+    fn name(&self) -> &'static str {
+        "dedup"
+    }
+
+    fn apply(&self, locations: &[Location]) -> ServiceResult<Vec<Location>> {
+        let mut kept: Vec<Location> = Vec::with_capacity(locations.len());
+        for location in locations {
+            let repeated = kept.last().is_some_and(|last| {
+                last.latitude == location.latitude && last.longitude == location.longitude
+            });
+            if !repeated {
+                kept.push(location.clone());
+            }
+        }
+        Ok(kept)
+    }
+}
+
+/// Drops timestamped points that imply a speed above `max_speed_mps` from the
+/// last kept point (timestamps in milliseconds). Points without a timestamp are
+/// kept.
+pub struct OutlierStage {
+    pub max_speed_mps: f64,
+}
+
+impl RouteStage for OutlierStage {
+    fn name(&self) -> &'static str {
+        "outliers"
+    }
+
+    fn apply(&self, locations: &[Location]) -> ServiceResult<Vec<Location>> {
+        let mut kept: Vec<Location> = Vec::with_capacity(locations.len());
+        let mut last_timed: Option<Location> = None;
+        for location in locations {
+            if let (Some(previous), Some(timestamp)) = (&last_timed, location.timestamp) {
+                let elapsed = timestamp.saturating_sub(previous.timestamp.unwrap_or(0));
+                let distance = haversine_distance(previous, location);
+                if elapsed > 0 && distance / millis_to_secs(elapsed) > self.max_speed_mps {
+                    continue;
+                }
+            }
+            if location.timestamp.is_some() {
+                last_timed = Some(location.clone());
+            }
+            kept.push(location.clone());
+        }
+        Ok(kept)
+    }
+}
+
+/// Drops points whose reported `accuracy` is worse than `max_accuracy`.
+/// Points without an accuracy are kept.
+pub struct AccuracyStage {
+    pub max_accuracy: f64,
+}
+
+impl RouteStage for AccuracyStage {
+    fn name(&self) -> &'static str {
+        "accuracy"
+    }
+
+    fn apply(&self, locations: &[Location]) -> ServiceResult<Vec<Location>> {
+        Ok(locations
+            .iter()
+            .filter(|loc| {
+                !loc.accuracy
+                    .is_some_and(|accuracy| accuracy > self.max_accuracy)
+            })
+            .cloned()
+            .collect())
+    }
+}
+
+/// Replaces each interior position with the mean of the `window` points
+/// centered on it; the endpoints are left in place
+pub struct SmoothingStage {
+    pub window: usize,
+}
+
+impl RouteStage for SmoothingStage {
+    fn name(&self) -> &'static str {
+        "smooth"
+    }
+
+    fn apply(&self, locations: &[Location]) -> ServiceResult<Vec<Location>> {
+        let half = self.window / 2;
+        let last = locations.len().saturating_sub(1);
+        Ok(locations
+            .iter()
+            .enumerate()
+            .map(|(i, location)| {
+                let reach = half.min(i).min(last - i);
+                if reach == 0 {
+                    return location.clone();
+                }
+                let neighbours = &locations[i - reach..=i + reach];
+                let count = neighbours.len() as f64;
+                Location {
+                    latitude: neighbours.iter().map(|loc| loc.latitude).sum::<f64>() / count,
+                    longitude: neighbours.iter().map(|loc| loc.longitude).sum::<f64>() / count,
+                    ..location.clone()
+                }
+            })
+            .collect())
+    }
+}
+
+/// Resamples the route to a fixed interval. Routes that cannot be resampled,
+/// such as ones without timestamps, pass through unchanged.
+pub struct ResampleStage {
+    pub interval_secs: u64,
+}
+
+impl RouteStage for ResampleStage {
+    fn name(&self) -> &'static str {
+        "resample"
+    }
+
+    fn apply(&self, locations: &[Location]) -> ServiceResult<Vec<Location>> {
+        match resample_fixed_interval(locations, self.interval_secs) {
+            Ok(resampled) => Ok(resampled),
+            Err(e) => {
+                warn!("Keeping route at its original sampling: {}", e);
+                Ok(locations.to_vec())
+            }
+        }
+    }
+}
+
+/// Fills gaps between timestamped fixes longer than `interval_secs` (and at most
+/// `max_gap_secs`) with one point every `interval_secs`, projected from the
+/// speed and heading of the fix before the gap. The projection drifts, so each
+/// point is pulled toward the fix after the gap in proportion to the elapsed
+/// time, keeping the route continuous. Filled points are flagged
+/// `dead_reckoned`; gaps after a fix without speed or heading are left alone.
+/// Timestamps are in milliseconds.
+pub struct DeadReckoningStage {
+    pub interval_secs: u64,
+    pub max_gap_secs: u64,
+}
+
+impl DeadReckoningStage {
+    fn fill(&self, from: &Location, to: &Location, filled: &mut Vec<Location>) {
+        let (Some(start), Some(end), Some(speed), Some(heading)) =
+            (from.timestamp, to.timestamp, from.speed, from.heading)
+        else {
+            return;
+        };
+        let interval_ms = self.interval_secs.saturating_mul(MILLIS_PER_SEC);
+        let gap = end.saturating_sub(start);
+        if gap <= interval_ms || gap > self.max_gap_secs.saturating_mul(MILLIS_PER_SEC) {
+            return;
+        }
+        let projected_end = destination(from, heading, speed * millis_to_secs(gap));
+        let lat_error = to.latitude - projected_end.latitude;
+        let lon_error = to.longitude - projected_end.longitude;
+
+        let mut timestamp = start + interval_ms;
+        while timestamp < end {
+            let elapsed = timestamp - start;
+            let fraction = elapsed as f64 / gap as f64;
+            let reckoned = destination(from, heading, speed * millis_to_secs(elapsed));
+            filled.push(Location {
+                timestamp: Some(timestamp),
+                speed: from.speed,
+                heading: from.heading,
+                dead_reckoned: true,
+                ..Location::new(
+                    reckoned.latitude + lat_error * fraction,
+                    reckoned.longitude + lon_error * fraction,
+                )
+            });
+            timestamp += interval_ms;
+        }
+    }
+}
+
+impl RouteStage for DeadReckoningStage {
+    fn name(&self) -> &'static str {
+        "dead_reckoning"
+    }
+
+    fn apply(&self, locations: &[Location]) -> ServiceResult<Vec<Location>> {
+        let mut filled = Vec::with_capacity(locations.len());
+        for pair in locations.windows(2) {
+            filled.push(pair[0].clone());
+            self.fill(&pair[0], &pair[1], &mut filled);
+        }
+        filled.extend(locations.last().cloned());
+        Ok(filled)
+    }
+}
+
+/// Ordered list of stages a route goes through on finalize
+#[derive(Default)]
+pub struct RoutePipeline {
+    stages: Vec<Box<dyn RouteStage>>,
+}
+
+impl RoutePipeline {
+    pub fn new(stages: Vec<Box<dyn RouteStage>>) -> Self {
+        Self { stages }
+    }
+
+    /// Build the stages listed in `FINALIZE_STAGES`, in order
+    pub fn from_config(config: &FinalizeConfig) -> Self {
+        Self::new(
+            config
+                .stages
+                .iter()
+                .map(|kind| -> Box<dyn RouteStage> {
+                    match kind {
+                        RouteStageKind::Dedup => Box::new(DedupStage),
+                        RouteStageKind::Outliers => Box::new(OutlierStage {
+                            max_speed_mps: config.outlier_max_speed_mps,
+                        }),
+                        RouteStageKind::Smooth => Box::new(SmoothingStage {
+                            window: config.smoothing_window,
+                        }),
+                        RouteStageKind::Resample => Box::new(ResampleStage {
+                            interval_secs: config.resample_interval_secs,
+                        }),
+                        RouteStageKind::Accuracy => Box::new(AccuracyStage {
+                            max_accuracy: config.max_accuracy,
+                        }),
+                        RouteStageKind::DeadReckoning => Box::new(DeadReckoningStage {
+                            interval_secs: config.dead_reckoning_interval_secs,
+                            max_gap_secs: config.dead_reckoning_max_gap_secs,
+                        }),
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Names of the stages, in the order they run
+    pub fn stage_names(&self) -> Vec<&'static str> {
+        self.stages.iter().map(|stage| stage.name()).collect()
+    }
+
+    pub fn apply(&self, locations: &[Location]) -> ServiceResult<Vec<Location>> {
+        let mut current = locations.to_vec();
+        for stage in &self.stages {
+            current = stage.apply(&current)?;
+        }
+        Ok(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn timed(latitude: f64, longitude: f64, timestamp: u64) -> Location {
+        Location {
+            timestamp: Some(timestamp),
+            ..Location::new(latitude, longitude)
+        }
+    }
+
+    fn pipeline(stages: &[RouteStageKind]) -> RoutePipeline {
+        RoutePipeline::from_config(&FinalizeConfig {
+            stages: stages.to_vec(),
+            ..FinalizeConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_stage_order_changes_output() {
+        // A parked stretch repeating one fix, then the drive resumes
+        let route = vec![
+            timed(0.0, 0.0, 0),
+            timed(0.0, 0.001, 10),
+            timed(0.0, 0.001, 20),
+            timed(0.0, 0.001, 30),
+            timed(0.0, 0.002, 40),
+            timed(0.0, 0.003, 50),
+        ];
+
+        let dedup_first = pipeline(&[RouteStageKind::Dedup, RouteStageKind::Smooth]);
+        let smooth_first = pipeline(&[RouteStageKind::Smooth, RouteStageKind::Dedup]);
+        assert_eq!(dedup_first.stage_names(), vec!["dedup", "smooth"]);
+
+        let a = dedup_first.apply(&route).unwrap();
+        let b = smooth_first.apply(&route).unwrap();
+        assert_ne!(a, b);
+        // Deduplicated first, the route is an evenly spaced line smoothing keeps
+        assert_eq!(a.len(), 4);
+        assert!((a[1].longitude - 0.001).abs() < 1e-12);
+        // Smoothed first, the parked fixes drift apart and all survive
+        assert_eq!(b.len(), 6);
+    }
+
+    #[test]
+    fn test_outlier_stage_drops_teleports() {
+        // One second apart, timestamped in milliseconds: ~11 m/s, then a
+        // 5.5 km jump
+        let route = vec![
+            timed(0.0, 0.0, 0),
+            timed(0.0, 0.0001, 1000),
+            timed(0.0, 0.05, 2000),
+            timed(0.0, 0.0002, 3000),
+        ];
+
+        let kept = OutlierStage {
+            max_speed_mps: 70.0,
+        }
+        .apply(&route)
+        .unwrap();
+        assert_eq!(kept.len(), 3);
+        assert!(kept.iter().all(|loc| loc.longitude < 0.001));
+    }
+
+    #[test]
+    fn test_accuracy_stage_drops_poor_fixes() {
+        let fix = |longitude: f64, accuracy: Option<f64>| Location {
+            accuracy,
+            ..Location::new(0.0, longitude)
+        };
+        let route = vec![
+            fix(0.0, Some(3.0)),
+            fix(0.001, Some(45.0)),
+            fix(0.002, None),
+            fix(0.003, Some(20.0)),
+            fix(0.004, Some(20.5)),
+        ];
+
+        let kept = pipeline(&[RouteStageKind::Accuracy]).apply(&route).unwrap();
+        let longitudes: Vec<f64> = kept.iter().map(|loc| loc.longitude).collect();
+        assert_eq!(longitudes, vec![0.0, 0.002, 0.003]);
+    }
+
+    #[test]
+    fn test_dead_reckoning_fills_short_gaps() {
+        let moving = |longitude: f64, timestamp: u64, heading: f64| Location {
+            speed: Some(10.0),
+            heading: Some(heading),
+            ..timed(0.0, longitude, timestamp)
+        };
+        // 10 m/s east: the next fix is 300 m away after a 30 s gap (in ms)
+        let next_fix = destination(&Location::new(0.0, 0.0), 90.0, 300.0).longitude;
+        let route = vec![moving(0.0, 0, 90.0), moving(next_fix, 30_000, 90.0)];
+        let stage = DeadReckoningStage {
+            interval_secs: 10,
+            max_gap_secs: 60,
+        };
+
+        let filled = stage.apply(&route).unwrap();
+        assert_eq!(filled.len(), 4);
+        assert_eq!(filled[0], route[0]);
+        assert_eq!(filled[3], route[1]);
+        for (point, meters) in filled[1..3].iter().zip([100.0, 200.0]) {
+            assert!(point.dead_reckoned);
+            assert!(point.latitude.abs() < 1e-9);
+            assert!((haversine_distance(&route[0], point) - meters).abs() < 1e-3);
+        }
+        assert_eq!(filled[1].timestamp, Some(10_000));
+
+        // Reckoned due north, the points bend toward the fix to the east
+        let turning = vec![moving(0.0, 0, 0.0), moving(next_fix, 30_000, 0.0)];
+        let filled = stage.apply(&turning).unwrap();
+        assert_eq!(filled.len(), 4);
+        assert!(filled[1].latitude > 0.0);
+        assert!(filled[1].longitude > 0.0 && filled[2].longitude > filled[1].longitude);
+        assert!(filled[2].longitude < next_fix);
+    }
+
+    #[test]
+    fn test_dead_reckoning_skips_unusable_gaps() {
+        let stage = DeadReckoningStage {
+            interval_secs: 5,
+            max_gap_secs: 60,
+        };
+        let with_motion = |timestamp: u64| Location {
+            speed: Some(10.0),
+            heading: Some(90.0),
+            ..timed(0.0, 0.0, timestamp)
+        };
+
+        // Too long, no heading, and no longer than the interval
+        let too_long = vec![with_motion(0), timed(0.0, 0.01, 120_000)];
+        let no_heading = vec![timed(0.0, 0.0, 0), timed(0.0, 0.001, 30_000)];
+        let short = vec![with_motion(0), timed(0.0, 0.0005, 5_000)];
+        for route in [too_long, no_heading, short] {
+            assert_eq!(stage.apply(&route).unwrap(), route);
+        }
+        assert!(stage.apply(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_empty_pipeline_is_identity() {
+        let route = vec![timed(0.0, 0.0, 0), timed(0.0, 0.0, 1)];
+        assert_eq!(RoutePipeline::default().apply(&route).unwrap(), route);
+    }
+}
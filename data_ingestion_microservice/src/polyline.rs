@@ -0,0 +1,139 @@
+use crate::types::{Location, ServiceError, ServiceResult};
+
+/// Encode a route using Google's encoded polyline algorithm.
+/// `precision` is the number of decimal digits kept (5 for Google Maps, 6 for OSRM).
+pub fn encode_polyline(locations: &[Location], precision: u32) -> String {
+    let factor = 10f64.powi(precision as i32);
+    let mut encoded = String::new();
+    let mut previous = (0i64, 0i64);
+
+    for location in locations {
+        let latitude = (location.latitude * factor).round() as i64;
+        let longitude = (location.longitude * factor).round() as i64;
+
+        encode_value(latitude - previous.0, &mut encoded);
+        encode_value(longitude - previous.1, &mut encoded);
+        previous = (latitude, longitude);
+    }
+
+    encoded
+}
+
+/// Decode a Google encoded polyline produced with the given `precision`
+pub fn decode_polyline(encoded: &str, precision: u32) -> ServiceResult<Vec<Location>> {
+    let factor = 10f64.powi(precision as i32);
+    let mut bytes = encoded.bytes();
+    let mut locations = Vec::new();
+    let mut current = (0i64, 0i64);
+
+    while let Some(latitude) = decode_value(&mut bytes)? {
+        let longitude = decode_value(&mut bytes)?.ok_or_else(|| {
+            ServiceError::Validation("Polyline ends with an unpaired latitude".to_string())
+        })?;
+
+        current = (current.0 + latitude, current.1 + longitude);
+        locations.push(Location::new(
+            current.0 as f64 / factor,
+            current.1 as f64 / factor,
+        ));
+    }
+
+    Ok(locations)
+}
+
+fn encode_value(value: i64, output: &mut String) {
+    let mut value = if value < 0 { !(value << 1) } else { value << 1 };
+
+    while value >= 0x20 {
+        output.push((((value & 0x1f) | 0x20) as u8 + 63) as char);
+        value >>= 5;
+    }
+    output.push((value as u8 + 63) as char);
+}
+
+fn decode_value(bytes: &mut impl Iterator<Item = u8>) -> ServiceResult<Option<i64>> {
+    let mut result = 0i64;
+    let mut shift = 0;
+
+    for byte in bytes {
+        if !(63..=126).contains(&byte) || shift > 60 {
+            return Err(ServiceError::Validation(format!(
+                "Invalid polyline character '{}'",
+                byte as char
+            )));
+        }
+
+        let chunk = (byte - 63) as i64;
+        result |= (chunk & 0x1f) << shift;
+        shift += 5;
+
+        if chunk < 0x20 {
+            let value = if result & 1 != 0 {
+                !(result >> 1)
+            } else {
+                result >> 1
+            };
+            return Ok(Some(value));
+        }
+    }
+
+    if shift == 0 {
+        Ok(None)
+    } else {
+        Err(ServiceError::Validation(
+            "Polyline ends in the middle of a value".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference_route() -> Vec<Location> {
+        vec![
+            Location::new(38.5, -120.2),
+            Location::new(40.7, -120.95),
+            Location::new(43.252, -126.453),
+        ]
+    }
+
+    #[test]
+    fn test_encode_reference_polyline() {
+        // Reference values from Google's polyline algorithm documentation
+        assert_eq!(
+            encode_polyline(&reference_route(), 5),
+            "_p~iF~ps|U_ulLnnqC_mqNvxq`@"
+        );
+        assert_eq!(encode_polyline(&[], 5), "");
+    }
+
+    #[test]
+    fn test_decode_reference_polyline() {
+        let decoded = decode_polyline("_p~iF~ps|U_ulLnnqC_mqNvxq`@", 5).unwrap();
+        assert_eq!(decoded, reference_route());
+    }
+
+    #[test]
+    fn test_round_trip_with_precision_6() {
+        let route = vec![
+            Location::new(6.244203, -75.581211),
+            Location::new(6.251840, -75.563591),
+            Location::new(4.710989, -74.072092),
+        ];
+
+        let encoded = encode_polyline(&route, 6);
+        let decoded = decode_polyline(&encoded, 6).unwrap();
+
+        for (original, decoded) in route.iter().zip(&decoded) {
+            assert!((original.latitude - decoded.latitude).abs() < 1e-6);
+            assert!((original.longitude - decoded.longitude).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn test_decode_rejects_truncated_input() {
+        assert!(decode_polyline("_p~iF", 5).is_err());
+        assert!(decode_polyline("_p~i", 5).is_err());
+    }
+}
@@ -0,0 +1,339 @@
+use crate::document::location_from_document;
+use crate::fields;
+#[cfg(feature = "postgis")]
+use crate::sink::TripSink;
+use crate::types::{Location, ServiceError, ServiceResult};
+#[cfg(feature = "postgis")]
+use async_trait::async_trait;
+#[cfg(feature = "postgis")]
+use log::{error, warn};
+use mongodb::bson::{Bson, Document};
+#[cfg(feature = "postgis")]
+use tokio::sync::Mutex;
+#[cfg(feature = "postgis")]
+use tokio_postgres::{Client, NoTls, Statement};
+
+/// `TripSink` inserting each trip into a PostGIS table, with its simplified
+/// route as a `geometry(LineString, 4326)`. A connection closed by a database
+/// restart is reopened on the next write.
+#[cfg(feature = "postgis")]
+pub struct PostgisSink {
+    url: String,
+    table: String,
+    connection: Mutex<PostgisConnection>,
+}
+
+/// An open client with the insert prepared on it
+#[cfg(feature = "postgis")]
+struct PostgisConnection {
+    client: Client,
+    insert: Statement,
+}
+
+#[cfg(feature = "postgis")]
+impl PostgisConnection {
+    /// Connect to `url`, create `table` and its index when absent and
+    /// prepare the insert
+    async fn open(url: &str, table: &str) -> ServiceResult<Self> {
+        let (client, connection) = tokio_postgres::connect(url, NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                error!("PostGIS connection closed: {}", e);
+            }
+        });
+        for statement in create_table_statements(table)? {
+            client.batch_execute(&statement).await?;
+        }
+        let insert = client.prepare(&insert_statement(table)?).await?;
+        Ok(Self { client, insert })
+    }
+
+    async fn insert(&self, row: &TripRow) -> Result<u64, tokio_postgres::Error> {
+        self.client
+            .execute(
+                &self.insert,
+                &[
+                    &row.driver_id,
+                    &row.route_id,
+                    &row.timestamp,
+                    &row.route_wkt,
+                ],
+            )
+            .await
+    }
+}
+
+#[cfg(feature = "postgis")]
+impl PostgisSink {
+    /// Connect to `url`, create `table` and its index when absent and
+    /// prepare the insert
+    pub async fn connect(url: &str, table: &str) -> ServiceResult<Self> {
+        Ok(Self {
+            url: url.to_string(),
+            table: table.to_string(),
+            connection: Mutex::new(PostgisConnection::open(url, table).await?),
+        })
+    }
+}
+
+#[cfg(feature = "postgis")]
+#[async_trait]
+impl TripSink for PostgisSink {
+    async fn write_trip(&self, trip: &Document) -> ServiceResult<()> {
+        let row = TripRow::from_trip(trip)?;
+        let mut connection = self.connection.lock().await;
+        if connection.client.is_closed() {
+            warn!("PostGIS connection lost; reconnecting");
+            *connection = PostgisConnection::open(&self.url, &self.table).await?;
+        }
+        match connection.insert(&row).await {
+            Ok(_) => Ok(()),
+            // Closed during the insert: reconnect and retry once
+            Err(e) if connection.client.is_closed() => {
+                warn!("PostGIS connection lost during insert ({}); retrying", e);
+                *connection = PostgisConnection::open(&self.url, &self.table).await?;
+                connection.insert(&row).await?;
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Statements creating the trips table and its spatial index when absent
+pub fn create_table_statements(table: &str) -> ServiceResult<[String; 2]> {
+    let table = validate_identifier(table)?;
+    Ok([
+        format!(
+            "CREATE TABLE IF NOT EXISTS {table} (\
+             id BIGSERIAL PRIMARY KEY, \
+             driver_id TEXT NOT NULL, \
+             route_id TEXT NOT NULL, \
+             timestamp BIGINT NOT NULL, \
+             route geometry(LineString, 4326) NOT NULL)"
+        ),
+        format!("CREATE INDEX IF NOT EXISTS {table}_route_idx ON {table} USING GIST (route)"),
+    ])
+}
+
+/// Parameterized insert of one `TripRow`, in field order
+pub fn insert_statement(table: &str) -> ServiceResult<String> {
+    let table = validate_identifier(table)?;
+    Ok(format!(
+        "INSERT INTO {table} (driver_id, route_id, timestamp, route) \
+         VALUES ($1, $2, $3, ST_GeomFromText($4, 4326))"
+    ))
+}
+
+/// Columns of a finalized trip as inserted into PostGIS
+#[derive(Debug, Clone, PartialEq)]
+pub struct TripRow {
+    pub driver_id: String,
+    pub route_id: String,
+    pub timestamp: i64,
+    /// Simplified route as WKT, in longitude/latitude order
+    pub route_wkt: String,
+}
+
+impl TripRow {
+    pub fn from_trip(trip: &Document) -> ServiceResult<Self> {
+        let field = |name: &str| {
+            trip.get_str(name)
+                .map(str::to_string)
+                .map_err(|e| ServiceError::Validation(format!("Trip has no {}: {}", name, e)))
+        };
+        let route = trip
+            .get_array(fields::SIMPLIFIED_ROUTE)
+            .map_err(|e| {
+                ServiceError::Validation(format!("Trip has no {}: {}", fields::SIMPLIFIED_ROUTE, e))
+            })?
+            .iter()
+            .map(|point| match point {
+                Bson::Document(point) => location_from_document(point),
+                other => Err(ServiceError::Validation(format!(
+                    "Invalid route point {:?}",
+                    other
+                ))),
+            })
+            .collect::<ServiceResult<Vec<_>>>()?;
+
+        Ok(Self {
+            driver_id: field(fields::DRIVER_ID)?,
+            route_id: field(fields::CURRENT_ROUTE_ID)?,
+            timestamp: trip.get_i64(fields::TIMESTAMP).map_err(|e| {
+                ServiceError::Validation(format!("Trip has no {}: {}", fields::TIMESTAMP, e))
+            })?,
+            route_wkt: line_string_wkt(&route)?,
+        })
+    }
+}
+
+/// WKT `LINESTRING` of a route. A single point is repeated, since a
+/// LineString needs two.
+pub fn line_string_wkt(locations: &[Location]) -> ServiceResult<String> {
+    let points: Vec<String> = locations
+        .iter()
+        .map(|loc| format!("{} {}", loc.longitude, loc.latitude))
+        .collect();
+    match points.as_slice() {
+        [] => Err(ServiceError::Validation(
+            "Cannot build a LineString from an empty route".to_string(),
+        )),
+        [point] => Ok(format!("LINESTRING({point}, {point})")),
+        _ => Ok(format!("LINESTRING({})", points.join(", "))),
+    }
+}
+
+/// Table names are interpolated into SQL, so only plain identifiers are allowed
+fn validate_identifier(name: &str) -> ServiceResult<&str> {
+    let mut chars = name.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(name)
+    } else {
+        Err(ServiceError::Validation(format!(
+            "Invalid PostGIS table name: {}",
+            name
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+
+    #[test]
+    fn test_trip_row_from_trip() {
+        let trip = doc! {
+            fields::DRIVER_ID: "driver_1",
+            fields::CURRENT_ROUTE_ID: "route_1",
+            fields::SIMPLIFIED_ROUTE: [
+                { fields::LATITUDE: 6.25, fields::LONGITUDE: -75.58 },
+                { fields::LATITUDE: 6.26, fields::LONGITUDE: -75.57 },
+            ],
+            fields::TIMESTAMP: 1_700_000_000i64,
+        };
+
+        let row = TripRow::from_trip(&trip).unwrap();
+        assert_eq!(row.driver_id, "driver_1");
+        assert_eq!(row.route_id, "route_1");
+        assert_eq!(row.timestamp, 1_700_000_000);
+        assert_eq!(row.route_wkt, "LINESTRING(-75.58 6.25, -75.57 6.26)");
+
+        assert!(TripRow::from_trip(&doc! { fields::DRIVER_ID: "driver_1" }).is_err());
+    }
+
+    #[test]
+    fn test_single_point_and_empty_routes() {
+        assert_eq!(
+            line_string_wkt(&[Location::new(1.5, 2.5)]).unwrap(),
+            "LINESTRING(2.5 1.5, 2.5 1.5)"
+        );
+        assert!(line_string_wkt(&[]).is_err());
+    }
+
+    #[test]
+    fn test_statements_reject_unsafe_table_names() {
+        let [create, index] = create_table_statements("trips").unwrap();
+        assert!(create.starts_with("CREATE TABLE IF NOT EXISTS trips ("));
+        assert!(create.contains("route geometry(LineString, 4326)"));
+        assert!(index.contains("ON trips USING GIST (route)"));
+        assert!(insert_statement("trips_2024").is_ok());
+
+        assert!(insert_statement("trips; DROP TABLE trips").is_err());
+        assert!(create_table_statements("1trips").is_err());
+        assert!(create_table_statements("").is_err());
+    }
+
+    /// Needs a database with the PostGIS extension at `POSTGIS_TEST_URL`
+    #[cfg(feature = "postgis-tests")]
+    #[tokio::test]
+    async fn test_postgis_sink_round_trip() {
+        let url = std::env::var("POSTGIS_TEST_URL")
+            .expect("POSTGIS_TEST_URL must point at a PostGIS database");
+        let table = format!("trips_test_{}", std::process::id());
+        let sink = PostgisSink::connect(&url, &table).await.unwrap();
+
+        sink.write_trip(&doc! {
+            fields::DRIVER_ID: "driver_1",
+            fields::CURRENT_ROUTE_ID: "route_1",
+            fields::SIMPLIFIED_ROUTE: [
+                { fields::LATITUDE: 6.25, fields::LONGITUDE: -75.58 },
+                { fields::LATITUDE: 6.26, fields::LONGITUDE: -75.57 },
+            ],
+            fields::TIMESTAMP: 1_700_000_000_000i64,
+        })
+        .await
+        .unwrap();
+
+        let connection = sink.connection.lock().await;
+        let row = connection
+            .client
+            .query_one(
+                &format!("SELECT driver_id, route_id, timestamp, ST_AsText(route) FROM {table}"),
+                &[],
+            )
+            .await
+            .unwrap();
+        connection
+            .client
+            .batch_execute(&format!("DROP TABLE {table}"))
+            .await
+            .unwrap();
+        assert_eq!(row.get::<_, String>(0), "driver_1");
+        assert_eq!(row.get::<_, String>(1), "route_1");
+        assert_eq!(row.get::<_, i64>(2), 1_700_000_000_000);
+        assert_eq!(
+            row.get::<_, String>(3),
+            "LINESTRING(-75.58 6.25,-75.57 6.26)"
+        );
+    }
+
+    /// Needs a database with the PostGIS extension at `POSTGIS_TEST_URL`
+    #[cfg(feature = "postgis-tests")]
+    #[tokio::test]
+    async fn test_postgis_sink_reconnects_after_connection_loss() {
+        let url = std::env::var("POSTGIS_TEST_URL")
+            .expect("POSTGIS_TEST_URL must point at a PostGIS database");
+        let table = format!("trips_reconnect_{}", std::process::id());
+        let sink = PostgisSink::connect(&url, &table).await.unwrap();
+        let trip = doc! {
+            fields::DRIVER_ID: "driver_1",
+            fields::CURRENT_ROUTE_ID: "route_1",
+            fields::SIMPLIFIED_ROUTE: [
+                { fields::LATITUDE: 6.25, fields::LONGITUDE: -75.58 },
+                { fields::LATITUDE: 6.26, fields::LONGITUDE: -75.57 },
+            ],
+            fields::TIMESTAMP: 1_700_000_000_000i64,
+        };
+        sink.write_trip(&trip).await.unwrap();
+
+        // As a database restart would, end the sink's own session
+        let _ = sink
+            .connection
+            .lock()
+            .await
+            .client
+            .batch_execute("SELECT pg_terminate_backend(pg_backend_pid())")
+            .await;
+        sink.write_trip(&trip).await.unwrap();
+
+        let connection = sink.connection.lock().await;
+        let count: i64 = connection
+            .client
+            .query_one(&format!("SELECT COUNT(*) FROM {table}"), &[])
+            .await
+            .unwrap()
+            .get(0);
+        connection
+            .client
+            .batch_execute(&format!("DROP TABLE {table}"))
+            .await
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+}
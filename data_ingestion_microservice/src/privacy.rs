@@ -0,0 +1,58 @@
+use crate::config::PrivacyConfig;
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+/// Salted SHA-256 of a driver id, hex encoded.
+/// Deterministic for a given salt, so trips can still be queried by hashing the raw id.
+pub fn hash_driver_id(driver_id: &str, salt: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(salt.as_bytes())
+        .chain_update(b":")
+        .chain_update(driver_id.as_bytes())
+        .finalize();
+
+    digest
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{:02x}", byte);
+            hex
+        })
+}
+
+/// Driver id as written to persistent storage: hashed when pseudonymization is
+/// enabled, unchanged otherwise. Use it to build queries by raw driver id.
+pub fn stored_driver_id(privacy: &PrivacyConfig, driver_id: &str) -> String {
+    if privacy.pseudonymize_drivers {
+        hash_driver_id(driver_id, &privacy.driver_id_salt)
+    } else {
+        driver_id.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_driver_id_is_deterministic() {
+        let hash = hash_driver_id("driver_1", "pepper");
+
+        assert_eq!(hash.len(), 64);
+        assert_eq!(hash, hash_driver_id("driver_1", "pepper"));
+        assert_ne!(hash, hash_driver_id("driver_1", "salt"));
+        assert_ne!(hash, hash_driver_id("driver_2", "pepper"));
+    }
+
+    #[test]
+    fn test_stored_driver_id() {
+        let mut privacy = PrivacyConfig::default();
+        assert_eq!(stored_driver_id(&privacy, "driver_1"), "driver_1");
+
+        privacy.pseudonymize_drivers = true;
+        privacy.driver_id_salt = "pepper".to_string();
+        assert_eq!(
+            stored_driver_id(&privacy, "driver_1"),
+            hash_driver_id("driver_1", "pepper")
+        );
+    }
+}
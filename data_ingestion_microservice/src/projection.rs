@@ -0,0 +1,180 @@
+use crate::types::{Location, ServiceError, ServiceResult};
+use std::f64::consts::FRAC_PI_4;
+
+/// WGS84 semi-major axis in meters
+const WGS84_A: f64 = 6_378_137.0;
+/// WGS84 flattening
+const WGS84_F: f64 = 1.0 / 298.257_223_563;
+/// Scale factor on the UTM central meridian
+const UTM_K0: f64 = 0.9996;
+/// Latitude beyond which Web Mercator is undefined in practice; the square map
+/// ends there
+const WEB_MERCATOR_MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// Projected coordinate system a trip route can be stored in, besides WGS84
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Projection {
+    /// EPSG:3857, the spherical Mercator used by web map tiles
+    WebMercator,
+    /// EPSG:326xx (north) and EPSG:327xx (south), WGS84 / UTM zone `zone`
+    Utm { zone: u8, north: bool },
+}
+
+impl Projection {
+    /// Projection for a supported EPSG code
+    pub fn from_epsg(code: u32) -> ServiceResult<Self> {
+        match code {
+            3857 => Ok(Projection::WebMercator),
+            32601..=32660 => Ok(Projection::Utm {
+                zone: (code - 32600) as u8,
+                north: true,
+            }),
+            32701..=32760 => Ok(Projection::Utm {
+                zone: (code - 32700) as u8,
+                north: false,
+            }),
+            _ => Err(ServiceError::Validation(format!(
+                "Unsupported projection EPSG:{}",
+                code
+            ))),
+        }
+    }
+
+    pub fn epsg(&self) -> u32 {
+        match self {
+            Projection::WebMercator => 3857,
+            Projection::Utm { zone, north: true } => 32600 + u32::from(*zone),
+            Projection::Utm { zone, north: false } => 32700 + u32::from(*zone),
+        }
+    }
+
+    /// Easting and northing in meters of a WGS84 location
+    pub fn project(&self, location: &Location) -> (f64, f64) {
+        match self {
+            Projection::WebMercator => web_mercator(location),
+            Projection::Utm { zone, north } => utm(location, *zone, *north),
+        }
+    }
+}
+
+/// Web Mercator treats the WGS84 coordinates as if they were on a sphere of
+/// the semi-major axis. Latitudes past the edge of the map are clamped to it.
+fn web_mercator(location: &Location) -> (f64, f64) {
+    let latitude = location
+        .latitude
+        .clamp(-WEB_MERCATOR_MAX_LATITUDE, WEB_MERCATOR_MAX_LATITUDE)
+        .to_radians();
+    let x = WGS84_A * location.longitude.to_radians();
+    let y = WGS84_A * (FRAC_PI_4 + latitude / 2.0).tan().ln();
+    (x, y)
+}
+
+/// Transverse Mercator on the WGS84 ellipsoid, using the series expansion of
+/// Snyder's "Map Projections: A Working Manual" (accurate to about a millimeter
+/// within the zone)
+fn utm(location: &Location, zone: u8, north: bool) -> (f64, f64) {
+    let e2 = WGS84_F * (2.0 - WGS84_F);
+    let e4 = e2 * e2;
+    let e6 = e4 * e2;
+    let ep2 = e2 / (1.0 - e2);
+
+    let phi = location.latitude.to_radians();
+    let central_meridian = f64::from(zone) * 6.0 - 183.0;
+    let (sin_phi, cos_phi) = phi.sin_cos();
+    let tan_phi = phi.tan();
+
+    let n = WGS84_A / (1.0 - e2 * sin_phi * sin_phi).sqrt();
+    let t = tan_phi * tan_phi;
+    let c = ep2 * cos_phi * cos_phi;
+    let a = cos_phi * (location.longitude - central_meridian).to_radians();
+    let m = WGS84_A
+        * ((1.0 - e2 / 4.0 - 3.0 * e4 / 64.0 - 5.0 * e6 / 256.0) * phi
+            - (3.0 * e2 / 8.0 + 3.0 * e4 / 32.0 + 45.0 * e6 / 1024.0) * (2.0 * phi).sin()
+            + (15.0 * e4 / 256.0 + 45.0 * e6 / 1024.0) * (4.0 * phi).sin()
+            - (35.0 * e6 / 3072.0) * (6.0 * phi).sin());
+
+    let easting = UTM_K0
+        * n
+        * (a + (1.0 - t + c) * a.powi(3) / 6.0
+            + (5.0 - 18.0 * t + t * t + 72.0 * c - 58.0 * ep2) * a.powi(5) / 120.0)
+        + 500_000.0;
+    let northing = UTM_K0
+        * (m + n
+            * tan_phi
+            * (a * a / 2.0
+                + (5.0 - t + 9.0 * c + 4.0 * c * c) * a.powi(4) / 24.0
+                + (61.0 - 58.0 * t + t * t + 600.0 * c - 330.0 * ep2) * a.powi(6) / 720.0));
+    let false_northing = if north { 0.0 } else { 10_000_000.0 };
+    (easting, northing + false_northing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: (f64, f64), expected: (f64, f64), tolerance: f64) {
+        assert!(
+            (actual.0 - expected.0).abs() < tolerance && (actual.1 - expected.1).abs() < tolerance,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn test_web_mercator_reference_values() {
+        let projection = Projection::from_epsg(3857).unwrap();
+        assert_close(
+            projection.project(&Location::new(0.0, 0.0)),
+            (0.0, 0.0),
+            1e-6,
+        );
+        assert_close(
+            projection.project(&Location::new(45.0, 45.0)),
+            (5_009_377.085_697_311, 5_621_521.486_192_066),
+            1e-3,
+        );
+        // The map is a square 2 * 20 037 508.34 m wide, ending at ~85.05113°
+        assert_close(
+            projection.project(&Location::new(90.0, 180.0)),
+            (20_037_508.342_789_244, 20_037_508.342_789_244),
+            1e-3,
+        );
+    }
+
+    #[test]
+    fn test_utm_reference_values() {
+        let zone_31n = Projection::from_epsg(32631).unwrap();
+        assert_eq!(
+            zone_31n,
+            Projection::Utm {
+                zone: 31,
+                north: true
+            }
+        );
+        assert_close(
+            zone_31n.project(&Location::new(0.0, 3.0)),
+            (500_000.0, 0.0),
+            1e-6,
+        );
+        // On the central meridian the northing is the scaled meridian arc
+        assert_close(
+            zone_31n.project(&Location::new(45.0, 3.0)),
+            (500_000.0, 4_982_950.400),
+            1e-2,
+        );
+
+        let zone_18s = Projection::from_epsg(32718).unwrap();
+        assert_eq!(zone_18s.epsg(), 32718);
+        let (easting, northing) = zone_18s.project(&Location::new(-12.0464, -77.0428));
+        // Lima, two degrees off the central meridian
+        assert_close((easting, northing), (277_617.453, 8_667_487.897), 1e-2);
+    }
+
+    #[test]
+    fn test_unsupported_epsg_rejected() {
+        assert!(Projection::from_epsg(4326).is_err());
+        assert!(Projection::from_epsg(32600).is_err());
+        assert!(Projection::from_epsg(32761).is_err());
+    }
+}
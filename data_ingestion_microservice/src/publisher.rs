@@ -0,0 +1,99 @@
+use crate::types::{ServiceError, ServiceResult};
+use async_trait::async_trait;
+use rumqttc::{AsyncClient, QoS};
+use std::sync::Mutex;
+
+/// Outbound message publishing (MQTT in production)
+#[async_trait]
+pub trait Publisher: Send + Sync {
+    async fn publish(&self, topic: &str, payload: Vec<u8>, qos: QoS) -> ServiceResult<()>;
+}
+
+/// `Publisher` backed by the service's MQTT client
+pub struct MqttPublisher {
+    client: AsyncClient,
+}
+
+impl MqttPublisher {
+    pub fn new(client: AsyncClient) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait]
+impl Publisher for MqttPublisher {
+    async fn publish(&self, topic: &str, payload: Vec<u8>, qos: QoS) -> ServiceResult<()> {
+        self.client.publish(topic, qos, false, payload).await?;
+        Ok(())
+    }
+}
+
+/// A message captured by `RecordingPublisher`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublishedMessage {
+    pub topic: String,
+    pub payload: Vec<u8>,
+    pub qos: QoS,
+}
+
+impl PublishedMessage {
+    /// Parse the payload as JSON
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.payload).unwrap_or(serde_json::Value::Null)
+    }
+}
+
+/// `Publisher` that records every message, used for tests
+#[derive(Default)]
+pub struct RecordingPublisher {
+    messages: Mutex<Vec<PublishedMessage>>,
+    failing_topics: Mutex<Vec<String>>,
+}
+
+impl RecordingPublisher {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Snapshot of all published messages in order
+    pub fn messages(&self) -> Vec<PublishedMessage> {
+        self.messages.lock().unwrap().clone()
+    }
+
+    /// Messages published to the given topic
+    pub fn messages_on(&self, topic: &str) -> Vec<PublishedMessage> {
+        self.messages()
+            .into_iter()
+            .filter(|message| message.topic == topic)
+            .collect()
+    }
+
+    /// Make publishes to `topic` fail from now on, without recording them
+    pub fn fail_on(&self, topic: &str) {
+        self.failing_topics.lock().unwrap().push(topic.to_string());
+    }
+}
+
+#[async_trait]
+impl Publisher for RecordingPublisher {
+    async fn publish(&self, topic: &str, payload: Vec<u8>, qos: QoS) -> ServiceResult<()> {
+        if self
+            .failing_topics
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|t| t == topic)
+        {
+            return Err(ServiceError::Connection(format!(
+                "Publish to {} failed",
+                topic
+            )));
+        }
+        self.messages.lock().unwrap().push(PublishedMessage {
+            topic: topic.to_string(),
+            payload,
+            qos,
+        });
+        Ok(())
+    }
+}
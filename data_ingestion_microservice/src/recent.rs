@@ -0,0 +1,126 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Bounded, process-local set of recently claimed keys.
+///
+/// Keys expire after `window` and the oldest ones are evicted once more than
+/// `capacity` are held, so memory stays flat under a storm of distinct keys.
+pub struct RecentKeys {
+    capacity: usize,
+    window: Duration,
+    inner: Mutex<RecentInner>,
+}
+
+#[derive(Default)]
+struct RecentInner {
+    /// Claim order; entries whose time no longer matches `claimed` are stale
+    order: VecDeque<(String, Instant)>,
+    claimed: HashMap<String, Instant>,
+}
+
+impl RecentKeys {
+    /// A `capacity` of 0 disables the set: every claim succeeds
+    pub fn new(capacity: usize, window: Duration) -> Self {
+        Self {
+            capacity,
+            window,
+            inner: Mutex::new(RecentInner::default()),
+        }
+    }
+
+    /// Claim `key`; returns `false` when it was already claimed within the window
+    pub fn claim(&self, key: &str) -> bool {
+        if self.capacity == 0 {
+            return true;
+        }
+
+        let now = Instant::now();
+        let mut inner = self.inner.lock().unwrap();
+        inner.expire(now, self.window);
+        if inner.claimed.contains_key(key) {
+            return false;
+        }
+
+        inner.claimed.insert(key.to_string(), now);
+        inner.order.push_back((key.to_string(), now));
+        while inner.claimed.len() > self.capacity {
+            inner.pop_oldest();
+        }
+        true
+    }
+
+    /// Drop a claim so the next `claim` of `key` succeeds again
+    pub fn release(&self, key: &str) {
+        self.inner.lock().unwrap().claimed.remove(key);
+    }
+}
+
+impl RecentInner {
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while let Some((_, claimed_at)) = self.order.front() {
+            if now.duration_since(*claimed_at) < window {
+                break;
+            }
+            self.pop_oldest();
+        }
+    }
+
+    fn pop_oldest(&mut self) {
+        if let Some((key, claimed_at)) = self.order.pop_front() {
+            if self.claimed.get(&key) == Some(&claimed_at) {
+                self.claimed.remove(&key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claim_rejects_repeats_until_released() {
+        let recent = RecentKeys::new(8, Duration::from_secs(60));
+
+        assert!(recent.claim("a"));
+        assert!(!recent.claim("a"));
+        assert!(recent.claim("b"));
+
+        recent.release("a");
+        assert!(recent.claim("a"));
+        assert!(!recent.claim("a"));
+    }
+
+    #[test]
+    fn test_oldest_keys_evicted_over_capacity() {
+        let recent = RecentKeys::new(2, Duration::from_secs(60));
+
+        assert!(recent.claim("a"));
+        assert!(recent.claim("b"));
+        assert!(recent.claim("c"));
+
+        assert!(recent.claim("a"));
+        assert!(!recent.claim("c"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_claims_expire_after_window() {
+        let recent = RecentKeys::new(8, Duration::from_secs(5));
+
+        assert!(recent.claim("a"));
+        tokio::time::advance(Duration::from_secs(4)).await;
+        assert!(!recent.claim("a"));
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(recent.claim("a"));
+    }
+
+    #[test]
+    fn test_zero_capacity_disables() {
+        let recent = RecentKeys::new(0, Duration::from_secs(60));
+
+        assert!(recent.claim("a"));
+        assert!(recent.claim("a"));
+    }
+}
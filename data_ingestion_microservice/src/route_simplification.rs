@@ -1,69 +1,337 @@
-use crate::types::{Location, ServiceError, ServiceResult};
-use geo::{algorithm::simplify::Simplify, LineString, Point};
-use log::{debug, info};
+use crate::config::AnomalyFallback;
+use crate::geodesy::{haversine_distance, EARTH_RADIUS_METERS};
+use crate::types::{Location, ServiceError, ServiceResult, MILLIS_PER_SEC};
+use geo::{algorithm::simplify::SimplifyIdx, LineString, Point};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::{Duration, Instant};
+
+/// Revision of the simplification algorithms stored with each trip
+/// (`algorithmVersion`). Bump the suffix whenever simplification output
+/// changes without a crate release.
+pub const ALGORITHM_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "+rdp.1");
+
+/// How `RouteSimplifier` measures the distance between two points
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DistanceMetric {
+    /// Planar distance over raw lat/lon degrees; the tolerance is in degrees
+    #[default]
+    Euclidean,
+    /// Great-circle distance; the tolerance is in meters
+    Haversine,
+}
 
 /// Route simplification service with different algorithms
 #[derive(Clone)]
 pub struct RouteSimplifier {
     tolerance: f64,
+    metric: DistanceMetric,
 }
 
 impl RouteSimplifier {
-    /// Create a new route simplifier with the given tolerance
+    /// Create a new route simplifier with the given tolerance, measuring
+    /// distances in degrees
     pub fn new(tolerance: f64) -> ServiceResult<Self> {
+        Self::with_metric(tolerance, DistanceMetric::Euclidean)
+    }
+
+    /// Create a route simplifier measuring distances with `metric`. The metric
+    /// applies to the custom, weighted, radial and Visvalingam-Whyatt
+    /// simplifiers; the `geo` backed RDP always works in degrees.
+    pub fn with_metric(tolerance: f64, metric: DistanceMetric) -> ServiceResult<Self> {
         if tolerance <= 0.0 {
             return Err(ServiceError::Validation(
                 "Tolerance must be greater than 0".to_string(),
             ));
         }
 
-        Ok(Self { tolerance })
+        Ok(Self { tolerance, metric })
     }
 
-    /// Simplify a route using the Ramer-Douglas-Peucker algorithm
+    /// Simplify a route using the Ramer-Douglas-Peucker algorithm.
+    /// Retained points are returned unchanged, including their optional fields.
     pub fn simplify_route(&self, locations: &[Location]) -> ServiceResult<Vec<Location>> {
-        if locations.is_empty() {
-            return Ok(Vec::new());
+        let indices = self.simplify_route_indices(locations)?;
+        let simplified_locations: Vec<Location> =
+            indices.iter().map(|&i| locations[i].clone()).collect();
+
+        if locations.len() > 2 {
+            let compression_ratio = simplified_locations.len() as f64 / locations.len() as f64;
+
+            info!(
+                "Route simplified: {} -> {} points (compression ratio: {:.2}%)",
+                locations.len(),
+                simplified_locations.len(),
+                compression_ratio * 100.0
+            );
         }
 
+        Ok(simplified_locations)
+    }
+
+    /// Indices of the points retained by Ramer-Douglas-Peucker, in route order
+    pub fn simplify_route_indices(&self, locations: &[Location]) -> ServiceResult<Vec<usize>> {
         if locations.len() <= 2 {
-            return Ok(locations.to_vec());
+            return Ok((0..locations.len()).collect());
         }
 
         debug!("Simplifying route with {} points", locations.len());
 
-        // Convert locations to geo::Point
-        let points: Vec<Point<f64>> = locations
+        // Apply the simplification algorithm
+        Ok(to_linestring(locations).simplify_idx(&self.tolerance))
+    }
+
+    /// Full account of one Ramer-Douglas-Peucker run, for diagnosing a route
+    /// that looks wrong: what was kept, how far each dropped point lies from
+    /// the simplified line, and how long it took
+    pub fn diagnose(&self, locations: &[Location]) -> ServiceResult<SimplifyDiagnostics> {
+        let started = Instant::now();
+        let retained_indices = self.simplify_route_indices(locations)?;
+        let elapsed_micros = started.elapsed().as_micros() as u64;
+
+        let simplified: Vec<Location> = retained_indices
             .iter()
-            .map(|loc| Point::new(loc.longitude, loc.latitude))
+            .map(|&i| locations[i].clone())
+            .collect();
+        let deviations = deviations_meters(locations, &simplified);
+        let mut retained = retained_indices.iter().peekable();
+        let dropped_points = deviations
+            .into_iter()
+            .enumerate()
+            .filter(|&(index, _)| {
+                if retained.peek() == Some(&&index) {
+                    retained.next();
+                    false
+                } else {
+                    true
+                }
+            })
+            .map(|(index, deviation_meters)| DroppedPoint {
+                index,
+                deviation_meters,
+            })
             .collect();
 
-        // Create a LineString from the points
-        let linestring = LineString::from(points);
+        Ok(SimplifyDiagnostics {
+            tolerance: self.tolerance,
+            original_points: locations.len(),
+            compression_ratio: if locations.is_empty() {
+                1.0
+            } else {
+                retained_indices.len() as f64 / locations.len() as f64
+            },
+            retained_indices,
+            dropped_points,
+            elapsed_micros,
+        })
+    }
 
-        // Apply the simplification algorithm
-        let simplified_linestring = linestring.simplify(&self.tolerance);
+    /// Visvalingam-Whyatt simplification: the interior point spanning the
+    /// smallest triangle with its current neighbours is removed until every
+    /// remaining triangle is larger than the tolerance squared (square degrees,
+    /// or square meters with the Haversine metric), so one tolerance means a
+    /// distance for every algorithm. Unlike RDP, which keeps any point far from
+    /// the line, this favours broad features over narrow spikes such as a
+    /// single bad fix, and wide shallow bends over dense jitter.
+    pub fn simplify_route_visvalingam(
+        &self,
+        locations: &[Location],
+    ) -> ServiceResult<Vec<Location>> {
+        if locations.len() <= 2 {
+            return Ok(locations.to_vec());
+        }
 
-        // Convert back to Location structs
-        let simplified_locations: Vec<Location> = simplified_linestring
-            .0
+        let lon_scale = match self.metric {
+            DistanceMetric::Euclidean => 1.0,
+            DistanceMetric::Haversine => {
+                locations[0].latitude.to_radians().cos() * METERS_PER_DEGREE
+            }
+        };
+        let lat_scale = match self.metric {
+            DistanceMetric::Euclidean => 1.0,
+            DistanceMetric::Haversine => METERS_PER_DEGREE,
+        };
+        let points: Vec<(f64, f64)> = locations
             .iter()
-            .map(|point| Location {
-                latitude: point.y,
-                longitude: point.x,
-            })
+            .map(|loc| (loc.longitude * lon_scale, loc.latitude * lat_scale))
             .collect();
+        let area = |a: usize, b: usize, c: usize| {
+            let (a, b, c) = (points[a], points[b], points[c]);
+            ((b.0 - a.0) * (c.1 - a.1) - (c.0 - a.0) * (b.1 - a.1)).abs() / 2.0
+        };
 
-        let compression_ratio = simplified_locations.len() as f64 / locations.len() as f64;
+        let min_area = self.tolerance * self.tolerance;
+        let kept = ranked_removal(locations.len(), area, |_, smallest| smallest <= min_area);
+        let simplified: Vec<Location> = locations
+            .iter()
+            .zip(kept)
+            .filter(|(_, kept)| *kept)
+            .map(|(location, _)| location.clone())
+            .collect();
 
-        info!(
-            "Route simplified: {} -> {} points (compression ratio: {:.2}%)",
+        debug!(
+            "Route simplified (Visvalingam-Whyatt): {} -> {} points",
             locations.len(),
-            simplified_locations.len(),
-            compression_ratio * 100.0
+            simplified.len()
         );
 
-        Ok(simplified_locations)
+        Ok(simplified)
+    }
+
+    /// Radial distance filter: keeps each point farther than the tolerance
+    /// from the last kept one, plus the endpoints. Cheap enough to be the last
+    /// resort of a fallback chain.
+    pub fn simplify_radial(&self, locations: &[Location]) -> ServiceResult<Vec<Location>> {
+        let Some((last, rest)) = locations.split_last() else {
+            return Ok(Vec::new());
+        };
+        let Some((first, interior)) = rest.split_first() else {
+            return Ok(vec![last.clone()]);
+        };
+
+        let mut kept = vec![first.clone()];
+        for location in interior {
+            let previous = &kept[kept.len() - 1];
+            if self.distance(location, previous) > self.tolerance {
+                kept.push(location.clone());
+            }
+        }
+        kept.push(last.clone());
+        Ok(kept)
+    }
+
+    /// Ramer-Douglas-Peucker result as a `geo::LineString` with `(x, y)` =
+    /// `(longitude, latitude)`, for callers working with the `geo` ecosystem
+    pub fn simplify_route_linestring(
+        &self,
+        locations: &[Location],
+    ) -> ServiceResult<LineString<f64>> {
+        let indices = self.simplify_route_indices(locations)?;
+        let retained: Vec<Location> = indices.iter().map(|&i| locations[i].clone()).collect();
+        Ok(to_linestring(&retained))
+    }
+
+    /// Simplify a route while always retaining the points at `anchors`.
+    /// Each span between consecutive anchors is simplified independently.
+    pub fn simplify_route_with_anchors(
+        &self,
+        locations: &[Location],
+        anchors: &[usize],
+    ) -> ServiceResult<Vec<Location>> {
+        self.simplify_spans(locations, anchors, false)
+    }
+
+    /// Importance-weighted variant of [`Self::simplify_route_with_anchors`]
+    pub fn simplify_weighted_with_anchors(
+        &self,
+        locations: &[Location],
+        anchors: &[usize],
+    ) -> ServiceResult<Vec<Location>> {
+        self.simplify_spans(locations, anchors, true)
+    }
+
+    /// Simplify a route where each point's perpendicular distance is scaled by
+    /// its `importance` (1.0 when absent), so important points survive
+    /// tolerances that would otherwise remove them.
+    pub fn simplify_weighted(&self, locations: &[Location]) -> ServiceResult<Vec<Location>> {
+        let simplified: Vec<Location> = self
+            .simplify_weighted_indices(locations)
+            .into_iter()
+            .map(|i| locations[i].clone())
+            .collect();
+
+        if locations.len() > 2 {
+            info!(
+                "Route simplified (weighted): {} -> {} points",
+                locations.len(),
+                simplified.len()
+            );
+        }
+
+        Ok(simplified)
+    }
+
+    /// Indices retained by the importance-weighted Ramer-Douglas-Peucker, in route order
+    pub fn simplify_weighted_indices(&self, locations: &[Location]) -> Vec<usize> {
+        if locations.len() <= 2 {
+            return (0..locations.len()).collect();
+        }
+
+        let mut keep = vec![false; locations.len()];
+        keep[0] = true;
+        keep[locations.len() - 1] = true;
+
+        let mut spans = vec![(0, locations.len() - 1)];
+        while let Some((start, end)) = spans.pop() {
+            let farthest = (start + 1..end)
+                .map(|i| {
+                    let weight = locations[i].importance.unwrap_or(1.0).max(0.0);
+                    let distance = self.perpendicular_distance(
+                        &locations[i],
+                        &locations[start],
+                        &locations[end],
+                    );
+                    (i, distance * weight)
+                })
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+
+            if let Some((index, distance)) = farthest {
+                if distance > self.tolerance {
+                    keep[index] = true;
+                    spans.push((start, index));
+                    spans.push((index, end));
+                }
+            }
+        }
+
+        (0..locations.len()).filter(|&i| keep[i]).collect()
+    }
+
+    fn simplify_spans(
+        &self,
+        locations: &[Location],
+        anchors: &[usize],
+        weighted: bool,
+    ) -> ServiceResult<Vec<Location>> {
+        if locations.len() <= 2 {
+            return Ok(locations.to_vec());
+        }
+
+        let mut boundaries: Vec<usize> = anchors
+            .iter()
+            .copied()
+            .filter(|&i| i < locations.len())
+            .chain([0, locations.len() - 1])
+            .collect();
+        boundaries.sort_unstable();
+        boundaries.dedup();
+
+        let mut simplified = vec![locations[0].clone()];
+        for span in boundaries.windows(2) {
+            let span_locations = &locations[span[0]..=span[1]];
+            let indices = if weighted {
+                self.simplify_weighted_indices(span_locations)
+            } else {
+                self.simplify_route_indices(span_locations)?
+            };
+            simplified.extend(
+                indices
+                    .into_iter()
+                    .skip(1)
+                    .map(|i| locations[span[0] + i].clone()),
+            );
+        }
+
+        debug!(
+            "Route simplified with {} anchors: {} -> {} points",
+            boundaries.len() - 2,
+            locations.len(),
+            simplified.len()
+        );
+
+        Ok(simplified)
     }
 
     /// Alternative simplification using custom implementation
@@ -123,13 +391,20 @@ impl RouteSimplifier {
         Ok(simplified)
     }
 
-    /// Calculate perpendicular distance from a point to a line
+    /// Calculate perpendicular distance from a point to a line. With the
+    /// Haversine metric this is the cross-track distance to the great circle
+    /// through the line, or the distance to the nearer end when the point
+    /// lies beyond one.
     fn perpendicular_distance(
         &self,
         point: &Location,
         line_start: &Location,
         line_end: &Location,
     ) -> f64 {
+        if self.metric == DistanceMetric::Haversine {
+            return cross_track_distance(point, line_start, line_end);
+        }
+
         let area = (line_start.longitude * (line_end.latitude - point.latitude)
             + line_end.longitude * (point.latitude - line_start.latitude)
             + point.longitude * (line_start.latitude - line_end.latitude))
@@ -144,11 +419,20 @@ impl RouteSimplifier {
         }
     }
 
-    /// Calculate Euclidean distance between two points
+    /// Distance between two points in the units of the metric
     fn distance(&self, p1: &Location, p2: &Location) -> f64 {
-        let dx = p1.longitude - p2.longitude;
-        let dy = p1.latitude - p2.latitude;
-        (dx * dx + dy * dy).sqrt()
+        match self.metric {
+            DistanceMetric::Euclidean => {
+                let dx = p1.longitude - p2.longitude;
+                let dy = p1.latitude - p2.latitude;
+                (dx * dx + dy * dy).sqrt()
+            }
+            DistanceMetric::Haversine => haversine_distance(p1, p2),
+        }
+    }
+
+    pub fn metric(&self) -> DistanceMetric {
+        self.metric
     }
 
     /// Get the current tolerance value
@@ -166,12 +450,395 @@ impl RouteSimplifier {
         self.tolerance = tolerance;
         Ok(())
     }
+
+    /// Simplify with the coarsest tolerance for which `percentile` percent of the
+    /// original points lie within `max_meters` of the simplified line.
+    /// The configured tolerance is not used; it is found by bisection.
+    pub fn simplify_to_deviation_percentile(
+        &self,
+        locations: &[Location],
+        percentile: f64,
+        max_meters: f64,
+    ) -> ServiceResult<Vec<Location>> {
+        if !(0.0..=100.0).contains(&percentile) || max_meters <= 0.0 {
+            return Err(ServiceError::Validation(
+                "Percentile must be within 0-100 and max meters greater than 0".to_string(),
+            ));
+        }
+        if locations.len() <= 2 {
+            return Ok(locations.to_vec());
+        }
+
+        let meets_target = |indices: &[usize]| {
+            let simplified: Vec<Location> = indices.iter().map(|&i| locations[i].clone()).collect();
+            deviation_percentile(&deviations_meters(locations, &simplified), percentile)
+                <= max_meters
+        };
+
+        // A vanishing tolerance keeps every point, so the lower bound always qualifies
+        let mut low = 1e-9;
+        let mut high = max_meters / METERS_PER_DEGREE * 100.0;
+        let mut best = (0..locations.len()).collect::<Vec<_>>();
+        for _ in 0..40 {
+            let mid = (low + high) / 2.0;
+            let indices = RouteSimplifier::new(mid)?.simplify_route_indices(locations)?;
+            if meets_target(&indices) {
+                low = mid;
+                best = indices;
+            } else {
+                high = mid;
+            }
+        }
+
+        debug!(
+            "Deviation target p{} <= {} m met with tolerance {}: {} -> {} points",
+            percentile,
+            max_meters,
+            low,
+            locations.len(),
+            best.len()
+        );
+
+        Ok(best.into_iter().map(|i| locations[i].clone()).collect())
+    }
+
+    /// Simplify to at most `max_points` points, whatever the route's spread,
+    /// keeping both endpoints. The configured tolerance is not used: points
+    /// are removed by the ranked removal of [`cap_point_count`], so the result
+    /// has exactly `max_points` points and is deterministic. Routes within the
+    /// cap are returned unchanged.
+    ///
+    /// There are `n - max_points` removals, each a heap operation plus
+    /// re-ranking two neighbours over the points they bridge. That is
+    /// `O(n log n)` while the kept points stay spread out, and `O(n²)` in the
+    /// worst case of one neighbour bridging most of the route.
+    pub fn simplify_to_count(
+        &self,
+        locations: &[Location],
+        max_points: usize,
+    ) -> ServiceResult<Vec<Location>> {
+        if max_points < 2 {
+            return Err(ServiceError::Validation(
+                "Max points must be at least 2 to keep both endpoints".to_string(),
+            ));
+        }
+        if locations.len() <= max_points {
+            return Ok(locations.to_vec());
+        }
+
+        let simplified = cap_point_count(locations, max_points);
+        debug!(
+            "Route simplified to a count of {}: {} -> {} points",
+            max_points,
+            locations.len(),
+            simplified.len()
+        );
+        Ok(simplified)
+    }
+}
+
+/// Distance in meters from `point` to the great-circle segment `start`-`end`
+fn cross_track_distance(point: &Location, start: &Location, end: &Location) -> f64 {
+    let to_point = haversine_distance(start, point);
+    let length = haversine_distance(start, end);
+    if length == 0.0 || to_point == 0.0 {
+        return to_point;
+    }
+
+    let angular = to_point / EARTH_RADIUS_METERS;
+    let bearing_offset = initial_bearing(start, point) - initial_bearing(start, end);
+    let cross_track = (angular.sin() * bearing_offset.sin())
+        .clamp(-1.0, 1.0)
+        .asin();
+    let along_track = (angular.cos() / cross_track.cos()).clamp(-1.0, 1.0).acos();
+    if bearing_offset.cos() < 0.0 || along_track * EARTH_RADIUS_METERS > length {
+        return to_point.min(haversine_distance(end, point));
+    }
+    (cross_track * EARTH_RADIUS_METERS).abs()
+}
+
+/// Initial bearing in radians of the great circle from `from` to `to`
+fn initial_bearing(from: &Location, to: &Location) -> f64 {
+    let (lat1, lat2) = (from.latitude.to_radians(), to.latitude.to_radians());
+    let d_lon = (to.longitude - from.longitude).to_radians();
+    (d_lon.sin() * lat2.cos())
+        .atan2(lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * d_lon.cos())
+}
+
+/// Convert locations to a `geo::LineString` with `(x, y)` = `(longitude, latitude)`
+fn to_linestring(locations: &[Location]) -> LineString<f64> {
+    let points: Vec<Point<f64>> = locations
+        .iter()
+        .map(|loc| Point::new(loc.longitude, loc.latitude))
+        .collect();
+    LineString::from(points)
+}
+
+/// A route simplification strategy.
+///
+/// Implementations must return an ordered subsequence of the input that keeps
+/// both endpoints, and should be stable when simplified again; the test harness
+/// in this module checks these invariants for every built-in strategy.
+pub trait Simplifier {
+    fn simplify(&self, locations: &[Location]) -> ServiceResult<Vec<Location>>;
+}
+
+/// Ramer-Douglas-Peucker with the configured tolerance
+impl Simplifier for RouteSimplifier {
+    fn simplify(&self, locations: &[Location]) -> ServiceResult<Vec<Location>> {
+        self.simplify_route(locations)
+    }
+}
+
+impl<F> Simplifier for F
+where
+    F: Fn(&[Location]) -> ServiceResult<Vec<Location>>,
+{
+    fn simplify(&self, locations: &[Location]) -> ServiceResult<Vec<Location>> {
+        self(locations)
+    }
+}
+
+/// Run the simplifiers of `chain` in order until one succeeds within
+/// `budget`, returning its result and its position in the chain. A
+/// simplifier that errors or runs over budget is skipped; the last one's
+/// result is accepted however long it took.
+pub fn simplify_with_fallback(
+    chain: &[&dyn Simplifier],
+    locations: &[Location],
+    budget: Option<Duration>,
+) -> ServiceResult<(Vec<Location>, usize)> {
+    let mut last_error = None;
+    for (position, simplifier) in chain.iter().enumerate() {
+        let started = Instant::now();
+        match simplifier.simplify(locations) {
+            Ok(simplified) => {
+                let elapsed = started.elapsed();
+                let is_last = position + 1 == chain.len();
+                if is_last || budget.is_none_or(|budget| elapsed <= budget) {
+                    return Ok((simplified, position));
+                }
+                warn!(
+                    "Simplifier {} of the chain took {:?}, over budget; falling back",
+                    position, elapsed
+                );
+            }
+            Err(e) => {
+                warn!(
+                    "Simplifier {} of the chain failed: {}; falling back",
+                    position, e
+                );
+                last_error = Some(e);
+            }
+        }
+    }
+
+    Err(last_error.unwrap_or_else(|| {
+        ServiceError::RouteProcessing("Simplification chain is empty".to_string())
+    }))
+}
+
+/// Route to store instead of `simplified` when simplification went wrong:
+/// fewer than two points left of a route with at least two. Returns `None`
+/// when the simplified route is fine.
+pub fn anomaly_fallback(
+    original: &[Location],
+    simplified: &[Location],
+    fallback: AnomalyFallback,
+) -> Option<Vec<Location>> {
+    if original.len() < 2 || simplified.len() >= 2 {
+        return None;
+    }
+    Some(match fallback {
+        AnomalyFallback::Endpoints => {
+            vec![original[0].clone(), original[original.len() - 1].clone()]
+        }
+        AnomalyFallback::Raw => original.to_vec(),
+    })
+}
+
+/// Meters per degree of latitude on the mean Earth sphere
+const METERS_PER_DEGREE: f64 = EARTH_RADIUS_METERS * std::f64::consts::PI / 180.0;
+
+/// Distance in meters from every original point to the nearest segment of the
+/// simplified line, using a local equirectangular projection
+pub fn deviations_meters(original: &[Location], simplified: &[Location]) -> Vec<f64> {
+    let Some(first) = simplified.first() else {
+        return Vec::new();
+    };
+    let lon_scale = first.latitude.to_radians().cos() * METERS_PER_DEGREE;
+    let project = |loc: &Location| (loc.longitude * lon_scale, loc.latitude * METERS_PER_DEGREE);
+
+    original
+        .iter()
+        .map(|point| {
+            let p = project(point);
+            if simplified.len() == 1 {
+                let a = project(first);
+                return (p.0 - a.0).hypot(p.1 - a.1);
+            }
+            simplified
+                .windows(2)
+                .map(|segment| segment_distance(p, project(&segment[0]), project(&segment[1])))
+                .fold(f64::INFINITY, f64::min)
+        })
+        .collect()
+}
+
+/// Nearest-rank percentile (0-100) of a set of deviations
+pub fn deviation_percentile(deviations: &[f64], percentile: f64) -> f64 {
+    if deviations.is_empty() {
+        return 0.0;
+    }
+
+    let mut sorted = deviations.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let rank = ((percentile / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Reduce a route to exactly `max_points` points (at least the two endpoints)
+/// by Visvalingam-style ranked removal. Each interior point is ranked by the
+/// deviation its removal would cause, the farthest the original points it
+/// would bridge lie from the merged segment, and the lowest-ranked point is
+/// removed until the count is reached. Unlike raising a tolerance until the
+/// route fits, this keeps every point the cap allows, spent where the route
+/// needs them most. Routes already within the cap are returned unchanged.
+pub fn cap_point_count(locations: &[Location], max_points: usize) -> Vec<Location> {
+    let target = max_points.max(2);
+    if locations.len() <= target {
+        return locations.to_vec();
+    }
+
+    let lon_scale = locations[0].latitude.to_radians().cos() * METERS_PER_DEGREE;
+    let points: Vec<(f64, f64)> = locations
+        .iter()
+        .map(|loc| (loc.longitude * lon_scale, loc.latitude * METERS_PER_DEGREE))
+        .collect();
+    let removal_cost = |before: usize, after: usize| {
+        (before + 1..after)
+            .map(|i| segment_distance(points[i], points[before], points[after]))
+            .fold(0.0, f64::max)
+    };
+
+    let kept = ranked_removal(
+        locations.len(),
+        |before, _, after| removal_cost(before, after),
+        |remaining, _| remaining > target,
+    );
+    locations
+        .iter()
+        .zip(kept)
+        .filter(|(_, kept)| *kept)
+        .map(|(location, _)| location.clone())
+        .collect()
+}
+
+/// Visvalingam-style ranked removal over a route of `len` points. The
+/// interior point with the lowest `rank(previous, index, next)`, given its
+/// current neighbours, is removed while `keep_removing(remaining, rank)`
+/// holds, and its neighbours are ranked again. Returns which points are kept;
+/// the endpoints always are. Each removal costs `O(log n)` plus two ranks.
+fn ranked_removal(
+    len: usize,
+    rank: impl Fn(usize, usize, usize) -> f64,
+    keep_removing: impl Fn(usize, f64) -> bool,
+) -> Vec<bool> {
+    let mut kept = vec![true; len];
+    if len <= 2 {
+        return kept;
+    }
+
+    let last = len - 1;
+    let mut previous: Vec<usize> = (0..=last).map(|i| i.saturating_sub(1)).collect();
+    let mut next: Vec<usize> = (0..=last).map(|i| (i + 1).min(last)).collect();
+    // Entries go stale when a neighbour is removed; `ranks` holds the current one
+    let mut ranks: Vec<f64> = (0..=last)
+        .map(|i| {
+            if i == 0 || i == last {
+                f64::INFINITY
+            } else {
+                rank(i - 1, i, i + 1)
+            }
+        })
+        .collect();
+    let mut heap: BinaryHeap<Reverse<(OrderedRank, usize)>> = (1..last)
+        .map(|i| Reverse((OrderedRank(ranks[i]), i)))
+        .collect();
+
+    let mut remaining = len;
+    while let Some(Reverse((OrderedRank(lowest), index))) = heap.pop() {
+        if !kept[index] || lowest != ranks[index] {
+            continue;
+        }
+        if !keep_removing(remaining, lowest) {
+            break;
+        }
+        kept[index] = false;
+        remaining -= 1;
+        let (before, after) = (previous[index], next[index]);
+        next[before] = after;
+        previous[after] = before;
+        for neighbour in [before, after] {
+            if neighbour != 0 && neighbour != last {
+                ranks[neighbour] = rank(previous[neighbour], neighbour, next[neighbour]);
+                heap.push(Reverse((OrderedRank(ranks[neighbour]), neighbour)));
+            }
+        }
+    }
+    kept
+}
+
+/// Distance from `p` to the segment `a`-`b`, in the units of the coordinates
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let length_sq = dx * dx + dy * dy;
+    let t = if length_sq == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / length_sq).clamp(0.0, 1.0)
+    };
+    (p.0 - (a.0 + t * dx)).hypot(p.1 - (a.1 + t * dy))
+}
+
+/// Removal rank ordered with `total_cmp`, for the removal heap
+#[derive(PartialEq)]
+struct OrderedRank(f64);
+
+impl Eq for OrderedRank {}
+
+impl PartialOrd for OrderedRank {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for OrderedRank {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Unit used for route lengths in `RouteStats`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DistanceUnit {
+    /// Raw coordinate units (planar distance over lat/lon degrees)
+    #[default]
+    Degrees,
+    /// Great-circle meters (Haversine)
+    Meters,
+    /// Great-circle kilometers (Haversine)
+    Kilometers,
 }
 
 /// Utility function to calculate route statistics
-pub fn calculate_route_stats(original: &[Location], simplified: &[Location]) -> RouteStats {
-    let original_length = calculate_total_distance(original);
-    let simplified_length = calculate_total_distance(simplified);
+pub fn calculate_route_stats(
+    original: &[Location],
+    simplified: &[Location],
+    unit: DistanceUnit,
+) -> RouteStats {
+    let original_length = calculate_total_distance(original, unit);
+    let simplified_length = calculate_total_distance(simplified, unit);
 
     RouteStats {
         original_points: original.len(),
@@ -184,25 +851,147 @@ pub fn calculate_route_stats(original: &[Location], simplified: &[Location]) ->
         original_length,
         simplified_length,
         length_difference: (original_length - simplified_length).abs(),
+        unit,
     }
 }
 
-/// Calculate the total distance of a route
-fn calculate_total_distance(locations: &[Location]) -> f64 {
+/// Concatenate two consecutive routes, e.g. the parts of a split or stitched trip.
+/// Points of `b` that repeat the boundary point or are timestamped at or before the
+/// end of `a` are dropped, so the merged timestamps stay monotonic.
+pub fn merge_routes(a: &[Location], b: &[Location]) -> Vec<Location> {
+    let mut merged = a.to_vec();
+
+    for location in b {
+        if let Some(last) = merged.last() {
+            let same_point =
+                last.latitude == location.latitude && last.longitude == location.longitude;
+            let out_of_order = matches!(
+                (last.timestamp, location.timestamp),
+                (Some(previous), Some(current)) if current <= previous
+            );
+            if same_point || out_of_order {
+                continue;
+            }
+        }
+        merged.push(location.clone());
+    }
+
+    merged
+}
+
+/// Resample a route to one point every `interval_secs` seconds from its first
+/// timestamp, interpolating linearly between the bracketing samples. Every point
+/// needs a timestamp (in milliseconds) and timestamps must not decrease.
+pub fn resample_fixed_interval(
+    locations: &[Location],
+    interval_secs: u64,
+) -> ServiceResult<Vec<Location>> {
+    if interval_secs == 0 {
+        return Err(ServiceError::Validation(
+            "Resampling interval must be greater than 0".to_string(),
+        ));
+    }
+
+    let samples = locations
+        .iter()
+        .map(|location| {
+            location
+                .timestamp
+                .map(|timestamp| (timestamp, location))
+                .ok_or_else(|| {
+                    ServiceError::Validation("Resampling requires timestamped points".to_string())
+                })
+        })
+        .collect::<ServiceResult<Vec<_>>>()?;
+    if samples.windows(2).any(|pair| pair[1].0 < pair[0].0) {
+        return Err(ServiceError::Validation(
+            "Resampling requires non-decreasing timestamps".to_string(),
+        ));
+    }
+    let (Some(&(start, _)), Some(&(end, _))) = (samples.first(), samples.last()) else {
+        return Ok(Vec::new());
+    };
+
+    let interval_ms = interval_secs.saturating_mul(MILLIS_PER_SEC);
+    let last = samples.len() - 1;
+    let mut resampled = Vec::new();
+    let mut segment = 0;
+    let mut timestamp = start;
+    while timestamp <= end {
+        while segment + 1 < last && samples[segment + 1].0 < timestamp {
+            segment += 1;
+        }
+        let (t0, a) = samples[segment];
+        let (t1, b) = samples[(segment + 1).min(last)];
+        let fraction = if t1 > t0 {
+            (timestamp - t0) as f64 / (t1 - t0) as f64
+        } else {
+            0.0
+        };
+        let lerp = |from: f64, to: f64| from + (to - from) * fraction;
+
+        resampled.push(Location {
+            latitude: lerp(a.latitude, b.latitude),
+            longitude: lerp(a.longitude, b.longitude),
+            timestamp: Some(timestamp),
+            altitude: a.altitude.zip(b.altitude).map(|(from, to)| lerp(from, to)),
+            importance: None,
+            // Occupancy changes at fixes, so hold the last known count
+            occupancy: a.occupancy,
+            accuracy: a.accuracy.zip(b.accuracy).map(|(a, b)| a.max(b)),
+            speed: a.speed.zip(b.speed).map(|(from, to)| lerp(from, to)),
+            // Headings wrap at 360°, so a lerp could point the wrong way
+            heading: a.heading,
+            dead_reckoned: a.dead_reckoned || b.dead_reckoned,
+        });
+        timestamp += interval_ms;
+    }
+
+    Ok(resampled)
+}
+
+/// Calculate the total distance of a route in the given unit
+pub fn calculate_total_distance(locations: &[Location], unit: DistanceUnit) -> f64 {
     if locations.len() < 2 {
         return 0.0;
     }
 
     locations
         .windows(2)
-        .map(|window| {
-            let dx = window[1].longitude - window[0].longitude;
-            let dy = window[1].latitude - window[0].latitude;
-            (dx * dx + dy * dy).sqrt()
+        .map(|window| match unit {
+            DistanceUnit::Degrees => {
+                let dx = window[1].longitude - window[0].longitude;
+                let dy = window[1].latitude - window[0].latitude;
+                (dx * dx + dy * dy).sqrt()
+            }
+            DistanceUnit::Meters => haversine_distance(&window[0], &window[1]),
+            DistanceUnit::Kilometers => haversine_distance(&window[0], &window[1]) / 1000.0,
         })
         .sum()
 }
 
+/// Result of `RouteSimplifier::diagnose`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimplifyDiagnostics {
+    pub tolerance: f64,
+    pub original_points: usize,
+    /// Indices of the retained points, in route order
+    pub retained_indices: Vec<usize>,
+    pub dropped_points: Vec<DroppedPoint>,
+    /// Retained points over original points
+    pub compression_ratio: f64,
+    pub elapsed_micros: u64,
+}
+
+/// A point removed by simplification and its distance to the simplified line
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DroppedPoint {
+    pub index: usize,
+    pub deviation_meters: f64,
+}
+
 /// Statistics about route simplification
 #[derive(Debug, Clone)]
 pub struct RouteStats {
@@ -212,34 +1001,21 @@ pub struct RouteStats {
     pub original_length: f64,
     pub simplified_length: f64,
     pub length_difference: f64,
+    pub unit: DistanceUnit,
 }
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
     use super::*;
+    use crate::analysis::elevation_extrema;
 
     fn create_test_locations() -> Vec<Location> {
         vec![
-            Location {
-                latitude: 0.0,
-                longitude: 0.0,
-            },
-            Location {
-                latitude: 0.5,
-                longitude: 0.5,
-            },
-            Location {
-                latitude: 1.0,
-                longitude: 1.0,
-            },
-            Location {
-                latitude: 1.5,
-                longitude: 1.5,
-            },
-            Location {
-                latitude: 2.0,
-                longitude: 2.0,
-            },
+            Location::new(0.0, 0.0),
+            Location::new(0.5, 0.5),
+            Location::new(1.0, 1.0),
+            Location::new(1.5, 1.5),
+            Location::new(2.0, 2.0),
         ]
     }
 
@@ -252,6 +1028,91 @@ mod tests {
         assert!(invalid_simplifier.is_err());
     }
 
+    #[test]
+    fn test_diagnose_accounts_for_every_point() {
+        // A straight line with one point pushed ~111 m off it
+        let route = vec![
+            Location::new(0.0, 0.0),
+            Location::new(0.0, 0.001),
+            Location::new(0.001, 0.002),
+            Location::new(0.0, 0.003),
+            Location::new(0.0, 0.004),
+        ];
+
+        let diagnostics = RouteSimplifier::new(0.0001)
+            .unwrap()
+            .diagnose(&route)
+            .unwrap();
+        assert_eq!(diagnostics.retained_indices, vec![0, 1, 2, 3, 4]);
+        assert!(diagnostics.dropped_points.is_empty());
+
+        let diagnostics = RouteSimplifier::new(0.01)
+            .unwrap()
+            .diagnose(&route)
+            .unwrap();
+        assert_eq!(diagnostics.original_points, 5);
+        assert_eq!(diagnostics.retained_indices, vec![0, 4]);
+        let dropped: Vec<usize> = diagnostics.dropped_points.iter().map(|p| p.index).collect();
+        assert_eq!(dropped, vec![1, 2, 3]);
+        assert!((diagnostics.dropped_points[1].deviation_meters - 111.2).abs() < 0.5);
+        assert!(diagnostics.dropped_points[0].deviation_meters < 1e-6);
+        assert!((diagnostics.compression_ratio - 0.4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_fallback_chain_skips_failing_simplifier() {
+        let route = create_test_locations();
+        let simplifier = RouteSimplifier::new(0.001).unwrap();
+        let failing = |_: &[Location]| -> ServiceResult<Vec<Location>> {
+            Err(ServiceError::RouteProcessing("boom".to_string()))
+        };
+        let never_fits = |l: &[Location]| -> ServiceResult<Vec<Location>> {
+            std::thread::sleep(Duration::from_millis(5));
+            Ok(l.to_vec())
+        };
+
+        let (simplified, position) =
+            simplify_with_fallback(&[&failing, &simplifier], &route, None).unwrap();
+        assert_eq!(position, 1);
+        assert_eq!(simplified, simplifier.simplify_route(&route).unwrap());
+
+        let budget = Some(Duration::from_millis(1));
+        let (_, position) =
+            simplify_with_fallback(&[&never_fits, &simplifier], &route, budget).unwrap();
+        assert_eq!(position, 1);
+        // The last resort is kept even over budget
+        let (simplified, position) =
+            simplify_with_fallback(&[&failing, &never_fits], &route, budget).unwrap();
+        assert_eq!((simplified.len(), position), (5, 1));
+
+        assert!(simplify_with_fallback(&[&failing], &route, None).is_err());
+        assert!(simplify_with_fallback(&[], &route, None).is_err());
+    }
+
+    #[test]
+    fn test_anomaly_fallback_replaces_empty_output() {
+        let locations = create_test_locations();
+        let broken = |_: &[Location]| -> ServiceResult<Vec<Location>> { Ok(Vec::new()) };
+        let (simplified, _) = simplify_with_fallback(&[&broken], &locations, None).unwrap();
+        assert!(simplified.is_empty());
+
+        let endpoints =
+            anomaly_fallback(&locations, &simplified, AnomalyFallback::Endpoints).unwrap();
+        assert_eq!(endpoints.first(), locations.first());
+        assert_eq!(endpoints.last(), locations.last());
+        assert_eq!(endpoints.len(), 2);
+        let raw = anomaly_fallback(&locations, &locations[..1], AnomalyFallback::Raw).unwrap();
+        assert_eq!(raw, locations);
+
+        // Healthy output, and routes too short to simplify, are left alone
+        let healthy = RouteSimplifier::new(0.0001)
+            .unwrap()
+            .simplify_route(&locations)
+            .unwrap();
+        assert!(anomaly_fallback(&locations, &healthy, AnomalyFallback::Endpoints).is_none());
+        assert!(anomaly_fallback(&locations[..1], &[], AnomalyFallback::Raw).is_none());
+    }
+
     #[test]
     fn test_empty_route_simplification() {
         let simplifier = RouteSimplifier::new(0.001).unwrap();
@@ -262,10 +1123,7 @@ mod tests {
     #[test]
     fn test_single_point_route() {
         let simplifier = RouteSimplifier::new(0.001).unwrap();
-        let locations = vec![Location {
-            latitude: 1.0,
-            longitude: 1.0,
-        }];
+        let locations = vec![Location::new(1.0, 1.0)];
         let result = simplifier.simplify_route(&locations).unwrap();
         assert_eq!(result.len(), 1);
         assert_eq!(result[0].latitude, 1.0);
@@ -284,42 +1142,389 @@ mod tests {
         assert_eq!(result.last().unwrap().latitude, 2.0);
     }
 
+    #[test]
+    fn test_simplify_route_keeps_point_fields() {
+        let simplifier = RouteSimplifier::new(0.1).unwrap();
+        let locations: Vec<Location> = create_test_locations()
+            .into_iter()
+            .enumerate()
+            .map(|(i, loc)| Location {
+                timestamp: Some(i as u64),
+                ..loc
+            })
+            .collect();
+
+        let result = simplifier.simplify_route(&locations).unwrap();
+
+        assert_eq!(result.first(), locations.first());
+        assert_eq!(result.last(), locations.last());
+        assert_eq!(result.last().unwrap().timestamp, Some(4));
+    }
+
+    #[test]
+    fn test_simplify_route_linestring_matches_simplify_route() {
+        let simplifier = RouteSimplifier::new(0.0005).unwrap();
+        let route: Vec<Location> = (0..50)
+            .map(|i| Location::new((i as f64 * 0.2).sin() * 0.01, i as f64 * 0.001))
+            .collect();
+
+        let simplified = simplifier.simplify_route(&route).unwrap();
+        let linestring = simplifier.simplify_route_linestring(&route).unwrap();
+
+        assert!(simplified.len() < route.len());
+        assert_eq!(linestring.0.len(), simplified.len());
+        for (coord, location) in linestring.coords().zip(&simplified) {
+            assert_eq!((coord.x, coord.y), (location.longitude, location.latitude));
+        }
+        assert!(simplifier
+            .simplify_route_linestring(&[])
+            .unwrap()
+            .0
+            .is_empty());
+    }
+
+    #[test]
+    fn test_visvalingam_keeps_wide_zig_zag_rdp_flattens() {
+        // Four wide, shallow zig-zags (550 m legs, 89 m deep), then GPS jitter
+        // of 11 m over 22 m steps. Every point is within the 0.001 degree
+        // tolerance of the straight line, but the wide teeth span triangles
+        // far larger than the tolerance squared and the jitter does not.
+        let locations: Vec<Location> = [
+            (0.0, 0.0),
+            (0.005, 0.0008),
+            (0.01, 0.0),
+            (0.015, 0.0008),
+            (0.02, 0.0),
+            (0.0202, 0.0001),
+            (0.0204, 0.0),
+            (0.0206, 0.0001),
+            (0.0208, 0.0),
+            (0.03, 0.0),
+        ]
+        .iter()
+        .map(|&(longitude, latitude)| Location::new(latitude, longitude))
+        .collect();
+        let simplifier = RouteSimplifier::new(0.001).unwrap();
+        let survivors = |simplified: &[Location]| -> Vec<usize> {
+            simplified
+                .iter()
+                .map(|kept| locations.iter().position(|loc| loc == kept).unwrap())
+                .collect()
+        };
+
+        let rdp = simplifier.simplify_route(&locations).unwrap();
+        let visvalingam = simplifier.simplify_route_visvalingam(&locations).unwrap();
+        // RDP flattens the zig-zag; Visvalingam-Whyatt keeps every wide tooth
+        // and drops only the jitter
+        assert_eq!(survivors(&rdp), vec![0, 9]);
+        assert_eq!(survivors(&visvalingam), vec![0, 1, 2, 3, 4, 9]);
+    }
+
+    #[test]
+    fn test_simplify_route_with_anchors() {
+        let simplifier = RouteSimplifier::new(0.1).unwrap();
+        let locations = create_test_locations();
+
+        assert_eq!(simplifier.simplify_route(&locations).unwrap().len(), 2);
+
+        let anchored = simplifier
+            .simplify_route_with_anchors(&locations, &[2, 99])
+            .unwrap();
+        assert_eq!(anchored.len(), 3);
+        assert_eq!(anchored[1], locations[2]);
+    }
+
+    #[test]
+    fn test_simplify_weighted_keeps_important_point() {
+        let simplifier = RouteSimplifier::new(0.001).unwrap();
+        // Every interior point deviates by 0.0005 degrees, below the tolerance
+        let mut locations: Vec<Location> = (0..5)
+            .map(|i| Location::new(if i % 4 == 0 { 0.0 } else { 0.0005 }, i as f64 * 0.01))
+            .collect();
+
+        let unweighted = simplifier.simplify_weighted(&locations).unwrap();
+        assert_eq!(unweighted.len(), 2);
+
+        locations[2].importance = Some(10.0);
+        let weighted = simplifier.simplify_weighted(&locations).unwrap();
+        assert_eq!(weighted.len(), 3);
+        assert_eq!(weighted[1], locations[2]);
+    }
+
+    fn timed(points: &[(f64, u64)]) -> Vec<Location> {
+        points
+            .iter()
+            .map(|&(longitude, timestamp)| Location {
+                timestamp: Some(timestamp),
+                ..Location::new(0.0, longitude)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_merge_routes_dedupes_shared_boundary() {
+        let a = timed(&[(0.0, 0), (1.0, 10)]);
+        let b = timed(&[(1.0, 10), (2.0, 20)]);
+
+        let merged = merge_routes(&a, &b);
+        assert_eq!(merged, timed(&[(0.0, 0), (1.0, 10), (2.0, 20)]));
+    }
+
+    #[test]
+    fn test_merge_routes_drops_overlapping_points() {
+        let a = timed(&[(0.0, 0), (1.0, 10), (2.0, 20)]);
+        let b = timed(&[(1.5, 15), (2.5, 20), (3.0, 30)]);
+
+        let merged = merge_routes(&a, &b);
+        assert_eq!(merged, timed(&[(0.0, 0), (1.0, 10), (2.0, 20), (3.0, 30)]));
+    }
+
+    #[test]
+    fn test_merge_routes_without_overlap() {
+        let a = timed(&[(0.0, 0), (1.0, 10)]);
+        let b = timed(&[(2.0, 20), (3.0, 30)]);
+
+        assert_eq!(merge_routes(&a, &b).len(), 4);
+        assert_eq!(merge_routes(&[], &b), b);
+        assert_eq!(merge_routes(&a, &[]), a);
+    }
+
+    #[test]
+    fn test_resample_irregular_route_to_fixed_interval() {
+        // Timestamps in milliseconds
+        let route = timed(&[
+            (0.0, 100_000),
+            (7.0, 107_000),
+            (10.0, 110_000),
+            (25.0, 125_000),
+            (26.0, 126_000),
+        ]);
+
+        let resampled = resample_fixed_interval(&route, 5).unwrap();
+        let timestamps: Vec<u64> = resampled.iter().filter_map(|loc| loc.timestamp).collect();
+        assert_eq!(
+            timestamps,
+            vec![100_000, 105_000, 110_000, 115_000, 120_000, 125_000]
+        );
+
+        // The route moves 1 degree per second, so longitude tracks elapsed time
+        for location in &resampled {
+            let elapsed = (location.timestamp.unwrap() - 100_000) as f64 / 1000.0;
+            assert!((location.longitude - elapsed).abs() < 1e-9);
+            assert_eq!(location.latitude, 0.0);
+        }
+    }
+
+    #[test]
+    fn test_resample_requires_timestamps() {
+        let mut route = timed(&[(0.0, 0), (1.0, 10)]);
+        assert!(resample_fixed_interval(&route, 0).is_err());
+        assert!(resample_fixed_interval(&[], 5).unwrap().is_empty());
+
+        route[1].timestamp = None;
+        assert!(resample_fixed_interval(&route, 5).is_err());
+    }
+
+    #[test]
+    fn test_simplify_to_deviation_percentile_meets_target() {
+        // A ~33 m wave with one sharp 200 m detour
+        let locations: Vec<Location> = (0..50)
+            .map(|i| {
+                let x = i as f64 * 0.0005;
+                let detour = if i == 25 { 0.0018 } else { 0.0 };
+                Location::new((i as f64 * 0.6).sin() * 0.0003 + detour, x)
+            })
+            .collect();
+        let simplifier = RouteSimplifier::new(0.1).unwrap();
+
+        let simplified = simplifier
+            .simplify_to_deviation_percentile(&locations, 95.0, 15.0)
+            .unwrap();
+
+        let deviations = deviations_meters(&locations, &simplified);
+        assert!(deviation_percentile(&deviations, 95.0) <= 15.0);
+        assert!(simplified.len() < locations.len());
+        assert_eq!(simplified.first(), locations.first());
+        assert_eq!(simplified.last(), locations.last());
+
+        // The fixed 0.1 degree tolerance would flatten the route far past the target
+        let coarse = simplifier.simplify_route(&locations).unwrap();
+        assert!(deviation_percentile(&deviations_meters(&locations, &coarse), 95.0) > 15.0);
+    }
+
+    #[test]
+    fn test_cap_point_count_keeps_exactly_the_cap() {
+        // A wave with a growing amplitude and one sharp detour
+        let locations: Vec<Location> = (0..200)
+            .map(|i| {
+                let amplitude = 0.0001 + i as f64 * 0.000002;
+                let detour = if i == 120 { 0.002 } else { 0.0 };
+                Location::new(
+                    (i as f64 * 0.3).sin() * amplitude + detour,
+                    i as f64 * 0.0002,
+                )
+            })
+            .collect();
+        let max_deviation = |simplified: &[Location]| {
+            deviations_meters(&locations, simplified)
+                .into_iter()
+                .fold(0.0, f64::max)
+        };
+
+        for cap in [2, 25, 60] {
+            let capped = cap_point_count(&locations, cap);
+            assert_eq!(capped.len(), cap);
+            assert_eq!(capped.first(), locations.first());
+            assert_eq!(capped.last(), locations.last());
+            if cap > 2 {
+                assert!(capped.contains(&locations[120]));
+            }
+
+            // Doubling the tolerance until the route fits overshoots the cap
+            let mut tolerance = 1e-6;
+            let naive = loop {
+                let simplified = RouteSimplifier::new(tolerance)
+                    .unwrap()
+                    .simplify_route(&locations)
+                    .unwrap();
+                if simplified.len() <= cap {
+                    break simplified;
+                }
+                tolerance *= 2.0;
+            };
+            assert!(naive.len() <= cap);
+            // Greedy removal is near the best the cap allows, and beats the
+            // naive search once that leaves points of the cap unused
+            assert!(max_deviation(&capped) <= max_deviation(&naive) * 1.2);
+            if naive.len() < cap * 3 / 4 {
+                assert!(max_deviation(&capped) < max_deviation(&naive));
+            }
+        }
+
+        assert_eq!(cap_point_count(&locations[..10], 25), &locations[..10]);
+    }
+
+    #[test]
+    fn test_simplify_to_count_caps_long_routes() {
+        // 1000 points winding north-east with a few sharp turns
+        let locations: Vec<Location> = (0..1000)
+            .map(|i| {
+                let t = i as f64;
+                let turn = if (i / 97) % 2 == 0 { 0.0 } else { 0.002 };
+                Location::new(t * 0.00001 + (t * 0.05).sin() * 0.0004 + turn, t * 0.0001)
+            })
+            .collect();
+        let simplifier = RouteSimplifier::new(0.0001).unwrap();
+
+        let simplified = simplifier.simplify_to_count(&locations, 50).unwrap();
+        assert!(simplified.len() <= 50);
+        assert_eq!(simplified.first(), locations.first());
+        assert_eq!(simplified.last(), locations.last());
+        assert_eq!(
+            simplifier.simplify_to_count(&locations, 50).unwrap(),
+            simplified
+        );
+
+        assert_eq!(
+            simplifier.simplify_to_count(&locations[..40], 50).unwrap(),
+            &locations[..40]
+        );
+        assert!(simplifier.simplify_to_count(&locations, 1).is_err());
+    }
+
+    #[test]
+    fn test_deviation_percentile() {
+        let deviations = [5.0, 1.0, 3.0, 2.0, 4.0];
+        assert_eq!(deviation_percentile(&deviations, 100.0), 5.0);
+        assert_eq!(deviation_percentile(&deviations, 60.0), 3.0);
+        assert_eq!(deviation_percentile(&[], 95.0), 0.0);
+    }
+
     #[test]
     fn test_route_stats() {
         let original = create_test_locations();
-        let simplified = vec![
-            Location {
-                latitude: 0.0,
-                longitude: 0.0,
-            },
-            Location {
-                latitude: 2.0,
-                longitude: 2.0,
-            },
-        ];
+        let simplified = vec![Location::new(0.0, 0.0), Location::new(2.0, 2.0)];
 
-        let stats = calculate_route_stats(&original, &simplified);
+        let stats = calculate_route_stats(&original, &simplified, DistanceUnit::Degrees);
         assert_eq!(stats.original_points, 5);
         assert_eq!(stats.simplified_points, 2);
         assert_eq!(stats.compression_ratio, 0.4);
     }
 
+    #[test]
+    fn test_route_stats_units() {
+        // Two degrees of longitude along the equator
+        let route = vec![
+            Location::new(0.0, 0.0),
+            Location::new(0.0, 1.0),
+            Location::new(0.0, 2.0),
+        ];
+
+        let degrees = calculate_route_stats(&route, &route, DistanceUnit::Degrees);
+        let meters = calculate_route_stats(&route, &route, DistanceUnit::Meters);
+        let kilometers = calculate_route_stats(&route, &route, DistanceUnit::Kilometers);
+
+        assert!((degrees.original_length - 2.0).abs() < 1e-9);
+        assert!((meters.original_length - 222_390.0).abs() < 20.0);
+        assert!((kilometers.original_length - meters.original_length / 1000.0).abs() < 1e-9);
+        assert_eq!(meters.unit, DistanceUnit::Meters);
+        assert_eq!(meters.length_difference, 0.0);
+    }
+
     #[test]
     fn test_distance_calculation() {
         let simplifier = RouteSimplifier::new(0.001).unwrap();
-        let p1 = Location {
-            latitude: 0.0,
-            longitude: 0.0,
-        };
-        let p2 = Location {
-            latitude: 3.0,
-            longitude: 4.0,
-        };
+        let p1 = Location::new(0.0, 0.0);
+        let p2 = Location::new(3.0, 4.0);
 
         let distance = simplifier.distance(&p1, &p2);
         assert!((distance - 5.0).abs() < 0.001); // 3-4-5 triangle
     }
 
+    #[test]
+    fn test_distance_metrics() {
+        let euclidean = RouteSimplifier::new(0.1).unwrap();
+        assert_eq!(euclidean.metric(), DistanceMetric::Euclidean);
+        let distance = euclidean.distance(&Location::new(0.0, 0.0), &Location::new(3.0, 4.0));
+        assert!((distance - 5.0).abs() < 1e-12);
+
+        let haversine = RouteSimplifier::with_metric(50.0, DistanceMetric::Haversine).unwrap();
+        let bogota = Location::new(4.7110, -74.0721);
+        let medellin = Location::new(6.2442, -75.5812);
+        let distance = haversine.distance(&bogota, &medellin);
+        assert!((distance - 240_000.0).abs() < 5_000.0, "{distance}");
+
+        // 0.001 degrees north of a line along the equator is ~111 m off it
+        let offset = haversine.perpendicular_distance(
+            &Location::new(0.001, 0.005),
+            &Location::new(0.0, 0.0),
+            &Location::new(0.0, 0.01),
+        );
+        assert!((offset - 111.2).abs() < 0.1, "{offset}");
+        // Beyond the end of the line, the distance is to that end
+        let beyond = haversine.perpendicular_distance(
+            &Location::new(0.0, 0.02),
+            &Location::new(0.0, 0.0),
+            &Location::new(0.0, 0.01),
+        );
+        assert!((beyond - 1_112.0).abs() < 1.0, "{beyond}");
+        assert!(RouteSimplifier::with_metric(0.0, DistanceMetric::Haversine).is_err());
+    }
+
+    #[test]
+    fn test_haversine_metric_scales_with_latitude() {
+        // The same 0.0009 degree longitude step is ~100 m at the equator but
+        // ~50 m at 60°; a 75 m radial tolerance only keeps the equator points
+        let route = |latitude: f64| -> Vec<Location> {
+            (0..5)
+                .map(|i| Location::new(latitude, i as f64 * 0.0009))
+                .collect()
+        };
+        let simplifier = RouteSimplifier::with_metric(75.0, DistanceMetric::Haversine).unwrap();
+        assert_eq!(simplifier.simplify_radial(&route(0.0)).unwrap().len(), 5);
+        assert_eq!(simplifier.simplify_radial(&route(60.0)).unwrap().len(), 3);
+    }
+
     #[test]
     fn test_tolerance_update() {
         let mut simplifier = RouteSimplifier::new(0.001).unwrap();
@@ -330,4 +1535,101 @@ mod tests {
 
         assert!(simplifier.set_tolerance(-1.0).is_err());
     }
+
+    /// Routes exercising the edge cases every simplifier must handle
+    fn invariant_routes() -> Vec<Vec<Location>> {
+        let zigzag = (0..40)
+            .map(|i| Location::new(if i % 2 == 0 { 0.0 } else { 0.002 }, i as f64 * 0.001))
+            .collect();
+        let wave = (0..200)
+            .map(|i| Location {
+                altitude: Some((i as f64 * 0.3).cos() * 50.0),
+                importance: Some(if i % 17 == 0 { 5.0 } else { 1.0 }),
+                ..Location::new((i as f64 * 0.05).sin() * 0.01, i as f64 * 0.0005)
+            })
+            .collect();
+        let parked = vec![Location::new(1.0, 1.0); 5];
+
+        vec![
+            Vec::new(),
+            vec![Location::new(1.0, 1.0)],
+            vec![Location::new(1.0, 1.0), Location::new(1.0, 2.0)],
+            create_test_locations(),
+            zigzag,
+            wave,
+            parked,
+        ]
+    }
+
+    /// Check the invariants every [`Simplifier`] must uphold on `invariant_routes`:
+    /// endpoints preserved, output no longer than input, idempotence, and an output
+    /// that maps to strictly increasing input indices.
+    pub(crate) fn assert_simplifier_invariants(name: &str, simplifier: &dyn Simplifier) {
+        check_invariants(name, simplifier, true);
+    }
+
+    /// [`assert_simplifier_invariants`] without the idempotence check, for
+    /// strategies whose target is measured against their own input
+    pub(crate) fn assert_simplifier_invariants_once(name: &str, simplifier: &dyn Simplifier) {
+        check_invariants(name, simplifier, false);
+    }
+
+    fn check_invariants(name: &str, simplifier: &dyn Simplifier, idempotent: bool) {
+        for (route_index, route) in invariant_routes().iter().enumerate() {
+            let context = format!("{} on route {}", name, route_index);
+            let simplified = simplifier.simplify(route).unwrap();
+
+            assert!(simplified.len() <= route.len(), "{}: output grew", context);
+            assert_eq!(
+                simplified.first(),
+                route.first(),
+                "{}: first point",
+                context
+            );
+            assert_eq!(simplified.last(), route.last(), "{}: last point", context);
+
+            let mut next = 0;
+            for location in &simplified {
+                let offset = route[next..].iter().position(|l| l == location);
+                let offset = offset
+                    .unwrap_or_else(|| panic!("{}: output is not an ordered subsequence", context));
+                next += offset + 1;
+            }
+
+            if idempotent {
+                let again = simplifier.simplify(&simplified).unwrap();
+                assert_eq!(again, simplified, "{}: not idempotent", context);
+            }
+        }
+    }
+
+    #[test]
+    fn test_built_in_simplifiers_meet_invariants() {
+        let simplifier = RouteSimplifier::new(0.0005).unwrap();
+        // Anchors are derived from the route itself, as on finalize
+        let anchors = |l: &[Location]| elevation_extrema(l, 10.0);
+
+        assert_simplifier_invariants("rdp", &simplifier);
+        assert_simplifier_invariants("weighted", &|l: &[Location]| {
+            simplifier.simplify_weighted(l)
+        });
+        assert_simplifier_invariants("anchored", &|l: &[Location]| {
+            simplifier.simplify_route_with_anchors(l, &anchors(l))
+        });
+        assert_simplifier_invariants("weighted anchored", &|l: &[Location]| {
+            simplifier.simplify_weighted_with_anchors(l, &anchors(l))
+        });
+        assert_simplifier_invariants("custom", &|l: &[Location]| {
+            simplifier.simplify_route_custom(l)
+        });
+        assert_simplifier_invariants("visvalingam", &|l: &[Location]| {
+            simplifier.simplify_route_visvalingam(l)
+        });
+        assert_simplifier_invariants("radial", &|l: &[Location]| simplifier.simplify_radial(l));
+        // Re-simplifying measures deviation from the already simplified line, so
+        // this strategy may drop further points on a second pass
+        assert_simplifier_invariants_once("deviation percentile", &|l: &[Location]| {
+            simplifier.simplify_to_deviation_percentile(l, 95.0, 20.0)
+        });
+    }
 }
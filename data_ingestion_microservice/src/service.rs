@@ -0,0 +1,5565 @@
+use crate::active::ActiveRoutes;
+use crate::analysis::{
+    bounding_box_overlap, detect_stops, elevation_extrema, elevation_gain_loss, fix_gaps,
+    moving_time_fraction, nearest_point_index, occupancy_summary, route_overlap,
+    sample_interval_stats, self_intersections, speed_profile, stationary_ends, straightness_index,
+    timestamp_range, within_bounding_box,
+};
+use crate::batch::AppendBuffer;
+use crate::codec::{decode_point, encode_point};
+use crate::config::{
+    ActiveRouteCapPolicy, AuthMode, Config, DuplicateRoutePolicy, EarlyFinishPolicy,
+    OverflowPolicy, ProgressSource, ProjectionMode, RouteStageKind, SimplificationAlgorithm,
+    StartupOversizePolicy,
+};
+use crate::coordinates::{normalize_edges, resolve_location};
+use crate::document::{location_from_document, location_to_document, route_lengths_document};
+use crate::error_log::ErrorLog;
+use crate::fields;
+use crate::filter::driver_allowed;
+use crate::geodesy::{haversine_distance, unwrap_longitudes, wrap_longitude};
+use crate::geofence::{evaluate_geofences, GeofenceEvent};
+use crate::health::HealthState;
+use crate::hooks::StatusHook;
+use crate::ingress::IngressMessage;
+use crate::keys::{RouteKey, EVENTS_SUFFIX, PROGRESS_PREFIX};
+use crate::metrics::{fleet_of, render_prometheus, FleetMetrics, RouteMemory, RoutePointCounts};
+use crate::pipeline::RoutePipeline;
+use crate::polyline::encode_polyline;
+use crate::privacy::stored_driver_id;
+use crate::projection::Projection;
+use crate::publisher::Publisher;
+use crate::recent::RecentKeys;
+use crate::route_simplification::{
+    anomaly_fallback, calculate_route_stats, cap_point_count, deviations_meters, merge_routes,
+    simplify_with_fallback, DistanceUnit, RouteSimplifier, Simplifier, ALGORITHM_VERSION,
+};
+use crate::sink::TripSink;
+use crate::spool::TripSpool;
+use crate::storage::{read_points_batched, PointStore, TripStore};
+use crate::types::{
+    BusMessage, BusStatus, DeadLetterKind, DeadLetterRecord, Location, MetricsSnapshot,
+    ResimplifyCommand, RouteEvent, RouteStart, ServiceError, ServiceMetrics, ServiceResult,
+    TraveledDistance, TripDocument, MILLIS_PER_SEC, SNAKE_CASE_ALIASES,
+};
+use crate::writer::TripWriter;
+
+use log::{debug, error, info, warn};
+use mongodb::bson::{self, doc, Bson, Document};
+use rumqttc::QoS;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::time::Instant;
+
+/// Stored trips of a driver compared against each new route for duplicates
+const DUPLICATE_CANDIDATE_LIMIT: usize = 50;
+
+/// Bytes of a malformed payload shown when it is logged
+const MALFORMED_PREVIEW_BYTES: usize = 64;
+
+/// Core ingestion logic shared by every MQTT message task
+#[derive(Clone)]
+pub struct IngestionService {
+    config: Arc<Config>,
+    trip_store: Arc<dyn TripStore>,
+    trip_writer: Arc<TripWriter>,
+    publisher: Arc<dyn Publisher>,
+    route_simplifier: RouteSimplifier,
+    pipeline: Arc<RoutePipeline>,
+    hooks: Vec<Arc<dyn StatusHook>>,
+    sinks: Vec<Arc<dyn TripSink>>,
+    spool: Option<Arc<TripSpool>>,
+    health: HealthState,
+    metrics: Arc<ServiceMetrics>,
+    fleet_metrics: Arc<FleetMetrics>,
+    route_memory: Arc<RouteMemory>,
+    route_points: Arc<RoutePointCounts>,
+    append_buffer: Arc<AppendBuffer>,
+    recent_finishes: Arc<RecentKeys>,
+    active_routes: Arc<ActiveRoutes>,
+    error_log: Arc<ErrorLog>,
+    processing_permits: Option<Arc<Semaphore>>,
+}
+
+impl IngestionService {
+    pub fn new(
+        config: Config,
+        trip_store: Arc<dyn TripStore>,
+        publisher: Arc<dyn Publisher>,
+    ) -> ServiceResult<Self> {
+        let route_simplifier = RouteSimplifier::new(config.route_simplification.tolerance)?;
+        let pipeline = RoutePipeline::from_config(&config.finalize);
+        let recent_finishes = RecentKeys::new(
+            config.finalize.recent_finish_cache_size,
+            Duration::from_millis(config.finalize.recent_finish_window_ms),
+        );
+        let error_log = ErrorLog::new(
+            config.logging.error_burst,
+            config.logging.error_sample_every,
+            Duration::from_secs(config.logging.error_summary_interval_secs),
+        );
+        let max_concurrent_tasks = config.processing.max_concurrent_tasks;
+        let processing_permits =
+            (max_concurrent_tasks > 0).then(|| Arc::new(Semaphore::new(max_concurrent_tasks)));
+        let trip_writer = TripWriter::new(trip_store.clone(), config.mongodb.batch_size);
+
+        Ok(Self {
+            config: Arc::new(config),
+            trip_store,
+            trip_writer: Arc::new(trip_writer),
+            publisher,
+            route_simplifier,
+            pipeline: Arc::new(pipeline),
+            hooks: Vec::new(),
+            sinks: Vec::new(),
+            spool: None,
+            health: HealthState::new(),
+            metrics: Arc::new(ServiceMetrics::default()),
+            fleet_metrics: Arc::new(FleetMetrics::new()),
+            route_memory: Arc::new(RouteMemory::new()),
+            route_points: Arc::new(RoutePointCounts::new()),
+            append_buffer: Arc::new(AppendBuffer::new()),
+            recent_finishes: Arc::new(recent_finishes),
+            active_routes: Arc::new(ActiveRoutes::new()),
+            error_log: Arc::new(error_log),
+            processing_permits,
+        })
+    }
+
+    /// Readiness state updated on every received message
+    pub fn health(&self) -> HealthState {
+        self.health.clone()
+    }
+
+    /// Snapshot of the service counters
+    pub fn metrics(&self) -> MetricsSnapshot {
+        let mut metrics = self.metrics.snapshot();
+        if let Some(permits) = &self.processing_permits {
+            let max = self.config.processing.max_concurrent_tasks;
+            metrics.processing_permits_in_use = (max - permits.available_permits()) as u64;
+        }
+        metrics
+    }
+
+    /// Service metrics in Prometheus text exposition format, for `GET /metrics`:
+    /// per fleet with `metrics.per_fleet`, plus the per-route byte estimates
+    /// when `redis.route_soft_limit_bytes` is set
+    pub fn render_prometheus(&self) -> String {
+        let fleets = self
+            .config
+            .metrics
+            .per_fleet
+            .then_some(self.fleet_metrics.as_ref());
+        let mut out = render_prometheus(&self.metrics(), fleets);
+        if self.config.redis.route_soft_limit_bytes > 0 {
+            out.push_str(&self.route_memory.render_prometheus());
+        }
+        out
+    }
+
+    /// Per-fleet counters; only populated when `metrics.per_fleet` is enabled
+    pub fn fleet_metrics(&self) -> Arc<FleetMetrics> {
+        self.fleet_metrics.clone()
+    }
+
+    /// Estimated Redis bytes per in-progress route; only tracked when
+    /// `redis.route_soft_limit_bytes` is set
+    pub fn route_memory(&self) -> Arc<RouteMemory> {
+        self.route_memory.clone()
+    }
+
+    /// Buffered point count of each in-progress route; only tracked when
+    /// `metrics.route_points_refresh_secs` is set
+    pub fn route_points(&self) -> Arc<RoutePointCounts> {
+        self.route_points.clone()
+    }
+
+    /// Buffer of finished trips inserted in batches of `mongodb.batch_size`
+    pub fn trip_writer(&self) -> Arc<TripWriter> {
+        self.trip_writer.clone()
+    }
+
+    /// Sampled log for per-message errors
+    pub fn error_log(&self) -> Arc<ErrorLog> {
+        self.error_log.clone()
+    }
+
+    /// Apply `update` to the service counters and, if enabled, to the counters
+    /// of the message's fleet
+    fn record(&self, msg: &BusMessage, update: impl Fn(&ServiceMetrics)) {
+        update(&self.metrics);
+        if self.config.metrics.per_fleet {
+            let fleet = fleet_of(&self.config.metrics, msg);
+            self.fleet_metrics.record(&fleet, update);
+        }
+    }
+
+    /// Register a hook run on every status transition
+    pub fn with_hook(mut self, hook: Arc<dyn StatusHook>) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Register a sink that receives every stored trip document
+    pub fn with_sink(mut self, sink: Arc<dyn TripSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Create the trip store indexes the configuration relies on. Called once
+    /// at startup.
+    pub async fn ensure_indexes(&self) -> ServiceResult<()> {
+        if self.config.mongodb.trip_ttl_secs > 0 {
+            // Each trip carries its own expiry date, so MongoDB deletes it right then
+            self.trip_store
+                .ensure_expiry_index(fields::EXPIRES_AT, Duration::ZERO)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Spool trips to a local file instead of failing when the trip store is down
+    pub fn with_spool(mut self, spool: Arc<TripSpool>) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
+    /// Process a message received from an `Ingress`: resimplify commands go to
+    /// [`Self::process_resimplify`], everything else to [`Self::process_message`].
+    /// Retained messages are skipped when configured, and so are messages that
+    /// waited too long to be processed. With `processing.max_concurrent_tasks`,
+    /// the message first waits for a free processing slot.
+    pub async fn process_delivery(
+        &self,
+        message: &IngressMessage,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<()> {
+        // Past `max_concurrent_tasks`, wait for another message to finish
+        let _permit = match &self.processing_permits {
+            Some(permits) => permits.acquire().await.ok(),
+            None => None,
+        };
+        let is_resimplify =
+            self.config.mqtt.resimplify_topic.as_deref() == Some(message.topic.as_str());
+        if message.retained && self.config.mqtt.ignore_retained {
+            info!("Skipping retained message on topic {}.", message.topic);
+            if !is_resimplify {
+                self.health.record_activity();
+                self.metrics.increment_messages_filtered();
+            }
+            return Ok(());
+        }
+
+        if is_resimplify {
+            return self.process_resimplify(&message.payload).await;
+        }
+
+        // Shed load under backpressure: a point this old is no longer worth storing
+        let max_age = self.config.ingestion.max_processing_age_ms;
+        let age = message.received_at.elapsed();
+        if max_age > 0 && age > Duration::from_millis(max_age) {
+            warn!(
+                "Dropping message on topic {} queued for {} ms.",
+                message.topic,
+                age.as_millis()
+            );
+            self.health.record_activity();
+            self.metrics.increment_stale_dropped();
+            return Ok(());
+        }
+        self.process_payload(&message.payload, Some(&message.topic), point_store)
+            .await
+    }
+
+    /// Process an incoming MQTT message payload.
+    /// For "in_route": store the JSON in Redis list keyed by driverId:currentRouteId.
+    /// For "finished": retrieve the list, simplify it, and store it in MongoDB.
+    /// A JSON array is processed as a batch; see [`Self::process_batch`].
+    /// A payload that does not parse is reported by [`Self::reject_malformed`].
+    pub async fn process_message(
+        &self,
+        payload: &[u8],
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<()> {
+        self.process_payload(payload, None, point_store).await
+    }
+
+    /// [`Self::process_message`] for a payload received on `topic`, if known
+    async fn process_payload(
+        &self,
+        payload: &[u8],
+        topic: Option<&str>,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<()> {
+        self.health.record_activity();
+
+        let is_batch = payload
+            .iter()
+            .find(|byte| !byte.is_ascii_whitespace())
+            .is_some_and(|&byte| byte == b'[');
+        if !is_batch {
+            let parsed = if self.config.ingestion.strict_field_names {
+                serde_json::from_slice(payload)
+                    .map_err(ServiceError::from)
+                    .and_then(|value| self.parse_bus_message(value))
+            } else {
+                serde_json::from_slice(payload).map_err(ServiceError::from)
+            };
+            return match parsed {
+                Ok(msg) => self.process_bus_message(&msg, point_store).await,
+                Err(ServiceError::Serialization(e)) => {
+                    self.reject_malformed(payload, topic, &e).await;
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            };
+        }
+
+        // Only parsing the array itself fails the whole batch with a serde error
+        let results = match self.process_batch(payload, point_store).await {
+            Ok(results) => results,
+            Err(ServiceError::Serialization(e)) => {
+                self.reject_malformed(payload, topic, &e).await;
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+        let failed = results.iter().filter(|result| result.is_err()).count();
+        if failed > 0 {
+            return Err(ServiceError::Validation(format!(
+                "{} of {} messages in batch failed",
+                failed,
+                results.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Process a JSON array of messages in order. Each element succeeds or fails
+    /// on its own; failures are logged and returned without aborting the batch.
+    pub async fn process_batch(
+        &self,
+        payload: &[u8],
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<Vec<ServiceResult<()>>> {
+        let elements: Vec<serde_json::Value> = serde_json::from_slice(payload)?;
+
+        let mut results = Vec::with_capacity(elements.len());
+        for (index, element) in elements.into_iter().enumerate() {
+            let result = match self.parse_bus_message(element) {
+                Ok(msg) => self.process_bus_message(&msg, point_store).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = &result {
+                self.error_log.error(format_args!(
+                    "Error processing batch message {}: {}",
+                    index, e
+                ));
+            }
+            results.push(result);
+        }
+
+        Ok(results)
+    }
+
+    /// Re-simplify the stored trip named by a [`ResimplifyCommand`] from its
+    /// `rawRoute` with the command's tolerance, and update the trip in place
+    pub async fn process_resimplify(&self, payload: &[u8]) -> ServiceResult<()> {
+        let command: ResimplifyCommand = serde_json::from_slice(payload)?;
+        if !command.tolerance.is_finite() {
+            return Err(ServiceError::Validation(format!(
+                "Invalid resimplify tolerance {}",
+                command.tolerance
+            )));
+        }
+        let simplification = &self.config.route_simplification;
+        let tolerance = command.tolerance.clamp(
+            simplification.min_payload_tolerance,
+            simplification.max_payload_tolerance,
+        );
+
+        let driver_id = stored_driver_id(&self.config.privacy, &command.driver_id);
+        let trip = self
+            .trip_store
+            .find_route_trip(&driver_id, &command.route_id)
+            .await?
+            .ok_or_else(|| {
+                ServiceError::Validation(format!(
+                    "No stored trip for route {}:{}",
+                    command.driver_id, command.route_id
+                ))
+            })?;
+        let raw_route = trip.get_array(fields::RAW_ROUTE).map_err(|_| {
+            ServiceError::Validation(format!(
+                "Trip of route {}:{} has no {}; enable STORE_RAW_ROUTE",
+                command.driver_id,
+                command.route_id,
+                fields::RAW_ROUTE
+            ))
+        })?;
+        let locations = raw_route
+            .iter()
+            .map(|point| match point {
+                Bson::Document(point) => location_from_document(point),
+                other => Err(ServiceError::Validation(format!(
+                    "Invalid raw route point {:?}",
+                    other
+                ))),
+            })
+            .collect::<ServiceResult<Vec<_>>>()?;
+
+        let simplified = self.simplify(&RouteSimplifier::new(tolerance)?, &locations)?;
+        let mut update = doc! {
+            fields::SIMPLIFIED_ROUTE: simplified.iter().map(|loc| {
+                location_to_document(loc, self.config.output.coordinate_storage)
+            }).collect::<Vec<_>>(),
+            fields::SIMPLIFIED_POINTS_COUNT: simplified.len() as i32,
+            fields::TOLERANCE: tolerance,
+        };
+        update.extend(route_lengths_document(&locations, &simplified));
+        if self.config.output.store_algorithm_version {
+            update.insert(fields::ALGORITHM_VERSION, ALGORITHM_VERSION);
+        }
+        if self.config.output.encoded_polyline {
+            update.insert(
+                fields::ENCODED_POLYLINE,
+                encode_polyline(&simplified, self.config.output.polyline_precision),
+            );
+        }
+        self.project_route(&mut update, &simplified)?;
+        let trip_id = trip.get("_id").cloned().unwrap_or(Bson::Null);
+        self.trip_store.update_trip(&trip_id, update).await?;
+        // A trip stored before `projected_only` was enabled still has its old
+        // WGS84 route, which no longer matches the projected one
+        if self.projected_only() && trip.contains_key(fields::SIMPLIFIED_ROUTE) {
+            self.trip_store
+                .remove_trip_fields(&trip_id, &[fields::SIMPLIFIED_ROUTE])
+                .await?;
+        }
+        info!(
+            "Re-simplified route {}:{} with tolerance {}: {} of {} points",
+            command.driver_id,
+            command.route_id,
+            tolerance,
+            simplified.len(),
+            locations.len()
+        );
+        Ok(())
+    }
+
+    /// Add the simplified route in the configured projection to a trip document
+    /// or update, dropping the WGS84 route in `projected_only` mode
+    fn project_route(&self, document: &mut Document, route: &[Location]) -> ServiceResult<()> {
+        let output = &self.config.output;
+        if output.projection_epsg == 0 {
+            return Ok(());
+        }
+
+        let projection = Projection::from_epsg(output.projection_epsg)?;
+        document.insert(fields::PROJECTION_EPSG, projection.epsg() as i32);
+        document.insert(
+            fields::PROJECTED_ROUTE,
+            route
+                .iter()
+                .map(|loc| {
+                    let (x, y) = projection.project(loc);
+                    doc! { fields::X: x, fields::Y: y }
+                })
+                .collect::<Vec<_>>(),
+        );
+        if self.projected_only() {
+            document.remove(fields::SIMPLIFIED_ROUTE);
+        }
+        Ok(())
+    }
+
+    /// Whether trips carry only the projected route, without `simplifiedRoute`
+    fn projected_only(&self) -> bool {
+        let output = &self.config.output;
+        output.projection_epsg != 0 && output.projection_mode == ProjectionMode::ProjectedOnly
+    }
+
+    /// Deserialize one message, rejecting snake_case field names in strict mode
+    fn parse_bus_message(&self, value: serde_json::Value) -> ServiceResult<BusMessage> {
+        if self.config.ingestion.strict_field_names {
+            if let Some(alias) = SNAKE_CASE_ALIASES
+                .iter()
+                .find(|alias| value.get(**alias).is_some())
+            {
+                return Err(ServiceError::Validation(format!(
+                    "Field '{}' is not accepted with STRICT_FIELD_NAMES; use camelCase",
+                    alias
+                )));
+            }
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    /// Log, count and dead-letter a payload that is not valid bus message
+    /// JSON. The message is consumed either way, so a bad publisher cannot
+    /// stall ingestion.
+    async fn reject_malformed(
+        &self,
+        payload: &[u8],
+        topic: Option<&str>,
+        error: &serde_json::Error,
+    ) {
+        self.metrics.increment_errors();
+        self.error_log.error(format_args!(
+            "Malformed payload on topic {} ({} bytes, {}): {}",
+            topic.unwrap_or("<unknown>"),
+            payload.len(),
+            payload_preview(payload),
+            error
+        ));
+
+        let Some(dead_letter_topic) = &self.config.mqtt.dead_letter_topic else {
+            return;
+        };
+        // Never feed a dead letter back to the topic it came from
+        if topic == Some(dead_letter_topic.as_str()) {
+            return;
+        }
+        if let Err(e) = self
+            .publisher
+            .publish(dead_letter_topic, payload.to_vec(), QoS::AtLeastOnce)
+            .await
+        {
+            warn!(
+                "Failed to publish malformed payload to {}: {}",
+                dead_letter_topic, e
+            );
+        }
+    }
+
+    async fn process_bus_message(
+        &self,
+        msg: &BusMessage,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<()> {
+        self.record(msg, ServiceMetrics::increment_messages_processed);
+        let result = self.dispatch_bus_message(msg, point_store).await;
+        if result.is_err() {
+            self.record(msg, ServiceMetrics::increment_errors);
+        }
+        result
+    }
+
+    /// Whether the message carries a session token valid under `auth.mode`
+    async fn session_authorized(
+        &self,
+        msg: &BusMessage,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<bool> {
+        let auth = &self.config.auth;
+        if auth.mode == AuthMode::Off {
+            return Ok(true);
+        }
+        let Some(token) = msg
+            .session_token
+            .as_deref()
+            .filter(|token| !token.is_empty())
+        else {
+            return Ok(false);
+        };
+        match auth.mode {
+            AuthMode::Off => Ok(true),
+            AuthMode::Static => Ok(auth.tokens.iter().any(|valid| valid == token)),
+            AuthMode::Redis => Ok(point_store
+                .get(&format!("{}{}", auth.redis_key_prefix, token))
+                .await?
+                .is_some()),
+        }
+    }
+
+    async fn dispatch_bus_message(
+        &self,
+        msg: &BusMessage,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<()> {
+        // A `started` message does not have to carry a position
+        let positionless_start = msg.status == BusStatus::Started
+            && !msg.has_driver_location()
+            && msg.coordinates.is_none();
+        let msg = &BusMessage {
+            driver_location: if positionless_start {
+                msg.driver_location.clone()
+            } else {
+                match resolve_location(msg, self.config.ingestion.input_coordinate_order) {
+                    Ok(location) if self.config.ingestion.normalize_edge_coordinates => {
+                        normalize_edges(&location)
+                    }
+                    Ok(location) => location,
+                    // A bad position costs the point, not the rest of the message
+                    Err(e) if msg.status == BusStatus::InRoute => {
+                        warn!(
+                            "Skipping invalid point for route {}: {}",
+                            RouteKey::from_message(msg),
+                            e
+                        );
+                        self.record(msg, ServiceMetrics::increment_invalid_points);
+                        return Ok(());
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Ignoring invalid position of {} message for route {}: {}",
+                            msg.status,
+                            RouteKey::from_message(msg),
+                            e
+                        );
+                        msg.driver_location.clone()
+                    }
+                }
+            },
+            coordinates: None,
+            ..msg.clone()
+        };
+        // `a:b` + `c` and `a` + `b:c` would share the Redis key `a:b:c`
+        if msg.driver_id.contains(':') {
+            return Err(ServiceError::Validation(format!(
+                "Driver id '{}' must not contain ':'",
+                msg.driver_id
+            )));
+        }
+        if !self.session_authorized(msg, point_store).await? {
+            self.record(msg, ServiceMetrics::increment_messages_unauthorized);
+            return Err(ServiceError::Unauthorized(format!(
+                "Missing or invalid session token from driver {}",
+                msg.driver_id
+            )));
+        }
+        if !driver_allowed(&self.config.ingestion, &msg.driver_id) {
+            info!("Skipping message from filtered driver {}.", msg.driver_id);
+            self.record(msg, ServiceMetrics::increment_messages_filtered);
+            return Ok(());
+        }
+
+        match msg.status {
+            BusStatus::Started => self.start_route(msg, point_store).await?,
+            BusStatus::InRoute => {
+                if self.config.ingestion.require_started
+                    && point_store
+                        .get(&RouteKey::from_message(msg).start_key())
+                        .await?
+                        .is_none()
+                {
+                    info!(
+                        "Skipping stray point for route {} with no started message.",
+                        RouteKey::from_message(msg)
+                    );
+                    self.record(msg, ServiceMetrics::increment_messages_filtered);
+                    return Ok(());
+                }
+                if !self.store_point(msg, point_store).await? {
+                    return Ok(());
+                }
+            }
+            BusStatus::Finished => {
+                if !self.finish_once(msg, point_store).await? {
+                    return Ok(());
+                }
+            }
+        }
+
+        self.run_hooks(msg).await;
+        Ok(())
+    }
+
+    /// Record the start of a route and its planned length and destination,
+    /// without storing a point
+    async fn start_route(
+        &self,
+        msg: &BusMessage,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<()> {
+        let key = RouteKey::from_message(msg);
+        let start = RouteStart {
+            started_at: msg.timestamp,
+            planned_length_meters: msg.planned_length_meters,
+            destination: msg.destination.clone(),
+        };
+        point_store
+            .set(&key.start_key(), serde_json::to_string(&start)?)
+            .await?;
+        info!("Route {} started.", key);
+        Ok(())
+    }
+
+    /// Append an `in_route` point; returns `false` if it was skipped as a duplicate
+    async fn store_point(
+        &self,
+        msg: &BusMessage,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<bool> {
+        let key = RouteKey::from_message(msg);
+
+        // Skip points redelivered by MQTT QoS 1
+        let ingestion = &self.config.ingestion;
+        if ingestion.dedup_points {
+            let first_delivery = point_store
+                .mark_seen(&key.seen_key(), &msg.dedup_id(), ingestion.dedup_ttl_secs)
+                .await?;
+            if !first_delivery {
+                info!("Skipping duplicate point for key {}.", key);
+                return Ok(false);
+            }
+        }
+
+        // Store the encoded location in Redis, stamped with the message time
+        let mut location = msg.driver_location.clone();
+        location.timestamp.get_or_insert(msg.timestamp);
+        if location.occupancy.is_none() {
+            location.occupancy = msg.occupancy;
+        }
+        let encoded = encode_point(&location, self.config.redis.point_encoding)?;
+        if self.config.redis.route_soft_limit_bytes > 0
+            && !self
+                .account_point(&key, &location, encoded.len(), point_store)
+                .await?
+        {
+            return Ok(false);
+        }
+        let evicted = self.active_routes.touch(
+            &msg.driver_id,
+            &msg.current_route_id,
+            ingestion.max_active_routes_per_driver,
+        );
+        for route_id in evicted {
+            self.evict_route(msg, route_id, point_store).await;
+        }
+        self.append_point(&key, encoded, point_store).await?;
+        if self.config.output.route_events && !msg.events.is_empty() {
+            self.store_events(msg, &key, &location, point_store).await?;
+        }
+        // Progress is informational: a failure must not lose the stored point
+        if self.config.progress.source != ProgressSource::Off {
+            if let Err(e) = self
+                .update_progress(msg, &key, &location, point_store)
+                .await
+            {
+                warn!("Failed to update progress for key {}: {}", key, e);
+                self.metrics.increment_errors();
+            }
+        }
+        if self.config.metrics.route_points_refresh_secs > 0 {
+            self.route_points.track(&key.to_string());
+        }
+        if self.config.logging.debug_echo {
+            self.echo_point(msg, &key, &location, point_store).await;
+        }
+        info!("Stored location for key {} in Redis.", key);
+
+        Ok(true)
+    }
+
+    /// Append the events sent with a point to the route's event list, stamped
+    /// with the point's time and position where they carry none
+    async fn store_events(
+        &self,
+        msg: &BusMessage,
+        key: &RouteKey,
+        location: &Location,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<()> {
+        let events = msg
+            .events
+            .iter()
+            .map(|event| {
+                let mut event = event.clone();
+                event.timestamp.get_or_insert(msg.timestamp);
+                event
+                    .location
+                    .get_or_insert_with(|| Location::new(location.latitude, location.longitude));
+                Ok(serde_json::to_vec(&event)?)
+            })
+            .collect::<ServiceResult<Vec<_>>>()?;
+        point_store.push_many(&key.events_key(), &events).await
+    }
+
+    /// Events of a route grouped by the index of the nearest point of `route`.
+    /// Entries that no longer parse are logged and skipped.
+    async fn route_events_by_point(
+        &self,
+        key: &RouteKey,
+        route: &[Location],
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<BTreeMap<usize, Vec<RouteEvent>>> {
+        let mut by_point: BTreeMap<usize, Vec<RouteEvent>> = BTreeMap::new();
+        for entry in point_store.range(&key.events_key(), 0, -1).await? {
+            let event: RouteEvent = match serde_json::from_slice(&entry) {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("Skipping unreadable event of route {}: {}", key, e);
+                    continue;
+                }
+            };
+            let nearest = event
+                .location
+                .as_ref()
+                .and_then(|location| nearest_point_index(route, location));
+            if let Some(index) = nearest {
+                by_point.entry(index).or_default().push(event);
+            }
+        }
+        Ok(by_point)
+    }
+
+    /// Publish a stored point with its position in the route to the debug echo
+    /// topic. Failures are logged only.
+    async fn echo_point(
+        &self,
+        msg: &BusMessage,
+        key: &RouteKey,
+        location: &Location,
+        point_store: &dyn PointStore,
+    ) {
+        let key = key.to_string();
+        let route_length = match point_store.len(&key).await {
+            Ok(stored) => stored + self.append_buffer.pending(&key),
+            Err(e) => {
+                warn!(
+                    "Failed to read the length of {} for the debug echo: {}",
+                    key, e
+                );
+                return;
+            }
+        };
+        let payload = serde_json::json!({
+            fields::DRIVER_ID: msg.driver_id,
+            fields::CURRENT_ROUTE_ID: msg.current_route_id,
+            fields::SEQUENCE: route_length.saturating_sub(1),
+            fields::ROUTE_LENGTH: route_length,
+            fields::LATITUDE: location.latitude,
+            fields::LONGITUDE: location.longitude,
+            fields::TIMESTAMP: location.timestamp,
+        });
+
+        let topic = &self.config.logging.debug_echo_topic;
+        if let Err(e) = self
+            .publisher
+            .publish(topic, payload.to_string().into_bytes(), QoS::AtMostOnce)
+            .await
+        {
+            warn!("Failed to publish debug echo to {}: {}", topic, e);
+        }
+    }
+
+    /// Append an encoded point to the route list, or buffer it until a batch of
+    /// `redis.append_batch_size` points is ready
+    async fn append_point(
+        &self,
+        key: &RouteKey,
+        encoded: Vec<u8>,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<()> {
+        let batch_size = self.config.redis.append_batch_size;
+        let key = key.to_string();
+        if batch_size == 0 {
+            return point_store.push(&key, encoded).await;
+        }
+        match self.append_buffer.push(&key, encoded, batch_size) {
+            Some(points) => self.append_batch(&key, points, point_store).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Append the buffered points of one route, before its list is read
+    async fn flush_route_appends(
+        &self,
+        key: &RouteKey,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<()> {
+        let key = key.to_string();
+        let points = self.append_buffer.take(&key);
+        self.append_batch(&key, points, point_store).await
+    }
+
+    /// Append the buffered points of every route. Run periodically by
+    /// `run_append_flusher` and once more on shutdown.
+    pub async fn flush_appends(&self, point_store: &dyn PointStore) -> ServiceResult<()> {
+        let mut result = Ok(());
+        for (key, points) in self.append_buffer.take_all() {
+            if let Err(e) = self.append_batch(&key, points, point_store).await {
+                warn!("Failed to append buffered points of {}: {}", key, e);
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    /// Flush the buffered points every `redis.append_flush_interval_ms`, so
+    /// none waits longer than that for its batch to fill. Never returns.
+    pub async fn run_append_flusher(self, point_store: impl PointStore) {
+        let period = Duration::from_millis(self.config.redis.append_flush_interval_ms);
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            let _ = self.flush_appends(&point_store).await;
+        }
+    }
+
+    /// Append a batch in one call; on failure the points are buffered again
+    async fn append_batch(
+        &self,
+        key: &str,
+        points: Vec<Vec<u8>>,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<()> {
+        if points.is_empty() {
+            return Ok(());
+        }
+        if let Err(e) = point_store.push_many(key, &points).await {
+            self.append_buffer.restore(key, points);
+            return Err(e);
+        }
+        debug!("Appended {} buffered points to {}", points.len(), key);
+        Ok(())
+    }
+
+    /// Scan the stored route lists for ones longer than `startup_max_points`
+    /// and handle them per `startup_oversize_policy`, so a backlog left by a
+    /// long outage does not stall the first finalize. Dead-letter lists are
+    /// left alone. Returns the number of oversized lists found.
+    pub async fn recover_oversized_routes(
+        &self,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<usize> {
+        let redis = &self.config.redis;
+        if redis.startup_max_points == 0 {
+            return Ok(0);
+        }
+
+        let dead_letter_prefix = format!("{}:", self.config.finalize.dead_letter_prefix);
+        let mut oversized = 0;
+        for list_key in point_store.list_keys().await? {
+            if list_key.starts_with(&dead_letter_prefix)
+                || list_key.starts_with(PROGRESS_PREFIX)
+                || list_key.ends_with(EVENTS_SUFFIX)
+            {
+                continue;
+            }
+            let Ok(route_key) = list_key
+                .strip_suffix(":finalizing")
+                .unwrap_or(&list_key)
+                .parse::<RouteKey>()
+            else {
+                continue;
+            };
+            let len = point_store.len(&list_key).await?;
+            if len <= redis.startup_max_points {
+                continue;
+            }
+
+            oversized += 1;
+            warn!(
+                "Route list {} holds {} points, over the startup limit of {} ({:?})",
+                list_key, len, redis.startup_max_points, redis.startup_oversize_policy
+            );
+            match redis.startup_oversize_policy {
+                StartupOversizePolicy::Log => {}
+                StartupOversizePolicy::Trim => {
+                    let keep = redis.startup_max_points as isize;
+                    point_store.trim(&list_key, -keep, -1).await?;
+                }
+                StartupOversizePolicy::Finalize => {
+                    // Stamp the trip with the time of its latest point
+                    let timestamp = match point_store.range(&list_key, -1, -1).await?.first() {
+                        Some(bytes) => decode_point(bytes)?.timestamp.unwrap_or_default(),
+                        None => 0,
+                    };
+                    let msg = BusMessage {
+                        driver_id: route_key.driver_id,
+                        current_route_id: route_key.route_id,
+                        timestamp,
+                        status: BusStatus::Finished,
+                        ..Default::default()
+                    };
+                    self.finish_once(&msg, point_store).await?;
+                }
+            }
+        }
+        Ok(oversized)
+    }
+
+    /// Handle the oldest route of a driver that opened more routes than allowed.
+    /// Failures are logged only so they never block the driver's current route.
+    async fn evict_route(&self, msg: &BusMessage, route_id: String, point_store: &dyn PointStore) {
+        let stale = BusMessage {
+            current_route_id: route_id,
+            status: BusStatus::Finished,
+            ..msg.clone()
+        };
+        let key = RouteKey::from_message(&stale);
+        warn!(
+            "Driver {} is over {} active routes; evicting route {} ({:?})",
+            msg.driver_id,
+            self.config.ingestion.max_active_routes_per_driver,
+            key,
+            self.config.ingestion.active_route_cap_policy
+        );
+        self.record(msg, ServiceMetrics::increment_routes_evicted);
+
+        let result = match self.config.ingestion.active_route_cap_policy {
+            ActiveRouteCapPolicy::Finalize => self.finish_once(&stale, point_store).await.map(drop),
+            ActiveRouteCapPolicy::DeadLetter => self.dead_letter_route(&key, point_store).await,
+        };
+        if let Err(e) = result {
+            warn!("Failed to evict route {}: {}", key, e);
+        }
+    }
+
+    /// Move the live points of a route to its dead-letter key
+    async fn dead_letter_route(
+        &self,
+        key: &RouteKey,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<()> {
+        self.flush_route_appends(key, point_store).await?;
+        let live_key = key.to_string();
+        let count = point_store.len(&live_key).await?;
+        if count > 0 {
+            self.record_dead_letter(
+                key,
+                DeadLetterKind::ActiveRouteCap,
+                None,
+                Some((&live_key, count)),
+                point_store,
+            )
+            .await?;
+        }
+        self.clear_route(key, point_store).await
+    }
+
+    /// Append a record of `kind` to the route's dead-letter list, first moving
+    /// the `count` points at `points` to the route's dead-letter points key.
+    /// Returns the dead-letter list key.
+    async fn record_dead_letter(
+        &self,
+        key: &RouteKey,
+        kind: DeadLetterKind,
+        message: Option<&BusMessage>,
+        points: Option<(&str, usize)>,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<String> {
+        let prefix = &self.config.finalize.dead_letter_prefix;
+        let mut record = DeadLetterRecord {
+            kind,
+            driver_id: key.driver_id.clone(),
+            current_route_id: key.route_id.clone(),
+            message: message.cloned(),
+            points_key: None,
+            points_count: None,
+        };
+        if let Some((points_key, count)) = points {
+            let dead_letter_points_key = key.dead_letter_points_key(prefix);
+            point_store
+                .rename(points_key, &dead_letter_points_key)
+                .await?;
+            record.points_key = Some(dead_letter_points_key);
+            record.points_count = Some(count);
+        }
+        let dead_letter_key = key.dead_letter_key(prefix);
+        point_store
+            .push(&dead_letter_key, serde_json::to_vec(&record)?)
+            .await?;
+        Ok(dead_letter_key)
+    }
+
+    /// Add a point of `size` bytes to the route's memory estimate. Once the route
+    /// is over its soft limit, points arriving within `over_limit_min_interval_secs`
+    /// of the last stored one are dropped instead; returns `false` for those.
+    async fn account_point(
+        &self,
+        key: &RouteKey,
+        location: &Location,
+        size: usize,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<bool> {
+        let redis = &self.config.redis;
+        let bytes_key = key.bytes_key();
+        let stored: u64 = point_store
+            .get(&bytes_key)
+            .await?
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+
+        if stored >= redis.route_soft_limit_bytes {
+            self.flush_route_appends(key, point_store).await?;
+            let previous = point_store.range(&key.to_string(), -1, -1).await?;
+            let previous_timestamp = match previous.first() {
+                Some(previous) => decode_point(previous)?.timestamp,
+                None => None,
+            };
+            if let (Some(previous), Some(current)) = (previous_timestamp, location.timestamp) {
+                let min_interval_ms = redis
+                    .over_limit_min_interval_secs
+                    .saturating_mul(MILLIS_PER_SEC);
+                if current.saturating_sub(previous) < min_interval_ms {
+                    debug!("Route {} is over its soft limit; downsampling point", key);
+                    return Ok(false);
+                }
+            }
+        }
+
+        let stored = stored + size as u64;
+        point_store.set(&bytes_key, stored.to_string()).await?;
+        self.route_memory.set(&key.to_string(), stored);
+        Ok(true)
+    }
+
+    /// Add the leg to `location` to the driver's distance traveled and report
+    /// progress against the planned route length. The distance restarts when
+    /// the driver moves on to another route.
+    async fn update_progress(
+        &self,
+        msg: &BusMessage,
+        key: &RouteKey,
+        location: &Location,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<()> {
+        let traveled_key = key.traveled_key();
+        let previous = point_store
+            .get(&traveled_key)
+            .await?
+            .and_then(|value| serde_json::from_str::<TraveledDistance>(&value).ok())
+            .filter(|previous| previous.current_route_id == key.route_id);
+        let traveled = previous.map_or(0.0, |previous| {
+            previous.traveled_meters + haversine_distance(&previous.last, location)
+        });
+        let state = TraveledDistance {
+            current_route_id: key.route_id.clone(),
+            traveled_meters: traveled,
+            last: location.clone(),
+        };
+        point_store
+            .set(&traveled_key, serde_json::to_string(&state)?)
+            .await?;
+
+        let planned = match self.config.progress.source {
+            ProgressSource::Off => None,
+            ProgressSource::PlannedLength => msg.planned_length_meters,
+            ProgressSource::Destination => msg
+                .destination
+                .as_ref()
+                .map(|destination| traveled + haversine_distance(location, destination)),
+        };
+        let Some(planned) = planned.filter(|&planned| planned > 0.0) else {
+            return Ok(());
+        };
+
+        let progress = (traveled / planned * 100.0).min(100.0);
+        let payload = serde_json::json!({
+            fields::DRIVER_ID: msg.driver_id,
+            fields::CURRENT_ROUTE_ID: msg.current_route_id,
+            fields::PROGRESS_PERCENT: progress,
+        })
+        .to_string();
+        point_store
+            .set(&key.progress_key(), payload.clone())
+            .await?;
+        if let Some(topic) = &self.config.progress.topic {
+            self.publisher
+                .publish(topic, payload.into_bytes(), QoS::AtMostOnce)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Simplify a raw route according to the configured simplification options.
+    /// Routes crossing the antimeridian are simplified on unwrapped longitudes.
+    fn simplify(
+        &self,
+        simplifier: &RouteSimplifier,
+        locations: &[Location],
+    ) -> ServiceResult<Vec<Location>> {
+        Ok(self.simplify_chain(simplifier, locations)?.0)
+    }
+
+    /// Simplify with the first algorithm of `SIMPLIFICATION_CHAIN` that
+    /// succeeds within the time budget, returning which one it was
+    fn simplify_chain(
+        &self,
+        simplifier: &RouteSimplifier,
+        locations: &[Location],
+    ) -> ServiceResult<(Vec<Location>, SimplificationAlgorithm)> {
+        let simplification = &self.config.route_simplification;
+        let chain = if simplification.fallback_chain.is_empty() {
+            &[SimplificationAlgorithm::Rdp][..]
+        } else {
+            &simplification.fallback_chain[..]
+        };
+        let attempts: Vec<_> = chain
+            .iter()
+            .map(|&algorithm| {
+                move |locations: &[Location]| self.simplify_using(algorithm, simplifier, locations)
+            })
+            .collect();
+        let attempts: Vec<&dyn Simplifier> = attempts
+            .iter()
+            .map(|attempt| attempt as &dyn Simplifier)
+            .collect();
+        let budget = (simplification.time_budget_ms > 0)
+            .then(|| Duration::from_millis(simplification.time_budget_ms));
+
+        let (simplified, position) = simplify_with_fallback(&attempts, locations, budget)?;
+        Ok((simplified, chain[position]))
+    }
+
+    fn simplify_using(
+        &self,
+        algorithm: SimplificationAlgorithm,
+        simplifier: &RouteSimplifier,
+        locations: &[Location],
+    ) -> ServiceResult<Vec<Location>> {
+        let run = |locations: &[Location]| match algorithm {
+            SimplificationAlgorithm::Rdp => self.simplify_with_edges(simplifier, locations),
+            SimplificationAlgorithm::Visvalingam => {
+                simplifier.simplify_route_visvalingam(locations)
+            }
+            SimplificationAlgorithm::Radial => simplifier.simplify_radial(locations),
+        };
+        if !self.config.route_simplification.unwrap_antimeridian {
+            return run(locations);
+        }
+
+        let unwrapped = unwrap_longitudes(locations);
+        let mut simplified = run(&unwrapped)?;
+        for location in &mut simplified {
+            location.longitude = wrap_longitude(location.longitude);
+        }
+        Ok(simplified)
+    }
+
+    /// The first and last `edge_points` points use the coarser edge tolerance
+    fn simplify_with_edges(
+        &self,
+        simplifier: &RouteSimplifier,
+        locations: &[Location],
+    ) -> ServiceResult<Vec<Location>> {
+        let simplification = &self.config.route_simplification;
+        if simplification.deviation_percentile > 0.0 {
+            return simplifier.simplify_to_deviation_percentile(
+                locations,
+                simplification.deviation_percentile,
+                simplification.deviation_max_meters,
+            );
+        }
+
+        let edge_points = simplification
+            .edge_points
+            .min(locations.len().saturating_sub(1) / 2);
+        if edge_points == 0 {
+            return self.simplify_span(simplifier, locations);
+        }
+
+        let edge_simplifier = RouteSimplifier::new(simplification.edge_tolerance)?;
+        let last = locations.len() - 1;
+        let mut simplified = self.simplify_span(&edge_simplifier, &locations[..=edge_points])?;
+        for (span_simplifier, span) in [
+            (simplifier, &locations[edge_points..=last - edge_points]),
+            (&edge_simplifier, &locations[last - edge_points..]),
+        ] {
+            // Each span starts on the point the previous one ended with
+            simplified.extend(
+                self.simplify_span(span_simplifier, span)?
+                    .into_iter()
+                    .skip(1),
+            );
+        }
+
+        Ok(simplified)
+    }
+
+    fn simplify_span(
+        &self,
+        simplifier: &RouteSimplifier,
+        locations: &[Location],
+    ) -> ServiceResult<Vec<Location>> {
+        let simplification = &self.config.route_simplification;
+        let weighted = simplification.weighted_by_importance;
+
+        if simplification.preserve_elevation_extrema || simplification.preserve_stops {
+            let mut anchors = Vec::new();
+            if simplification.preserve_elevation_extrema {
+                anchors.extend(elevation_extrema(
+                    locations,
+                    simplification.elevation_extrema_min_meters,
+                ));
+            }
+            if simplification.preserve_stops {
+                // Keep where each stop began and ended so the halt stays visible
+                for (first, last) in detect_stops(
+                    locations,
+                    simplification.stop_radius_meters,
+                    simplification.stop_min_dwell_secs,
+                ) {
+                    anchors.extend([first, last]);
+                }
+            }
+            if weighted {
+                simplifier.simplify_weighted_with_anchors(locations, &anchors)
+            } else {
+                simplifier.simplify_route_with_anchors(locations, &anchors)
+            }
+        } else if weighted {
+            simplifier.simplify_weighted(locations)
+        } else {
+            simplifier.simplify_route(locations)
+        }
+    }
+
+    /// Finalize a route unless this instance finalized it moments ago; returns
+    /// `false` if the `finished` was absorbed as a duplicate. Failed or empty
+    /// finalizations release the claim so a retry is processed in full.
+    async fn finish_once(
+        &self,
+        msg: &BusMessage,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<bool> {
+        let key = RouteKey::from_message(msg).to_string();
+        if !self.recent_finishes.claim(&key) {
+            info!("Skipping duplicate finished for key {}.", key);
+            self.record(msg, ServiceMetrics::increment_messages_filtered);
+            return Ok(false);
+        }
+
+        let result = self.finalize_route(msg, point_store).await;
+        if matches!(result, Ok(true)) {
+            self.active_routes
+                .remove(&msg.driver_id, &msg.current_route_id);
+        } else {
+            self.recent_finishes.release(&key);
+        }
+        result.map(|_| true)
+    }
+
+    /// Turn the stored points of a route into a trip. Returns `true` once the
+    /// route is consumed (stored, discarded or dead-lettered), `false` when there
+    /// were no points to finalize.
+    async fn finalize_route(
+        &self,
+        msg: &BusMessage,
+        point_store: &dyn PointStore,
+    ) -> ServiceResult<bool> {
+        let route_key = RouteKey::from_message(msg);
+        let key = route_key.to_string();
+        let finalizing_key = route_key.finalizing_key();
+
+        // A `finished` may overtake its own points when messages are reordered
+        self.flush_route_appends(&route_key, point_store).await?;
+        let finalize = &self.config.finalize;
+        let resuming = point_store.len(&finalizing_key).await? > 0;
+        if !resuming && point_store.len(&key).await? == 0 {
+            match finalize.early_finish_policy {
+                EarlyFinishPolicy::Ignore => {}
+                EarlyFinishPolicy::Wait => {
+                    warn!(
+                        "Route {} finished before any point; waiting {} ms",
+                        key, finalize.early_finish_grace_ms
+                    );
+                    tokio::time::sleep(Duration::from_millis(finalize.early_finish_grace_ms)).await;
+                }
+                EarlyFinishPolicy::DeadLetter => {
+                    let dead_letter_key = self
+                        .record_dead_letter(
+                            &route_key,
+                            DeadLetterKind::EarlyFinish,
+                            Some(msg),
+                            None,
+                            point_store,
+                        )
+                        .await?;
+                    return Err(ServiceError::RouteProcessing(format!(
+                        "Route {} finished before any point; recorded in {}",
+                        key, dead_letter_key
+                    )));
+                }
+            }
+        }
+
+        // Detach the points from the live list in one atomic RENAME, so points
+        // appended while the route is read start a new list instead of being
+        // deleted unread. A finalize that failed left its points detached; resume it.
+        if resuming {
+            warn!("Resuming interrupted finalize of route {}", key);
+        } else {
+            // Points buffered during an early-finish wait
+            self.flush_route_appends(&route_key, point_store).await?;
+            if point_store.len(&key).await? > 0 {
+                point_store.rename(&key, &finalizing_key).await?;
+            }
+        }
+
+        // Guard against runaway routes before loading them into memory
+        let mut offset = 0;
+        if finalize.max_points > 0 {
+            let stored = point_store.len(&finalizing_key).await?;
+            if stored > finalize.max_points {
+                match finalize.overflow_policy {
+                    OverflowPolicy::Truncate => {
+                        warn!(
+                            "Route {} has {} points, over the cap of {}; keeping the most recent",
+                            key, stored, finalize.max_points
+                        );
+                        offset = stored - finalize.max_points;
+                    }
+                    OverflowPolicy::DeadLetter => {
+                        let dead_letter_key = self
+                            .record_dead_letter(
+                                &route_key,
+                                DeadLetterKind::Overflow,
+                                None,
+                                Some((&finalizing_key, stored)),
+                                point_store,
+                            )
+                            .await?;
+                        error!(
+                            "Route {} has {} points, over the cap of {}; recorded in {}",
+                            key, stored, finalize.max_points, dead_letter_key
+                        );
+                        self.clear_route(&route_key, point_store).await?;
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+
+        // Retrieve all stored points from Redis in batches
+        let locations = read_points_batched(
+            point_store,
+            &finalizing_key,
+            offset,
+            self.config.redis.finalize_batch_size,
+            self.config.redis.skip_unreadable_points,
+        )
+        .await?;
+        if locations.is_empty() {
+            info!("No stored points for key {}.", key);
+            return Ok(false);
+        }
+        let original_points = locations.len();
+        let time_range = timestamp_range(&locations);
+        let route_start: Option<RouteStart> = point_store
+            .get(&route_key.start_key())
+            .await?
+            .and_then(|start| serde_json::from_str(&start).ok());
+
+        let raw_endpoints = self.config.output.store_raw_endpoints.then(|| {
+            [&locations[0], &locations[locations.len() - 1]].map(|loc| {
+                let mut point = location_to_document(loc, self.config.output.coordinate_storage);
+                if let Some(timestamp) = loc.timestamp {
+                    point.insert(fields::TIMESTAMP, timestamp as i64);
+                }
+                point
+            })
+        });
+
+        // Dropouts are measured on the fixes as received, before any downsampling
+        let gaps = (finalize.gap_threshold_secs > 0)
+            .then(|| fix_gaps(&locations, finalize.gap_threshold_secs))
+            .flatten();
+
+        // Clean the raw route with the configured preprocessing stages, unless
+        // simplification is disabled for debugging and the raw route is stored as is
+        let raw_only = self.config.route_simplification.disabled;
+        let low_accuracy_points = finalize
+            .stages
+            .contains(&RouteStageKind::Accuracy)
+            .then(|| {
+                locations
+                    .iter()
+                    .filter(|loc| loc.accuracy.is_some_and(|a| a > finalize.max_accuracy))
+                    .count()
+            });
+        let locations = if raw_only {
+            locations
+        } else {
+            self.pipeline.apply(&locations)?
+        };
+        if locations.len() != original_points {
+            debug!(
+                "Route {} stages {:?} kept {} of {} points",
+                key,
+                self.pipeline.stage_names(),
+                locations.len(),
+                original_points
+            );
+        }
+
+        // Start and end the route where the vehicle actually moves
+        let mut trimmed = None;
+        let locations = if finalize.trim_stationary_meters > 0.0 && !raw_only {
+            let (leading, trailing) = stationary_ends(&locations, finalize.trim_stationary_meters);
+            trimmed = Some((leading, trailing));
+            if leading + trailing > 0 {
+                debug!(
+                    "Route {} trimmed {} leading and {} trailing stationary points",
+                    key, leading, trailing
+                );
+            }
+            locations[leading..locations.len() - trailing].to_vec()
+        } else {
+            locations
+        };
+
+        // Drop trips that were mostly parked
+        let moving_fraction = moving_time_fraction(&locations, finalize.min_moving_speed_mps);
+        if let Some(fraction) = moving_fraction {
+            if fraction < finalize.min_moving_fraction {
+                info!(
+                    "Route {} was moving {:.0}% of the time; discarding trip",
+                    key,
+                    fraction * 100.0
+                );
+                self.clear_route(&route_key, point_store).await?;
+                return Ok(true);
+            }
+        }
+
+        // Simplify the route using the Ramer-Douglas-Peucker algorithm, honoring a
+        // publisher-provided tolerance within the configured safe range
+        let simplification = &self.config.route_simplification;
+        let route_simplifier = match msg.tolerance.filter(|tolerance| tolerance.is_finite()) {
+            Some(tolerance) => RouteSimplifier::new(tolerance.clamp(
+                simplification.min_payload_tolerance,
+                simplification.max_payload_tolerance,
+            ))?,
+            None => self.route_simplifier.clone(),
+        };
+        // With simplification disabled for debugging, the raw route is stored as is
+        let simplify_started = Instant::now();
+        let mut simplify_passes = u32::from(!simplification.disabled);
+        let mut algorithm = None;
+        let mut simplified_locations = if simplification.disabled {
+            locations.clone()
+        } else {
+            let (simplified, used) = self.simplify_chain(&route_simplifier, &locations)?;
+            algorithm = Some(used);
+            simplified
+        };
+
+        // Re-simplify with a finer tolerance while the route length drifts too far
+        let mut length_preserved = None;
+        if simplification.max_length_difference_meters > 0.0 && !simplification.disabled {
+            let mut simplifier = route_simplifier.clone();
+            let mut attempts = 0;
+            loop {
+                let stats =
+                    calculate_route_stats(&locations, &simplified_locations, DistanceUnit::Meters);
+                if stats.length_difference <= simplification.max_length_difference_meters {
+                    length_preserved = Some(true);
+                    break;
+                }
+                if attempts == simplification.length_check_attempts {
+                    warn!(
+                        "Route {} length still differs by {:.1} m after {} attempts",
+                        key, stats.length_difference, attempts
+                    );
+                    length_preserved = Some(false);
+                    break;
+                }
+                attempts += 1;
+                simplify_passes += 1;
+                simplifier.set_tolerance(simplifier.tolerance() / 2.0)?;
+                let (simplified, used) = self.simplify_chain(&simplifier, &locations)?;
+                simplified_locations = simplified;
+                algorithm = Some(used);
+            }
+        }
+        let simplify_micros = simplify_started.elapsed().as_micros();
+
+        // A route of two or more points never legitimately simplifies to fewer
+        let fallback = anomaly_fallback(
+            &locations,
+            &simplified_locations,
+            simplification.anomaly_fallback,
+        );
+        let simplification_anomaly = fallback.is_some();
+        if let Some(fallback) = fallback {
+            error!(
+                "Route {} simplified from {} to {} points; storing {:?} instead",
+                key,
+                locations.len(),
+                simplified_locations.len(),
+                simplification.anomaly_fallback
+            );
+            simplified_locations = fallback;
+        }
+
+        // Keep full fidelity when simplification removes too few points to matter
+        let reduction = 1.0 - simplified_locations.len() as f64 / locations.len() as f64;
+        let mut simplification_skipped =
+            !simplification.disabled && reduction < simplification.min_reduction_fraction;
+        if simplification_skipped {
+            info!(
+                "Route {} simplification removed only {:.1}% of points; keeping the raw route",
+                key,
+                reduction * 100.0
+            );
+            simplified_locations = locations.clone();
+        }
+
+        // Over the point cap, the kept points are chosen again from the whole
+        // route, so each removal is weighed against every original point
+        let mut capped_deviation = None;
+        let max_points = simplification.max_output_points;
+        if max_points > 0 && !simplification.disabled && simplified_locations.len() > max_points {
+            simplified_locations = if simplification.unwrap_antimeridian {
+                let mut capped = cap_point_count(&unwrap_longitudes(&locations), max_points);
+                for location in &mut capped {
+                    location.longitude = wrap_longitude(location.longitude);
+                }
+                capped
+            } else {
+                cap_point_count(&locations, max_points)
+            };
+            let deviation = deviations_meters(&locations, &simplified_locations)
+                .into_iter()
+                .fold(0.0, f64::max);
+            info!(
+                "Route {} capped at {} points, max deviation {:.1} m",
+                key, max_points, deviation
+            );
+            simplification_skipped = false;
+            capped_deviation = Some(deviation);
+        }
+
+        // Coarser copies of the route for zoom-dependent rendering
+        let lods = if simplification.disabled {
+            Vec::new()
+        } else {
+            simplification
+                .lod_tolerances
+                .iter()
+                .map(|&tolerance| self.simplify(&RouteSimplifier::new(tolerance)?, &locations))
+                .collect::<ServiceResult<Vec<_>>>()?
+        };
+
+        // Simplification only drops points, so nothing may fall outside the original box
+        let bounding_box_mismatch = simplification.validate_bounding_box
+            && !within_bounding_box(
+                &locations,
+                &simplified_locations,
+                simplification.bounding_box_margin_degrees,
+            );
+        if bounding_box_mismatch {
+            warn!(
+                "Route {} simplified route leaves the original bounding box",
+                key
+            );
+        }
+
+        info!(
+            "Route {} finished. Original: {} points, Simplified: {} points",
+            key,
+            original_points,
+            simplified_locations.len()
+        );
+
+        // Detect geofence crossings on the raw route
+        let geofence_config = &self.config.geofence;
+        let geofence_events = evaluate_geofences(&locations, &geofence_config.geofences);
+        if !geofence_events.is_empty() {
+            info!(
+                "Route {} produced {} geofence events",
+                key,
+                geofence_events.len()
+            );
+        }
+        let mut route_points: Vec<Document> = simplified_locations
+            .iter()
+            .map(|loc| location_to_document(loc, self.config.output.coordinate_storage))
+            .collect();
+        if self.config.output.route_events {
+            let events = self
+                .route_events_by_point(&route_key, &simplified_locations, point_store)
+                .await?;
+            for (index, events) in events {
+                route_points[index].insert(fields::EVENTS, bson::to_bson(&events)?);
+            }
+        }
+
+        // Insert the simplified route into the MongoDB trips collection.
+        let driver_id = stored_driver_id(&self.config.privacy, &msg.driver_id);
+        let trip = TripDocument::new(
+            driver_id.clone(),
+            msg.current_route_id.clone(),
+            simplified_locations.clone(),
+            msg.timestamp as i64,
+            original_points,
+        );
+        let mut trip_doc = bson::to_document(&trip)?;
+        // Points are stored in the configured coordinate format, with their events
+        trip_doc.insert(fields::SIMPLIFIED_ROUTE, route_points);
+        trip_doc.extend(route_lengths_document(&locations, &simplified_locations));
+        if geofence_config.store_events && !geofence_events.is_empty() {
+            trip_doc.insert(fields::GEOFENCE_EVENTS, bson::to_bson(&geofence_events)?);
+        }
+        if let Some((gain, loss)) = elevation_gain_loss(&locations) {
+            trip_doc.insert(fields::ELEVATION_GAIN_METERS, gain);
+            trip_doc.insert(fields::ELEVATION_LOSS_METERS, loss);
+        }
+        if let Some(stats) = sample_interval_stats(&locations) {
+            trip_doc.insert(fields::SAMPLE_INTERVAL_STATS, bson::to_bson(&stats)?);
+        }
+        if let Some(fraction) = moving_fraction {
+            trip_doc.insert(fields::MOVING_TIME_FRACTION, fraction);
+        }
+        if let Some(count) = low_accuracy_points {
+            trip_doc.insert(fields::LOW_ACCURACY_POINTS, count as i32);
+        }
+        if let Some((max_gap, gap_count)) = gaps {
+            trip_doc.insert(fields::MAX_GAP_SECONDS, max_gap as i64);
+            trip_doc.insert(fields::GAP_COUNT, gap_count as i32);
+        }
+        if let Some((leading, trailing)) = trimmed {
+            trip_doc.insert(fields::TRIMMED_LEADING, leading as i32);
+            trip_doc.insert(fields::TRIMMED_TRAILING, trailing as i32);
+        }
+        if let Some((start, end)) = time_range {
+            trip_doc.insert(fields::START_TIMESTAMP, start as i64);
+            trip_doc.insert(fields::END_TIMESTAMP, end as i64);
+        }
+        if let Some(start) = &route_start {
+            trip_doc.insert(fields::STARTED_AT, start.started_at as i64);
+            if let Some(planned) = start.planned_length_meters {
+                trip_doc.insert(fields::PLANNED_LENGTH_METERS, planned);
+            }
+        }
+        if let Some((average, max)) = occupancy_summary(&locations) {
+            trip_doc.insert(fields::AVG_OCCUPANCY, average);
+            trip_doc.insert(fields::MAX_OCCUPANCY, i64::from(max));
+        }
+        if simplification_skipped {
+            trip_doc.insert(fields::SIMPLIFICATION_SKIPPED, true);
+        }
+        if simplification_anomaly {
+            trip_doc.insert(fields::SIMPLIFICATION_ANOMALY, true);
+        }
+        if let Some(deviation) = capped_deviation {
+            trip_doc.insert(fields::POINT_CAP_APPLIED, true);
+            trip_doc.insert(fields::MAX_DEVIATION_METERS, deviation);
+        }
+        if let Some(algorithm) = algorithm
+            .filter(|_| !simplification.fallback_chain.is_empty() && !simplification_skipped)
+        {
+            trip_doc.insert(fields::SIMPLIFICATION_ALGORITHM, algorithm.name());
+        }
+        if simplification.disabled {
+            trip_doc.insert(fields::SIMPLIFICATION_DISABLED, true);
+        }
+        if bounding_box_mismatch {
+            trip_doc.insert(fields::BOUNDING_BOX_MISMATCH, true);
+        }
+        if let Some(preserved) = length_preserved {
+            trip_doc.insert(fields::LENGTH_PRESERVED, preserved);
+        }
+        if self.config.output.simplify_benchmark {
+            trip_doc.insert(fields::SIMPLIFY_MICROS, simplify_micros as i64);
+            trip_doc.insert(fields::SIMPLIFY_ITERATIONS, simplify_passes as i32);
+        }
+        if self.config.output.global_route_id {
+            trip_doc.insert(
+                fields::GLOBAL_ROUTE_ID,
+                RouteKey::new(&driver_id, &msg.current_route_id).to_string(),
+            );
+        }
+        if self.config.output.store_algorithm_version {
+            trip_doc.insert(fields::ALGORITHM_VERSION, ALGORITHM_VERSION);
+        }
+        if self.config.mongodb.trip_ttl_secs > 0 {
+            let ttl_ms = self
+                .config
+                .mongodb
+                .trip_ttl_secs
+                .saturating_mul(MILLIS_PER_SEC);
+            let expires_at = msg.timestamp.saturating_add(ttl_ms);
+            trip_doc.insert(
+                fields::EXPIRES_AT,
+                bson::DateTime::from_millis(expires_at.min(i64::MAX as u64) as i64),
+            );
+        }
+        for (level, lod) in lods.iter().enumerate() {
+            trip_doc.insert(
+                format!("{}{}", fields::LOD_PREFIX, level),
+                lod.iter()
+                    .map(|loc| location_to_document(loc, self.config.output.coordinate_storage))
+                    .collect::<Vec<_>>(),
+            );
+        }
+        if let Some([start, end]) = raw_endpoints {
+            trip_doc.insert(fields::RAW_START, start);
+            trip_doc.insert(fields::RAW_END, end);
+        }
+        if self.config.output.store_raw_route {
+            trip_doc.insert(fields::TOLERANCE, route_simplifier.tolerance());
+            trip_doc.insert(
+                fields::RAW_ROUTE,
+                locations
+                    .iter()
+                    .map(|loc| location_to_document(loc, self.config.output.coordinate_storage))
+                    .collect::<Vec<_>>(),
+            );
+        }
+        if self.config.output.self_intersections {
+            let crossings = self_intersections(&simplified_locations);
+            trip_doc.insert(fields::SELF_INTERSECTION_COUNT, crossings.len() as i32);
+            trip_doc.insert(
+                fields::SELF_INTERSECTIONS,
+                crossings
+                    .iter()
+                    .map(|loc| location_to_document(loc, self.config.output.coordinate_storage))
+                    .collect::<Vec<_>>(),
+            );
+        }
+        if self.config.output.straightness_index {
+            if let Some(index) = straightness_index(&locations) {
+                trip_doc.insert(fields::STRAIGHTNESS_INDEX, index);
+            }
+        }
+        if self.config.output.speed_profile {
+            if let Some(profile) = speed_profile(&simplified_locations) {
+                trip_doc.insert(fields::SPEED_PROFILE, profile);
+            }
+        }
+        if self.config.output.encoded_polyline {
+            trip_doc.insert(
+                fields::ENCODED_POLYLINE,
+                encode_polyline(&simplified_locations, self.config.output.polyline_precision),
+            );
+        }
+        self.project_route(&mut trip_doc, &simplified_locations)?;
+
+        // The same trip reported again under another route id, e.g. after a device reset
+        if finalize.duplicate_policy != DuplicateRoutePolicy::Off {
+            if let Some((original, original_route)) = self
+                .find_duplicate_trip(&driver_id, &msg.current_route_id, msg.timestamp, &locations)
+                .await?
+            {
+                self.record(msg, ServiceMetrics::increment_routes_merged);
+                if finalize.duplicate_policy == DuplicateRoutePolicy::Merge {
+                    self.merge_duplicate_trip(
+                        &original,
+                        &original_route,
+                        &msg.current_route_id,
+                        &simplified_locations,
+                    )
+                    .await?;
+                    self.publish_geofence_events(msg, &geofence_events).await;
+                    self.clear_route(&route_key, point_store).await?;
+                    return Ok(true);
+                }
+                if let Some(original_id) = original.get("_id") {
+                    trip_doc.insert(fields::DUPLICATE_OF, original_id.clone());
+                }
+            }
+        }
+        let sink_doc = (!self.sinks.is_empty()).then(|| trip_doc.clone());
+        let spool_doc = self.spool.as_ref().map(|spool| (spool, trip_doc.clone()));
+        let trip_id = match self.trip_writer.push(trip_doc).await {
+            Ok(trip_id) => {
+                info!("Stored trip for key {} in MongoDB.", key);
+                Some(trip_id)
+            }
+            // Keep the trip locally so the Redis points can still be released
+            Err(e) => match spool_doc {
+                Some((spool, trip_doc)) => {
+                    warn!("Failed to store trip for key {} ({}); spooling it", key, e);
+                    spool.append(&trip_doc).await?;
+                    None
+                }
+                None => return Err(e),
+            },
+        };
+        if let Some(sink_doc) = sink_doc {
+            for sink in &self.sinks {
+                if let Err(e) = sink.write_trip(&sink_doc).await {
+                    warn!("Trip sink failed for key {}: {}", key, e);
+                }
+            }
+        }
+        self.publish_geofence_events(msg, &geofence_events).await;
+
+        if let Some(trip_id) = &trip_id {
+            if self.config.mongodb.verify_writes {
+                self.verify_trip_write(&key, trip_id, simplified_locations.len())
+                    .await;
+            }
+            self.publish_ack(msg, trip_id, simplified_locations.len())
+                .await;
+        }
+
+        self.record(msg, |metrics| {
+            metrics.increment_routes_completed();
+            metrics.add_points_processed(original_points as u64);
+            metrics.add_points_simplified(simplified_locations.len() as u64);
+        });
+        if self.config.metrics.compression_drift_threshold > 0.0 {
+            self.track_compression(original_points, simplified_locations.len());
+        }
+
+        // Delete the Redis key
+        self.clear_route(&route_key, point_store).await?;
+        info!("Cleared route data for key {} from Redis.", key);
+
+        Ok(true)
+    }
+
+    /// Latest trip of the driver stored under another route id within
+    /// `duplicate_window_secs` that `route` repeats, with its simplified route
+    async fn find_duplicate_trip(
+        &self,
+        driver_id: &str,
+        route_id: &str,
+        timestamp: u64,
+        route: &[Location],
+    ) -> ServiceResult<Option<(Document, Vec<Location>)>> {
+        let finalize = &self.config.finalize;
+        let window_ms = finalize
+            .duplicate_window_secs
+            .saturating_mul(MILLIS_PER_SEC);
+        let from = timestamp.saturating_sub(window_ms) as i64;
+        let candidates = self
+            .trip_store
+            .find_driver_trips(
+                driver_id,
+                Some(from),
+                Some(timestamp as i64),
+                DUPLICATE_CANDIDATE_LIMIT,
+            )
+            .await?;
+
+        let radius = finalize.duplicate_match_radius_meters;
+        for trip in candidates.into_iter().rev() {
+            if trip.get_str(fields::CURRENT_ROUTE_ID) == Ok(route_id) {
+                continue;
+            }
+            let Ok(stored) = trip.get_array(fields::SIMPLIFIED_ROUTE) else {
+                continue;
+            };
+            let Some(stored) = stored
+                .iter()
+                .map(|point| {
+                    point
+                        .as_document()
+                        .and_then(|p| location_from_document(p).ok())
+                })
+                .collect::<Option<Vec<_>>>()
+            else {
+                continue;
+            };
+
+            let box_overlap = bounding_box_overlap(route, &stored, radius).unwrap_or(0.0);
+            let point_overlap = route_overlap(route, &stored, radius).unwrap_or(0.0);
+            if box_overlap >= finalize.duplicate_min_box_overlap
+                && point_overlap >= finalize.duplicate_min_point_overlap
+            {
+                info!(
+                    "Route {} repeats trip of route {} ({:.0}% of its points overlap)",
+                    route_id,
+                    trip.get_str(fields::CURRENT_ROUTE_ID).unwrap_or_default(),
+                    point_overlap * 100.0
+                );
+                return Ok(Some((trip, stored)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Append to a stored trip the points of a repeated route it does not cover
+    /// yet, and record the repeated route id in `mergedRouteIds`
+    async fn merge_duplicate_trip(
+        &self,
+        trip: &Document,
+        stored: &[Location],
+        route_id: &str,
+        simplified: &[Location],
+    ) -> ServiceResult<()> {
+        let Some(trip_id) = trip.get("_id") else {
+            return Err(ServiceError::Validation(
+                "Duplicate trip has no _id".to_string(),
+            ));
+        };
+        let radius = self.config.finalize.duplicate_match_radius_meters;
+        let uncovered: Vec<Location> = simplified
+            .iter()
+            .zip(deviations_meters(simplified, stored))
+            .filter(|(_, deviation)| *deviation > radius)
+            .map(|(location, _)| location.clone())
+            .collect();
+        let merged = merge_routes(stored, &uncovered);
+
+        let mut merged_ids = trip
+            .get_array(fields::MERGED_ROUTE_IDS)
+            .cloned()
+            .unwrap_or_default();
+        merged_ids.push(Bson::String(route_id.to_string()));
+        let storage = self.config.output.coordinate_storage;
+        self.trip_store
+            .update_trip(
+                trip_id,
+                doc! {
+                    fields::SIMPLIFIED_ROUTE: merged
+                        .iter()
+                        .map(|loc| location_to_document(loc, storage))
+                        .collect::<Vec<_>>(),
+                    fields::SIMPLIFIED_POINTS_COUNT: merged.len() as i32,
+                    fields::MERGED_ROUTE_IDS: merged_ids,
+                },
+            )
+            .await?;
+        info!(
+            "Merged route {} into trip {} ({} new points)",
+            route_id,
+            trip_id,
+            uncovered.len()
+        );
+        Ok(())
+    }
+
+    /// Fold a trip's compression ratio into the running averages and warn once
+    /// the recent average drifts past `compression_drift_threshold`
+    fn track_compression(&self, original_points: usize, simplified_points: usize) {
+        let config = &self.config.metrics;
+        let ratio = simplified_points as f64 / original_points as f64;
+        let drift = self.metrics.observe_compression_ratio(
+            ratio,
+            config.compression_ewma_alpha,
+            config.compression_baseline_alpha,
+        );
+
+        let metrics = self.metrics.snapshot();
+        let drifting = metrics.compression_trips >= config.compression_min_trips
+            && drift > config.compression_drift_threshold;
+        if self.metrics.set_compression_drifting(drifting) {
+            warn!(
+                "Compression ratio drifted {:.0}% from its baseline (recent {:.3}, baseline {:.3}); \
+                 the simplification tolerance may no longer suit the data",
+                drift * 100.0,
+                metrics.compression_ratio_ewma,
+                metrics.compression_ratio_baseline
+            );
+        }
+    }
+
+    /// Delete the detached points of a route and the bookkeeping kept alongside them
+    async fn clear_route(&self, key: &RouteKey, point_store: &dyn PointStore) -> ServiceResult<()> {
+        point_store.delete(&key.finalizing_key()).await?;
+        point_store.delete(&key.start_key()).await?;
+        if self.config.output.route_events {
+            point_store.delete(&key.events_key()).await?;
+        }
+        if self.config.progress.source != ProgressSource::Off {
+            // Keep the distance if the driver already moved on to another route
+            let traveled = point_store
+                .get(&key.traveled_key())
+                .await?
+                .and_then(|value| serde_json::from_str::<TraveledDistance>(&value).ok());
+            if traveled.is_some_and(|traveled| traveled.current_route_id == key.route_id) {
+                point_store.delete(&key.traveled_key()).await?;
+            }
+        }
+        if self.config.redis.route_soft_limit_bytes > 0 {
+            point_store.delete(&key.bytes_key()).await?;
+            self.route_memory.remove(&key.to_string());
+        }
+        self.route_points.remove(&key.to_string());
+        Ok(())
+    }
+
+    /// Publish the geofence crossings of a route once its trip is persisted, so
+    /// a retried finalize does not repeat them. Failures are logged and counted
+    /// only: the trip is already stored.
+    async fn publish_geofence_events(&self, msg: &BusMessage, events: &[GeofenceEvent]) {
+        let Some(topic) = &self.config.geofence.events_topic else {
+            return;
+        };
+        for event in events {
+            let payload = serde_json::json!({
+                fields::DRIVER_ID: msg.driver_id,
+                fields::CURRENT_ROUTE_ID: msg.current_route_id,
+                fields::EVENT: event,
+            });
+            if let Err(e) = self
+                .publisher
+                .publish(topic, payload.to_string().into_bytes(), QoS::AtLeastOnce)
+                .await
+            {
+                warn!("Failed to publish geofence event to {}: {}", topic, e);
+                self.metrics.increment_errors();
+            }
+        }
+    }
+
+    /// Tell the device its trip is stored so it can purge its local buffer.
+    /// Failures are logged only: the trip is already safe in the trip store.
+    async fn publish_ack(&self, msg: &BusMessage, trip_id: &Bson, stored_points: usize) {
+        let ack = &self.config.ack;
+        let Some(template) = &ack.topic_template else {
+            return;
+        };
+
+        let topic = template
+            .replace("{driver_id}", &msg.driver_id)
+            .replace("{route_id}", &msg.current_route_id);
+        let trip_id = match trip_id {
+            Bson::ObjectId(id) => id.to_hex(),
+            other => other.to_string(),
+        };
+        let payload = serde_json::json!({
+            fields::DRIVER_ID: msg.driver_id,
+            fields::CURRENT_ROUTE_ID: msg.current_route_id,
+            fields::TRIP_ID: trip_id,
+            fields::STORED_POINTS_COUNT: stored_points,
+        });
+        let qos = match ack.qos {
+            0 => QoS::AtMostOnce,
+            2 => QoS::ExactlyOnce,
+            _ => QoS::AtLeastOnce,
+        };
+
+        if let Err(e) = self
+            .publisher
+            .publish(&topic, payload.to_string().into_bytes(), qos)
+            .await
+        {
+            warn!("Failed to publish trip acknowledgement to {}: {}", topic, e);
+        }
+    }
+
+    /// Read a trip back and compare its stored point count with what was written.
+    /// Mismatches are logged and flagged on the document with `writeVerified: false`.
+    /// The trip is already stored, so failures here are logged and counted only:
+    /// aborting would leave the route in Redis to be stored a second time.
+    async fn verify_trip_write(&self, key: &str, trip_id: &Bson, expected_points: usize) {
+        let stored_points = match self.trip_store.find_trip(trip_id).await {
+            Ok(trip) => trip.and_then(|trip| {
+                trip.get_array(fields::SIMPLIFIED_ROUTE)
+                    .ok()
+                    .map(|route| route.len())
+            }),
+            Err(e) => {
+                warn!("Failed to read back trip for key {}: {}", key, e);
+                None
+            }
+        };
+
+        if stored_points == Some(expected_points) {
+            return;
+        }
+
+        error!(
+            "Write verification failed for key {}: wrote {} points, read back {:?}",
+            key, expected_points, stored_points
+        );
+        self.metrics.increment_errors();
+        if let Err(e) = self
+            .trip_store
+            .update_trip(trip_id, doc! { fields::WRITE_VERIFIED: false })
+            .await
+        {
+            warn!("Failed to flag unverified trip for key {}: {}", key, e);
+        }
+    }
+
+    /// Run status hooks; failures are logged and never interrupt ingestion
+    async fn run_hooks(&self, msg: &BusMessage) {
+        for hook in &self.hooks {
+            let result = match msg.status {
+                BusStatus::Started => hook.on_started(msg).await,
+                BusStatus::InRoute => hook.on_in_route(msg).await,
+                BusStatus::Finished => hook.on_finished(msg).await,
+            };
+            if let Err(e) = result {
+                warn!("Status hook failed for driver {}: {}", msg.driver_id, e);
+            }
+        }
+    }
+}
+
+/// The first [`MALFORMED_PREVIEW_BYTES`] of a payload, quoted when they are
+/// UTF-8 and in hex otherwise
+fn payload_preview(payload: &[u8]) -> String {
+    let end = payload.len().min(MALFORMED_PREVIEW_BYTES);
+    let ellipsis = if end < payload.len() { "..." } else { "" };
+    match std::str::from_utf8(&payload[..end]) {
+        Ok(text) => format!("{:?}{}", text, ellipsis),
+        // A multi-byte character cut at the limit still counts as text
+        Err(e) if e.error_len().is_none() && end < payload.len() => {
+            let text = std::str::from_utf8(&payload[..e.valid_up_to()]).unwrap_or_default();
+            format!("{:?}...", text)
+        }
+        Err(_) => {
+            let hex: String = payload[..end]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            format!("0x{}{}", hex, ellipsis)
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::{CoordinateOrder, PointEncoding};
+    use crate::geofence::Geofence;
+    use crate::ingress::{ChannelIngress, Ingress};
+    use crate::privacy::hash_driver_id;
+    use crate::publisher::RecordingPublisher;
+    use crate::route_simplification::calculate_total_distance;
+    use crate::sink::tests::SharedBuffer;
+    use crate::sink::NdjsonSink;
+    use crate::spool::tests::TempSpool;
+    use crate::storage::tests::FakeRedis;
+    use crate::storage::{
+        InMemoryPointStore, InMemoryTripStore, PointStore, RedisPointStore, RetryPolicy,
+        SharedRedisConnection,
+    };
+    use async_trait::async_trait;
+    use mongodb::bson::Document;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    pub(crate) struct Harness {
+        pub service: IngestionService,
+        pub points: InMemoryPointStore,
+        pub trips: Arc<InMemoryTripStore>,
+        pub publisher: Arc<RecordingPublisher>,
+    }
+
+    impl Harness {
+        pub fn new(config: Config) -> Self {
+            let trips = Arc::new(InMemoryTripStore::new());
+            let publisher = Arc::new(RecordingPublisher::new());
+            let service = IngestionService::new(config, trips.clone(), publisher.clone()).unwrap();
+
+            Self {
+                service,
+                points: InMemoryPointStore::new(),
+                trips,
+                publisher,
+            }
+        }
+
+        pub async fn send(&self, msg: &BusMessage) -> ServiceResult<()> {
+            let payload = serde_json::to_vec(msg).unwrap();
+            self.service.process_message(&payload, &self.points).await
+        }
+    }
+
+    pub(crate) fn message(
+        status: BusStatus,
+        latitude: f64,
+        longitude: f64,
+        timestamp: u64,
+    ) -> BusMessage {
+        BusMessage {
+            driver_id: "driver_1".to_string(),
+            driver_location: Location::new(latitude, longitude),
+            timestamp,
+            current_route_id: "route_1".to_string(),
+            status,
+            ..Default::default()
+        }
+    }
+
+    #[derive(Default)]
+    struct RecordingHook {
+        calls: Mutex<Vec<BusStatus>>,
+    }
+
+    #[async_trait]
+    impl StatusHook for RecordingHook {
+        async fn on_in_route(&self, _msg: &BusMessage) -> ServiceResult<()> {
+            self.calls.lock().unwrap().push(BusStatus::InRoute);
+            Ok(())
+        }
+
+        async fn on_finished(&self, _msg: &BusMessage) -> ServiceResult<()> {
+            self.calls.lock().unwrap().push(BusStatus::Finished);
+            Ok(())
+        }
+    }
+
+    struct FailingHook;
+
+    #[async_trait]
+    impl StatusHook for FailingHook {
+        async fn on_in_route(&self, _msg: &BusMessage) -> ServiceResult<()> {
+            Err(ServiceError::Connection("webhook down".to_string()))
+        }
+
+        async fn on_finished(&self, _msg: &BusMessage) -> ServiceResult<()> {
+            Err(ServiceError::Connection("webhook down".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hooks_called_on_each_status() {
+        let hook = Arc::new(RecordingHook::default());
+        let mut harness = Harness::new(Config::default());
+        harness.service = harness
+            .service
+            .clone()
+            .with_hook(Arc::new(FailingHook))
+            .with_hook(hook.clone());
+
+        harness
+            .send(&message(BusStatus::InRoute, 0.0, 0.0, 1))
+            .await
+            .unwrap();
+        harness
+            .send(&message(BusStatus::InRoute, 0.0, 1.0, 2))
+            .await
+            .unwrap();
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 1.0, 3))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            *hook.calls.lock().unwrap(),
+            vec![BusStatus::InRoute, BusStatus::InRoute, BusStatus::Finished]
+        );
+        // The failing hook did not prevent the trip from being stored
+        assert_eq!(harness.trips.trips().len(), 1);
+    }
+
+    /// Square between longitudes 1 and 2 straddling the equator
+    fn depot_geofence() -> Geofence {
+        Geofence {
+            name: "depot".to_string(),
+            polygon: vec![
+                Location::new(-1.0, 1.0),
+                Location::new(-1.0, 2.0),
+                Location::new(1.0, 2.0),
+                Location::new(1.0, 1.0),
+            ],
+        }
+    }
+
+    #[tokio::test]
+    async fn test_geofence_events_published_and_stored() {
+        let mut config = Config::default();
+        config.geofence.geofences = vec![depot_geofence()];
+        config.geofence.events_topic = Some("geofence_events".to_string());
+        let harness = Harness::new(config);
+
+        for (i, longitude) in [0.0, 0.5, 1.5, 2.5].into_iter().enumerate() {
+            let msg = message(BusStatus::InRoute, 0.0, longitude, i as u64);
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 2.5, 10))
+            .await
+            .unwrap();
+
+        let published = harness.publisher.messages_on("geofence_events");
+        assert_eq!(published.len(), 2);
+        assert_eq!(published[0].json()["event"]["kind"], "enter");
+        assert_eq!(published[0].json()["event"]["timestamp"], 2);
+        assert_eq!(published[1].json()["event"]["kind"], "exit");
+
+        let trips = harness.trips.trips();
+        assert_eq!(trips[0].get_array("geofenceEvents").unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_geofence_publish_failure_still_stores_trip() {
+        let mut config = Config::default();
+        config.geofence.geofences = vec![depot_geofence()];
+        config.geofence.events_topic = Some("geofence_events".to_string());
+        let harness = Harness::new(config);
+        harness.publisher.fail_on("geofence_events");
+
+        for (i, longitude) in [0.0, 0.5, 1.5, 2.5].into_iter().enumerate() {
+            let msg = message(BusStatus::InRoute, 0.0, longitude, i as u64);
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 2.5, 10))
+            .await
+            .unwrap();
+
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].get_array("geofenceEvents").unwrap().len(), 2);
+        assert_eq!(harness.service.metrics().errors_count, 2);
+    }
+
+    #[tokio::test]
+    async fn test_geofence_events_omitted_when_none_crossed() {
+        let harness = Harness::new(Config::default());
+
+        send_route(&harness, 3).await;
+
+        assert!(harness.trips.trips()[0].get("geofenceEvents").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_redelivered_point_stored_once() {
+        let harness = Harness::new(Config::default());
+        let point = message(BusStatus::InRoute, 0.0, 0.0, 1);
+
+        harness.send(&point).await.unwrap();
+        harness.send(&point).await.unwrap();
+        harness
+            .send(&message(BusStatus::InRoute, 0.0, 1.0, 2))
+            .await
+            .unwrap();
+
+        let stored = harness
+            .points
+            .range("driver_1:route_1", 0, -1)
+            .await
+            .unwrap();
+        assert_eq!(stored.len(), 2);
+
+        // With client-provided ids, same contents but distinct ids are both kept
+        let harness = Harness::new(Config::default());
+        for id in ["a", "b", "a"] {
+            let msg = BusMessage {
+                point_id: Some(id.to_string()),
+                ..point.clone()
+            };
+            harness.send(&msg).await.unwrap();
+        }
+        let stored = harness
+            .points
+            .range("driver_1:route_1", 0, -1)
+            .await
+            .unwrap();
+        assert_eq!(stored.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_dedup_disabled_keeps_redeliveries() {
+        let mut config = Config::default();
+        config.ingestion.dedup_points = false;
+        let harness = Harness::new(config);
+        let point = message(BusStatus::InRoute, 0.0, 0.0, 1);
+
+        harness.send(&point).await.unwrap();
+        harness.send(&point).await.unwrap();
+
+        let stored = harness
+            .points
+            .range("driver_1:route_1", 0, -1)
+            .await
+            .unwrap();
+        assert_eq!(stored.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_encoded_polyline_stored_when_enabled() {
+        let mut config = Config::default();
+        config.output.encoded_polyline = true;
+        let harness = Harness::new(config);
+
+        harness
+            .send(&message(BusStatus::InRoute, 38.5, -120.2, 1))
+            .await
+            .unwrap();
+        harness
+            .send(&message(BusStatus::InRoute, 40.7, -120.95, 2))
+            .await
+            .unwrap();
+        harness
+            .send(&message(BusStatus::Finished, 40.7, -120.95, 3))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(
+            trip.get_str("encodedPolyline").unwrap(),
+            "_p~iF~ps|U_ulLnnqC"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_finalize_mixes_old_and_new_point_formats() {
+        let harness = Harness::new(Config::default());
+
+        // A point written before `timestamp` existed on `Location`
+        harness
+            .points
+            .push(
+                "driver_1:route_1",
+                r#"{"latitude":0.0,"longitude":0.0}"#.into(),
+            )
+            .await
+            .unwrap();
+        harness
+            .send(&message(BusStatus::InRoute, 0.0, 1.0, 2))
+            .await
+            .unwrap();
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 1.0, 3))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32("originalPointsCount").unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_binary_point_encoding_matches_json() {
+        let mut trips = Vec::new();
+        for encoding in [PointEncoding::Json, PointEncoding::Binary] {
+            let mut config = Config::default();
+            config.redis.point_encoding = encoding;
+            let harness = Harness::new(config);
+
+            send_zigzag_route(&harness).await;
+
+            let mut trip = harness.trips.trips().remove(0);
+            trip.remove("_id");
+            trips.push(trip);
+        }
+
+        assert_eq!(trips[0], trips[1]);
+        assert_eq!(trips[0].get_i32("originalPointsCount").unwrap(), 9);
+    }
+
+    /// Trip store whose reads return a truncated route, as if a write was lost,
+    /// or fail outright when `unreadable`
+    struct MismatchingTripStore {
+        inner: InMemoryTripStore,
+        unreadable: bool,
+    }
+
+    #[async_trait]
+    impl TripStore for MismatchingTripStore {
+        async fn insert_trip(&self, trip: Document) -> ServiceResult<Bson> {
+            self.inner.insert_trip(trip).await
+        }
+
+        async fn find_trip(&self, id: &Bson) -> ServiceResult<Option<Document>> {
+            if self.unreadable {
+                return Err(ServiceError::Connection("read timed out".to_string()));
+            }
+            let mut trip = self.inner.find_trip(id).await?;
+            if let Some(trip) = trip.as_mut() {
+                trip.insert("simplifiedRoute", Vec::<Bson>::new());
+            }
+            Ok(trip)
+        }
+
+        async fn update_trip(&self, id: &Bson, fields: Document) -> ServiceResult<()> {
+            self.inner.update_trip(id, fields).await
+        }
+
+        async fn remove_trip_fields(&self, id: &Bson, fields: &[&str]) -> ServiceResult<()> {
+            self.inner.remove_trip_fields(id, fields).await
+        }
+
+        async fn find_driver_trips(
+            &self,
+            driver_id: &str,
+            from: Option<i64>,
+            to: Option<i64>,
+            limit: usize,
+        ) -> ServiceResult<Vec<Document>> {
+            self.inner
+                .find_driver_trips(driver_id, from, to, limit)
+                .await
+        }
+
+        async fn find_route_trip(
+            &self,
+            driver_id: &str,
+            route_id: &str,
+        ) -> ServiceResult<Option<Document>> {
+            self.inner.find_route_trip(driver_id, route_id).await
+        }
+
+        async fn ensure_expiry_index(
+            &self,
+            field: &str,
+            expire_after: Duration,
+        ) -> ServiceResult<()> {
+            self.inner.ensure_expiry_index(field, expire_after).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_write_verification_flags_mismatch() {
+        let mut config = Config::default();
+        config.mongodb.verify_writes = true;
+
+        // A faithful store passes verification without flags
+        let harness = Harness::new(config.clone());
+        harness
+            .send(&message(BusStatus::InRoute, 0.0, 0.0, 1))
+            .await
+            .unwrap();
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 2))
+            .await
+            .unwrap();
+        assert!(!harness.trips.trips()[0].contains_key("writeVerified"));
+
+        let store = Arc::new(MismatchingTripStore {
+            inner: InMemoryTripStore::new(),
+            unreadable: false,
+        });
+        let service =
+            IngestionService::new(config, store.clone(), Arc::new(RecordingPublisher::new()))
+                .unwrap();
+        let points = InMemoryPointStore::new();
+        for msg in [
+            message(BusStatus::InRoute, 0.0, 0.0, 1),
+            message(BusStatus::Finished, 0.0, 0.0, 2),
+        ] {
+            let payload = serde_json::to_vec(&msg).unwrap();
+            service.process_message(&payload, &points).await.unwrap();
+        }
+
+        let trip = &store.inner.trips()[0];
+        assert!(!trip.get_bool("writeVerified").unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_write_verification_failure_still_clears_route() {
+        let mut config = Config::default();
+        config.mongodb.verify_writes = true;
+        config.ack.topic_template = Some("ack/{driver_id}".to_string());
+        let store = Arc::new(MismatchingTripStore {
+            inner: InMemoryTripStore::new(),
+            unreadable: true,
+        });
+        let publisher = Arc::new(RecordingPublisher::new());
+        let service = IngestionService::new(config, store.clone(), publisher.clone()).unwrap();
+        let points = InMemoryPointStore::new();
+        let finished = message(BusStatus::Finished, 0.0, 0.0, 2);
+        for msg in [message(BusStatus::InRoute, 0.0, 0.0, 1), finished.clone()] {
+            let payload = serde_json::to_vec(&msg).unwrap();
+            service.process_message(&payload, &points).await.unwrap();
+        }
+        // A retried finish finds nothing left to store again
+        let payload = serde_json::to_vec(&finished).unwrap();
+        service.process_message(&payload, &points).await.unwrap();
+
+        let trips = store.inner.trips();
+        assert_eq!(trips.len(), 1);
+        assert!(!trips[0].get_bool("writeVerified").unwrap());
+        assert!(points.list_keys().await.unwrap().is_empty());
+        assert_eq!(publisher.messages_on("ack/driver_1").len(), 1);
+        assert_eq!(service.metrics().errors_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_elevation_stats_and_extrema_preserved() {
+        let mut config = Config::default();
+        config.route_simplification.tolerance = 0.1;
+        config.route_simplification.preserve_elevation_extrema = true;
+        let harness = Harness::new(config);
+
+        // A straight line over a hill: geometry alone would keep only the endpoints
+        for (i, altitude) in [100.0, 110.0, 130.0, 115.0, 100.0].into_iter().enumerate() {
+            let msg = BusMessage {
+                driver_location: Location {
+                    altitude: Some(altitude),
+                    ..Location::new(0.0, i as f64 * 0.01)
+                },
+                ..message(BusStatus::InRoute, 0.0, 0.0, i as u64)
+            };
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.04, 9))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_f64("elevationGainMeters").unwrap(), 30.0);
+        assert_eq!(trip.get_f64("elevationLossMeters").unwrap(), 30.0);
+        assert_eq!(trip.get_i32("simplifiedPointsCount").unwrap(), 3);
+    }
+
+    /// A zig-zag whose 0.001 degree (~111 m) deviations are all dropped at a 0.002 tolerance
+    async fn send_zigzag_route(harness: &Harness) {
+        for i in 0..9 {
+            let latitude = if i % 2 == 0 { 0.0 } else { 0.001 };
+            let msg = message(BusStatus::InRoute, latitude, i as f64 * 0.0005, i as u64);
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 9))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_length_check_resimplifies_route() {
+        let mut config = Config::default();
+        config.route_simplification.tolerance = 0.002;
+        config.route_simplification.max_length_difference_meters = 10.0;
+        let harness = Harness::new(config);
+
+        send_zigzag_route(&harness).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert!(trip.get_bool("lengthPreserved").unwrap());
+        assert_eq!(trip.get_i32("simplifiedPointsCount").unwrap(), 9);
+    }
+
+    #[tokio::test]
+    async fn test_length_check_flags_unpreserved_route() {
+        let mut config = Config::default();
+        config.route_simplification.tolerance = 0.002;
+        config.route_simplification.max_length_difference_meters = 10.0;
+        config.route_simplification.length_check_attempts = 0;
+        let harness = Harness::new(config);
+
+        send_zigzag_route(&harness).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert!(!trip.get_bool("lengthPreserved").unwrap());
+        assert_eq!(trip.get_i32("simplifiedPointsCount").unwrap(), 2);
+    }
+
+    /// Three 10 s legs of ~111 m followed by `idle_legs` legs parked in place,
+    /// timestamped in milliseconds
+    async fn send_timed_route(harness: &Harness, idle_legs: u64) {
+        for i in 0..4 + idle_legs {
+            let longitude = i.min(3) as f64 * 0.001;
+            let msg = message(BusStatus::InRoute, 0.0, longitude, i * 10_000);
+            harness.send(&msg).await.unwrap();
+        }
+        let finished = message(BusStatus::Finished, 0.0, 0.0, (4 + idle_legs) * 10_000);
+        harness.send(&finished).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_fix_gaps_flagged() {
+        let mut config = Config::default();
+        config.finalize.gap_threshold_secs = 60;
+        let harness = Harness::new(config);
+
+        // Two dropouts: 90 s and 300 s, timestamped in milliseconds
+        for (i, secs) in [0, 10, 100, 110, 120, 420, 430].into_iter().enumerate() {
+            harness
+                .send(&message(
+                    BusStatus::InRoute,
+                    0.0,
+                    i as f64 * 0.01,
+                    secs * 1000,
+                ))
+                .await
+                .unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.07, 440_000))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i64(fields::MAX_GAP_SECONDS).unwrap(), 300);
+        assert_eq!(trip.get_i32(fields::GAP_COUNT).unwrap(), 2);
+
+        // Off by default
+        let harness = Harness::new(Config::default());
+        send_route(&harness, 3).await;
+        assert!(!harness.trips.trips()[0].contains_key(fields::GAP_COUNT));
+    }
+
+    #[tokio::test]
+    async fn test_straightness_index_stored() {
+        let mut config = Config::default();
+        config.output.straightness_index = true;
+        let harness = Harness::new(config);
+
+        send_route(&harness, 5).await;
+
+        let trip = &harness.trips.trips()[0];
+        let index = trip.get_f64(fields::STRAIGHTNESS_INDEX).unwrap();
+        assert!((index - 1.0).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn test_speed_profile_stored() {
+        let mut config = Config::default();
+        config.output.speed_profile = true;
+        let harness = Harness::new(config);
+
+        // 0.01° of longitude at the equator is ~1112 m: 10 s, then 20 s (in ms),
+        // at the corners of a right angle so every point is retained
+        let points = [(0.0, 0.0, 0), (0.0, 0.01, 10_000), (0.01, 0.01, 30_000)];
+        for (latitude, longitude, timestamp) in points {
+            harness
+                .send(&message(BusStatus::InRoute, latitude, longitude, timestamp))
+                .await
+                .unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.01, 0.01, 31_000))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        let profile: Vec<f64> = trip
+            .get_array(fields::SPEED_PROFILE)
+            .unwrap()
+            .iter()
+            .map(|speed| speed.as_f64().unwrap())
+            .collect();
+        assert_eq!(profile.len(), 3);
+        assert!((profile[0] - 111.195).abs() < 0.01);
+        assert_eq!(profile[1], profile[0]);
+        assert!((profile[2] - 55.597).abs() < 0.01);
+
+        // Off by default
+        let harness = Harness::new(Config::default());
+        send_route(&harness, 3).await;
+        assert!(!harness.trips.trips()[0].contains_key(fields::SPEED_PROFILE));
+    }
+
+    #[tokio::test]
+    async fn test_moving_time_fraction_stored() {
+        let mut config = Config::default();
+        config.finalize.min_moving_fraction = 0.5;
+        let harness = Harness::new(config);
+
+        send_timed_route(&harness, 1).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_f64("movingTimeFraction").unwrap(), 0.75);
+    }
+
+    #[tokio::test]
+    async fn test_sample_interval_stats_stored() {
+        let harness = Harness::new(Config::default());
+
+        send_timed_route(&harness, 1).await;
+
+        let trip = &harness.trips.trips()[0];
+        let stats = trip.get_document(fields::SAMPLE_INTERVAL_STATS).unwrap();
+        assert_eq!(stats.get_i64("sampleCount").unwrap(), 5);
+        assert_eq!(stats.get_f64("median").unwrap(), 10.0);
+    }
+
+    #[tokio::test]
+    async fn test_mostly_idle_trip_discarded() {
+        let mut config = Config::default();
+        config.finalize.min_moving_fraction = 0.5;
+        let harness = Harness::new(config);
+
+        send_timed_route(&harness, 9).await;
+
+        assert!(harness.trips.trips().is_empty());
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 0);
+    }
+
+    /// Route with a 0.0005 degree triangular bump peaking in the middle and
+    /// 0.0003 degree jitter over the first and last five points
+    async fn send_noisy_edges_route(harness: &Harness) {
+        for i in 0..=20u64 {
+            let latitude = match i {
+                5..=15 => 0.0001 * (5 - i.abs_diff(10)) as f64,
+                _ if i % 2 == 1 => 0.0003,
+                _ => 0.0,
+            };
+            let msg = message(BusStatus::InRoute, latitude, i as f64 * 0.0001, i);
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 21))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_edge_tolerance_trims_noisy_endpoints() {
+        let mut config = Config::default();
+        config.route_simplification.edge_points = 5;
+        config.route_simplification.edge_tolerance = 0.001;
+        let harness = Harness::new(config);
+
+        send_noisy_edges_route(&harness).await;
+
+        let trip = &harness.trips.trips()[0];
+        let route = trip.get_array(fields::SIMPLIFIED_ROUTE).unwrap();
+        let longitudes: Vec<f64> = route
+            .iter()
+            .map(|point| point.as_document().unwrap().get_f64("longitude").unwrap())
+            .collect();
+        assert_eq!(longitudes, vec![0.0, 0.0005, 0.001, 0.0015, 0.002]);
+    }
+
+    #[tokio::test]
+    async fn test_noisy_endpoints_kept_without_edge_tolerance() {
+        let harness = Harness::new(Config::default());
+
+        send_noisy_edges_route(&harness).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert!(trip.get_i32("simplifiedPointsCount").unwrap() > 5);
+    }
+
+    #[tokio::test]
+    async fn test_progress_from_planned_length() {
+        let mut config = Config::default();
+        config.progress.source = ProgressSource::PlannedLength;
+        config.progress.topic = Some("progress".to_string());
+        let harness = Harness::new(config);
+
+        // Legs of 0.001 degrees of longitude on the equator, ~111.2 m each
+        let leg = haversine_distance(&Location::new(0.0, 0.0), &Location::new(0.0, 0.001));
+        for i in 0..5 {
+            let msg = BusMessage {
+                planned_length_meters: Some(leg * 4.0),
+                ..message(BusStatus::InRoute, 0.0, i as f64 * 0.001, i as u64)
+            };
+            harness.send(&msg).await.unwrap();
+        }
+
+        let progress: Vec<f64> = harness
+            .publisher
+            .messages_on("progress")
+            .iter()
+            .map(|published| published.json()["progressPercent"].as_f64().unwrap())
+            .collect();
+        assert_eq!(progress.len(), 5);
+        for (actual, expected) in progress.iter().zip([0.0, 25.0, 50.0, 75.0, 100.0]) {
+            assert!(
+                (actual - expected).abs() < 1e-6,
+                "{} != {}",
+                actual,
+                expected
+            );
+        }
+
+        let last = harness
+            .points
+            .get("progress:driver_1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(last.contains("\"progressPercent\":100"));
+    }
+
+    #[tokio::test]
+    async fn test_progress_towards_destination() {
+        let mut config = Config::default();
+        config.progress.source = ProgressSource::Destination;
+        let harness = Harness::new(config);
+
+        for i in 0..2 {
+            let msg = BusMessage {
+                destination: Some(Location::new(0.0, 0.004)),
+                ..message(BusStatus::InRoute, 0.0, i as f64 * 0.001, i as u64)
+            };
+            harness.send(&msg).await.unwrap();
+        }
+
+        let last = harness
+            .points
+            .get("progress:driver_1")
+            .await
+            .unwrap()
+            .unwrap();
+        let progress: serde_json::Value = serde_json::from_str(&last).unwrap();
+        assert!((progress["progressPercent"].as_f64().unwrap() - 25.0).abs() < 1e-6);
+
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 2))
+            .await
+            .unwrap();
+        assert!(harness
+            .points
+            .get("progress:driver_1:traveled")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_progress_failure_keeps_batched_points() {
+        let mut config = Config::default();
+        config.progress.source = ProgressSource::PlannedLength;
+        config.progress.topic = Some("progress".to_string());
+        config.redis.append_batch_size = 10;
+        let harness = Harness::new(config);
+        harness.publisher.fail_on("progress");
+
+        for i in 0..3 {
+            let msg = BusMessage {
+                planned_length_meters: Some(1000.0),
+                ..message(BusStatus::InRoute, 0.0, i as f64 * 0.001, i * 1000)
+            };
+            harness.send(&msg).await.unwrap();
+        }
+
+        // Still buffered: progress neither forced a flush nor dropped a point
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 0);
+        assert_eq!(harness.service.metrics().errors_count, 3);
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 3000))
+            .await
+            .unwrap();
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32("originalPointsCount").unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_progress_kept_per_driver_across_routes() {
+        let mut config = Config::default();
+        config.progress.source = ProgressSource::PlannedLength;
+        let harness = Harness::new(config);
+        let leg = haversine_distance(&Location::new(0.0, 0.0), &Location::new(0.0, 0.001));
+
+        for (route_id, longitude) in [("route_1", 0.0), ("route_1", 0.001), ("route_2", 0.002)] {
+            let msg = BusMessage {
+                current_route_id: route_id.to_string(),
+                planned_length_meters: Some(leg * 4.0),
+                ..message(BusStatus::InRoute, 0.0, longitude, 0)
+            };
+            harness.send(&msg).await.unwrap();
+            let last = harness
+                .points
+                .get("progress:driver_1")
+                .await
+                .unwrap()
+                .unwrap();
+            let progress: serde_json::Value = serde_json::from_str(&last).unwrap();
+            assert_eq!(progress["currentRouteId"], route_id);
+        }
+
+        // The distance restarted with the new route
+        let last = harness
+            .points
+            .get("progress:driver_1")
+            .await
+            .unwrap()
+            .unwrap();
+        let progress: serde_json::Value = serde_json::from_str(&last).unwrap();
+        assert_eq!(progress["progressPercent"].as_f64().unwrap(), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_mixed_batch_partially_succeeds() {
+        let harness = Harness::new(Config::default());
+        for driver in ["driver_1", "driver_2"] {
+            for i in 0..3 {
+                let msg = BusMessage {
+                    driver_id: driver.to_string(),
+                    ..message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i)
+                };
+                harness.send(&msg).await.unwrap();
+            }
+        }
+
+        let finished = |driver: &str| BusMessage {
+            driver_id: driver.to_string(),
+            ..message(BusStatus::Finished, 0.0, 0.0, 3)
+        };
+        let batch = serde_json::json!([
+            finished("driver_1"),
+            {"driverId": "driver_3", "status": "parked"},
+            finished("driver_2"),
+        ]);
+        let payload = serde_json::to_vec(&batch).unwrap();
+
+        let results = harness
+            .service
+            .process_batch(&payload, &harness.points)
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok());
+        assert!(results[1].is_err());
+        assert!(results[2].is_ok());
+
+        let drivers: Vec<String> = harness
+            .trips
+            .trips()
+            .iter()
+            .map(|trip| trip.get_str("driverId").unwrap().to_string())
+            .collect();
+        assert_eq!(drivers, vec!["driver_1", "driver_2"]);
+
+        // The single-payload entry point reports the partial failure
+        let result = harness
+            .service
+            .process_message(&payload, &harness.points)
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_occupancy_summarized_and_kept_on_points() {
+        let harness = Harness::new(Config::default());
+
+        // Occupancy inside driverLocation, next to it, or not reported at all
+        let payloads = [
+            r#"{"driverId":"driver_1","currentRouteId":"route_1","timestamp":1,"status":"in_route",
+                "driverLocation":{"latitude":0.0,"longitude":0.0,"occupancy":10}}"#,
+            r#"{"driverId":"driver_1","currentRouteId":"route_1","timestamp":2,"status":"in_route",
+                "driverLocation":{"latitude":0.0,"longitude":0.001},"occupancy":30}"#,
+            r#"{"driverId":"driver_1","currentRouteId":"route_1","timestamp":3,"status":"in_route",
+                "driverLocation":{"latitude":0.0,"longitude":0.002}}"#,
+            r#"{"driverId":"driver_1","currentRouteId":"route_1","timestamp":4,"status":"finished",
+                "driverLocation":{"latitude":0.0,"longitude":0.002}}"#,
+        ];
+        for payload in payloads {
+            harness
+                .service
+                .process_message(payload.as_bytes(), &harness.points)
+                .await
+                .unwrap();
+        }
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_f64(fields::AVG_OCCUPANCY).unwrap(), 20.0);
+        assert_eq!(trip.get_i64(fields::MAX_OCCUPANCY).unwrap(), 30);
+        let first = trip.get_array(fields::SIMPLIFIED_ROUTE).unwrap()[0]
+            .as_document()
+            .unwrap();
+        assert_eq!(location_from_document(first).unwrap().occupancy, Some(10));
+    }
+
+    #[tokio::test]
+    async fn test_strict_field_names_rejects_snake_case() {
+        let payload =
+            br#"{"driver_id":"driver_1","current_route_id":"route_1","timestamp":1,"status":"in_route",
+                "driverLocation":{"latitude":0.0,"longitude":0.0}}"#;
+
+        let tolerant = Harness::new(Config::default());
+        tolerant
+            .service
+            .process_message(payload, &tolerant.points)
+            .await
+            .unwrap();
+        assert_eq!(tolerant.points.len("driver_1:route_1").await.unwrap(), 1);
+
+        let mut config = Config::default();
+        config.ingestion.strict_field_names = true;
+        let strict = Harness::new(config);
+        let error = strict
+            .service
+            .process_message(payload, &strict.points)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("driver_id"));
+        assert_eq!(strict.points.len("driver_1:route_1").await.unwrap(), 0);
+
+        // camelCase payloads are still accepted in strict mode
+        strict
+            .send(&message(BusStatus::InRoute, 0.0, 0.0, 1))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_sampled_errors_still_counted() {
+        let mut config = Config::default();
+        config.logging.error_burst = 2;
+        config.logging.error_sample_every = 0;
+        let harness = Harness::new(config);
+
+        // Driver ids with ':' fail validation one by one
+        let batch: Vec<BusMessage> = (0..5)
+            .map(|i| BusMessage {
+                driver_id: "driver:1".to_string(),
+                ..message(BusStatus::InRoute, 0.0, 0.0, i)
+            })
+            .collect();
+        let payload = serde_json::to_vec(&batch).unwrap();
+        let results = harness
+            .service
+            .process_batch(&payload, &harness.points)
+            .await
+            .unwrap();
+
+        assert!(results.iter().all(|result| result.is_err()));
+        assert_eq!(harness.service.metrics().errors_count, 5);
+        assert_eq!(harness.service.error_log().suppressed(), 3);
+    }
+
+    async fn send_antimeridian_route(harness: &Harness) {
+        for (i, longitude) in [179.997, 179.998, 179.999, -179.999, -179.998, -179.997]
+            .into_iter()
+            .enumerate()
+        {
+            let msg = message(BusStatus::InRoute, 0.0, longitude, i as u64);
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 6))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_antimeridian_route_simplified_without_teleport() {
+        let harness = Harness::new(Config::default());
+
+        send_antimeridian_route(&harness).await;
+
+        let trip = &harness.trips.trips()[0];
+        let route = trip.get_array(fields::SIMPLIFIED_ROUTE).unwrap();
+        let longitudes: Vec<f64> = route
+            .iter()
+            .map(|point| point.as_document().unwrap().get_f64("longitude").unwrap())
+            .collect();
+        assert_eq!(longitudes, vec![179.997, -179.997]);
+    }
+
+    #[tokio::test]
+    async fn test_route_over_north_pole_and_antimeridian_stays_finite() {
+        let mut config = Config::default();
+        config.ingestion.normalize_edge_coordinates = true;
+        config.output.store_raw_route = true;
+        let harness = Harness::new(config);
+
+        let points = [
+            (89.998, 179.999),
+            (89.999, -180.0),
+            (90.0, -45.0),
+            (89.999, 10.0),
+            (89.998, 20.0),
+        ];
+        for (i, &(latitude, longitude)) in points.iter().enumerate() {
+            harness
+                .send(&message(BusStatus::InRoute, latitude, longitude, i as u64))
+                .await
+                .unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 89.997, 30.0, 5))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        let raw: Vec<Location> = trip
+            .get_array(fields::RAW_ROUTE)
+            .unwrap()
+            .iter()
+            .map(|point| location_from_document(point.as_document().unwrap()).unwrap())
+            .collect();
+        assert_eq!(raw[1].longitude, 180.0);
+        assert_eq!(raw[2], Location::new(90.0, 0.0));
+
+        let simplified: Vec<Location> = trip
+            .get_array(fields::SIMPLIFIED_ROUTE)
+            .unwrap()
+            .iter()
+            .map(|point| location_from_document(point.as_document().unwrap()).unwrap())
+            .collect();
+        assert!(simplified.len() >= 2);
+        assert!(simplified
+            .iter()
+            .all(|loc| loc.latitude.is_finite() && (-180.0..=180.0).contains(&loc.longitude)));
+
+        // Each leg is a few hundred meters at most, even across the pole
+        let length = calculate_total_distance(&raw, DistanceUnit::Meters);
+        assert!(length.is_finite() && length > 0.0 && length < 2_000.0);
+    }
+
+    #[tokio::test]
+    async fn test_antimeridian_unwrapping_can_be_disabled() {
+        let mut config = Config::default();
+        config.route_simplification.unwrap_antimeridian = false;
+        let harness = Harness::new(config);
+
+        send_antimeridian_route(&harness).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert!(trip.get_i32("simplifiedPointsCount").unwrap() > 2);
+    }
+
+    #[tokio::test]
+    async fn test_session_tokens_checked_in_static_mode() {
+        let mut config = Config::default();
+        config.auth.mode = AuthMode::Static;
+        config.auth.tokens = vec!["token-a".to_string(), "token-b".to_string()];
+        let harness = Harness::new(config);
+
+        let mut valid = message(BusStatus::InRoute, 0.0, 0.0, 1);
+        valid.session_token = Some("token-b".to_string());
+        harness.send(&valid).await.unwrap();
+
+        let mut invalid = message(BusStatus::InRoute, 0.0, 0.01, 2);
+        invalid.session_token = Some("token-c".to_string());
+        let error = harness.send(&invalid).await.unwrap_err();
+        assert!(matches!(error, ServiceError::Unauthorized(_)));
+
+        let missing = message(BusStatus::InRoute, 0.0, 0.02, 3);
+        assert!(harness.send(&missing).await.is_err());
+
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 1);
+        assert_eq!(harness.service.metrics().messages_unauthorized, 2);
+    }
+
+    #[tokio::test]
+    async fn test_expired_redis_session_token_rejected() {
+        let mut config = Config::default();
+        config.auth.mode = AuthMode::Redis;
+        let harness = Harness::new(config);
+        harness
+            .points
+            .set("session_token:token-a", "driver_1".to_string())
+            .await
+            .unwrap();
+
+        let mut msg = message(BusStatus::InRoute, 0.0, 0.0, 1);
+        msg.session_token = Some("token-a".to_string());
+        harness.send(&msg).await.unwrap();
+
+        // The token key expired in Redis
+        harness
+            .points
+            .delete("session_token:token-a")
+            .await
+            .unwrap();
+        let mut msg = message(BusStatus::InRoute, 0.0, 0.01, 2);
+        msg.session_token = Some("token-a".to_string());
+        assert!(matches!(
+            harness.send(&msg).await,
+            Err(ServiceError::Unauthorized(_))
+        ));
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_denied_driver_skipped_and_counted() {
+        let mut config = Config::default();
+        config.ingestion.allow_drivers = vec!["driver_*".to_string()];
+        config.ingestion.deny_drivers = vec!["driver_1".to_string()];
+        let harness = Harness::new(config);
+
+        send_route(&harness, 3).await;
+
+        assert!(harness.trips.trips().is_empty());
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 0);
+        assert_eq!(harness.service.metrics().messages_filtered, 4);
+    }
+
+    #[tokio::test]
+    async fn test_stdout_sink_gets_one_line_per_trip() {
+        let buffer = SharedBuffer::default();
+        let mut config = Config::default();
+        // Both routes reuse the same points and ids
+        config.ingestion.dedup_points = false;
+        config.finalize.recent_finish_cache_size = 0;
+        let mut harness = Harness::new(config);
+        harness.service = harness
+            .service
+            .with_sink(Arc::new(NdjsonSink::new(Box::new(buffer.clone()))));
+
+        send_route(&harness, 3).await;
+        send_route(&harness, 4).await;
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        let trip: serde_json::Value = serde_json::from_str(&lines[1]).unwrap();
+        assert_eq!(trip["driverId"], "driver_1");
+        assert_eq!(trip["originalPointsCount"], 4);
+    }
+
+    fn delivery(msg: &BusMessage, retained: bool) -> IngressMessage {
+        IngressMessage {
+            retained,
+            ..IngressMessage::new(
+                "drivers_location/driver_1",
+                serde_json::to_vec(msg).unwrap(),
+            )
+        }
+    }
+
+    /// Hook taking a second per `in_route` point, recording how many ran at once
+    #[derive(Default)]
+    struct SlowHook {
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl StatusHook for SlowHook {
+        async fn on_in_route(&self, _msg: &BusMessage) -> ServiceResult<()> {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_messages_wait_for_a_permit() {
+        let mut config = Config::default();
+        config.processing.max_concurrent_tasks = 1;
+        let hook = Arc::new(SlowHook::default());
+        let mut harness = Harness::new(config);
+        harness.service = harness.service.with_hook(hook.clone());
+
+        let first = delivery(&message(BusStatus::InRoute, 0.0, 0.0, 1), false);
+        let second = delivery(&message(BusStatus::InRoute, 0.0, 0.01, 2), false);
+        let started = Instant::now();
+        let in_use = async {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            harness.service.metrics().processing_permits_in_use
+        };
+        let (first, second, in_use) = tokio::join!(
+            harness.service.process_delivery(&first, &harness.points),
+            harness.service.process_delivery(&second, &harness.points),
+            in_use,
+        );
+        first.unwrap();
+        second.unwrap();
+
+        // The second message queued behind the first instead of failing
+        assert_eq!(hook.max_running.load(Ordering::SeqCst), 1);
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+        assert_eq!(in_use, 1);
+        assert_eq!(harness.service.metrics().processing_permits_in_use, 0);
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_message_dropped() {
+        let mut config = Config::default();
+        config.ingestion.max_processing_age_ms = 500;
+        let harness = Harness::new(config);
+
+        let fresh = delivery(&message(BusStatus::InRoute, 0.0, 0.0, 1), false);
+        let stale = delivery(&message(BusStatus::InRoute, 0.0, 0.01, 2), false);
+        harness
+            .service
+            .process_delivery(&fresh, &harness.points)
+            .await
+            .unwrap();
+        tokio::time::advance(Duration::from_millis(501)).await;
+        harness
+            .service
+            .process_delivery(&stale, &harness.points)
+            .await
+            .unwrap();
+
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 1);
+        assert_eq!(harness.service.metrics().stale_dropped, 1);
+    }
+
+    #[tokio::test]
+    async fn test_retained_publish_skipped() {
+        let harness = Harness::new(Config::default());
+        let msg = message(BusStatus::InRoute, 0.0, 0.0, 1);
+
+        harness
+            .service
+            .process_delivery(&delivery(&msg, true), &harness.points)
+            .await
+            .unwrap();
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 0);
+        assert_eq!(harness.service.metrics().messages_filtered, 1);
+
+        harness
+            .service
+            .process_delivery(&delivery(&msg, false), &harness.points)
+            .await
+            .unwrap();
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_retained_publish_processed_when_allowed() {
+        let mut config = Config::default();
+        config.mqtt.ignore_retained = false;
+        let harness = Harness::new(config);
+        let msg = message(BusStatus::InRoute, 0.0, 0.0, 1);
+
+        harness
+            .service
+            .process_delivery(&delivery(&msg, true), &harness.points)
+            .await
+            .unwrap();
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_route_consumed_through_fake_ingress() {
+        let harness = Harness::new(Config::default());
+        let (sender, mut ingress) = ChannelIngress::new(8);
+        tokio::spawn(async move {
+            for i in 0..3 {
+                let msg = message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i);
+                sender.send(delivery(&msg, false)).await.unwrap();
+            }
+            let finished = message(BusStatus::Finished, 0.0, 0.0, 3);
+            sender.send(delivery(&finished, false)).await.unwrap();
+        });
+
+        let ingress: &mut dyn Ingress = &mut ingress;
+        while let Some(delivered) = ingress.recv().await {
+            harness
+                .service
+                .process_delivery(&delivered, &harness.points)
+                .await
+                .unwrap();
+        }
+
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].get_i32(fields::ORIGINAL_POINTS_COUNT).unwrap(), 3);
+        assert_eq!(harness.service.metrics().messages_processed, 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_route_finished_across_redis_reconnect() {
+        let harness = Harness::new(Config::default());
+        let server = Arc::new(FakeRedis::default());
+        let shared = SharedRedisConnection::with_connect(0, server.connector());
+        let retry = RetryPolicy::new(2, Duration::from_millis(50));
+
+        for i in 0..3 {
+            let points = RedisPointStore::new(shared.clone()).with_retry(retry);
+            let msg = message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i);
+            let payload = serde_json::to_vec(&msg).unwrap();
+            harness
+                .service
+                .process_message(&payload, &points)
+                .await
+                .unwrap();
+        }
+
+        // Redis restarts between the last point and the end of the route
+        server.drop_connections();
+        let points = RedisPointStore::new(shared).with_retry(retry);
+        let payload = serde_json::to_vec(&message(BusStatus::Finished, 0.0, 0.0, 3)).unwrap();
+        harness
+            .service
+            .process_message(&payload, &points)
+            .await
+            .unwrap();
+
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].get_i32(fields::ORIGINAL_POINTS_COUNT).unwrap(), 3);
+        assert!(server.list("driver_1:route_1").is_empty());
+        assert_eq!(server.connects(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_routes_finishing_together_inserted_in_one_batch() {
+        let mut config = Config::default();
+        config.mongodb.batch_size = 2;
+        let harness = Harness::new(config);
+
+        let finished = |route_id: &str| BusMessage {
+            current_route_id: route_id.to_string(),
+            ..message(BusStatus::Finished, 0.0, 0.0, 10)
+        };
+        for route_id in ["route_a", "route_b"] {
+            for i in 0..3 {
+                let msg = BusMessage {
+                    current_route_id: route_id.to_string(),
+                    ..message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i)
+                };
+                harness.send(&msg).await.unwrap();
+            }
+        }
+
+        let (finished_a, finished_b) = (finished("route_a"), finished("route_b"));
+        let (a, b) = tokio::join!(harness.send(&finished_a), harness.send(&finished_b));
+        a.unwrap();
+        b.unwrap();
+
+        assert_eq!(harness.trips.batch_sizes(), vec![2]);
+        assert_eq!(harness.trips.trips().len(), 2);
+        assert_eq!(harness.points.len("driver_1:route_a").await.unwrap(), 0);
+        assert_eq!(harness.points.len("driver_1:route_b").await.unwrap(), 0);
+        assert_eq!(harness.service.metrics().routes_completed, 2);
+    }
+
+    #[tokio::test]
+    async fn test_barely_compressible_route_keeps_raw_points() {
+        let mut config = Config::default();
+        config.route_simplification.min_reduction_fraction = 0.5;
+        let harness = Harness::new(config);
+
+        // Only the midpoint of the first leg is collinear; every other point is a corner
+        let points = [
+            (0.0, 0.0),
+            (0.0, 0.01),
+            (0.0, 0.02),
+            (0.01, 0.02),
+            (0.01, 0.03),
+        ];
+        for (i, (latitude, longitude)) in points.into_iter().enumerate() {
+            let msg = message(BusStatus::InRoute, latitude, longitude, i as u64);
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 5))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32("simplifiedPointsCount").unwrap(), 5);
+        assert!(trip.get_bool(fields::SIMPLIFICATION_SKIPPED).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_route_lengths_stored_in_meters() {
+        let harness = Harness::new(Config::default());
+
+        // East 0.01° along the equator, then north 0.001° with a bend to cut
+        let points = [(0.0, 0.0), (0.0, 0.01), (0.0005, 0.01002), (0.001, 0.01)];
+        for (i, (latitude, longitude)) in points.into_iter().enumerate() {
+            let msg = message(BusStatus::InRoute, latitude, longitude, i as u64);
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 5))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        let original = trip.get_f64(fields::ORIGINAL_LENGTH_METERS).unwrap();
+        let simplified = trip.get_f64(fields::SIMPLIFIED_LENGTH_METERS).unwrap();
+        let difference = trip.get_f64(fields::LENGTH_DIFFERENCE_METERS).unwrap();
+        // ~1112 m east plus ~111 m north, not fractions of a degree
+        assert!((original - 1_223.0).abs() < 2.0, "{original}");
+        assert!(simplified <= original && simplified > 1_100.0);
+        assert!((difference - (original - simplified)).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_point_cap_keeps_exactly_the_cap() {
+        let mut config = Config::default();
+        config.route_simplification.max_output_points = 4;
+        let harness = Harness::new(config);
+
+        // A zigzag whose every point is a corner at the default tolerance
+        for i in 0..12u64 {
+            let latitude = if i % 2 == 0 {
+                0.0
+            } else {
+                0.002 + i as f64 * 0.0001
+            };
+            let msg = message(BusStatus::InRoute, latitude, i as f64 * 0.002, i);
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 12))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32(fields::SIMPLIFIED_POINTS_COUNT).unwrap(), 4);
+        assert_eq!(trip.get_array(fields::SIMPLIFIED_ROUTE).unwrap().len(), 4);
+        assert!(trip.get_bool(fields::POINT_CAP_APPLIED).unwrap());
+        let deviation = trip.get_f64(fields::MAX_DEVIATION_METERS).unwrap();
+        // The zigzag is 220-330 m tall, so some peak must be bridged
+        assert!(deviation > 200.0 && deviation < 400.0);
+    }
+
+    #[tokio::test]
+    async fn test_stop_boundaries_survive_aggressive_simplification() {
+        let mut config = Config::default();
+        config.route_simplification.tolerance = 1.0;
+        config.route_simplification.preserve_stops = true;
+        let harness = Harness::new(config);
+
+        // A straight line with a 60 s dwell at longitude 0.001-0.00102
+        let route = [
+            (0.0, 0),
+            (0.0005, 5),
+            (0.001, 10),
+            (0.00101, 30),
+            (0.00102, 70),
+            (0.0015, 80),
+            (0.002, 90),
+        ];
+        for (longitude, secs) in route {
+            harness
+                .send(&message(BusStatus::InRoute, 0.0, longitude, secs * 1000))
+                .await
+                .unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 100_000))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        let longitudes: Vec<f64> = trip
+            .get_array(fields::SIMPLIFIED_ROUTE)
+            .unwrap()
+            .iter()
+            .map(|point| {
+                location_from_document(point.as_document().unwrap())
+                    .unwrap()
+                    .longitude
+            })
+            .collect();
+        assert_eq!(longitudes, vec![0.0, 0.001, 0.00102, 0.002]);
+    }
+
+    #[tokio::test]
+    async fn test_simplify_benchmark_stored_when_enabled() {
+        let mut config = Config::default();
+        config.output.simplify_benchmark = true;
+        config.route_simplification.max_length_difference_meters = 1.0;
+        let harness = Harness::new(config);
+
+        send_zigzag_route(&harness).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert!(trip.get_i64(fields::SIMPLIFY_MICROS).unwrap() >= 0);
+        assert!(trip.get_i32(fields::SIMPLIFY_ITERATIONS).unwrap() >= 1);
+
+        let harness = Harness::new(Config::default());
+        send_zigzag_route(&harness).await;
+        assert!(harness.trips.trips()[0]
+            .get(fields::SIMPLIFY_MICROS)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_resimplify_command_updates_stored_trip() {
+        let mut config = Config::default();
+        config.output.store_raw_route = true;
+        config.route_simplification.max_payload_tolerance = 0.01;
+        let harness = Harness::new(config);
+
+        send_zigzag_route(&harness).await;
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_f64(fields::TOLERANCE).unwrap(), 0.0001);
+        assert_eq!(trip.get_array(fields::RAW_ROUTE).unwrap().len(), 9);
+        assert_eq!(trip.get_i32(fields::SIMPLIFIED_POINTS_COUNT).unwrap(), 9);
+
+        // A tolerance above the zigzag amplitude flattens the route
+        let command = br#"{"driverId":"driver_1","routeId":"route_1","tolerance":0.005}"#;
+        harness.service.process_resimplify(command).await.unwrap();
+
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].get_f64(fields::TOLERANCE).unwrap(), 0.005);
+        assert_eq!(
+            trips[0].get_i32(fields::SIMPLIFIED_POINTS_COUNT).unwrap(),
+            2
+        );
+        assert_eq!(
+            trips[0].get_array(fields::SIMPLIFIED_ROUTE).unwrap().len(),
+            2
+        );
+        assert_eq!(trips[0].get_array(fields::RAW_ROUTE).unwrap().len(), 9);
+
+        let unknown = br#"{"driverId":"driver_1","routeId":"route_9","tolerance":0.005}"#;
+        assert!(harness.service.process_resimplify(unknown).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_resimplify_under_projected_only_drops_stale_wgs84_route() {
+        let mut config = Config::default();
+        config.output.store_raw_route = true;
+        config.route_simplification.max_payload_tolerance = 0.01;
+        let harness = Harness::new(config.clone());
+        send_zigzag_route(&harness).await;
+        assert!(harness.trips.trips()[0].contains_key(fields::SIMPLIFIED_ROUTE));
+
+        // Projection enabled after the trip was stored
+        config.output.projection_epsg = 3857;
+        config.output.projection_mode = ProjectionMode::ProjectedOnly;
+        let service = IngestionService::new(
+            config,
+            harness.trips.clone(),
+            Arc::new(RecordingPublisher::new()),
+        )
+        .unwrap();
+        let command = br#"{"driverId":"driver_1","routeId":"route_1","tolerance":0.005}"#;
+        service.process_resimplify(command).await.unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        assert!(trip.get(fields::SIMPLIFIED_ROUTE).is_none());
+        assert_eq!(trip.get_array(fields::PROJECTED_ROUTE).unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_resimplify_requires_raw_route() {
+        let harness = Harness::new(Config::default());
+        send_zigzag_route(&harness).await;
+
+        let command = br#"{"driverId":"driver_1","routeId":"route_1","tolerance":0.0005}"#;
+        let error = harness
+            .service
+            .process_resimplify(command)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains(fields::RAW_ROUTE));
+    }
+
+    #[tokio::test]
+    async fn test_started_in_route_finished_lifecycle() {
+        let mut config = Config::default();
+        config.ingestion.require_started = true;
+        let harness = Harness::new(config);
+
+        // A stray point before the start is skipped
+        harness
+            .send(&message(BusStatus::InRoute, 0.0, 0.0, 1))
+            .await
+            .unwrap();
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 0);
+        assert_eq!(harness.service.metrics().messages_filtered, 1);
+
+        let started = br#"{"driverId":"driver_1","currentRouteId":"route_1","timestamp":5,
+            "status":"started","plannedLengthMeters":1200.0}"#;
+        harness
+            .service
+            .process_message(started, &harness.points)
+            .await
+            .unwrap();
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 0);
+
+        send_route(&harness, 3).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i64(fields::STARTED_AT).unwrap(), 5);
+        assert_eq!(trip.get_f64(fields::PLANNED_LENGTH_METERS).unwrap(), 1200.0);
+        assert_eq!(trip.get_i32(fields::ORIGINAL_POINTS_COUNT).unwrap(), 3);
+        assert!(harness
+            .points
+            .get("driver_1:route_1:start")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_trip_stores_first_and_last_point_times() {
+        let harness = Harness::new(Config::default());
+        for (i, timestamp) in [1_700_000_100u64, 1_700_000_160, 1_700_000_220]
+            .into_iter()
+            .enumerate()
+        {
+            harness
+                .send(&message(
+                    BusStatus::InRoute,
+                    0.0,
+                    i as f64 * 0.01,
+                    timestamp,
+                ))
+                .await
+                .unwrap();
+        }
+        // The finish message arrives well after the last point
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 1_700_000_900))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(
+            trip.get_i64(fields::START_TIMESTAMP).unwrap(),
+            1_700_000_100
+        );
+        assert_eq!(trip.get_i64(fields::END_TIMESTAMP).unwrap(), 1_700_000_220);
+        assert_eq!(trip.get_i64(fields::TIMESTAMP).unwrap(), 1_700_000_900);
+    }
+
+    #[tokio::test]
+    async fn test_points_without_start_accepted_by_default() {
+        let harness = Harness::new(Config::default());
+        send_route(&harness, 3).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32(fields::ORIGINAL_POINTS_COUNT).unwrap(), 3);
+        assert!(trip.get(fields::STARTED_AT).is_none());
+    }
+
+    async fn seed_oversized_route(harness: &Harness) {
+        for i in 0..20 {
+            harness
+                .send(&message(BusStatus::InRoute, 0.0, i as f64 * 0.001, i))
+                .await
+                .unwrap();
+        }
+        harness
+            .points
+            .push("dead_letter:driver_9:route_9", b"{}".to_vec())
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_startup_scan_trims_or_logs_oversized_routes() {
+        let mut config = Config::default();
+        config.redis.startup_max_points = 5;
+        config.redis.startup_oversize_policy = StartupOversizePolicy::Trim;
+        let harness = Harness::new(config.clone());
+        seed_oversized_route(&harness).await;
+
+        let found = harness
+            .service
+            .recover_oversized_routes(&harness.points)
+            .await
+            .unwrap();
+        assert_eq!(found, 1);
+        let kept = harness
+            .points
+            .range("driver_1:route_1", 0, -1)
+            .await
+            .unwrap();
+        assert_eq!(kept.len(), 5);
+        assert_eq!(decode_point(&kept[0]).unwrap().timestamp, Some(15));
+
+        config.redis.startup_oversize_policy = StartupOversizePolicy::Log;
+        let harness = Harness::new(config);
+        seed_oversized_route(&harness).await;
+        harness
+            .service
+            .recover_oversized_routes(&harness.points)
+            .await
+            .unwrap();
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 20);
+    }
+
+    #[tokio::test]
+    async fn test_startup_scan_finalizes_oversized_routes() {
+        let mut config = Config::default();
+        config.redis.startup_max_points = 5;
+        config.redis.startup_oversize_policy = StartupOversizePolicy::Finalize;
+        let harness = Harness::new(config);
+        seed_oversized_route(&harness).await;
+
+        harness
+            .service
+            .recover_oversized_routes(&harness.points)
+            .await
+            .unwrap();
+
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].get_i32(fields::ORIGINAL_POINTS_COUNT).unwrap(), 20);
+        assert_eq!(trips[0].get_i64(fields::TIMESTAMP).unwrap(), 19);
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 0);
+        assert_eq!(
+            harness
+                .points
+                .len("dead_letter:driver_9:route_9")
+                .await
+                .unwrap(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn test_startup_scan_skips_progress_keys() {
+        let mut config = Config::default();
+        config.redis.startup_max_points = 5;
+        config.redis.startup_oversize_policy = StartupOversizePolicy::Finalize;
+        let harness = Harness::new(config);
+        seed_oversized_route(&harness).await;
+        // Progress keys share the `{a}:{b}` shape of route keys
+        for key in ["progress:driver_1", "progress:driver_1:traveled"] {
+            for _ in 0..10 {
+                harness.points.push(key, b"{}".to_vec()).await.unwrap();
+            }
+        }
+
+        let found = harness
+            .service
+            .recover_oversized_routes(&harness.points)
+            .await
+            .unwrap();
+
+        assert_eq!(found, 1);
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].get_str(fields::DRIVER_ID).unwrap(), "driver_1");
+        assert_eq!(harness.points.len("progress:driver_1").await.unwrap(), 10);
+        assert_eq!(
+            harness
+                .points
+                .len("progress:driver_1:traveled")
+                .await
+                .unwrap(),
+            10
+        );
+    }
+
+    #[tokio::test]
+    async fn test_self_intersections_stored_when_enabled() {
+        let mut config = Config::default();
+        config.output.self_intersections = true;
+        let harness = Harness::new(config);
+
+        // A bow tie crossing itself at (0.001, 0.001)
+        let route = [(0.0, 0.0), (0.002, 0.002), (0.0, 0.002), (0.002, 0.0)];
+        for (i, (latitude, longitude)) in route.into_iter().enumerate() {
+            harness
+                .send(&message(BusStatus::InRoute, latitude, longitude, i as u64))
+                .await
+                .unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 10))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32(fields::SELF_INTERSECTION_COUNT).unwrap(), 1);
+        let crossing = trip.get_array(fields::SELF_INTERSECTIONS).unwrap()[0]
+            .as_document()
+            .unwrap();
+        let crossing = location_from_document(crossing).unwrap();
+        assert!((crossing.latitude - 0.001).abs() < 1e-9);
+        assert!((crossing.longitude - 0.001).abs() < 1e-9);
+    }
+
+    #[tokio::test]
+    async fn test_low_accuracy_fixes_dropped_and_counted() {
+        let mut config = Config::default();
+        config.finalize.stages = vec![RouteStageKind::Accuracy];
+        config.finalize.max_accuracy = 10.0;
+        config.output.store_raw_route = true;
+        let harness = Harness::new(config);
+
+        let accuracies = [
+            Some(4.0),
+            Some(80.0),
+            None,
+            Some(10.0),
+            Some(35.0),
+            Some(2.5),
+        ];
+        for (i, accuracy) in accuracies.into_iter().enumerate() {
+            let mut msg = message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i as u64);
+            msg.driver_location.accuracy = accuracy;
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 10))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32(fields::ORIGINAL_POINTS_COUNT).unwrap(), 6);
+        assert_eq!(trip.get_i32(fields::LOW_ACCURACY_POINTS).unwrap(), 2);
+        let kept: Vec<f64> = trip
+            .get_array(fields::RAW_ROUTE)
+            .unwrap()
+            .iter()
+            .map(|point| {
+                location_from_document(point.as_document().unwrap())
+                    .unwrap()
+                    .longitude
+            })
+            .collect();
+        assert_eq!(kept, vec![0.0, 0.02, 0.03, 0.05]);
+    }
+
+    #[tokio::test]
+    async fn test_global_route_id_unique_across_drivers() {
+        let mut config = Config::default();
+        config.output.global_route_id = true;
+        let harness = Harness::new(config);
+
+        for driver_id in ["driver_1", "driver_2"] {
+            for (i, status) in [BusStatus::InRoute, BusStatus::InRoute, BusStatus::Finished]
+                .into_iter()
+                .enumerate()
+            {
+                let msg = BusMessage {
+                    driver_id: driver_id.to_string(),
+                    current_route_id: "1".to_string(),
+                    ..message(status, 0.0, i as f64 * 0.01, i as u64)
+                };
+                harness.send(&msg).await.unwrap();
+            }
+        }
+
+        let ids: Vec<String> = harness
+            .trips
+            .trips()
+            .iter()
+            .map(|trip| trip.get_str(fields::GLOBAL_ROUTE_ID).unwrap().to_string())
+            .collect();
+        assert_eq!(ids, vec!["driver_1:1", "driver_2:1"]);
+    }
+
+    #[tokio::test]
+    async fn test_driver_id_with_key_separator_rejected() {
+        let harness = Harness::new(Config::default());
+        let msg = BusMessage {
+            driver_id: "driver:1".to_string(),
+            ..message(BusStatus::InRoute, 0.0, 0.0, 1)
+        };
+
+        assert!(harness.send(&msg).await.is_err());
+        assert_eq!(harness.points.len("driver:1:route_1").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_simplification_chain_records_algorithm() {
+        let harness = Harness::new(Config::default());
+        send_zigzag_route(&harness).await;
+        assert!(harness.trips.trips()[0]
+            .get(fields::SIMPLIFICATION_ALGORITHM)
+            .is_none());
+
+        let mut config = Config::default();
+        config.route_simplification.fallback_chain = vec![
+            SimplificationAlgorithm::Radial,
+            SimplificationAlgorithm::Rdp,
+        ];
+        config.route_simplification.tolerance = 0.005;
+        let harness = Harness::new(config);
+        send_route(&harness, 10).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(
+            trip.get_str(fields::SIMPLIFICATION_ALGORITHM).unwrap(),
+            "radial"
+        );
+        // Points 0.01° apart all clear the radial tolerance
+        assert_eq!(trip.get_i32(fields::SIMPLIFIED_POINTS_COUNT).unwrap(), 10);
+    }
+
+    #[tokio::test]
+    async fn test_algorithm_version_stored() {
+        let harness = Harness::new(Config::default());
+        send_route(&harness, 3).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(
+            trip.get_str(fields::ALGORITHM_VERSION).unwrap(),
+            ALGORITHM_VERSION
+        );
+        assert!(ALGORITHM_VERSION.starts_with(env!("CARGO_PKG_VERSION")));
+
+        let mut config = Config::default();
+        config.output.store_algorithm_version = false;
+        let harness = Harness::new(config);
+        send_route(&harness, 3).await;
+        assert!(harness.trips.trips()[0]
+            .get(fields::ALGORITHM_VERSION)
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_trip_ttl_sets_expiry_and_index() {
+        let mut config = Config::default();
+        config.mongodb.trip_ttl_secs = 86_400;
+        let harness = Harness::new(config);
+        harness.service.ensure_indexes().await.unwrap();
+
+        let indexes = harness.trips.expiry_indexes();
+        assert_eq!(indexes.len(), 1);
+        assert_eq!(indexes[0].keys, doc! { fields::EXPIRES_AT: 1 });
+        let options = indexes[0].options.as_ref().unwrap();
+        assert_eq!(options.expire_after, Some(Duration::ZERO));
+
+        harness
+            .send(&message(BusStatus::InRoute, 0.0, 0.0, 1_700_000_000_000))
+            .await
+            .unwrap();
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.01, 1_700_000_100_000))
+            .await
+            .unwrap();
+        // Message timestamps are in milliseconds: the trip expires a day later
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(
+            trip.get_datetime(fields::EXPIRES_AT)
+                .unwrap()
+                .timestamp_millis(),
+            1_700_000_100_000 + 86_400_000
+        );
+
+        // Without a TTL trips never expire and no index is created
+        let harness = Harness::new(Config::default());
+        harness.service.ensure_indexes().await.unwrap();
+        assert!(harness.trips.expiry_indexes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stationary_padding_trimmed() {
+        let mut config = Config::default();
+        config.finalize.trim_stationary_meters = 10.0;
+        config.output.store_raw_route = true;
+        let harness = Harness::new(config);
+
+        // Three fixes parked at the depot, a drive east, then two fixes parked
+        let longitudes = [0.0, 0.0, 0.0, 0.01, 0.02, 0.03, 0.03, 0.03];
+        for (i, longitude) in longitudes.into_iter().enumerate() {
+            harness
+                .send(&message(BusStatus::InRoute, 0.0, longitude, i as u64))
+                .await
+                .unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 10))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32(fields::TRIMMED_LEADING).unwrap(), 2);
+        assert_eq!(trip.get_i32(fields::TRIMMED_TRAILING).unwrap(), 2);
+        assert_eq!(trip.get_i32(fields::ORIGINAL_POINTS_COUNT).unwrap(), 8);
+        assert_eq!(trip.get_i64(fields::START_TIMESTAMP).unwrap(), 0);
+        // The kept route runs from the last depot fix to the first parked one
+        assert_eq!(trip.get_array(fields::RAW_ROUTE).unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_events_attached_to_nearest_retained_point() {
+        let mut config = Config::default();
+        config.output.route_events = true;
+        let harness = Harness::new(config);
+
+        // An L-shaped route east then north: only its ends and corner are kept
+        let event = |kind: &str| RouteEvent {
+            kind: kind.to_string(),
+            timestamp: None,
+            location: None,
+        };
+        let points = (0..=5)
+            .map(|i| (0.0, i as f64 * 0.001))
+            .chain((1..=5).map(|i| (i as f64 * 0.001, 0.005)));
+        for (i, (latitude, longitude)) in points.enumerate() {
+            let mut msg = message(BusStatus::InRoute, latitude, longitude, i as u64);
+            msg.events = match i {
+                1 => vec![event("door_open")],
+                4 => vec![event("brake"), event("speeding")],
+                9 => vec![event("door_close")],
+                _ => Vec::new(),
+            };
+            harness.send(&msg).await.unwrap();
+        }
+        assert_eq!(
+            harness.points.len("driver_1:route_1:events").await.unwrap(),
+            4
+        );
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 20))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        let route = trip.get_array(fields::SIMPLIFIED_ROUTE).unwrap();
+        assert_eq!(route.len(), 3);
+        let kinds: Vec<Vec<String>> = route
+            .iter()
+            .map(|point| {
+                let events = match point.as_document().unwrap().get_array(fields::EVENTS) {
+                    Ok(events) => events.clone(),
+                    Err(_) => Vec::new(),
+                };
+                events
+                    .iter()
+                    .map(|event| {
+                        let event = event.as_document().unwrap();
+                        event.get_str("kind").unwrap().to_string()
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                vec!["door_open".to_string()],
+                vec!["brake".to_string(), "speeding".to_string()],
+                vec!["door_close".to_string()],
+            ]
+        );
+        // Events default to the time and place of their point
+        let brake = route[1]
+            .as_document()
+            .unwrap()
+            .get_array(fields::EVENTS)
+            .unwrap()[0]
+            .as_document()
+            .unwrap()
+            .clone();
+        assert_eq!(brake.get_i64(fields::TIMESTAMP).unwrap(), 4);
+        assert_eq!(
+            harness.points.len("driver_1:route_1:events").await.unwrap(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dead_reckoned_points_flagged_in_raw_route() {
+        let mut config = Config::default();
+        config.finalize.stages = vec![RouteStageKind::DeadReckoning];
+        config.finalize.dead_reckoning_interval_secs = 10;
+        config.output.store_raw_route = true;
+        let harness = Harness::new(config);
+
+        // Heading east at 10 m/s, then the GPS drops out for 40 s
+        for (longitude, secs) in [(0.0, 0), (0.0009, 10), (0.0045, 50)] {
+            let mut msg = message(BusStatus::InRoute, 0.0, longitude, secs * 1000);
+            msg.driver_location.speed = Some(10.0);
+            msg.driver_location.heading = Some(90.0);
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 60_000))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        let flags: Vec<bool> = trip
+            .get_array(fields::RAW_ROUTE)
+            .unwrap()
+            .iter()
+            .map(|point| {
+                let point = point.as_document().unwrap();
+                location_from_document(point).unwrap().dead_reckoned
+            })
+            .collect();
+        assert_eq!(flags, vec![false, false, true, true, true, false]);
+    }
+
+    #[tokio::test]
+    async fn test_raw_endpoints_kept_when_preprocessing_drops_them() {
+        let mut config = Config::default();
+        config.finalize.stages = vec![RouteStageKind::Accuracy];
+        config.finalize.max_accuracy = 10.0;
+        config.output.store_raw_endpoints = true;
+        let harness = Harness::new(config);
+
+        // The first and last fixes are too inaccurate to keep
+        let accuracies = [50.0, 3.0, 3.0, 3.0, 50.0];
+        for (i, accuracy) in accuracies.into_iter().enumerate() {
+            let mut msg = message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i as u64);
+            msg.driver_location.accuracy = Some(accuracy);
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 10))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        let longitude = |point: &Bson| {
+            location_from_document(point.as_document().unwrap())
+                .unwrap()
+                .longitude
+        };
+        let route = trip.get_array(fields::SIMPLIFIED_ROUTE).unwrap();
+        assert_eq!(longitude(&route[0]), 0.01);
+        assert_eq!(longitude(route.last().unwrap()), 0.03);
+
+        let start = trip.get_document(fields::RAW_START).unwrap();
+        let end = trip.get_document(fields::RAW_END).unwrap();
+        assert_eq!(location_from_document(start).unwrap().longitude, 0.0);
+        assert_eq!(start.get_i64(fields::TIMESTAMP).unwrap(), 0);
+        assert_eq!(location_from_document(end).unwrap().longitude, 0.04);
+        assert_eq!(end.get_i64(fields::TIMESTAMP).unwrap(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_lod_levels_coarser_with_tolerance() {
+        let mut config = Config::default();
+        config.route_simplification.lod_tolerances = vec![0.00001, 0.0005, 0.002];
+        let harness = Harness::new(config);
+
+        // Zigzag peaks of three heights, so each coarser tolerance drops more of them
+        for i in 0..40u64 {
+            let amplitude = [0.003, 0.001, 0.0003][(i / 2 % 3) as usize];
+            let latitude = if i % 2 == 0 { 0.0 } else { amplitude };
+            harness
+                .send(&message(BusStatus::InRoute, latitude, i as f64 * 0.001, i))
+                .await
+                .unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 50))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        let counts: Vec<usize> = (0..3)
+            .map(|level| trip.get_array(format!("lod{}", level)).unwrap().len())
+            .collect();
+        assert!(counts.windows(2).all(|pair| pair[0] > pair[1]));
+        assert!(counts[0] <= 40);
+        assert!(!trip.contains_key("lod3"));
+    }
+
+    #[tokio::test]
+    async fn test_bounding_box_check_passes_correct_route() {
+        let mut config = Config::default();
+        config.route_simplification.validate_bounding_box = true;
+        let harness = Harness::new(config);
+
+        send_route(&harness, 10).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32(fields::SIMPLIFIED_POINTS_COUNT).unwrap(), 2);
+        assert!(trip.get(fields::BOUNDING_BOX_MISMATCH).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_disabled_simplification_stores_raw_route() {
+        let mut config = Config::default();
+        config.route_simplification.disabled = true;
+        config.route_simplification.max_length_difference_meters = 1.0;
+        // Stages and trimming that would otherwise change the stored points
+        config.finalize.stages = vec![RouteStageKind::Dedup, RouteStageKind::Smooth];
+        config.finalize.trim_stationary_meters = 5.0;
+        let harness = Harness::new(config);
+
+        // Collinear points simplification would reduce to the two ends, after
+        // a repeated, stationary start
+        let raw: Vec<Location> = [0.0, 0.0, 0.0001, 0.0003, 0.0004, 0.0007]
+            .into_iter()
+            .map(|longitude| Location::new(0.0, longitude))
+            .collect();
+        for (i, location) in raw.iter().enumerate() {
+            let msg = message(
+                BusStatus::InRoute,
+                location.latitude,
+                location.longitude,
+                (i as u64 + 1) * 1000,
+            );
+            harness.send(&msg).await.unwrap();
+        }
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 10_000))
+            .await
+            .unwrap();
+
+        let trip = &harness.trips.trips()[0];
+        let stored: Vec<Location> = trip
+            .get_array(fields::SIMPLIFIED_ROUTE)
+            .unwrap()
+            .iter()
+            .map(|point| location_from_document(point.as_document().unwrap()).unwrap())
+            .collect();
+        assert_eq!(stored, raw);
+        assert_eq!(trip.get_i32(fields::SIMPLIFIED_POINTS_COUNT).unwrap(), 6);
+        assert_eq!(trip.get_i32("originalPointsCount").unwrap(), 6);
+        assert_eq!(trip.get_f64("compressionRatio").unwrap(), 1.0);
+        assert!(trip.get_bool(fields::SIMPLIFICATION_DISABLED).unwrap());
+        assert!(trip.get(fields::LENGTH_PRESERVED).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_compressible_route_is_simplified_under_reduction_policy() {
+        let mut config = Config::default();
+        config.route_simplification.min_reduction_fraction = 0.5;
+        let harness = Harness::new(config);
+
+        send_route(&harness, 10).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32("simplifiedPointsCount").unwrap(), 2);
+        assert!(trip.get(fields::SIMPLIFICATION_SKIPPED).is_none());
+    }
+
+    /// Three points with a 0.0005 degree bump in the middle, finished with `tolerance`
+    async fn send_bump_route(harness: &Harness, tolerance: Option<f64>) -> i32 {
+        // Distinct timestamps per call so repeated routes are not deduplicated
+        let start = harness.trips.trips().len() as u64 * 10;
+        for (i, latitude) in [0.0, 0.0005, 0.0].into_iter().enumerate() {
+            let msg = message(
+                BusStatus::InRoute,
+                latitude,
+                i as f64 * 0.01,
+                start + i as u64,
+            );
+            harness.send(&msg).await.unwrap();
+        }
+        let finished = BusMessage {
+            tolerance,
+            ..message(BusStatus::Finished, 0.0, 0.0, start + 3)
+        };
+        harness.send(&finished).await.unwrap();
+
+        let trips = harness.trips.trips();
+        trips
+            .last()
+            .unwrap()
+            .get_i32("simplifiedPointsCount")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_payload_tolerance_overrides_config() {
+        let mut config = Config::default();
+        config.finalize.recent_finish_cache_size = 0;
+        let harness = Harness::new(config);
+
+        assert_eq!(send_bump_route(&harness, None).await, 3);
+        assert_eq!(send_bump_route(&harness, Some(0.001)).await, 2);
+    }
+
+    #[tokio::test]
+    async fn test_payload_tolerance_is_clamped() {
+        let mut config = Config::default();
+        config.route_simplification.max_payload_tolerance = 0.0003;
+        config.finalize.recent_finish_cache_size = 0;
+        let harness = Harness::new(config);
+
+        // Clamped to 0.0003, below the bump, so the bump survives
+        assert_eq!(send_bump_route(&harness, Some(1.0)).await, 3);
+        // Negative values are raised to the minimum instead of being rejected
+        assert_eq!(send_bump_route(&harness, Some(-1.0)).await, 3);
+    }
+
+    async fn send_route(harness: &Harness, points: usize) {
+        for i in 0..points {
+            let msg = message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i as u64);
+            harness.send(&msg).await.unwrap();
+        }
+        let finished = message(BusStatus::Finished, 0.0, 0.0, points as u64);
+        harness.send(&finished).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_overflow_truncates_to_most_recent_points() {
+        let mut config = Config::default();
+        config.finalize.max_points = 3;
+        config.finalize.overflow_policy = OverflowPolicy::Truncate;
+        let harness = Harness::new(config);
+
+        send_route(&harness, 5).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32("originalPointsCount").unwrap(), 3);
+        let route = trip.get_array("simplifiedRoute").unwrap();
+        let first = route[0].as_document().unwrap();
+        assert_eq!(first.get_f64("longitude").unwrap(), 0.02);
+    }
+
+    #[tokio::test]
+    async fn test_overflow_dead_letters_route() {
+        let mut config = Config::default();
+        config.finalize.max_points = 3;
+        config.finalize.overflow_policy = OverflowPolicy::DeadLetter;
+        let harness = Harness::new(config);
+
+        send_route(&harness, 5).await;
+
+        assert!(harness.trips.trips().is_empty());
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 0);
+        let records = dead_letter_records(&harness, "route_1").await;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].kind, DeadLetterKind::Overflow);
+        assert!(records[0].message.is_none());
+        assert_eq!(records[0].points_count, Some(5));
+        let points_key = records[0].points_key.as_deref().unwrap();
+        assert_eq!(points_key, "dead_letter:driver_1:route_1:points");
+        assert_eq!(harness.points.len(points_key).await.unwrap(), 5);
+    }
+
+    /// Decoded entries of a route's dead-letter list, each one a `DeadLetterRecord`
+    async fn dead_letter_records(harness: &Harness, route_id: &str) -> Vec<DeadLetterRecord> {
+        harness
+            .points
+            .range(&format!("dead_letter:driver_1:{}", route_id), 0, -1)
+            .await
+            .unwrap()
+            .iter()
+            .map(|record| serde_json::from_slice(record).unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_pseudonymized_driver_id_not_stored() {
+        let mut config = Config::default();
+        config.privacy.pseudonymize_drivers = true;
+        config.privacy.driver_id_salt = "pepper".to_string();
+        let harness = Harness::new(config);
+
+        send_route(&harness, 3).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(
+            trip.get_str("driverId").unwrap(),
+            hash_driver_id("driver_1", "pepper")
+        );
+        assert!(!trip.to_string().contains("driver_1"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_early_finish_waits_for_late_points() {
+        let mut config = Config::default();
+        config.finalize.early_finish_policy = EarlyFinishPolicy::Wait;
+        config.finalize.early_finish_grace_ms = 1000;
+        let harness = Harness::new(config);
+
+        let finished = message(BusStatus::Finished, 0.0, 0.0, 10);
+        let late_points = async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            for i in 0..3 {
+                let msg = message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i as u64);
+                harness.send(&msg).await.unwrap();
+            }
+        };
+        let (result, _) = tokio::join!(harness.send(&finished), late_points);
+        result.unwrap();
+
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].get_i32("originalPointsCount").unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_early_finish_dead_letters_message() {
+        let mut config = Config::default();
+        config.finalize.early_finish_policy = EarlyFinishPolicy::DeadLetter;
+        let harness = Harness::new(config);
+
+        let finished = message(BusStatus::Finished, 0.0, 0.0, 10);
+        assert!(harness.send(&finished).await.is_err());
+
+        // Points arriving afterwards are kept for a later finish
+        let msg = message(BusStatus::InRoute, 0.0, 0.0, 11);
+        harness.send(&msg).await.unwrap();
+
+        assert!(harness.trips.trips().is_empty());
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 1);
+        let records = dead_letter_records(&harness, "route_1").await;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].kind, DeadLetterKind::EarlyFinish);
+        assert_eq!(records[0].current_route_id, "route_1");
+        let recorded = records[0].message.as_ref().unwrap();
+        assert_eq!(recorded.status, BusStatus::Finished);
+        assert_eq!(recorded.timestamp, 10);
+        assert!(records[0].points_key.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_finish_storm_absorbed() {
+        let mut config = Config::default();
+        // Duplicates reaching Redis would each be recorded as dead letters
+        config.finalize.early_finish_policy = EarlyFinishPolicy::DeadLetter;
+        let harness = Harness::new(config);
+
+        for i in 0..3 {
+            let msg = message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i);
+            harness.send(&msg).await.unwrap();
+        }
+        let finished = message(BusStatus::Finished, 0.0, 0.0, 3);
+        for _ in 0..100 {
+            harness.send(&finished).await.unwrap();
+        }
+
+        assert_eq!(harness.trips.trips().len(), 1);
+        assert_eq!(harness.service.metrics().messages_filtered, 99);
+        assert!(harness
+            .points
+            .range("dead_letter:driver_1:route_1", 0, -1)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_finish_without_points_does_not_block_retry() {
+        let harness = Harness::new(Config::default());
+
+        let finished = message(BusStatus::Finished, 0.0, 0.0, 3);
+        harness.send(&finished).await.unwrap();
+        for i in 0..3 {
+            let msg = message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i);
+            harness.send(&msg).await.unwrap();
+        }
+        harness.send(&finished).await.unwrap();
+
+        assert_eq!(harness.trips.trips().len(), 1);
+    }
+
+    /// Trip store that fails every call while `down` is set, like an unreachable MongoDB
+    #[derive(Default)]
+    struct FlakyTripStore {
+        inner: InMemoryTripStore,
+        down: std::sync::atomic::AtomicBool,
+    }
+
+    impl FlakyTripStore {
+        fn check(&self) -> ServiceResult<()> {
+            if self.down.load(std::sync::atomic::Ordering::SeqCst) {
+                return Err(ServiceError::Connection("MongoDB unreachable".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait]
+    impl TripStore for FlakyTripStore {
+        async fn insert_trip(&self, trip: Document) -> ServiceResult<Bson> {
+            self.check()?;
+            self.inner.insert_trip(trip).await
+        }
+
+        async fn find_trip(&self, id: &Bson) -> ServiceResult<Option<Document>> {
+            self.check()?;
+            self.inner.find_trip(id).await
+        }
+
+        async fn update_trip(&self, id: &Bson, fields: Document) -> ServiceResult<()> {
+            self.check()?;
+            self.inner.update_trip(id, fields).await
+        }
+
+        async fn remove_trip_fields(&self, id: &Bson, fields: &[&str]) -> ServiceResult<()> {
+            self.check()?;
+            self.inner.remove_trip_fields(id, fields).await
+        }
+
+        async fn find_driver_trips(
+            &self,
+            driver_id: &str,
+            from: Option<i64>,
+            to: Option<i64>,
+            limit: usize,
+        ) -> ServiceResult<Vec<Document>> {
+            self.check()?;
+            self.inner
+                .find_driver_trips(driver_id, from, to, limit)
+                .await
+        }
+
+        async fn find_route_trip(
+            &self,
+            driver_id: &str,
+            route_id: &str,
+        ) -> ServiceResult<Option<Document>> {
+            self.inner.find_route_trip(driver_id, route_id).await
+        }
+
+        async fn ensure_expiry_index(
+            &self,
+            field: &str,
+            expire_after: Duration,
+        ) -> ServiceResult<()> {
+            self.inner.ensure_expiry_index(field, expire_after).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_trips_spooled_while_trip_store_down() {
+        let spool = TempSpool::new("service-downtime");
+        let store = Arc::new(FlakyTripStore::default());
+        store.down.store(true, std::sync::atomic::Ordering::SeqCst);
+        let service = IngestionService::new(
+            Config::default(),
+            store.clone(),
+            Arc::new(RecordingPublisher::new()),
+        )
+        .unwrap()
+        .with_spool(spool.0.clone());
+        let points = InMemoryPointStore::new();
+
+        for msg in [
+            message(BusStatus::InRoute, 0.0, 0.0, 1),
+            message(BusStatus::InRoute, 0.0, 0.01, 2),
+            message(BusStatus::Finished, 0.0, 0.01, 3),
+        ] {
+            let payload = serde_json::to_vec(&msg).unwrap();
+            service.process_message(&payload, &points).await.unwrap();
+        }
+
+        assert!(store.inner.trips().is_empty());
+        assert_eq!(spool.0.len().await.unwrap(), 1);
+        assert_eq!(points.len("driver_1:route_1").await.unwrap(), 0);
+
+        // Still down: the trip stays spooled
+        assert_eq!(spool.0.drain(store.as_ref()).await.unwrap(), 0);
+        assert_eq!(spool.0.len().await.unwrap(), 1);
+
+        store.down.store(false, std::sync::atomic::Ordering::SeqCst);
+        assert_eq!(spool.0.drain(store.as_ref()).await.unwrap(), 1);
+        assert!(spool.0.is_empty().await.unwrap());
+        let trip = &store.inner.trips()[0];
+        assert_eq!(trip.get_i32("originalPointsCount").unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_trip_store_failure_without_spool_keeps_points() {
+        let store = Arc::new(FlakyTripStore::default());
+        store.down.store(true, std::sync::atomic::Ordering::SeqCst);
+        let service = IngestionService::new(
+            Config::default(),
+            store.clone(),
+            Arc::new(RecordingPublisher::new()),
+        )
+        .unwrap();
+        let points = InMemoryPointStore::new();
+
+        let payload = serde_json::to_vec(&message(BusStatus::InRoute, 0.0, 0.0, 1)).unwrap();
+        service.process_message(&payload, &points).await.unwrap();
+        let payload = serde_json::to_vec(&message(BusStatus::Finished, 0.0, 0.0, 2)).unwrap();
+        assert!(service.process_message(&payload, &points).await.is_err());
+
+        assert_eq!(points.len("driver_1:route_1:finalizing").await.unwrap(), 1);
+
+        // A retry once the store is back resumes the detached points
+        store.down.store(false, std::sync::atomic::Ordering::SeqCst);
+        service.process_message(&payload, &points).await.unwrap();
+        assert_eq!(store.inner.trips().len(), 1);
+        assert_eq!(points.len("driver_1:route_1:finalizing").await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_geofence_events_published_once_trip_stored() {
+        let mut config = Config::default();
+        config.geofence.geofences = vec![depot_geofence()];
+        config.geofence.events_topic = Some("geofence_events".to_string());
+        let store = Arc::new(FlakyTripStore::default());
+        let publisher = Arc::new(RecordingPublisher::new());
+        let service = IngestionService::new(config, store.clone(), publisher.clone()).unwrap();
+        let points = InMemoryPointStore::new();
+
+        for (i, longitude) in [0.0, 1.5, 2.5].into_iter().enumerate() {
+            let msg = message(BusStatus::InRoute, 0.0, longitude, i as u64);
+            let payload = serde_json::to_vec(&msg).unwrap();
+            service.process_message(&payload, &points).await.unwrap();
+        }
+        store.down.store(true, std::sync::atomic::Ordering::SeqCst);
+        let payload = serde_json::to_vec(&message(BusStatus::Finished, 0.0, 2.5, 10)).unwrap();
+        assert!(service.process_message(&payload, &points).await.is_err());
+        assert!(publisher.messages_on("geofence_events").is_empty());
+
+        // The retry stores the trip and publishes the crossings exactly once
+        store.down.store(false, std::sync::atomic::Ordering::SeqCst);
+        service.process_message(&payload, &points).await.unwrap();
+        assert_eq!(store.inner.trips().len(), 1);
+        assert_eq!(publisher.messages_on("geofence_events").len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_metrics_partitioned_by_fleet() {
+        let mut config = Config::default();
+        config.metrics.per_fleet = true;
+        config.finalize.early_finish_policy = EarlyFinishPolicy::DeadLetter;
+        let harness = Harness::new(config);
+
+        let fleet_message = |status, driver_id: &str, fleet: Option<&str>| BusMessage {
+            driver_id: driver_id.to_string(),
+            fleet: fleet.map(str::to_string),
+            ..message(status, 0.0, 0.0, 1)
+        };
+        for msg in [
+            fleet_message(BusStatus::InRoute, "north-1", None),
+            fleet_message(BusStatus::InRoute, "north-2", None),
+            fleet_message(BusStatus::Finished, "north-1", None),
+        ] {
+            harness.send(&msg).await.unwrap();
+        }
+        // A finish without points fails under the dead-letter policy
+        let failing = fleet_message(BusStatus::Finished, "7", Some("south"));
+        assert!(harness.send(&failing).await.is_err());
+
+        let fleets = harness.service.fleet_metrics().snapshot();
+        assert_eq!(fleets.len(), 2);
+        assert_eq!(fleets["north"].messages_processed, 3);
+        assert_eq!(fleets["north"].routes_completed, 1);
+        assert_eq!(fleets["north"].errors_count, 0);
+        assert_eq!(fleets["south"].messages_processed, 1);
+        assert_eq!(fleets["south"].routes_completed, 0);
+        assert_eq!(fleets["south"].errors_count, 1);
+        assert_eq!(harness.service.metrics().messages_processed, 4);
+    }
+
+    #[tokio::test]
+    async fn test_fleet_metrics_disabled_by_default() {
+        let harness = Harness::new(Config::default());
+        send_route(&harness, 2).await;
+
+        assert!(harness.service.fleet_metrics().snapshot().is_empty());
+        assert_eq!(harness.service.metrics().routes_completed, 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_points_skipped_without_failing() {
+        let harness = Harness::new(Config::default());
+
+        for i in 0..3 {
+            let msg = message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i);
+            harness.send(&msg).await.unwrap();
+        }
+        for (latitude, longitude) in [(999.0, 0.0), (0.0, -181.0)] {
+            let spoofed = message(BusStatus::InRoute, latitude, longitude, 5);
+            harness.send(&spoofed).await.unwrap();
+        }
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 3);
+        assert_eq!(harness.service.metrics().invalid_points, 2);
+
+        // The route still finishes on a message with a bad position
+        harness
+            .send(&message(BusStatus::Finished, 91.0, 0.0, 6))
+            .await
+            .unwrap();
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].get_i32(fields::ORIGINAL_POINTS_COUNT).unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_malformed_payload_dead_lettered() {
+        let mut config = Config::default();
+        config.mqtt.dead_letter_topic = Some("drivers_location_dead".to_string());
+        let harness = Harness::new(config);
+
+        let garbage = vec![0xff, 0x00, b'{', 0xfe];
+        for payload in [garbage.clone(), b"[{\"driverId\":".to_vec()] {
+            let received = IngressMessage::new("drivers_location/driver_1", payload);
+            harness
+                .service
+                .process_delivery(&received, &harness.points)
+                .await
+                .unwrap();
+        }
+        let dead_letters = harness.publisher.messages_on("drivers_location_dead");
+        assert_eq!(dead_letters.len(), 2);
+        assert_eq!(dead_letters[0].payload, garbage);
+        assert_eq!(dead_letters[0].qos, QoS::AtLeastOnce);
+        assert_eq!(harness.service.metrics().errors_count, 2);
+
+        // Ingestion carries on with the next well-formed message
+        harness
+            .send(&message(BusStatus::InRoute, 0.0, 0.0, 1))
+            .await
+            .unwrap();
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 1);
+
+        // Republishing a dead letter seen on its own topic would loop
+        let looped = IngressMessage::new("drivers_location_dead", garbage);
+        harness
+            .service
+            .process_delivery(&looped, &harness.points)
+            .await
+            .unwrap();
+        assert_eq!(
+            harness.publisher.messages_on("drivers_location_dead").len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_payload_preview_truncates_text_and_hexes_binary() {
+        assert_eq!(payload_preview(b"{\"a\":"), r#""{\"a\":""#);
+        // The limit falls in the middle of a two-byte character
+        let long = format!("a{}", "é".repeat(MALFORMED_PREVIEW_BYTES));
+        let preview = payload_preview(long.as_bytes());
+        assert!(preview.ends_with("\"..."));
+        assert_eq!(
+            preview.matches('é').count(),
+            (MALFORMED_PREVIEW_BYTES - 1) / 2
+        );
+        assert_eq!(payload_preview(&[0xff, 0x00]), "0xff00");
+    }
+
+    #[tokio::test]
+    async fn test_coordinates_pair_stored_in_configured_order() {
+        let mut config = Config::default();
+        config.ingestion.input_coordinate_order = CoordinateOrder::LonLat;
+        let harness = Harness::new(config);
+
+        let payload = br#"{"driverId":"driver_1","currentRouteId":"route_1","timestamp":1,
+            "status":"in_route","coordinates":[-75.5812,6.2442]}"#;
+        harness
+            .service
+            .process_message(payload, &harness.points)
+            .await
+            .unwrap();
+        // Latitude-first data under the GeoJSON order is out of range
+        let swapped = br#"{"driverId":"driver_1","currentRouteId":"route_1","timestamp":2,
+            "status":"in_route","coordinates":[6.2442,-95.5812]}"#;
+        harness
+            .service
+            .process_message(swapped, &harness.points)
+            .await
+            .unwrap();
+        assert_eq!(harness.service.metrics().invalid_points, 1);
+
+        let stored = harness
+            .points
+            .range("driver_1:route_1", 0, -1)
+            .await
+            .unwrap();
+        assert_eq!(stored.len(), 1);
+        let location = decode_point(&stored[0]).unwrap();
+        assert_eq!((location.latitude, location.longitude), (6.2442, -75.5812));
+    }
+
+    #[tokio::test]
+    async fn test_trip_ack_published_to_device_topic() {
+        let mut config = Config::default();
+        config.ack.topic_template = Some("devices/{driver_id}/ack".to_string());
+        config.ack.qos = 2;
+        let harness = Harness::new(config);
+
+        send_route(&harness, 3).await;
+
+        let acks = harness.publisher.messages_on("devices/driver_1/ack");
+        assert_eq!(acks.len(), 1);
+        assert_eq!(acks[0].qos, QoS::ExactlyOnce);
+        let trip = &harness.trips.trips()[0];
+        let ack = acks[0].json();
+        assert_eq!(
+            ack["tripId"],
+            trip.get_object_id("_id").unwrap().to_hex().as_str()
+        );
+        assert_eq!(ack["storedPointsCount"], 2);
+        assert_eq!(ack["currentRouteId"], "route_1");
+    }
+
+    #[tokio::test]
+    async fn test_no_ack_without_topic_template() {
+        let harness = Harness::new(Config::default());
+
+        send_route(&harness, 3).await;
+
+        assert!(harness.publisher.messages().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_route_over_soft_limit_is_downsampled() {
+        let mut config = Config::default();
+        // Binary points with a timestamp are 26 bytes, so the limit is hit after 4
+        config.redis.point_encoding = PointEncoding::Binary;
+        config.redis.route_soft_limit_bytes = 100;
+        config.redis.over_limit_min_interval_secs = 5;
+        let harness = Harness::new(config);
+
+        // One point per second, timestamped in milliseconds
+        for i in 0..30 {
+            let msg = message(BusStatus::InRoute, 0.0, i as f64 * 0.001, i * 1000);
+            harness.send(&msg).await.unwrap();
+        }
+
+        // 0-3 before the limit, then one point every 5 seconds: 8, 13, 18, 23, 28
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 9);
+        let memory = harness.service.route_memory().snapshot();
+        assert_eq!(memory["driver_1:route_1"], 9 * 26);
+
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.0, 30_000))
+            .await
+            .unwrap();
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32("originalPointsCount").unwrap(), 9);
+        assert!(harness.service.route_memory().snapshot().is_empty());
+        assert!(harness
+            .points
+            .get("driver_1:route_1:bytes")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_route_point_counts_match_pushes() {
+        let mut config = Config::default();
+        config.metrics.route_points_refresh_secs = 10;
+        let harness = Harness::new(config);
+        for i in 0..3 {
+            harness
+                .send(&message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i))
+                .await
+                .unwrap();
+        }
+        let mut other = message(BusStatus::InRoute, 0.0, 0.0, 3);
+        other.driver_id = "driver_2".to_string();
+        harness.send(&other).await.unwrap();
+
+        // Lengths are only read from the point store on refresh
+        let counts = harness.service.route_points();
+        assert_eq!(counts.snapshot()["driver_1:route_1"], 0);
+        counts.refresh(&harness.points).await.unwrap();
+        let snapshot = counts.snapshot();
+        assert_eq!(snapshot["driver_1:route_1"], 3);
+        assert_eq!(snapshot["driver_2:route_1"], 1);
+
+        harness
+            .send(&message(BusStatus::Finished, 0.0, 0.03, 4))
+            .await
+            .unwrap();
+        assert_eq!(
+            counts.snapshot().keys().collect::<Vec<_>>(),
+            ["driver_2:route_1"]
+        );
+
+        // Disabled by default
+        let harness = Harness::new(Config::default());
+        harness
+            .send(&message(BusStatus::InRoute, 0.0, 0.0, 1))
+            .await
+            .unwrap();
+        assert!(harness.service.route_points().snapshot().is_empty());
+    }
+
+    /// Point store that appends one point to the live list on the first read,
+    /// as a concurrent `in_route` message would
+    struct AppendDuringRead {
+        inner: InMemoryPointStore,
+        appended: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait]
+    impl PointStore for AppendDuringRead {
+        async fn push(&self, key: &str, value: Vec<u8>) -> ServiceResult<()> {
+            self.inner.push(key, value).await
+        }
+
+        async fn range(&self, key: &str, start: isize, stop: isize) -> ServiceResult<Vec<Vec<u8>>> {
+            if !self
+                .appended
+                .swap(true, std::sync::atomic::Ordering::SeqCst)
+            {
+                let late = Location::new(0.0, 9.0);
+                self.inner
+                    .push("driver_1:route_1", serde_json::to_vec(&late)?)
+                    .await?;
+            }
+            self.inner.range(key, start, stop).await
+        }
+
+        async fn len(&self, key: &str) -> ServiceResult<usize> {
+            self.inner.len(key).await
+        }
+
+        async fn delete(&self, key: &str) -> ServiceResult<()> {
+            self.inner.delete(key).await
+        }
+
+        async fn rename(&self, key: &str, new_key: &str) -> ServiceResult<()> {
+            self.inner.rename(key, new_key).await
+        }
+
+        async fn trim(&self, key: &str, start: isize, stop: isize) -> ServiceResult<()> {
+            self.inner.trim(key, start, stop).await
+        }
+
+        async fn list_keys(&self) -> ServiceResult<Vec<String>> {
+            self.inner.list_keys().await
+        }
+
+        async fn mark_seen(&self, key: &str, member: &str, ttl_secs: u64) -> ServiceResult<bool> {
+            self.inner.mark_seen(key, member, ttl_secs).await
+        }
+
+        async fn get(&self, key: &str) -> ServiceResult<Option<String>> {
+            self.inner.get(key).await
+        }
+
+        async fn set(&self, key: &str, value: String) -> ServiceResult<()> {
+            self.inner.set(key, value).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_append_during_finalize_is_not_lost() {
+        let mut config = Config::default();
+        config.finalize.recent_finish_cache_size = 0;
+        let harness = Harness::new(config);
+        let points = AppendDuringRead {
+            inner: InMemoryPointStore::new(),
+            appended: std::sync::atomic::AtomicBool::new(true),
+        };
+
+        for i in 0..3 {
+            let msg = message(BusStatus::InRoute, 0.0, i as f64, i + 1);
+            let payload = serde_json::to_vec(&msg).unwrap();
+            harness
+                .service
+                .process_message(&payload, &points)
+                .await
+                .unwrap();
+        }
+        points
+            .appended
+            .store(false, std::sync::atomic::Ordering::SeqCst);
+
+        let payload = serde_json::to_vec(&message(BusStatus::Finished, 0.0, 0.0, 10)).unwrap();
+        harness
+            .service
+            .process_message(&payload, &points)
+            .await
+            .unwrap();
+
+        // The late point survived the finalize and was not folded into the trip
+        assert_eq!(points.len("driver_1:route_1").await.unwrap(), 1);
+        harness
+            .service
+            .process_message(&payload, &points)
+            .await
+            .unwrap();
+
+        let counts: Vec<i32> = harness
+            .trips
+            .trips()
+            .iter()
+            .map(|trip| trip.get_i32(fields::ORIGINAL_POINTS_COUNT).unwrap())
+            .collect();
+        assert_eq!(counts, vec![3, 1]);
+        assert_eq!(points.len("driver_1:route_1").await.unwrap(), 0);
+    }
+
+    async fn open_routes(harness: &Harness, routes: &[&str]) {
+        for (i, route_id) in routes.iter().enumerate() {
+            let msg = BusMessage {
+                current_route_id: route_id.to_string(),
+                ..message(BusStatus::InRoute, 0.0, i as f64 * 0.001, i as u64 + 1)
+            };
+            harness.send(&msg).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oldest_route_finalized_over_driver_cap() {
+        let mut config = Config::default();
+        config.ingestion.max_active_routes_per_driver = 2;
+        let harness = Harness::new(config);
+
+        open_routes(&harness, &["route_a", "route_b", "route_a", "route_c"]).await;
+
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(
+            trips[0].get_str(fields::CURRENT_ROUTE_ID).unwrap(),
+            "route_a"
+        );
+        assert_eq!(trips[0].get_i32(fields::ORIGINAL_POINTS_COUNT).unwrap(), 2);
+        assert_eq!(harness.points.len("driver_1:route_a").await.unwrap(), 0);
+        assert_eq!(harness.points.len("driver_1:route_c").await.unwrap(), 1);
+        assert_eq!(harness.service.metrics().routes_evicted, 1);
+    }
+
+    #[tokio::test]
+    async fn test_oldest_route_dead_lettered_over_driver_cap() {
+        let mut config = Config::default();
+        config.ingestion.max_active_routes_per_driver = 1;
+        config.ingestion.active_route_cap_policy = ActiveRouteCapPolicy::DeadLetter;
+        let harness = Harness::new(config);
+
+        open_routes(&harness, &["route_a", "route_b"]).await;
+
+        assert!(harness.trips.trips().is_empty());
+        assert_eq!(harness.points.len("driver_1:route_a").await.unwrap(), 0);
+        let records = dead_letter_records(&harness, "route_a").await;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].kind, DeadLetterKind::ActiveRouteCap);
+        assert_eq!(records[0].points_count, Some(1));
+        assert_eq!(
+            harness
+                .points
+                .len("dead_letter:driver_1:route_a:points")
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(harness.service.metrics().routes_evicted, 1);
+    }
+
+    /// Point store counting the append calls made to the route lists
+    #[derive(Default)]
+    struct CountingAppends {
+        inner: InMemoryPointStore,
+        pushes: std::sync::atomic::AtomicUsize,
+        batches: Mutex<Vec<usize>>,
+    }
+
+    #[async_trait]
+    impl PointStore for CountingAppends {
+        async fn push(&self, key: &str, value: Vec<u8>) -> ServiceResult<()> {
+            self.pushes
+                .fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            self.inner.push(key, value).await
+        }
+
+        async fn push_many(&self, key: &str, values: &[Vec<u8>]) -> ServiceResult<()> {
+            self.batches.lock().unwrap().push(values.len());
+            self.inner.push_many(key, values).await
+        }
+
+        async fn range(&self, key: &str, start: isize, stop: isize) -> ServiceResult<Vec<Vec<u8>>> {
+            self.inner.range(key, start, stop).await
+        }
+
+        async fn len(&self, key: &str) -> ServiceResult<usize> {
+            self.inner.len(key).await
+        }
+
+        async fn delete(&self, key: &str) -> ServiceResult<()> {
+            self.inner.delete(key).await
+        }
+
+        async fn rename(&self, key: &str, new_key: &str) -> ServiceResult<()> {
+            self.inner.rename(key, new_key).await
+        }
+
+        async fn trim(&self, key: &str, start: isize, stop: isize) -> ServiceResult<()> {
+            self.inner.trim(key, start, stop).await
+        }
+
+        async fn list_keys(&self) -> ServiceResult<Vec<String>> {
+            self.inner.list_keys().await
+        }
+
+        async fn mark_seen(&self, key: &str, member: &str, ttl_secs: u64) -> ServiceResult<bool> {
+            self.inner.mark_seen(key, member, ttl_secs).await
+        }
+
+        async fn get(&self, key: &str) -> ServiceResult<Option<String>> {
+            self.inner.get(key).await
+        }
+
+        async fn set(&self, key: &str, value: String) -> ServiceResult<()> {
+            self.inner.set(key, value).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batched_appends_flushed_before_finalize() {
+        let mut config = Config::default();
+        config.redis.append_batch_size = 10;
+        let harness = Harness::new(config);
+        let points = CountingAppends::default();
+        let send = |msg: BusMessage| {
+            let payload = serde_json::to_vec(&msg).unwrap();
+            let service = harness.service.clone();
+            let points = &points;
+            async move { service.process_message(&payload, points).await.unwrap() }
+        };
+
+        for i in 0..6 {
+            send(message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i + 1)).await;
+        }
+        assert_eq!(points.len("driver_1:route_1").await.unwrap(), 0);
+        assert!(points.batches.lock().unwrap().is_empty());
+
+        send(message(BusStatus::Finished, 0.0, 0.06, 7)).await;
+
+        assert_eq!(*points.batches.lock().unwrap(), vec![6]);
+        assert_eq!(points.pushes.load(std::sync::atomic::Ordering::SeqCst), 0);
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].get_i32(fields::ORIGINAL_POINTS_COUNT).unwrap(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_full_batches_and_shutdown_flush() {
+        let mut config = Config::default();
+        config.redis.append_batch_size = 3;
+        let harness = Harness::new(config);
+
+        for i in 0..4 {
+            let msg = message(BusStatus::InRoute, 0.0, i as f64 * 0.01, i + 1);
+            harness.send(&msg).await.unwrap();
+        }
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 3);
+
+        harness
+            .service
+            .flush_appends(&harness.points)
+            .await
+            .unwrap();
+        assert_eq!(harness.points.len("driver_1:route_1").await.unwrap(), 4);
+    }
+
+    /// Send a straight route along the equator over `steps` of 0.01 degrees,
+    /// one second apart, then finish it `finished_at` seconds in (timestamped
+    /// in milliseconds)
+    async fn send_route_over(
+        harness: &Harness,
+        route_id: &str,
+        steps: std::ops::Range<u64>,
+        finished_at: u64,
+    ) {
+        for step in steps.clone() {
+            let msg = BusMessage {
+                current_route_id: route_id.to_string(),
+                ..message(
+                    BusStatus::InRoute,
+                    0.0,
+                    step as f64 * 0.01,
+                    (finished_at - 10 + step) * 1000,
+                )
+            };
+            harness.send(&msg).await.unwrap();
+        }
+        let finished = BusMessage {
+            current_route_id: route_id.to_string(),
+            ..message(BusStatus::Finished, 0.0, 0.0, finished_at * 1000)
+        };
+        harness.send(&finished).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_repeated_route_outside_window_not_linked() {
+        let mut config = Config::default();
+        config.finalize.duplicate_policy = DuplicateRoutePolicy::Link;
+        config.finalize.duplicate_window_secs = 600;
+        let harness = Harness::new(config);
+
+        send_route_over(&harness, "route_a", 0..6, 100).await;
+        // Five minutes later is inside the ten minute window, an hour is not
+        send_route_over(&harness, "route_b", 0..6, 400).await;
+        send_route_over(&harness, "route_c", 0..6, 4000).await;
+
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 3);
+        assert_eq!(trips[1].get(fields::DUPLICATE_OF), trips[0].get("_id"));
+        assert!(trips[2].get(fields::DUPLICATE_OF).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_repeated_route_linked_to_earlier_trip() {
+        let mut config = Config::default();
+        config.finalize.duplicate_policy = DuplicateRoutePolicy::Link;
+        let harness = Harness::new(config);
+
+        send_route_over(&harness, "route_a", 0..6, 100).await;
+        send_route_over(&harness, "route_b", 1..5, 200).await;
+        send_route_over(&harness, "route_c", 20..25, 300).await;
+
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 3);
+        assert!(trips[0].get(fields::DUPLICATE_OF).is_none());
+        assert_eq!(trips[1].get(fields::DUPLICATE_OF), trips[0].get("_id"));
+        assert!(trips[2].get(fields::DUPLICATE_OF).is_none());
+        assert_eq!(harness.service.metrics().routes_merged, 1);
+    }
+
+    #[tokio::test]
+    async fn test_repeated_route_merged_into_earlier_trip() {
+        let mut config = Config::default();
+        config.finalize.duplicate_policy = DuplicateRoutePolicy::Merge;
+        config.finalize.duplicate_min_point_overlap = 0.5;
+        let harness = Harness::new(config);
+
+        send_route_over(&harness, "route_a", 0..6, 100).await;
+        // Reported again from the middle after a device reset, then continued
+        send_route_over(&harness, "route_b", 2..8, 200).await;
+
+        let trips = harness.trips.trips();
+        assert_eq!(trips.len(), 1);
+        let route: Vec<f64> = trips[0]
+            .get_array(fields::SIMPLIFIED_ROUTE)
+            .unwrap()
+            .iter()
+            .map(|point| location_from_document(point.as_document().unwrap()).unwrap())
+            .map(|loc| loc.longitude)
+            .collect();
+        assert_eq!(route, vec![0.0, 0.05, 0.07]);
+        assert_eq!(
+            trips[0].get_array(fields::MERGED_ROUTE_IDS).unwrap(),
+            &vec![Bson::String("route_b".to_string())]
+        );
+        assert_eq!(
+            trips[0].get_i32(fields::SIMPLIFIED_POINTS_COUNT).unwrap(),
+            3
+        );
+        assert_eq!(harness.points.len("driver_1:route_b").await.unwrap(), 0);
+        assert_eq!(harness.service.metrics().routes_merged, 1);
+    }
+
+    #[tokio::test]
+    async fn test_debug_echo_reports_sequence_and_length() {
+        let mut config = Config::default();
+        config.logging.debug_echo = true;
+        let harness = Harness::new(config);
+
+        for i in 0..3 {
+            let msg = message(BusStatus::InRoute, 6.25, -75.58 + i as f64 * 0.01, 10 + i);
+            harness.send(&msg).await.unwrap();
+        }
+
+        let echoes = harness.publisher.messages_on("debug/points");
+        assert_eq!(echoes.len(), 3);
+        assert_eq!(echoes[2].qos, QoS::AtMostOnce);
+        assert_eq!(
+            echoes[2].json(),
+            serde_json::json!({
+                "driverId": "driver_1",
+                "currentRouteId": "route_1",
+                "sequence": 2,
+                "routeLength": 3,
+                "latitude": 6.25,
+                "longitude": -75.58 + 0.02,
+                "timestamp": 12,
+            })
+        );
+        assert_eq!(echoes[0].json()["sequence"], 0);
+    }
+
+    #[tokio::test]
+    async fn test_no_debug_echo_by_default() {
+        let harness = Harness::new(Config::default());
+        harness
+            .send(&message(BusStatus::InRoute, 6.25, -75.58, 10))
+            .await
+            .unwrap();
+
+        assert!(harness.publisher.messages_on("debug/points").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_simplified_route_stored_in_web_mercator() {
+        let mut config = Config::default();
+        config.output.projection_epsg = 3857;
+        let harness = Harness::new(config);
+
+        send_route(&harness, 3).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert_eq!(trip.get_i32(fields::PROJECTION_EPSG).unwrap(), 3857);
+        let projected = trip.get_array(fields::PROJECTED_ROUTE).unwrap();
+        assert_eq!(projected.len(), 2);
+        let end = projected[1].as_document().unwrap();
+        // 0.02 degrees of longitude along the equator
+        assert!((end.get_f64(fields::X).unwrap() - 2_226.389_8).abs() < 1e-3);
+        assert!(end.get_f64(fields::Y).unwrap().abs() < 1e-6);
+        assert_eq!(trip.get_array(fields::SIMPLIFIED_ROUTE).unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_projected_only_drops_wgs84_route() {
+        let mut config = Config::default();
+        config.output.projection_epsg = 32618;
+        config.output.projection_mode = ProjectionMode::ProjectedOnly;
+        let harness = Harness::new(config);
+
+        send_route(&harness, 3).await;
+
+        let trip = &harness.trips.trips()[0];
+        assert!(trip.get(fields::SIMPLIFIED_ROUTE).is_none());
+        assert_eq!(trip.get_array(fields::PROJECTED_ROUTE).unwrap().len(), 2);
+        assert_eq!(trip.get_i32(fields::SIMPLIFIED_POINTS_COUNT).unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_compression_drift_alert_fires_past_threshold() {
+        let mut config = Config::default();
+        config.metrics.compression_drift_threshold = 0.5;
+        config.metrics.compression_ewma_alpha = 0.5;
+        config.metrics.compression_baseline_alpha = 0.05;
+        config.metrics.compression_min_trips = 5;
+        let harness = Harness::new(config);
+
+        // Straight 10-point routes keep 2 points: a ratio of 0.2
+        for i in 0..10 {
+            send_route_over(&harness, &format!("route_{i}"), 0..10, 100 * i + 100).await;
+        }
+        let metrics = harness.service.metrics();
+        assert!((metrics.compression_ratio_ewma - 0.2).abs() < 1e-9);
+        assert_eq!(metrics.compression_drift_alerts, 0);
+
+        // Devices start sending sparse 3-point routes: a ratio of 2/3
+        for i in 10..12 {
+            send_route_over(&harness, &format!("route_{i}"), 0..3, 100 * i + 100).await;
+        }
+        let metrics = harness.service.metrics();
+        assert_eq!(metrics.compression_drift_alerts, 1);
+        assert!(metrics.compression_drifting);
+
+        // Alerts fire on crossing the threshold, not on every drifted trip
+        send_route_over(&harness, "route_12", 0..3, 1300).await;
+        assert_eq!(harness.service.metrics().compression_drift_alerts, 1);
+    }
+}
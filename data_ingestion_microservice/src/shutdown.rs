@@ -0,0 +1,99 @@
+use crate::ingress::Ingress;
+use log::{debug, error, info};
+use tokio::task::JoinSet;
+
+/// Completes once the process is asked to stop: Ctrl-C, or SIGTERM on Unix
+/// (sent by Kubernetes ahead of SIGKILL on a rolling deploy)
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for Ctrl-C: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received Ctrl-C"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
+
+/// Wait for every in-flight processing task and return how many there were.
+/// The ingress keeps being polled meanwhile, so tasks publishing through the
+/// shared MQTT connection are not stuck behind a full request queue; messages
+/// arriving after shutdown started are dropped.
+pub async fn drain(tasks: &mut JoinSet<()>, ingress: &mut (impl Ingress + ?Sized)) -> usize {
+    let mut drained = 0;
+    let mut ingress_open = true;
+    while !tasks.is_empty() {
+        tokio::select! {
+            joined = tasks.join_next() => {
+                if let Some(Err(e)) = joined {
+                    error!("Message task failed during shutdown: {}", e);
+                }
+                drained += 1;
+            }
+            message = ingress.recv(), if ingress_open => match message {
+                Some(message) => debug!("Dropping message on {} during shutdown", message.topic),
+                None => ingress_open = false,
+            },
+        }
+    }
+    drained
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingress::{ChannelIngress, IngressMessage};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_drain_waits_for_in_flight_tasks() {
+        let (sender, mut ingress) = ChannelIngress::new(4);
+        let finished = Arc::new(AtomicUsize::new(0));
+        let mut tasks = JoinSet::new();
+        for delay in [1, 5, 3] {
+            let finished = finished.clone();
+            tasks.spawn(async move {
+                tokio::time::sleep(Duration::from_secs(delay)).await;
+                finished.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        // Messages arriving while draining are consumed, not processed
+        sender
+            .send(IngressMessage::new("drivers_location/d", b"{}".to_vec()))
+            .await
+            .unwrap();
+
+        let started = tokio::time::Instant::now();
+        assert_eq!(drain(&mut tasks, &mut ingress).await, 3);
+        assert_eq!(finished.load(Ordering::SeqCst), 3);
+        assert_eq!(started.elapsed(), Duration::from_secs(5));
+        assert_eq!(sender.capacity(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_drain_without_tasks_returns_immediately() {
+        let (_sender, mut ingress) = ChannelIngress::new(1);
+        assert_eq!(drain(&mut JoinSet::new(), &mut ingress).await, 0);
+    }
+}
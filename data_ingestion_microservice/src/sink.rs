@@ -0,0 +1,90 @@
+use crate::types::ServiceResult;
+use async_trait::async_trait;
+use mongodb::bson::{Bson, Document};
+use std::io::Write;
+use std::sync::Mutex;
+
+/// Additional destination for finalized trip documents
+#[async_trait]
+pub trait TripSink: Send + Sync {
+    async fn write_trip(&self, trip: &Document) -> ServiceResult<()>;
+}
+
+/// Writes each trip as one line of relaxed extended JSON (NDJSON)
+pub struct NdjsonSink {
+    writer: Mutex<Box<dyn Write + Send>>,
+}
+
+impl NdjsonSink {
+    pub fn new(writer: Box<dyn Write + Send>) -> Self {
+        Self {
+            writer: Mutex::new(writer),
+        }
+    }
+
+    /// Sink on the process stdout; logs go to stderr so lines never interleave
+    pub fn stdout() -> Self {
+        Self::new(Box::new(std::io::stdout()))
+    }
+}
+
+#[async_trait]
+impl TripSink for NdjsonSink {
+    async fn write_trip(&self, trip: &Document) -> ServiceResult<()> {
+        let line = Bson::Document(trip.clone()).into_relaxed_extjson();
+        let mut writer = self.writer.lock().unwrap();
+        writeln!(writer, "{}", line)?;
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+    use std::sync::Arc;
+
+    /// `Write` handle over a buffer the test keeps a reference to
+    #[derive(Clone, Default)]
+    pub(crate) struct SharedBuffer(pub Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuffer {
+        pub fn lines(&self) -> Vec<String> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(str::to_string)
+                .collect()
+        }
+    }
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_sink_writes_one_line_per_trip() {
+        let buffer = SharedBuffer::default();
+        let sink = NdjsonSink::new(Box::new(buffer.clone()));
+
+        sink.write_trip(&doc! { "driverId": "driver_1", "timestamp": 1_i64 })
+            .await
+            .unwrap();
+        sink.write_trip(&doc! { "driverId": "driver_2" })
+            .await
+            .unwrap();
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+        let first: serde_json::Value = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(first["driverId"], "driver_1");
+        assert_eq!(first["timestamp"], 1);
+    }
+}
@@ -0,0 +1,186 @@
+use crate::storage::TripStore;
+use crate::types::{ServiceError, ServiceResult};
+use log::{error, info, warn};
+use mongodb::bson::{Bson, Document};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Local file holding finalized trips that could not be written to MongoDB,
+/// one canonical extended JSON document per line, oldest first
+pub struct TripSpool {
+    path: PathBuf,
+    /// Serializes appends with drains so no line is lost while the file is rewritten
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl TripSpool {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Append a trip to the end of the spool
+    pub async fn append(&self, trip: &Document) -> ServiceResult<()> {
+        let _guard = self.lock.lock().await;
+        let line = Bson::Document(trip.clone()).into_canonical_extjson();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", line)?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// Number of trips waiting in the spool
+    pub async fn len(&self) -> ServiceResult<usize> {
+        let _guard = self.lock.lock().await;
+        Ok(self.read_lines()?.len())
+    }
+
+    pub async fn is_empty(&self) -> ServiceResult<bool> {
+        Ok(self.len().await? == 0)
+    }
+
+    /// Insert spooled trips into `store` in order, stopping at the first failed
+    /// insert. Returns how many trips left the spool. Lines that cannot be parsed
+    /// are logged and dropped so they never block the trips behind them.
+    pub async fn drain(&self, store: &dyn TripStore) -> ServiceResult<usize> {
+        let _guard = self.lock.lock().await;
+        let lines = self.read_lines()?;
+
+        let mut processed = 0;
+        for line in &lines {
+            match parse_trip(line) {
+                Ok(trip) => {
+                    if let Err(e) = store.insert_trip(trip).await {
+                        warn!("Trip store still unavailable, keeping spool: {}", e);
+                        break;
+                    }
+                }
+                Err(e) => error!("Dropping unreadable spooled trip: {}", e),
+            }
+            processed += 1;
+        }
+
+        if processed == lines.len() {
+            if !lines.is_empty() {
+                fs::remove_file(&self.path)?;
+            }
+        } else {
+            let tmp_path = self.path.with_extension("tmp");
+            fs::write(&tmp_path, lines[processed..].join("\n") + "\n")?;
+            fs::rename(&tmp_path, &self.path)?;
+        }
+
+        Ok(processed)
+    }
+
+    /// Drain the spool into `store` every `interval`, forever
+    pub async fn run_flusher(self: Arc<Self>, store: Arc<dyn TripStore>, interval: Duration) {
+        loop {
+            tokio::time::sleep(interval).await;
+            match self.drain(store.as_ref()).await {
+                Ok(0) => {}
+                Ok(drained) => info!("Flushed {} spooled trips to the trip store", drained),
+                Err(e) => error!("Failed to flush trip spool: {}", e),
+            }
+        }
+    }
+
+    fn read_lines(&self) -> ServiceResult<Vec<String>> {
+        match fs::read_to_string(&self.path) {
+            Ok(contents) => Ok(contents
+                .lines()
+                .filter(|line| !line.trim().is_empty())
+                .map(str::to_string)
+                .collect()),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn parse_trip(line: &str) -> ServiceResult<Document> {
+    let value: serde_json::Value = serde_json::from_str(line)?;
+    match Bson::try_from(value) {
+        Ok(Bson::Document(trip)) => Ok(trip),
+        Ok(other) => Err(ServiceError::Validation(format!(
+            "Spooled trip is not a document: {}",
+            other
+        ))),
+        Err(e) => Err(ServiceError::Validation(format!(
+            "Invalid extended JSON in spool: {}",
+            e
+        ))),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::storage::InMemoryTripStore;
+    use mongodb::bson::{doc, oid::ObjectId};
+
+    /// Spool file in the system temp directory, removed when dropped
+    pub(crate) struct TempSpool(pub Arc<TripSpool>, PathBuf);
+
+    impl TempSpool {
+        pub fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!(
+                "trip-spool-{}-{}.ndjson",
+                std::process::id(),
+                name
+            ));
+            let _ = fs::remove_file(&path);
+            Self(Arc::new(TripSpool::new(&path)), path)
+        }
+    }
+
+    impl Drop for TempSpool {
+        fn drop(&mut self) {
+            let _ = fs::remove_file(&self.1);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_drain_round_trips_documents() {
+        let spool = TempSpool::new("round-trip");
+        let trip = doc! {
+            "_id": ObjectId::new(),
+            "driverId": "driver_1",
+            "originalPointsCount": 3,
+            "timestamp": 1_700_000_000_000i64,
+            "compressionRatio": 0.5,
+        };
+        spool.0.append(&trip).await.unwrap();
+        assert_eq!(spool.0.len().await.unwrap(), 1);
+
+        let store = InMemoryTripStore::new();
+        assert_eq!(spool.0.drain(&store).await.unwrap(), 1);
+        assert_eq!(store.trips(), vec![trip]);
+        assert!(spool.0.is_empty().await.unwrap());
+        assert_eq!(spool.0.drain(&store).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_lines_dropped() {
+        let spool = TempSpool::new("unreadable");
+        fs::write(&spool.1, "not json\n").unwrap();
+        spool
+            .0
+            .append(&doc! { "driverId": "driver_1" })
+            .await
+            .unwrap();
+
+        let store = InMemoryTripStore::new();
+        assert_eq!(spool.0.drain(&store).await.unwrap(), 2);
+        assert_eq!(store.trips().len(), 1);
+        assert!(spool.0.is_empty().await.unwrap());
+    }
+}
@@ -0,0 +1,1241 @@
+use crate::codec::decode_point;
+use crate::fields;
+use crate::types::{Location, ServiceResult};
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use futures::TryStreamExt;
+use log::{debug, info, warn};
+use mongodb::bson::{doc, oid::ObjectId, Bson, Document};
+use mongodb::error::{BulkWriteFailure, ErrorKind};
+use mongodb::options::{FindOneOptions, FindOptions, IndexOptions, InsertManyOptions};
+use mongodb::IndexModel;
+use redis::aio::{ConnectionLike, MultiplexedConnection};
+use redis::AsyncCommands;
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
+use std::hash::{BuildHasher, Hasher};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// List-oriented storage for in-progress route points (Redis in production)
+#[async_trait]
+pub trait PointStore: Send + Sync {
+    /// Append a serialized point to the end of the list at `key`
+    async fn push(&self, key: &str, value: Vec<u8>) -> ServiceResult<()>;
+
+    /// Append several serialized points to the list at `key`, in order
+    async fn push_many(&self, key: &str, values: &[Vec<u8>]) -> ServiceResult<()> {
+        for value in values {
+            self.push(key, value.clone()).await?;
+        }
+        Ok(())
+    }
+
+    /// Read the inclusive range `[start, stop]`, with Redis LRANGE index semantics
+    async fn range(&self, key: &str, start: isize, stop: isize) -> ServiceResult<Vec<Vec<u8>>>;
+
+    /// Number of entries in the list at `key`
+    async fn len(&self, key: &str) -> ServiceResult<usize>;
+
+    /// Remove the list at `key`
+    async fn delete(&self, key: &str) -> ServiceResult<()>;
+
+    /// Move the list at `key` to `new_key`, replacing anything stored there
+    async fn rename(&self, key: &str, new_key: &str) -> ServiceResult<()>;
+
+    /// Keep only the inclusive range `[start, stop]` of the list at `key`, with
+    /// Redis LTRIM index semantics
+    async fn trim(&self, key: &str, start: isize, stop: isize) -> ServiceResult<()>;
+
+    /// Keys of every list currently stored
+    async fn list_keys(&self) -> ServiceResult<Vec<String>>;
+
+    /// Add `member` to the set at `key`, refreshing its expiry to `ttl_secs`.
+    /// Returns `false` when the member was already present.
+    async fn mark_seen(&self, key: &str, member: &str, ttl_secs: u64) -> ServiceResult<bool>;
+
+    /// Read the string value at `key`
+    async fn get(&self, key: &str) -> ServiceResult<Option<String>>;
+
+    /// Store a string value at `key`, replacing the previous one
+    async fn set(&self, key: &str, value: String) -> ServiceResult<()>;
+}
+
+/// Retries of Redis commands failing with a transient error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Extra attempts after the first failure; 0 disables retries
+    pub retries: u32,
+    /// Delay before the first retry, doubled on each further one
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(retries: u32, base_delay: Duration) -> Self {
+        Self {
+            retries,
+            base_delay,
+        }
+    }
+
+    /// Exponential backoff with jitter: a random delay between half and all of
+    /// `base_delay * 2^(retry - 1)`, so instances retrying together spread out
+    fn delay(&self, retry: u32) -> Duration {
+        let backoff = self.base_delay.saturating_mul(1 << (retry - 1).min(16));
+        let jitter = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+        backoff.mul_f64(0.5 + jitter / 2.0)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(0, Duration::ZERO)
+    }
+}
+
+/// Whether a Redis error is worth retrying: dropped or refused connections,
+/// timeouts and a server that is loading, failing over or asking to try again.
+/// Command errors such as a wrong key type are fatal.
+pub fn is_retryable(error: &redis::RedisError) -> bool {
+    error.is_io_error()
+        || error.is_timeout()
+        || error.is_connection_dropped()
+        || error.is_connection_refusal()
+        || matches!(
+            error.kind(),
+            redis::ErrorKind::BusyLoadingError
+                | redis::ErrorKind::TryAgain
+                | redis::ErrorKind::ClusterDown
+                | redis::ErrorKind::MasterDown
+        )
+}
+
+/// Opens a new connection for a `SharedRedisConnection`
+pub type Connect<C> = Arc<dyn Fn() -> BoxFuture<'static, redis::RedisResult<C>> + Send + Sync>;
+
+/// Redis connection opened once at startup and cloned into every message
+/// task, instead of a connection (and its handshake) per message. Commands are
+/// multiplexed over it. Once it drops, the failed command returns its error,
+/// which `RedisPointStore` retries per its retry policy, and the next command
+/// opens a new connection for every clone.
+pub struct SharedRedisConnection<C = MultiplexedConnection> {
+    connect: Connect<C>,
+    current: Arc<tokio::sync::Mutex<CurrentConnection<C>>>,
+    db: i64,
+}
+
+/// Connection shared by the clones of a `SharedRedisConnection`; `generation`
+/// counts the connections opened so far
+struct CurrentConnection<C> {
+    connection: Option<C>,
+    generation: u64,
+}
+
+impl<C> Clone for SharedRedisConnection<C> {
+    fn clone(&self) -> Self {
+        Self {
+            connect: self.connect.clone(),
+            current: self.current.clone(),
+            db: self.db,
+        }
+    }
+}
+
+impl SharedRedisConnection {
+    /// Multiplexed connection to `client`'s server, connected right away so a
+    /// wrong URL fails at startup
+    pub async fn open(client: redis::Client) -> ServiceResult<Self> {
+        let db = client.get_connection_info().redis.db;
+        let shared = Self::with_connect(
+            db,
+            Arc::new(move || {
+                let client = client.clone();
+                Box::pin(async move { client.get_multiplexed_tokio_connection().await })
+            }),
+        );
+        shared.connection().await?;
+        Ok(shared)
+    }
+}
+
+impl<C> SharedRedisConnection<C>
+where
+    C: ConnectionLike + Clone + Send + 'static,
+{
+    /// Shared connection opened with `connect` on the first command, for the
+    /// database `db`
+    pub fn with_connect(db: i64, connect: Connect<C>) -> Self {
+        Self {
+            connect,
+            current: Arc::new(tokio::sync::Mutex::new(CurrentConnection {
+                connection: None,
+                generation: 0,
+            })),
+            db,
+        }
+    }
+
+    /// The current connection and its generation, opening one if there is none
+    async fn connection(&self) -> redis::RedisResult<(u64, C)> {
+        let mut current = self.current.lock().await;
+        if current.connection.is_none() {
+            let connection = (self.connect)().await?;
+            current.generation += 1;
+            if current.generation > 1 {
+                info!("Reconnected to Redis");
+            }
+            current.connection = Some(connection);
+        }
+        let connection = current.connection.clone().expect("connection just opened");
+        Ok((current.generation, connection))
+    }
+
+    /// Drop connection `generation` after it failed, unless another task
+    /// already replaced it
+    async fn discard(&self, generation: u64, error: &redis::RedisError) {
+        let is_connection_error =
+            error.is_io_error() || error.is_connection_dropped() || error.is_connection_refusal();
+        if !is_connection_error {
+            return;
+        }
+        let mut current = self.current.lock().await;
+        if current.generation == generation && current.connection.is_some() {
+            warn!(
+                "Redis connection lost, reconnecting on the next command: {}",
+                error
+            );
+            current.connection = None;
+        }
+    }
+}
+
+impl<C> ConnectionLike for SharedRedisConnection<C>
+where
+    C: ConnectionLike + Clone + Send + 'static,
+{
+    fn req_packed_command<'a>(
+        &'a mut self,
+        cmd: &'a redis::Cmd,
+    ) -> redis::RedisFuture<'a, redis::Value> {
+        Box::pin(async move {
+            let (generation, mut connection) = self.connection().await?;
+            let result = connection.req_packed_command(cmd).await;
+            if let Err(e) = &result {
+                self.discard(generation, e).await;
+            }
+            result
+        })
+    }
+
+    fn req_packed_commands<'a>(
+        &'a mut self,
+        cmd: &'a redis::Pipeline,
+        offset: usize,
+        count: usize,
+    ) -> redis::RedisFuture<'a, Vec<redis::Value>> {
+        Box::pin(async move {
+            let (generation, mut connection) = self.connection().await?;
+            let result = connection.req_packed_commands(cmd, offset, count).await;
+            if let Err(e) = &result {
+                self.discard(generation, e).await;
+            }
+            result
+        })
+    }
+
+    fn get_db(&self) -> i64 {
+        self.db
+    }
+}
+
+/// `PointStore` backed by a Redis connection
+pub struct RedisPointStore<C> {
+    conn: tokio::sync::Mutex<C>,
+    retry: RetryPolicy,
+}
+
+impl<C> RedisPointStore<C>
+where
+    C: redis::aio::ConnectionLike + Send,
+{
+    pub fn new(conn: C) -> Self {
+        Self {
+            conn: tokio::sync::Mutex::new(conn),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Retry point list commands on transient errors. A retried `RPUSH` whose
+    /// first attempt reached Redis before the connection dropped stores the
+    /// point twice.
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Run `cmd`, retrying per the retry policy while it fails transiently
+    async fn query_with_retry<T: redis::FromRedisValue>(
+        &self,
+        cmd: &redis::Cmd,
+    ) -> ServiceResult<T> {
+        let mut retry = 0;
+        loop {
+            let result = {
+                let mut conn = self.conn.lock().await;
+                cmd.query_async(&mut *conn).await
+            };
+            match result {
+                Err(e) if retry < self.retry.retries && is_retryable(&e) => {
+                    retry += 1;
+                    let delay = self.retry.delay(retry);
+                    warn!(
+                        "Transient Redis error, retry {} of {} in {:?}: {}",
+                        retry, self.retry.retries, delay, e
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                result => return Ok(result?),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl<C> PointStore for RedisPointStore<C>
+where
+    C: redis::aio::ConnectionLike + Send,
+{
+    async fn push(&self, key: &str, value: Vec<u8>) -> ServiceResult<()> {
+        self.query_with_retry(&redis::Cmd::rpush(key, value)).await
+    }
+
+    async fn push_many(&self, key: &str, values: &[Vec<u8>]) -> ServiceResult<()> {
+        if values.is_empty() {
+            return Ok(());
+        }
+        self.query_with_retry(&redis::Cmd::rpush(key, values)).await
+    }
+
+    async fn range(&self, key: &str, start: isize, stop: isize) -> ServiceResult<Vec<Vec<u8>>> {
+        self.query_with_retry(&redis::Cmd::lrange(key, start, stop))
+            .await
+    }
+
+    async fn len(&self, key: &str) -> ServiceResult<usize> {
+        self.query_with_retry(&redis::Cmd::llen(key)).await
+    }
+
+    async fn delete(&self, key: &str) -> ServiceResult<()> {
+        self.query_with_retry(&redis::Cmd::del(key)).await
+    }
+
+    async fn rename(&self, key: &str, new_key: &str) -> ServiceResult<()> {
+        let mut conn = self.conn.lock().await;
+        let _: () = conn.rename(key, new_key).await?;
+        Ok(())
+    }
+
+    async fn trim(&self, key: &str, start: isize, stop: isize) -> ServiceResult<()> {
+        let mut conn = self.conn.lock().await;
+        let _: () = conn.ltrim(key, start, stop).await?;
+        Ok(())
+    }
+
+    async fn list_keys(&self) -> ServiceResult<Vec<String>> {
+        let mut conn = self.conn.lock().await;
+        let mut iter: redis::AsyncIter<String> = redis::cmd("SCAN")
+            .cursor_arg(0)
+            .arg("TYPE")
+            .arg("list")
+            .arg("COUNT")
+            .arg(1000)
+            .clone()
+            .iter_async(&mut *conn)
+            .await?;
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_item().await {
+            keys.push(key);
+        }
+        Ok(keys)
+    }
+
+    async fn mark_seen(&self, key: &str, member: &str, ttl_secs: u64) -> ServiceResult<bool> {
+        let mut conn = self.conn.lock().await;
+        let (added, _): (i64, i64) = redis::pipe()
+            .sadd(key, member)
+            .expire(key, ttl_secs as i64)
+            .query_async(&mut *conn)
+            .await?;
+        Ok(added == 1)
+    }
+
+    async fn get(&self, key: &str) -> ServiceResult<Option<String>> {
+        let mut conn = self.conn.lock().await;
+        Ok(conn.get(key).await?)
+    }
+
+    async fn set(&self, key: &str, value: String) -> ServiceResult<()> {
+        let mut conn = self.conn.lock().await;
+        let _: () = conn.set(key, value).await?;
+        Ok(())
+    }
+}
+
+/// In-memory `PointStore`, used for tests and local experimentation
+#[derive(Default)]
+pub struct InMemoryPointStore {
+    lists: Mutex<HashMap<String, Vec<Vec<u8>>>>,
+    sets: Mutex<HashMap<String, HashSet<String>>>,
+    values: Mutex<HashMap<String, String>>,
+}
+
+impl InMemoryPointStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl PointStore for InMemoryPointStore {
+    async fn push(&self, key: &str, value: Vec<u8>) -> ServiceResult<()> {
+        let mut lists = self.lists.lock().unwrap();
+        lists.entry(key.to_string()).or_default().push(value);
+        Ok(())
+    }
+
+    async fn push_many(&self, key: &str, values: &[Vec<u8>]) -> ServiceResult<()> {
+        let mut lists = self.lists.lock().unwrap();
+        lists
+            .entry(key.to_string())
+            .or_default()
+            .extend_from_slice(values);
+        Ok(())
+    }
+
+    async fn range(&self, key: &str, start: isize, stop: isize) -> ServiceResult<Vec<Vec<u8>>> {
+        let lists = self.lists.lock().unwrap();
+        let Some(list) = lists.get(key) else {
+            return Ok(Vec::new());
+        };
+
+        let len = list.len() as isize;
+        let start = if start < 0 {
+            (len + start).max(0)
+        } else {
+            start
+        };
+        let stop = if stop < 0 {
+            len + stop
+        } else {
+            stop.min(len - 1)
+        };
+        if start > stop {
+            return Ok(Vec::new());
+        }
+
+        Ok(list[start as usize..=stop as usize].to_vec())
+    }
+
+    async fn len(&self, key: &str) -> ServiceResult<usize> {
+        Ok(self.lists.lock().unwrap().get(key).map_or(0, Vec::len))
+    }
+
+    async fn delete(&self, key: &str) -> ServiceResult<()> {
+        self.lists.lock().unwrap().remove(key);
+        self.sets.lock().unwrap().remove(key);
+        self.values.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn rename(&self, key: &str, new_key: &str) -> ServiceResult<()> {
+        let mut lists = self.lists.lock().unwrap();
+        if let Some(list) = lists.remove(key) {
+            lists.insert(new_key.to_string(), list);
+        }
+        Ok(())
+    }
+
+    async fn trim(&self, key: &str, start: isize, stop: isize) -> ServiceResult<()> {
+        let kept = self.range(key, start, stop).await?;
+        let mut lists = self.lists.lock().unwrap();
+        if kept.is_empty() {
+            lists.remove(key);
+        } else if let Some(list) = lists.get_mut(key) {
+            *list = kept;
+        }
+        Ok(())
+    }
+
+    async fn list_keys(&self) -> ServiceResult<Vec<String>> {
+        let mut keys: Vec<String> = self.lists.lock().unwrap().keys().cloned().collect();
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Expiry is not simulated; members stay until the key is deleted
+    async fn mark_seen(&self, key: &str, member: &str, _ttl_secs: u64) -> ServiceResult<bool> {
+        let mut sets = self.sets.lock().unwrap();
+        Ok(sets
+            .entry(key.to_string())
+            .or_default()
+            .insert(member.to_string()))
+    }
+
+    async fn get(&self, key: &str) -> ServiceResult<Option<String>> {
+        Ok(self.values.lock().unwrap().get(key).cloned())
+    }
+
+    async fn set(&self, key: &str, value: String) -> ServiceResult<()> {
+        self.values.lock().unwrap().insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Document storage for finished trips (MongoDB in production)
+#[async_trait]
+pub trait TripStore: Send + Sync {
+    /// Persist a finished trip document, returning its `_id`
+    async fn insert_trip(&self, trip: Document) -> ServiceResult<Bson>;
+
+    /// Persist several finished trips, returning the `_id` or the error of each
+    /// one, in order
+    async fn insert_trips(&self, trips: Vec<Document>) -> Vec<ServiceResult<Bson>> {
+        let mut results = Vec::with_capacity(trips.len());
+        for trip in trips {
+            results.push(self.insert_trip(trip).await);
+        }
+        results
+    }
+
+    /// Fetch a stored trip by `_id`
+    async fn find_trip(&self, id: &Bson) -> ServiceResult<Option<Document>>;
+
+    /// Set the given fields on a stored trip
+    async fn update_trip(&self, id: &Bson, fields: Document) -> ServiceResult<()>;
+
+    /// Remove the given fields from a stored trip
+    async fn remove_trip_fields(&self, id: &Bson, fields: &[&str]) -> ServiceResult<()>;
+
+    /// Trips of a driver timestamped within `[from, to]`, oldest first, at most `limit`
+    async fn find_driver_trips(
+        &self,
+        driver_id: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+        limit: usize,
+    ) -> ServiceResult<Vec<Document>>;
+
+    /// Most recent trip stored for a driver's route
+    async fn find_route_trip(
+        &self,
+        driver_id: &str,
+        route_id: &str,
+    ) -> ServiceResult<Option<Document>>;
+
+    /// Create, if absent, a TTL index deleting trips `expire_after` past the
+    /// date stored in `field`
+    async fn ensure_expiry_index(&self, field: &str, expire_after: Duration) -> ServiceResult<()>;
+}
+
+/// TTL index on a date field of the trips collection
+pub fn expiry_index(field: &str, expire_after: Duration) -> IndexModel {
+    IndexModel::builder()
+        .keys(doc! { field: 1 })
+        .options(
+            IndexOptions::builder()
+                .name(format!("{}_ttl", field))
+                .expire_after(expire_after)
+                .build(),
+        )
+        .build()
+}
+
+/// `TripStore` backed by a MongoDB collection
+pub struct MongoTripStore {
+    collection: mongodb::Collection<Document>,
+}
+
+impl MongoTripStore {
+    pub fn new(collection: mongodb::Collection<Document>) -> Self {
+        Self { collection }
+    }
+}
+
+#[async_trait]
+impl TripStore for MongoTripStore {
+    async fn insert_trip(&self, trip: Document) -> ServiceResult<Bson> {
+        let result = self.collection.insert_one(trip, None).await?;
+        Ok(result.inserted_id)
+    }
+
+    /// One unordered `insert_many`, so a trip the server rejects does not keep
+    /// the rest of the batch from being stored
+    async fn insert_trips(&self, mut trips: Vec<Document>) -> Vec<ServiceResult<Bson>> {
+        // A partly failed insert_many does not report the ids it assigned
+        let ids: Vec<Bson> = trips
+            .iter_mut()
+            .map(|trip| {
+                trip.entry("_id".to_string())
+                    .or_insert_with(|| ObjectId::new().into())
+                    .clone()
+            })
+            .collect();
+        let options = InsertManyOptions::builder().ordered(false).build();
+        let mut failed: HashMap<usize, mongodb::error::Error> =
+            match self.collection.insert_many(&trips, options).await {
+                Ok(_) => HashMap::new(),
+                Err(e) => match e.kind.as_ref() {
+                    ErrorKind::BulkWrite(BulkWriteFailure {
+                        write_errors: Some(write_errors),
+                        write_concern_error: None,
+                        ..
+                    }) => write_errors
+                        .iter()
+                        .map(|write_error| (write_error.index, e.clone()))
+                        .collect(),
+                    _ => (0..ids.len()).map(|index| (index, e.clone())).collect(),
+                },
+            };
+        ids.into_iter()
+            .enumerate()
+            .map(|(index, id)| match failed.remove(&index) {
+                Some(e) => Err(e.into()),
+                None => Ok(id),
+            })
+            .collect()
+    }
+
+    async fn find_trip(&self, id: &Bson) -> ServiceResult<Option<Document>> {
+        Ok(self.collection.find_one(doc! { "_id": id }, None).await?)
+    }
+
+    async fn update_trip(&self, id: &Bson, fields: Document) -> ServiceResult<()> {
+        self.collection
+            .update_one(doc! { "_id": id }, doc! { "$set": fields }, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_trip_fields(&self, id: &Bson, fields: &[&str]) -> ServiceResult<()> {
+        let unset: Document = fields
+            .iter()
+            .map(|field| (field.to_string(), Bson::from("")))
+            .collect();
+        self.collection
+            .update_one(doc! { "_id": id }, doc! { "$unset": unset }, None)
+            .await?;
+        Ok(())
+    }
+
+    async fn find_driver_trips(
+        &self,
+        driver_id: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+        limit: usize,
+    ) -> ServiceResult<Vec<Document>> {
+        let mut filter = doc! { fields::DRIVER_ID: driver_id };
+        let mut range = Document::new();
+        if let Some(from) = from {
+            range.insert("$gte", from);
+        }
+        if let Some(to) = to {
+            range.insert("$lte", to);
+        }
+        if !range.is_empty() {
+            filter.insert(fields::TIMESTAMP, range);
+        }
+        let options = FindOptions::builder()
+            .sort(doc! { fields::TIMESTAMP: 1 })
+            .limit(limit as i64)
+            .build();
+
+        let cursor = self.collection.find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    async fn find_route_trip(
+        &self,
+        driver_id: &str,
+        route_id: &str,
+    ) -> ServiceResult<Option<Document>> {
+        let options = FindOneOptions::builder()
+            .sort(doc! { fields::TIMESTAMP: -1 })
+            .build();
+        Ok(self
+            .collection
+            .find_one(
+                doc! { fields::DRIVER_ID: driver_id, fields::CURRENT_ROUTE_ID: route_id },
+                options,
+            )
+            .await?)
+    }
+
+    async fn ensure_expiry_index(&self, field: &str, expire_after: Duration) -> ServiceResult<()> {
+        self.collection
+            .create_index(expiry_index(field, expire_after), None)
+            .await?;
+        Ok(())
+    }
+}
+
+/// In-memory `TripStore`, used for tests and local experimentation
+#[derive(Default)]
+pub struct InMemoryTripStore {
+    trips: Mutex<Vec<Document>>,
+    batch_sizes: Mutex<Vec<usize>>,
+    expiry_indexes: Mutex<Vec<IndexModel>>,
+}
+
+impl InMemoryTripStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Snapshot of all stored trips in insertion order
+    pub fn trips(&self) -> Vec<Document> {
+        self.trips.lock().unwrap().clone()
+    }
+
+    /// Number of trips in each `insert_trips` call, in call order
+    pub fn batch_sizes(&self) -> Vec<usize> {
+        self.batch_sizes.lock().unwrap().clone()
+    }
+
+    /// TTL indexes requested through `ensure_expiry_index`. Trips are not
+    /// actually expired.
+    pub fn expiry_indexes(&self) -> Vec<IndexModel> {
+        self.expiry_indexes.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl TripStore for InMemoryTripStore {
+    async fn insert_trip(&self, mut trip: Document) -> ServiceResult<Bson> {
+        if !trip.contains_key("_id") {
+            trip.insert("_id", ObjectId::new());
+        }
+        let id = trip.get("_id").cloned().unwrap_or(Bson::Null);
+        self.trips.lock().unwrap().push(trip);
+        Ok(id)
+    }
+
+    async fn insert_trips(&self, trips: Vec<Document>) -> Vec<ServiceResult<Bson>> {
+        self.batch_sizes.lock().unwrap().push(trips.len());
+        let mut results = Vec::with_capacity(trips.len());
+        for trip in trips {
+            results.push(self.insert_trip(trip).await);
+        }
+        results
+    }
+
+    async fn find_trip(&self, id: &Bson) -> ServiceResult<Option<Document>> {
+        let trips = self.trips.lock().unwrap();
+        Ok(trips
+            .iter()
+            .find(|trip| trip.get("_id") == Some(id))
+            .cloned())
+    }
+
+    async fn update_trip(&self, id: &Bson, fields: Document) -> ServiceResult<()> {
+        let mut trips = self.trips.lock().unwrap();
+        if let Some(trip) = trips.iter_mut().find(|trip| trip.get("_id") == Some(id)) {
+            trip.extend(fields);
+        }
+        Ok(())
+    }
+
+    async fn remove_trip_fields(&self, id: &Bson, fields: &[&str]) -> ServiceResult<()> {
+        let mut trips = self.trips.lock().unwrap();
+        if let Some(trip) = trips.iter_mut().find(|trip| trip.get("_id") == Some(id)) {
+            for field in fields {
+                trip.remove(*field);
+            }
+        }
+        Ok(())
+    }
+
+    async fn find_driver_trips(
+        &self,
+        driver_id: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+        limit: usize,
+    ) -> ServiceResult<Vec<Document>> {
+        let mut trips: Vec<Document> = self
+            .trips
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|trip| trip.get_str(fields::DRIVER_ID) == Ok(driver_id))
+            .filter(|trip| {
+                let timestamp = trip.get_i64(fields::TIMESTAMP).unwrap_or_default();
+                from.is_none_or(|from| timestamp >= from) && to.is_none_or(|to| timestamp <= to)
+            })
+            .cloned()
+            .collect();
+        trips.sort_by_key(|trip| trip.get_i64(fields::TIMESTAMP).unwrap_or_default());
+        trips.truncate(limit);
+        Ok(trips)
+    }
+
+    async fn find_route_trip(
+        &self,
+        driver_id: &str,
+        route_id: &str,
+    ) -> ServiceResult<Option<Document>> {
+        let trips = self.trips.lock().unwrap();
+        Ok(trips
+            .iter()
+            .filter(|trip| {
+                trip.get_str(fields::DRIVER_ID) == Ok(driver_id)
+                    && trip.get_str(fields::CURRENT_ROUTE_ID) == Ok(route_id)
+            })
+            .max_by_key(|trip| trip.get_i64(fields::TIMESTAMP).unwrap_or_default())
+            .cloned())
+    }
+
+    async fn ensure_expiry_index(&self, field: &str, expire_after: Duration) -> ServiceResult<()> {
+        let mut indexes = self.expiry_indexes.lock().unwrap();
+        let index = expiry_index(field, expire_after);
+        if !indexes.iter().any(|existing| existing.keys == index.keys) {
+            indexes.push(index);
+        }
+        Ok(())
+    }
+}
+
+/// Read the points of a route from index `offset` onwards in chunks of `batch_size`.
+/// Each chunk is decoded before the next one is fetched, so only one batch of raw
+/// points is held in memory at a time alongside the parsed locations.
+/// With `skip_unreadable`, points that cannot be decoded are logged and dropped
+/// instead of failing the whole read.
+pub async fn read_points_batched(
+    store: &dyn PointStore,
+    key: &str,
+    offset: usize,
+    batch_size: usize,
+    skip_unreadable: bool,
+) -> ServiceResult<Vec<Location>> {
+    let batch_size = batch_size.max(1);
+    let mut locations = Vec::new();
+    let mut start = offset;
+
+    loop {
+        let stop = start + batch_size - 1;
+        let batch = store.range(key, start as isize, stop as isize).await?;
+        let fetched = batch.len();
+
+        for point in batch {
+            match decode_point(&point) {
+                Ok(location) => locations.push(location),
+                Err(e) if skip_unreadable => {
+                    warn!("Skipping unreadable point for key {}: {}", key, e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        debug!("Read {} points for key {} (offset {})", fetched, key, start);
+
+        if fetched < batch_size {
+            break;
+        }
+        start += batch_size;
+    }
+
+    Ok(locations)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::route_simplification::RouteSimplifier;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// In-process Redis server answering the list, set and string commands of
+    /// `RedisPointStore` (key expiry is ignored), whose connections can all be
+    /// dropped at once
+    #[derive(Default)]
+    pub(crate) struct FakeRedis {
+        lists: Mutex<HashMap<Vec<u8>, Vec<Vec<u8>>>>,
+        values: Mutex<HashMap<Vec<u8>, Vec<u8>>>,
+        sets: Mutex<HashMap<Vec<u8>, HashSet<Vec<u8>>>>,
+        connects: AtomicU64,
+        generation: AtomicU64,
+    }
+
+    /// Connection to a `FakeRedis`, failing once the server dropped it
+    #[derive(Clone)]
+    pub(crate) struct FakeRedisConnection {
+        server: Arc<FakeRedis>,
+        generation: u64,
+    }
+
+    impl FakeRedis {
+        pub fn connector(self: &Arc<Self>) -> Connect<FakeRedisConnection> {
+            let server = self.clone();
+            Arc::new(move || {
+                server.connects.fetch_add(1, Ordering::SeqCst);
+                let connection = FakeRedisConnection {
+                    server: server.clone(),
+                    generation: server.generation.load(Ordering::SeqCst),
+                };
+                Box::pin(async move { Ok(connection) })
+            })
+        }
+
+        /// Connections opened so far
+        pub fn connects(&self) -> u64 {
+            self.connects.load(Ordering::SeqCst)
+        }
+
+        /// Drop every open connection, as a Redis restart would
+        pub fn drop_connections(&self) {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+
+        pub fn list(&self, key: &str) -> Vec<Vec<u8>> {
+            let lists = self.lists.lock().unwrap();
+            lists.get(key.as_bytes()).cloned().unwrap_or_default()
+        }
+
+        fn execute(&self, args: &[Vec<u8>]) -> redis::RedisResult<redis::Value> {
+            let int_arg =
+                |i: usize| -> isize { std::str::from_utf8(&args[i]).unwrap().parse().unwrap() };
+            let mut lists = self.lists.lock().unwrap();
+            let mut values = self.values.lock().unwrap();
+            let mut sets = self.sets.lock().unwrap();
+            let reply = match args[0].to_ascii_uppercase().as_slice() {
+                b"RPUSH" => {
+                    let list = lists.entry(args[1].clone()).or_default();
+                    list.extend_from_slice(&args[2..]);
+                    redis::Value::Int(list.len() as i64)
+                }
+                b"LRANGE" => {
+                    let list = lists.get(&args[1]).cloned().unwrap_or_default();
+                    let len = list.len() as isize;
+                    let resolve = |index: isize| if index < 0 { len + index } else { index };
+                    let start = resolve(int_arg(2)).max(0);
+                    let stop = resolve(int_arg(3)).min(len - 1);
+                    let items = (start..=stop)
+                        .map(|i| redis::Value::Data(list[i as usize].clone()))
+                        .collect();
+                    redis::Value::Bulk(items)
+                }
+                b"LLEN" => redis::Value::Int(lists.get(&args[1]).map_or(0, Vec::len) as i64),
+                b"DEL" => {
+                    let removed = args[1..]
+                        .iter()
+                        .filter(|key| {
+                            lists.remove(*key).is_some()
+                                | values.remove(*key).is_some()
+                                | sets.remove(*key).is_some()
+                        })
+                        .count();
+                    redis::Value::Int(removed as i64)
+                }
+                b"RENAME" => match lists.remove(&args[1]) {
+                    Some(list) => {
+                        lists.insert(args[2].clone(), list);
+                        redis::Value::Okay
+                    }
+                    None => {
+                        return Err(redis::RedisError::from((
+                            redis::ErrorKind::ResponseError,
+                            "no such key",
+                        )))
+                    }
+                },
+                b"SADD" => {
+                    let set = sets.entry(args[1].clone()).or_default();
+                    let added = args[2..].iter().filter(|m| set.insert(m.to_vec())).count();
+                    redis::Value::Int(added as i64)
+                }
+                b"EXPIRE" => redis::Value::Int(1),
+                b"GET" => values
+                    .get(&args[1])
+                    .map_or(redis::Value::Nil, |value| redis::Value::Data(value.clone())),
+                b"SET" => {
+                    values.insert(args[1].clone(), args[2].clone());
+                    redis::Value::Okay
+                }
+                command => {
+                    return Err(redis::RedisError::from((
+                        redis::ErrorKind::ResponseError,
+                        "unknown command",
+                        String::from_utf8_lossy(command).into_owned(),
+                    )))
+                }
+            };
+            Ok(reply)
+        }
+    }
+
+    impl FakeRedisConnection {
+        fn execute(&self, cmd: &redis::Cmd) -> redis::RedisResult<redis::Value> {
+            if self.generation != self.server.generation.load(Ordering::SeqCst) {
+                return Err(connection_reset());
+            }
+            let args: Vec<Vec<u8>> = cmd
+                .args_iter()
+                .map(|arg| match arg {
+                    redis::Arg::Simple(bytes) => bytes.to_vec(),
+                    redis::Arg::Cursor => b"0".to_vec(),
+                })
+                .collect();
+            self.server.execute(&args)
+        }
+    }
+
+    impl redis::aio::ConnectionLike for FakeRedisConnection {
+        fn req_packed_command<'a>(
+            &'a mut self,
+            cmd: &'a redis::Cmd,
+        ) -> redis::RedisFuture<'a, redis::Value> {
+            let result = self.execute(cmd);
+            Box::pin(async move { result })
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            pipeline: &'a redis::Pipeline,
+            offset: usize,
+            count: usize,
+        ) -> redis::RedisFuture<'a, Vec<redis::Value>> {
+            // Non-atomic pipelines only: one reply per command
+            let result = pipeline
+                .cmd_iter()
+                .map(|cmd| self.execute(cmd))
+                .collect::<redis::RedisResult<Vec<_>>>()
+                .map(|replies| replies.into_iter().skip(offset).take(count).collect());
+            Box::pin(async move { result })
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+    }
+
+    async fn seed_route(store: &InMemoryPointStore, key: &str, count: usize) {
+        for i in 0..count {
+            let location = Location::new((i as f64 * 0.01).sin(), i as f64 * 0.001);
+            store
+                .push(key, serde_json::to_vec(&location).unwrap())
+                .await
+                .unwrap();
+        }
+    }
+
+    /// Redis connection answering every command with `reply`, after failing
+    /// the first `failures` commands with `error`
+    struct FlakyConnection {
+        failures: u32,
+        error: fn() -> redis::RedisError,
+        reply: redis::Value,
+        calls: std::sync::Arc<std::sync::atomic::AtomicU32>,
+    }
+
+    impl redis::aio::ConnectionLike for FlakyConnection {
+        fn req_packed_command<'a>(
+            &'a mut self,
+            _cmd: &'a redis::Cmd,
+        ) -> redis::RedisFuture<'a, redis::Value> {
+            let call = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            let result = if call < self.failures {
+                Err((self.error)())
+            } else {
+                Ok(self.reply.clone())
+            };
+            Box::pin(async move { result })
+        }
+
+        fn req_packed_commands<'a>(
+            &'a mut self,
+            _cmd: &'a redis::Pipeline,
+            _offset: usize,
+            _count: usize,
+        ) -> redis::RedisFuture<'a, Vec<redis::Value>> {
+            unimplemented!("pipelines are not retried")
+        }
+
+        fn get_db(&self) -> i64 {
+            0
+        }
+    }
+
+    fn flaky_store(
+        failures: u32,
+        error: fn() -> redis::RedisError,
+        reply: redis::Value,
+    ) -> (
+        RedisPointStore<FlakyConnection>,
+        std::sync::Arc<std::sync::atomic::AtomicU32>,
+    ) {
+        let calls = std::sync::Arc::default();
+        let conn = FlakyConnection {
+            failures,
+            error,
+            reply,
+            calls: std::sync::Arc::clone(&calls),
+        };
+        let store =
+            RedisPointStore::new(conn).with_retry(RetryPolicy::new(2, Duration::from_millis(50)));
+        (store, calls)
+    }
+
+    fn connection_reset() -> redis::RedisError {
+        std::io::Error::from(std::io::ErrorKind::ConnectionReset).into()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_transient_redis_error_succeeds_on_retry() {
+        let reply = redis::Value::Bulk(vec![redis::Value::Data(b"point".to_vec())]);
+        let (store, calls) = flaky_store(1, connection_reset, reply);
+
+        let points = store.range("driver_1:route_1", 0, -1).await.unwrap();
+        assert_eq!(points, vec![b"point".to_vec()]);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // More failures than retries still surface the error
+        let (store, calls) = flaky_store(5, connection_reset, redis::Value::Int(1));
+        assert!(store
+            .push("driver_1:route_1", b"{}".to_vec())
+            .await
+            .is_err());
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shared_connection_reconnects_after_drop() {
+        let server = Arc::new(FakeRedis::default());
+        let shared = SharedRedisConnection::with_connect(0, server.connector());
+        let retry = RetryPolicy::new(2, Duration::from_millis(50));
+        let first = RedisPointStore::new(shared.clone()).with_retry(retry);
+        let second = RedisPointStore::new(shared).with_retry(retry);
+
+        first.push("driver_1:route_1", b"a".to_vec()).await.unwrap();
+        second
+            .push("driver_1:route_1", b"b".to_vec())
+            .await
+            .unwrap();
+        // Every clone uses the one connection
+        assert_eq!(server.connects(), 1);
+
+        // The command hitting the dropped connection is retried on a new one
+        server.drop_connections();
+        assert_eq!(second.len("driver_1:route_1").await.unwrap(), 2);
+        assert_eq!(first.len("driver_1:route_1").await.unwrap(), 2);
+        assert_eq!(server.connects(), 2);
+
+        // Command errors keep the connection
+        assert!(first.trim("driver_1:route_1", 0, 0).await.is_err());
+        first.delete("driver_1:route_1").await.unwrap();
+        assert_eq!(server.connects(), 2);
+        assert!(server.list("driver_1:route_1").is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fatal_redis_error_not_retried() {
+        let wrong_type = || {
+            redis::RedisError::from((
+                redis::ErrorKind::TypeError,
+                "WRONGTYPE Operation against a key holding the wrong kind of value",
+            ))
+        };
+        let (store, calls) = flaky_store(1, wrong_type, redis::Value::Int(1));
+
+        assert!(store.delete("driver_1:route_1").await.is_err());
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry_delay_backs_off_with_jitter() {
+        let policy = RetryPolicy::new(3, Duration::from_millis(100));
+        for retry in 1..=3 {
+            let ceiling = Duration::from_millis(100 << (retry - 1));
+            let delay = policy.delay(retry);
+            assert!(delay >= ceiling / 2 && delay <= ceiling, "{:?}", delay);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_range_semantics() {
+        let store = InMemoryPointStore::new();
+        for value in ["a", "b", "c"] {
+            store.push("key", value.into()).await.unwrap();
+        }
+
+        assert_eq!(
+            store.range("key", 0, -1).await.unwrap(),
+            vec![b"a", b"b", b"c"]
+        );
+        assert_eq!(store.range("key", 1, 10).await.unwrap(), vec![b"b", b"c"]);
+        assert_eq!(store.range("key", -2, -1).await.unwrap(), vec![b"b", b"c"]);
+        assert!(store.range("key", 3, 5).await.unwrap().is_empty());
+        assert!(store.range("missing", 0, -1).await.unwrap().is_empty());
+        assert_eq!(store.list_keys().await.unwrap(), vec!["key"]);
+
+        store.trim("key", -2, -1).await.unwrap();
+        assert_eq!(store.range("key", 0, -1).await.unwrap(), vec![b"b", b"c"]);
+
+        store.delete("key").await.unwrap();
+        assert!(store.range("key", 0, -1).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_batched_read_matches_single_range() {
+        let store = InMemoryPointStore::new();
+        seed_route(&store, "driver:route", 10_000).await;
+
+        let single: Vec<Location> = store
+            .range("driver:route", 0, -1)
+            .await
+            .unwrap()
+            .iter()
+            .map(|p| serde_json::from_slice(p).unwrap())
+            .collect();
+        let batched = read_points_batched(&store, "driver:route", 0, 333, false)
+            .await
+            .unwrap();
+        let exact = read_points_batched(&store, "driver:route", 0, 10_000, false)
+            .await
+            .unwrap();
+
+        assert_eq!(batched.len(), 10_000);
+        assert_eq!(batched, single);
+        assert_eq!(exact, single);
+
+        let tail = read_points_batched(&store, "driver:route", 9_000, 333, false)
+            .await
+            .unwrap();
+        assert_eq!(tail, single[9_000..]);
+
+        let simplifier = RouteSimplifier::new(0.001).unwrap();
+        assert_eq!(
+            simplifier.simplify_route(&batched).unwrap(),
+            simplifier.simplify_route(&single).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_points_skipped_when_enabled() {
+        let store = InMemoryPointStore::new();
+        store
+            .push("key", r#"{"latitude":1.0,"longitude":2.0}"#.into())
+            .await
+            .unwrap();
+        store
+            .push("key", r#"{"latitude":1.0}"#.into())
+            .await
+            .unwrap();
+
+        assert!(read_points_batched(&store, "key", 0, 10, false)
+            .await
+            .is_err());
+        let locations = read_points_batched(&store, "key", 0, 10, true)
+            .await
+            .unwrap();
+        assert_eq!(locations, vec![Location::new(1.0, 2.0)]);
+    }
+}
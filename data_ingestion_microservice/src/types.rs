@@ -1,28 +1,188 @@
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
 
 /// Represents an incoming MQTT message from a bus/driver
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct BusMessage {
+    /// Also accepted as `driver_id`, unless `STRICT_FIELD_NAMES` is set
+    #[serde(alias = "driver_id")]
     pub driver_id: String,
+    /// Position of the fix; may be omitted when `coordinates` is sent instead
+    #[serde(default = "Location::missing")]
     pub driver_location: Location,
+    /// Unix time of the fix in milliseconds, see [`MILLIS_PER_SEC`]
     pub timestamp: u64,
+    /// Also accepted as `current_route_id`, unless `STRICT_FIELD_NAMES` is set
+    #[serde(alias = "current_route_id")]
     pub current_route_id: String,
     pub status: BusStatus,
+    /// Client-provided identifier used to detect redelivered points
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub point_id: Option<String>,
+    /// Planned total route length in meters, used for progress reporting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub planned_length_meters: Option<f64>,
+    /// Route destination, used for progress reporting
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<Location>,
+    /// Simplification tolerance for this route, clamped to the configured range
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tolerance: Option<f64>,
+    /// Fleet the driver belongs to, used to label per-fleet metrics
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fleet: Option<String>,
+    /// Position as a bare pair, in the order set by `INPUT_COORDINATE_ORDER`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coordinates: Option<[f64; 2]>,
+    /// Passengers on board, for publishers that send it next to the location;
+    /// `driverLocation.occupancy` takes precedence
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occupancy: Option<u32>,
+    /// Session token of the device, checked when `AUTH_MODE` is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+    /// Discrete events tied to this point, kept when `STORE_ROUTE_EVENTS` is set
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub events: Vec<RouteEvent>,
 }
 
-/// Represents a GPS location
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+/// Milliseconds per second. Every message, point and trip timestamp is Unix
+/// time in milliseconds, as sent by `simulation/simulate_buses.py`; durations
+/// configured in seconds are converted with this before comparing.
+pub const MILLIS_PER_SEC: u64 = 1000;
+
+/// Seconds in a span of `millis` milliseconds
+pub fn millis_to_secs(millis: u64) -> f64 {
+    millis as f64 / MILLIS_PER_SEC as f64
+}
+
+/// snake_case spellings of `BusMessage` fields, accepted for payloads of the
+/// legacy service
+pub const SNAKE_CASE_ALIASES: [&str; 2] = ["driver_id", "current_route_id"];
+
+impl BusMessage {
+    /// Whether the payload carried a `driverLocation`
+    pub fn has_driver_location(&self) -> bool {
+        self.driver_location.latitude.is_finite() && self.driver_location.longitude.is_finite()
+    }
+
+    /// Identity of this point for duplicate detection: the client-provided
+    /// `pointId` when present, otherwise a stable hash of the point contents
+    pub fn dedup_id(&self) -> String {
+        if let Some(id) = &self.point_id {
+            return id.clone();
+        }
+
+        let identity = format!(
+            "{}|{}|{}|{}|{}",
+            self.driver_id,
+            self.current_route_id,
+            self.timestamp,
+            self.driver_location.latitude,
+            self.driver_location.longitude
+        );
+        format!("{:016x}", fnv1a_64(identity.as_bytes()))
+    }
+}
+
+/// 64-bit FNV-1a hash, stable across processes and releases
+fn fnv1a_64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Represents a GPS location.
+///
+/// Points are persisted in Redis (as JSON or in the binary layout of `codec`) and
+/// may be read back by a newer release after an upgrade. Every field added after
+/// `latitude`/`longitude` must be optional and carry `#[serde(default)]` so older
+/// points still deserialize; the binary layout needs a new flag bit for it.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
 pub struct Location {
     pub latitude: f64,
     pub longitude: f64,
+    /// Time of the fix in Unix milliseconds, copied from the message timestamp
+    /// when the point is stored
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Altitude in meters above sea level
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub altitude: Option<f64>,
+    /// Publisher-assigned weight for importance-weighted simplification (1.0 when absent)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub importance: Option<f64>,
+    /// Passengers on board at the time of the fix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub occupancy: Option<u32>,
+    /// Reported fix quality, as horizontal accuracy in meters or HDOP; lower is better
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accuracy: Option<f64>,
+    /// Ground speed in m/s reported with the fix
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<f64>,
+    /// Course over ground in degrees clockwise from north
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heading: Option<f64>,
+    /// Estimated by the `dead_reckoning` stage rather than reported by the device
+    #[serde(
+        default,
+        rename = "deadReckoned",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub dead_reckoned: bool,
+}
+
+impl Location {
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        Self {
+            latitude,
+            longitude,
+            ..Default::default()
+        }
+    }
+
+    /// Placeholder for an absent `driverLocation`; NaN never comes from JSON
+    fn missing() -> Self {
+        Self::new(f64::NAN, f64::NAN)
+    }
+
+    /// Check that the position is finite and within the valid latitude and
+    /// longitude ranges
+    pub fn validate(&self) -> ServiceResult<()> {
+        if !self.latitude.is_finite() || !self.longitude.is_finite() {
+            return Err(ServiceError::Validation(format!(
+                "Position ({}, {}) is not a finite number",
+                self.latitude, self.longitude
+            )));
+        }
+        if !(-90.0..=90.0).contains(&self.latitude) {
+            return Err(ServiceError::Validation(format!(
+                "Latitude {} is out of range; are latitude and longitude swapped? \
+                 Check INPUT_COORDINATE_ORDER",
+                self.latitude
+            )));
+        }
+        if !(-180.0..=180.0).contains(&self.longitude) {
+            return Err(ServiceError::Validation(format!(
+                "Longitude {} is out of range",
+                self.longitude
+            )));
+        }
+        Ok(())
+    }
 }
 
 /// Status of a bus in its route
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq)]
 #[serde(rename_all = "snake_case")]
 pub enum BusStatus {
+    /// Explicit start of a trip; records the route start without storing a point
+    Started,
+    #[default]
     InRoute,
     Finished,
 }
@@ -30,6 +190,7 @@ pub enum BusStatus {
 impl fmt::Display for BusStatus {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            BusStatus::Started => write!(f, "started"),
             BusStatus::InRoute => write!(f, "in_route"),
             BusStatus::Finished => write!(f, "finished"),
         }
@@ -41,6 +202,7 @@ impl std::str::FromStr for BusStatus {
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
+            "started" => Ok(BusStatus::Started),
             "in_route" => Ok(BusStatus::InRoute),
             "finished" => Ok(BusStatus::Finished),
             _ => Err(ServiceError::InvalidStatus(s.to_string())),
@@ -48,7 +210,85 @@ impl std::str::FromStr for BusStatus {
     }
 }
 
-/// Trip document structure for MongoDB storage
+/// Route metadata recorded by a `started` message
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteStart {
+    /// Timestamp of the `started` message
+    pub started_at: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub planned_length_meters: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub destination: Option<Location>,
+}
+
+/// Distance a driver has traveled on its current route, kept per driver so
+/// progress is computed without reading back the buffered route
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TraveledDistance {
+    pub current_route_id: String,
+    pub traveled_meters: f64,
+    /// Last point the distance was measured to
+    pub last: Location,
+}
+
+/// Discrete event reported with a point, such as a door opening or hard braking
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RouteEvent {
+    /// Publisher-defined type, e.g. `door_open` or `speeding`
+    pub kind: String,
+    /// The message timestamp when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u64>,
+    /// Where the event happened; the message's location when absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<Location>,
+}
+
+/// Why a route was dead-lettered
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadLetterKind {
+    /// A `finished` arrived before any point of its route
+    EarlyFinish,
+    /// The route had more points than `FINALIZE_MAX_POINTS`
+    Overflow,
+    /// The route was evicted over `MAX_ACTIVE_ROUTES_PER_DRIVER`
+    ActiveRouteCap,
+}
+
+/// Entry of a route's dead-letter list. Rejected points are not copied into
+/// the record: they are moved as a list to `points_key`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterRecord {
+    pub kind: DeadLetterKind,
+    pub driver_id: String,
+    pub current_route_id: String,
+    /// The rejected message, for `early_finish`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<BusMessage>,
+    /// Key holding the rejected points, for `overflow` and `active_route_cap`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub points_count: Option<usize>,
+}
+
+/// Control command re-simplifying the stored trip of a route, received on
+/// `MQTT_RESIMPLIFY_TOPIC`
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResimplifyCommand {
+    pub driver_id: String,
+    pub route_id: String,
+    /// New simplification tolerance, clamped to the payload tolerance range
+    pub tolerance: f64,
+}
+
+/// Trip document structure for MongoDB storage: the fields every stored trip
+/// starts from. Counts are `i32` so they are stored as BSON int32.
 #[derive(Debug, Clone, Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct TripDocument {
@@ -56,8 +296,8 @@ pub struct TripDocument {
     pub current_route_id: String,
     pub simplified_route: Vec<Location>,
     pub timestamp: i64,
-    pub original_points_count: usize,
-    pub simplified_points_count: usize,
+    pub original_points_count: i32,
+    pub simplified_points_count: i32,
     pub compression_ratio: f64,
 }
 
@@ -81,8 +321,8 @@ impl TripDocument {
             current_route_id,
             simplified_route,
             timestamp,
-            original_points_count: original_count,
-            simplified_points_count: simplified_count,
+            original_points_count: original_count as i32,
+            simplified_points_count: simplified_count as i32,
             compression_ratio,
         }
     }
@@ -94,15 +334,32 @@ pub enum ServiceError {
     #[error("MQTT error: {0}")]
     Mqtt(#[from] rumqttc::ClientError),
 
+    #[cfg(feature = "kafka")]
+    #[error("Kafka error: {0}")]
+    Kafka(#[from] rdkafka::error::KafkaError),
+
     #[error("Redis error: {0}")]
     Redis(#[from] redis::RedisError),
 
     #[error("MongoDB error: {0}")]
     MongoDB(#[from] mongodb::error::Error),
 
+    #[cfg(feature = "postgis")]
+    #[error("PostgreSQL error: {0}")]
+    Postgres(#[from] tokio_postgres::Error),
+
     #[error("Serialization error: {0}")]
     Serialization(#[from] serde_json::Error),
 
+    #[error("BSON serialization error: {0}")]
+    Bson(#[from] mongodb::bson::ser::Error),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+
     #[error("Configuration error: {0}")]
     Config(String),
 
@@ -117,60 +374,217 @@ pub enum ServiceError {
 
     #[error("Validation error: {0}")]
     Validation(String),
+
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
 }
 
 /// Type alias for Results using our custom error type
 pub type ServiceResult<T> = Result<T, ServiceError>;
 
-/// Metrics structure for monitoring
-#[derive(Debug, Clone, Default)]
+/// Service counters for monitoring. Every update takes `&self` and is a
+/// single atomic operation, so one instance behind an `Arc` is shared by all
+/// message tasks without serializing them on a lock.
+#[derive(Debug, Default)]
 pub struct ServiceMetrics {
-    pub messages_processed: u64,
-    pub routes_in_progress: u64,
-    pub routes_completed: u64,
-    pub errors_count: u64,
-    pub total_points_processed: u64,
-    pub total_points_simplified: u64,
+    messages_processed: AtomicU64,
+    routes_in_progress: AtomicU64,
+    routes_completed: AtomicU64,
+    errors_count: AtomicU64,
+    total_points_processed: AtomicU64,
+    total_points_simplified: AtomicU64,
+    messages_filtered: AtomicU64,
+    routes_evicted: AtomicU64,
+    messages_unauthorized: AtomicU64,
+    stale_dropped: AtomicU64,
+    invalid_points: AtomicU64,
+    routes_merged: AtomicU64,
+    compression_drift_alerts: AtomicU64,
+    compression_drifting: AtomicBool,
+    /// Updated together once per finalized trip, off the per-point path
+    compression: Mutex<CompressionAverages>,
+}
+
+/// Running averages of the per-trip compression ratio
+#[derive(Debug, Clone, Copy, Default)]
+struct CompressionAverages {
+    ewma: f64,
+    baseline: f64,
+    trips: u64,
 }
 
 impl ServiceMetrics {
-    pub fn increment_messages_processed(&mut self) {
-        self.messages_processed += 1;
+    pub fn increment_messages_processed(&self) {
+        self.messages_processed.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn increment_routes_in_progress(&mut self) {
-        self.routes_in_progress += 1;
+    pub fn increment_routes_in_progress(&self) {
+        self.routes_in_progress.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn decrement_routes_in_progress(&mut self) {
-        if self.routes_in_progress > 0 {
-            self.routes_in_progress -= 1;
-        }
+    pub fn decrement_routes_in_progress(&self) {
+        let _ =
+            self.routes_in_progress
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                    count.checked_sub(1)
+                });
     }
 
-    pub fn increment_routes_completed(&mut self) {
-        self.routes_completed += 1;
+    pub fn increment_routes_completed(&self) {
+        self.routes_completed.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn increment_errors(&mut self) {
-        self.errors_count += 1;
+    pub fn increment_messages_filtered(&self) {
+        self.messages_filtered.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn add_points_processed(&mut self, count: u64) {
-        self.total_points_processed += count;
+    pub fn increment_routes_evicted(&self) {
+        self.routes_evicted.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn add_points_simplified(&mut self, count: u64) {
-        self.total_points_simplified += count;
+    pub fn increment_messages_unauthorized(&self) {
+        self.messages_unauthorized.fetch_add(1, Ordering::Relaxed);
     }
 
-    pub fn compression_ratio(&self) -> f64 {
-        if self.total_points_processed > 0 {
-            self.total_points_simplified as f64 / self.total_points_processed as f64
+    pub fn increment_stale_dropped(&self) {
+        self.stale_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_invalid_points(&self) {
+        self.invalid_points.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_routes_merged(&self) {
+        self.routes_merged.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn increment_errors(&self) {
+        self.errors_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_points_processed(&self, count: u64) {
+        self.total_points_processed
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn add_points_simplified(&self, count: u64) {
+        self.total_points_simplified
+            .fetch_add(count, Ordering::Relaxed);
+    }
+
+    /// Fold the compression ratio of one trip into the recent (`alpha`) and
+    /// baseline (`baseline_alpha`) averages. Returns how far the recent average
+    /// has drifted from the baseline, relative to the baseline.
+    pub fn observe_compression_ratio(&self, ratio: f64, alpha: f64, baseline_alpha: f64) -> f64 {
+        let mut averages = self.compression.lock().unwrap();
+        if averages.trips == 0 {
+            averages.ewma = ratio;
+            averages.baseline = ratio;
+        } else {
+            averages.ewma += alpha * (ratio - averages.ewma);
+            averages.baseline += baseline_alpha * (ratio - averages.baseline);
+        }
+        averages.trips += 1;
+
+        if averages.baseline > 0.0 {
+            (averages.ewma - averages.baseline).abs() / averages.baseline
         } else {
             0.0
         }
     }
+
+    /// Record whether the recent compression ratio is drifted. Returns true,
+    /// counting a drift alert, when it was not drifted before.
+    pub fn set_compression_drifting(&self, drifting: bool) -> bool {
+        let was_drifting = self.compression_drifting.swap(drifting, Ordering::Relaxed);
+        let started = drifting && !was_drifting;
+        if started {
+            self.compression_drift_alerts
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        started
+    }
+
+    pub fn compression_ratio(&self) -> f64 {
+        ratio(
+            self.total_points_simplified.load(Ordering::Relaxed),
+            self.total_points_processed.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Current value of every counter
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let compression = *self.compression.lock().unwrap();
+        MetricsSnapshot {
+            messages_processed: self.messages_processed.load(Ordering::Relaxed),
+            routes_in_progress: self.routes_in_progress.load(Ordering::Relaxed),
+            routes_completed: self.routes_completed.load(Ordering::Relaxed),
+            errors_count: self.errors_count.load(Ordering::Relaxed),
+            total_points_processed: self.total_points_processed.load(Ordering::Relaxed),
+            total_points_simplified: self.total_points_simplified.load(Ordering::Relaxed),
+            messages_filtered: self.messages_filtered.load(Ordering::Relaxed),
+            routes_evicted: self.routes_evicted.load(Ordering::Relaxed),
+            messages_unauthorized: self.messages_unauthorized.load(Ordering::Relaxed),
+            stale_dropped: self.stale_dropped.load(Ordering::Relaxed),
+            invalid_points: self.invalid_points.load(Ordering::Relaxed),
+            routes_merged: self.routes_merged.load(Ordering::Relaxed),
+            compression_ratio_ewma: compression.ewma,
+            compression_ratio_baseline: compression.baseline,
+            compression_trips: compression.trips,
+            compression_drift_alerts: self.compression_drift_alerts.load(Ordering::Relaxed),
+            compression_drifting: self.compression_drifting.load(Ordering::Relaxed),
+            processing_permits_in_use: 0,
+        }
+    }
+}
+
+/// Values of the `ServiceMetrics` counters at one point in time
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSnapshot {
+    pub messages_processed: u64,
+    pub routes_in_progress: u64,
+    pub routes_completed: u64,
+    pub errors_count: u64,
+    pub total_points_processed: u64,
+    pub total_points_simplified: u64,
+    pub messages_filtered: u64,
+    /// Routes finalized or dead-lettered for exceeding the per-driver route cap
+    pub routes_evicted: u64,
+    /// Messages rejected for a missing or invalid session token
+    pub messages_unauthorized: u64,
+    /// Messages dropped for waiting longer than `max_processing_age_ms`
+    pub stale_dropped: u64,
+    /// `in_route` points skipped for a missing, non-finite or out-of-range position
+    pub invalid_points: u64,
+    /// Routes found to repeat a stored trip, then linked to or merged into it
+    pub routes_merged: u64,
+    /// Recent per-trip compression ratio, as an EWMA
+    pub compression_ratio_ewma: f64,
+    /// Long-run per-trip compression ratio the recent average is compared with
+    pub compression_ratio_baseline: f64,
+    /// Trips folded into the compression ratio averages
+    pub compression_trips: u64,
+    /// Times the recent compression ratio drifted away from the baseline
+    pub compression_drift_alerts: u64,
+    /// Whether the recent compression ratio is currently drifted
+    pub compression_drifting: bool,
+    /// Message tasks currently holding one of the `max_concurrent_tasks` slots
+    pub processing_permits_in_use: u64,
+}
+
+impl MetricsSnapshot {
+    pub fn compression_ratio(&self) -> f64 {
+        ratio(self.total_points_simplified, self.total_points_processed)
+    }
+}
+
+/// Simplified points per processed point, 0 before any point was processed
+fn ratio(simplified: u64, processed: u64) -> f64 {
+    if processed > 0 {
+        simplified as f64 / processed as f64
+    } else {
+        0.0
+    }
 }
 
 #[cfg(test)]
@@ -179,6 +593,8 @@ mod tests {
 
     #[test]
     fn test_bus_status_parsing() {
+        assert_eq!("started".parse::<BusStatus>().unwrap(), BusStatus::Started);
+        assert_eq!(BusStatus::Started.to_string(), "started");
         assert_eq!("in_route".parse::<BusStatus>().unwrap(), BusStatus::InRoute);
         assert_eq!(
             "finished".parse::<BusStatus>().unwrap(),
@@ -187,18 +603,119 @@ mod tests {
         assert!("invalid".parse::<BusStatus>().is_err());
     }
 
+    #[test]
+    fn test_location_defaults_missing_optional_fields() {
+        let old: Location = serde_json::from_str(r#"{"latitude":1.0,"longitude":2.0}"#).unwrap();
+        assert_eq!(old, Location::new(1.0, 2.0));
+
+        let new: Location =
+            serde_json::from_str(r#"{"latitude":1.0,"longitude":2.0,"timestamp":7}"#).unwrap();
+        assert_eq!(new.timestamp, Some(7));
+    }
+
+    #[test]
+    fn test_location_range_boundaries() {
+        for (latitude, longitude) in [(90.0, 180.0), (-90.0, -180.0), (0.0, 0.0)] {
+            assert!(Location::new(latitude, longitude).validate().is_ok());
+        }
+        for (latitude, longitude) in [
+            (90.000_001, 0.0),
+            (-90.000_001, 0.0),
+            (0.0, 180.000_001),
+            (0.0, -180.000_001),
+            (999.0, 0.0),
+        ] {
+            let error = Location::new(latitude, longitude).validate().unwrap_err();
+            assert!(matches!(error, ServiceError::Validation(_)));
+        }
+    }
+
+    #[test]
+    fn test_non_finite_location_rejected() {
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            for location in [Location::new(value, 0.0), Location::new(0.0, value)] {
+                let error = location.validate().unwrap_err();
+                assert!(matches!(error, ServiceError::Validation(_)));
+                assert!(error.to_string().contains("finite"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_occupancy_deserialized() {
+        let msg: BusMessage = serde_json::from_str(
+            r#"{"driverId":"d","currentRouteId":"r","timestamp":1,"status":"in_route",
+                "driverLocation":{"latitude":1.0,"longitude":2.0,"occupancy":31},"occupancy":12}"#,
+        )
+        .unwrap();
+        assert_eq!(msg.driver_location.occupancy, Some(31));
+        assert_eq!(msg.occupancy, Some(12));
+
+        let msg: BusMessage = serde_json::from_str(
+            r#"{"driverId":"d","currentRouteId":"r","timestamp":1,"status":"in_route",
+                "driverLocation":{"latitude":1.0,"longitude":2.0}}"#,
+        )
+        .unwrap();
+        assert_eq!(msg.driver_location.occupancy, None);
+        assert_eq!(msg.occupancy, None);
+    }
+
+    #[test]
+    fn test_camel_and_snake_case_payloads() {
+        let camel: BusMessage = serde_json::from_str(
+            r#"{"driverId":"d","currentRouteId":"r","timestamp":1,"status":"in_route"}"#,
+        )
+        .unwrap();
+        let snake: BusMessage = serde_json::from_str(
+            r#"{"driver_id":"d","current_route_id":"r","timestamp":1,"status":"in_route"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            (snake.driver_id, snake.current_route_id),
+            ("d".into(), "r".into())
+        );
+        assert_eq!(
+            (camel.driver_id, camel.current_route_id),
+            ("d".into(), "r".into())
+        );
+
+        // Serialization always uses camelCase
+        let json = serde_json::to_string(&BusMessage::default()).unwrap();
+        assert!(json.contains("\"driverId\"") && !json.contains("driver_id"));
+    }
+
+    #[test]
+    fn test_dedup_id() {
+        let msg = BusMessage {
+            driver_id: "driver1".to_string(),
+            driver_location: Location::new(1.0, 2.0),
+            timestamp: 10,
+            current_route_id: "route1".to_string(),
+            ..Default::default()
+        };
+
+        assert_eq!(msg.dedup_id(), msg.clone().dedup_id());
+        assert_ne!(
+            msg.dedup_id(),
+            BusMessage {
+                timestamp: 11,
+                ..msg.clone()
+            }
+            .dedup_id()
+        );
+        assert_eq!(
+            BusMessage {
+                point_id: Some("p-1".to_string()),
+                ..msg
+            }
+            .dedup_id(),
+            "p-1"
+        );
+    }
+
     #[test]
     fn test_trip_document_creation() {
-        let route = vec![
-            Location {
-                latitude: 1.0,
-                longitude: 2.0,
-            },
-            Location {
-                latitude: 3.0,
-                longitude: 4.0,
-            },
-        ];
+        let route = vec![Location::new(1.0, 2.0), Location::new(3.0, 4.0)];
 
         let trip = TripDocument::new(
             "driver1".to_string(),
@@ -215,13 +732,57 @@ mod tests {
 
     #[test]
     fn test_metrics() {
-        let mut metrics = ServiceMetrics::default();
+        let metrics = ServiceMetrics::default();
 
         metrics.increment_messages_processed();
         metrics.add_points_processed(100);
         metrics.add_points_simplified(20);
 
-        assert_eq!(metrics.messages_processed, 1);
+        assert_eq!(metrics.snapshot().messages_processed, 1);
         assert_eq!(metrics.compression_ratio(), 0.2);
+        assert_eq!(metrics.snapshot().compression_ratio(), 0.2);
+
+        metrics.decrement_routes_in_progress();
+        assert_eq!(metrics.snapshot().routes_in_progress, 0);
+    }
+
+    #[test]
+    fn test_compression_ratio_averages() {
+        let metrics = ServiceMetrics::default();
+        assert_eq!(metrics.observe_compression_ratio(0.2, 0.5, 0.1), 0.0);
+        assert_eq!(metrics.snapshot().compression_ratio_baseline, 0.2);
+
+        let drift = metrics.observe_compression_ratio(0.4, 0.5, 0.1);
+        let snapshot = metrics.snapshot();
+        assert!((snapshot.compression_ratio_ewma - 0.3).abs() < 1e-12);
+        assert!((snapshot.compression_ratio_baseline - 0.22).abs() < 1e-12);
+        assert!((drift - 0.08 / 0.22).abs() < 1e-12);
+        assert_eq!(snapshot.compression_trips, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_updates_are_exact() {
+        let metrics = std::sync::Arc::new(ServiceMetrics::default());
+
+        let tasks: Vec<_> = (0..100)
+            .map(|_| {
+                let metrics = metrics.clone();
+                tokio::spawn(async move {
+                    for _ in 0..1000 {
+                        metrics.increment_messages_processed();
+                        metrics.add_points_processed(3);
+                    }
+                    metrics.increment_routes_completed();
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.messages_processed, 100_000);
+        assert_eq!(snapshot.total_points_processed, 300_000);
+        assert_eq!(snapshot.routes_completed, 100);
     }
 }
@@ -0,0 +1,195 @@
+use crate::storage::TripStore;
+use crate::types::{ServiceError, ServiceResult};
+use log::debug;
+use mongodb::bson::{Bson, Document};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+/// Process-local buffer of finished trips written to the trip store together,
+/// in one `insert_trips` call, once `batch_size` of them are waiting or on the
+/// next periodic flush. `push` only returns once its trip's batch was written,
+/// so a route's points are not released before its trip is stored.
+pub struct TripWriter {
+    store: Arc<dyn TripStore>,
+    batch_size: usize,
+    pending: Mutex<Vec<PendingTrip>>,
+}
+
+/// Buffered trip and where to report how its write went
+struct PendingTrip {
+    trip: Document,
+    stored: oneshot::Sender<ServiceResult<Bson>>,
+}
+
+impl TripWriter {
+    /// Writer batching `batch_size` trips; 0 inserts each trip on its own
+    pub fn new(store: Arc<dyn TripStore>, batch_size: usize) -> Self {
+        Self {
+            store,
+            batch_size,
+            pending: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Store a trip, returning its `_id` once the batch it went out in was
+    /// written. The push filling a batch writes it; otherwise the trip waits
+    /// for the next `flush`.
+    pub async fn push(&self, trip: Document) -> ServiceResult<Bson> {
+        if self.batch_size == 0 {
+            return self.store.insert_trip(trip).await;
+        }
+
+        let (stored, written) = oneshot::channel();
+        let batch = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.push(PendingTrip { trip, stored });
+            (pending.len() >= self.batch_size).then(|| std::mem::take(&mut *pending))
+        };
+        if let Some(batch) = batch {
+            self.write(batch).await;
+        }
+
+        written.await.map_err(|_| {
+            ServiceError::Connection("Trip writer stopped before storing the trip".to_string())
+        })?
+    }
+
+    /// Write every buffered trip. Run periodically by `run_flusher` and once
+    /// more on shutdown. Returns the number of trips stored.
+    pub async fn flush(&self) -> usize {
+        let batch = std::mem::take(&mut *self.pending.lock().unwrap());
+        self.write(batch).await
+    }
+
+    /// Flush the buffered trips every `period`, so none waits longer than that
+    /// for its batch to fill. Never returns.
+    pub async fn run_flusher(self: Arc<Self>, period: Duration) {
+        let mut interval = tokio::time::interval(period);
+        loop {
+            interval.tick().await;
+            self.flush().await;
+        }
+    }
+
+    /// Number of buffered trips
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Insert a batch and hand each trip's result to its `push`
+    async fn write(&self, batch: Vec<PendingTrip>) -> usize {
+        if batch.is_empty() {
+            return 0;
+        }
+        let (trips, senders): (Vec<_>, Vec<_>) = batch
+            .into_iter()
+            .map(|pending| (pending.trip, pending.stored))
+            .unzip();
+        let count = trips.len();
+        let results = self.store.insert_trips(trips).await;
+
+        let mut stored = 0;
+        for (sender, result) in senders.into_iter().zip(results) {
+            stored += usize::from(result.is_ok());
+            // Nobody to tell if the push was cancelled
+            let _ = sender.send(result);
+        }
+        debug!("Wrote a batch of {} trips ({} stored)", count, stored);
+        stored
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::InMemoryTripStore;
+    use mongodb::bson::doc;
+
+    fn trip(route_id: &str) -> Document {
+        doc! { "driverId": "driver_1", "currentRouteId": route_id }
+    }
+
+    fn stored_routes(store: &InMemoryTripStore) -> Vec<String> {
+        store
+            .trips()
+            .iter()
+            .map(|trip| trip.get_str("currentRouteId").unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_full_batch_written_at_once() {
+        let store = Arc::new(InMemoryTripStore::new());
+        let writer = Arc::new(TripWriter::new(store.clone(), 3));
+
+        let mut waiting = Vec::new();
+        for route_id in ["route_1", "route_2"] {
+            let writer = writer.clone();
+            waiting.push(tokio::spawn(
+                async move { writer.push(trip(route_id)).await },
+            ));
+        }
+        tokio::task::yield_now().await;
+        assert_eq!(writer.len(), 2);
+        assert!(store.trips().is_empty());
+
+        // The third trip fills the batch and writes it
+        let id = writer.push(trip("route_3")).await.unwrap();
+        assert_eq!(store.batch_sizes(), vec![3]);
+        assert_eq!(stored_routes(&store), vec!["route_1", "route_2", "route_3"]);
+        assert_eq!(store.trips()[2].get("_id"), Some(&id));
+        for push in waiting {
+            assert!(push.await.unwrap().is_ok());
+        }
+        assert!(writer.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_partial_batch_written_after_flush_interval() {
+        let store = Arc::new(InMemoryTripStore::new());
+        let writer = Arc::new(TripWriter::new(store.clone(), 10));
+        tokio::spawn(writer.clone().run_flusher(Duration::from_millis(200)));
+
+        let pushing = writer.clone();
+        let push = tokio::spawn(async move { pushing.push(trip("route_1")).await });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(store.trips().is_empty());
+
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(stored_routes(&store), vec!["route_1"]);
+        assert_eq!(store.batch_sizes(), vec![1]);
+        assert!(push.await.unwrap().is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_flush_writes_waiting_trips() {
+        let store = Arc::new(InMemoryTripStore::new());
+        let writer = Arc::new(TripWriter::new(store.clone(), 10));
+
+        let pushing = writer.clone();
+        let push = tokio::spawn(async move { pushing.push(trip("route_1")).await });
+        tokio::task::yield_now().await;
+
+        // As on shutdown: no flusher, nothing left behind
+        assert_eq!(writer.flush().await, 1);
+        assert!(push.await.unwrap().is_ok());
+        assert_eq!(stored_routes(&store), vec!["route_1"]);
+        assert_eq!(writer.flush().await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_unbatched_writer_inserts_each_trip() {
+        let store = Arc::new(InMemoryTripStore::new());
+        let writer = TripWriter::new(store.clone(), 0);
+
+        writer.push(trip("route_1")).await.unwrap();
+        writer.push(trip("route_2")).await.unwrap();
+        assert_eq!(stored_routes(&store), vec!["route_1", "route_2"]);
+        assert!(store.batch_sizes().is_empty());
+    }
+}
@@ -1,6 +1,7 @@
 use futures::StreamExt;
 use log::{error, info};
 use mongodb::{bson::doc, Client as MongoClient};
+use redis::aio::ConnectionManager;
 use redis::AsyncCommands;
 use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
 use serde::{Deserialize, Serialize};
@@ -37,8 +38,10 @@ async fn main() -> Result<(), Box<dyn Error>> {
         .await?;
 
     // --- Setup Redis connection ---
+    // A single multiplexed connection shared by every task; the manager
+    // reconnects on its own when the connection drops.
     let redis_client = redis::Client::open("redis://127.0.0.1/")?;
-    let mut redis_conn = redis_client.get_async_connection().await?;
+    let redis_conn = ConnectionManager::new(redis_client).await?;
 
     // --- Setup MongoDB connection (optional) ---
     // For this example, we'll assume a local MongoDB instance; adjust the URI as needed.
@@ -56,7 +59,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
             Event::Incoming(Packet::Publish(publish)) => {
                 let payload = publish.payload;
                 // Spawn a task to process each message concurrently
-                let mut redis_conn = redis_client.get_async_connection().await?;
+                let mut redis_conn = redis_conn.clone();
                 let trips_collection = trips_collection.clone();
                 tokio::spawn(async move {
                     if let Err(e) =
@@ -78,7 +81,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
 /// For "finished": retrieve the list, simplify it, and store it (here we log it and insert into MongoDB).
 async fn process_message(
     payload: &[u8],
-    redis_conn: &mut redis::aio::Connection,
+    redis_conn: &mut ConnectionManager,
     trips_collection: &mongodb::Collection<mongodb::bson::Document>,
 ) -> Result<(), Box<dyn Error>> {
     let msg: BusMessage = serde_json::from_slice(payload)?;